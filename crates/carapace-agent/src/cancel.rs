@@ -0,0 +1,54 @@
+use std::sync::Arc;
+
+use crate::connection::Connection;
+use crate::multiplexer::Multiplexer;
+
+/// Cancels a request on the server if dropped while still armed.
+///
+/// Axum drops a handler's future when the HTTP client disconnects, so a guard
+/// held across the wait for the response fires exactly when nobody is left to
+/// receive it. Call `disarm()` once the response has arrived.
+pub struct CancelGuard {
+    request_id: String,
+    multiplexer: Arc<Multiplexer>,
+    connection: Arc<Connection>,
+    armed: bool,
+}
+
+impl CancelGuard {
+    pub fn new(
+        request_id: String,
+        multiplexer: Arc<Multiplexer>,
+        connection: Arc<Connection>,
+    ) -> Self {
+        CancelGuard {
+            request_id,
+            multiplexer,
+            connection,
+            armed: true,
+        }
+    }
+
+    /// The request completed; dropping the guard no longer cancels it
+    pub fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+
+        let request_id = std::mem::take(&mut self.request_id);
+        let multiplexer = self.multiplexer.clone();
+        let connection = self.connection.clone();
+        tokio::spawn(async move {
+            multiplexer.remove_waiter(&request_id).await;
+            if let Err(e) = connection.cancel(&request_id).await {
+                tracing::warn!("Failed to cancel request {}: {}", request_id, e);
+            }
+        });
+    }
+}
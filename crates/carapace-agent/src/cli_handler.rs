@@ -1,9 +1,11 @@
-use carapace_protocol::{CliRequest, Message};
+use carapace_protocol::{CliRequest, Message, PtyData, PtyResize};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::{UnixListener, UnixStream};
 use uuid::Uuid;
 
+use crate::cancel::CancelGuard;
 use crate::connection::Connection;
 use crate::error::Result;
 use crate::multiplexer::Multiplexer;
@@ -12,6 +14,8 @@ pub struct CliHandler {
     socket_path: String,
     multiplexer: Arc<Multiplexer>,
     connection: Arc<Connection>,
+    /// How long to wait for the server's first reply to a request
+    reply_timeout: Duration,
 }
 
 impl CliHandler {
@@ -24,9 +28,16 @@ impl CliHandler {
             socket_path,
             multiplexer,
             connection,
+            reply_timeout: Duration::from_secs(30),
         }
     }
 
+    /// Wait this long for the server's first reply instead of 30 seconds
+    pub fn with_reply_timeout(mut self, reply_timeout: Duration) -> Self {
+        self.reply_timeout = reply_timeout;
+        self
+    }
+
     /// Start listening for CLI requests on Unix socket
     pub async fn listen(&self) -> Result<()> {
         // Remove existing socket if present
@@ -39,9 +50,12 @@ impl CliHandler {
             let (socket, _) = listener.accept().await?;
             let multiplexer = self.multiplexer.clone();
             let connection = self.connection.clone();
+            let reply_timeout = self.reply_timeout;
 
             tokio::spawn(async move {
-                if let Err(e) = Self::handle_client(socket, multiplexer, connection).await {
+                if let Err(e) =
+                    Self::handle_client(socket, multiplexer, connection, reply_timeout).await
+                {
                     tracing::error!("Error handling CLI client: {}", e);
                 }
             });
@@ -49,20 +63,25 @@ impl CliHandler {
     }
 
     async fn handle_client(
-        mut socket: UnixStream,
+        socket: UnixStream,
         multiplexer: Arc<Multiplexer>,
         connection: Arc<Connection>,
+        reply_timeout: Duration,
     ) -> Result<()> {
-        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 
-        // Read request from socket until EOF.
-        // The shim calls shutdown() after writing, so we read until we get EOF (n==0).
-        // Previous heuristic (break if n < buf size) was unreliable and could hang
-        // if the message happened to fill a read buffer exactly.
+        // Read the request line. Current shims terminate the request with a
+        // newline and keep the socket open to send control lines (forwarded
+        // signals, cancel); closing the socket aborts the command. Older shims
+        // call shutdown() after writing instead, so a request without the
+        // newline runs to completion.
+        let (read_half, mut write_half) = socket.into_split();
+        let mut reader = BufReader::new(read_half);
         let mut buf = Vec::with_capacity(8192);
-        socket.read_to_end(&mut buf).await?;
+        reader.read_until(b'\n', &mut buf).await?;
+        let cancellable = buf.last() == Some(&b'\n');
 
-        if buf.is_empty() {
+        if buf.iter().all(u8::is_ascii_whitespace) {
             return Ok(());
         }
 
@@ -89,6 +108,14 @@ impl CliHandler {
             }
         }
 
+        // The shim reports its terminal size when it has one; only servers
+        // that negotiated terminal support can run interactive sessions
+        let tty = if connection.negotiated().await.pty {
+            serde_json::from_value(req_json["tty"].clone()).ok()
+        } else {
+            None
+        };
+
         // Create CLI request
         let id = Uuid::new_v4().to_string();
         let cli_req = CliRequest {
@@ -98,8 +125,20 @@ impl CliHandler {
             env,
             stdin: None,
             cwd: std::env::current_dir()?.to_str().unwrap_or("/").to_string(),
+            tty,
         };
 
+        // Tell the shim it may keep the socket open for control lines, and
+        // whether signals reach the process or only cancel it. Shims that
+        // hear nothing assume an older agent and close their write half.
+        let forward_signals = connection.negotiated().await.signals;
+        if cancellable {
+            let accepted = serde_json::json!({"type": "accepted", "signals": forward_signals});
+            let mut line = serde_json::to_vec(&accepted)?;
+            line.push(b'\n');
+            write_half.write_all(&line).await?;
+        }
+
         // Register waiter for response
         let mut rx = multiplexer.register_waiter(id.clone()).await;
 
@@ -110,35 +149,147 @@ impl CliHandler {
             return Err(e);
         }
 
-        // Wait for response (with timeout)
-        let response =
-            match tokio::time::timeout(tokio::time::Duration::from_secs(30), rx.recv()).await {
-                Ok(Some(msg)) => msg,
-                Ok(None) => {
-                    multiplexer.remove_waiter(&id).await;
-                    return Err(crate::error::AgentError::RequestNotFound(id));
-                }
-                Err(_) => {
+        // Cancel the request on the server if the shim goes away first
+        let guard = CancelGuard::new(id.clone(), multiplexer.clone(), connection.clone());
+
+        // Wait for the response, relaying control lines from the shim and, for
+        // interactive sessions, terminal data in both directions. The timeout
+        // only covers the wait for the first reply: once a session is
+        // interactive, the server's policy timeout bounds it instead.
+        let timeout = tokio::time::sleep(reply_timeout);
+        tokio::pin!(timeout);
+        let mut interactive = false;
+        // Kept across iterations: a read interrupted by another branch
+        // leaves the start of its line here
+        let mut control_line = Vec::new();
+        let response = loop {
+            tokio::select! {
+                msg = rx.recv() => match msg {
+                    Some(Message::PtyData(data)) => {
+                        interactive = true;
+                        // If the shim is gone, the guard cancels the session
+                        write_line(&mut write_half, &Message::PtyData(data)).await?;
+                    }
+                    Some(msg) => break msg,
+                    None => {
+                        guard.disarm();
+                        multiplexer.remove_waiter(&id).await;
+                        return Err(crate::error::AgentError::RequestNotFound(id));
+                    }
+                },
+                // The guard tells the server to stop working on the request
+                _ = &mut timeout, if !interactive => {
                     multiplexer.remove_waiter(&id).await;
                     return Err(crate::error::AgentError::RequestTimeout(
                         "CLI request timeout".to_string(),
                     ));
                 }
-            };
+                control = next_control(&mut reader, &mut control_line), if cancellable => match control {
+                    ShimControl::Signal(signal) if forward_signals => {
+                        connection.signal(&id, signal).await?;
+                    }
+                    ShimControl::Input(data) => {
+                        if interactive {
+                            connection
+                                .send(Message::PtyData(PtyData { id: id.clone(), data }))
+                                .await?;
+                        }
+                    }
+                    ShimControl::Resize { rows, cols } => {
+                        if interactive {
+                            connection
+                                .send(Message::PtyResize(PtyResize {
+                                    id: id.clone(),
+                                    rows,
+                                    cols,
+                                }))
+                                .await?;
+                        }
+                    }
+                    // Without signal forwarding, cancelling is the closest match
+                    _ => {
+                        tracing::info!("CLI client for request {} went away, cancelling", id);
+                        return Ok(());
+                    }
+                },
+            }
+        };
 
         // Clean up waiter after receiving response
+        guard.disarm();
         multiplexer.remove_waiter(&id).await;
 
         // Send response back to client
-        let json = serde_json::to_vec(&response)?;
-        socket.write_all(&json).await?;
+        write_line(&mut write_half, &response).await?;
 
         Ok(())
     }
 }
 
+/// Write a message to the shim as one JSON line
+async fn write_line<W: tokio::io::AsyncWrite + Unpin>(writer: &mut W, msg: &Message) -> Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut json = serde_json::to_vec(msg)?;
+    json.push(b'\n');
+    writer.write_all(&json).await?;
+    Ok(())
+}
+
+/// A control line sent by the shim while its request is running
+#[derive(Debug, PartialEq)]
+enum ShimControl {
+    /// `{"type":"signal","signal":N}`: the shim received signal N
+    Signal(i32),
+    /// `{"type":"pty_data","data":B64}`: keystrokes of an interactive session
+    Input(String),
+    /// `{"type":"pty_resize","rows":R,"cols":C}`: the terminal was resized
+    Resize { rows: u16, cols: u16 },
+    /// `{"type":"cancel"}`, or the socket closing
+    Cancel,
+}
+
+/// Wait for the next control line from the shim. `line` holds what has been
+/// read of it so far and must outlive the call: in a `select!`, the read
+/// may be dropped part way through a line and is resumed by the next call.
+async fn next_control<R: tokio::io::AsyncBufRead + Unpin>(
+    reader: &mut R,
+    line: &mut Vec<u8>,
+) -> ShimControl {
+    use tokio::io::AsyncBufReadExt;
+
+    loop {
+        match reader.read_until(b'\n', line).await {
+            // Only the shim going away ends the request
+            Ok(_) if line.last() == Some(&b'\n') => {}
+            _ => return ShimControl::Cancel,
+        }
+        let control = parse_control(line);
+        line.clear();
+        match control {
+            Some(control) => return control,
+            None => tracing::warn!("Ignoring unrecognized control line from shim"),
+        }
+    }
+}
+
+fn parse_control(line: &[u8]) -> Option<ShimControl> {
+    let value = serde_json::from_slice::<serde_json::Value>(line).ok()?;
+    match value["type"].as_str()? {
+        "signal" => Some(ShimControl::Signal(value["signal"].as_i64()? as i32)),
+        "pty_data" => Some(ShimControl::Input(value["data"].as_str()?.to_string())),
+        "pty_resize" => Some(ShimControl::Resize {
+            rows: value["rows"].as_u64()? as u16,
+            cols: value["cols"].as_u64()? as u16,
+        }),
+        "cancel" => Some(ShimControl::Cancel),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
 
     #[test]
     fn test_cli_handler_compilation() {
@@ -146,4 +297,150 @@ mod tests {
         // Real testing is done in integration tests
         // This test just verifies the module compiles
     }
+
+    #[tokio::test]
+    async fn test_shim_control_lines() {
+        let input: &[u8] = b"{\"type\":\"signal\",\"signal\":2}\n\
+            {\"type\":\"pty_data\",\"data\":\"aGk=\"}\n\
+            not json\n\
+            {\"type\":\"pty_resize\",\"rows\":24,\"cols\":80}\n\
+            {\"type\":\"cancel\"}\n";
+        let mut reader = tokio::io::BufReader::new(input);
+        let mut line = Vec::new();
+        assert_eq!(
+            next_control(&mut reader, &mut line).await,
+            ShimControl::Signal(2)
+        );
+        assert_eq!(
+            next_control(&mut reader, &mut line).await,
+            ShimControl::Input("aGk=".to_string())
+        );
+        // Lines that make no sense are skipped rather than cancelling
+        assert_eq!(
+            next_control(&mut reader, &mut line).await,
+            ShimControl::Resize { rows: 24, cols: 80 }
+        );
+        assert_eq!(
+            next_control(&mut reader, &mut line).await,
+            ShimControl::Cancel
+        );
+        // EOF: the shim went away
+        assert_eq!(
+            next_control(&mut reader, &mut line).await,
+            ShimControl::Cancel
+        );
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_control_line_resumes() {
+        use tokio::io::AsyncWriteExt;
+
+        let (shim, agent) = tokio::io::duplex(1024);
+        let (mut shim, mut reader) = (shim, tokio::io::BufReader::new(agent));
+        let mut line = Vec::new();
+
+        // Another select! branch wins while half a line has arrived
+        shim.write_all(b"{\"type\":\"pty_resize\",").await.unwrap();
+        let interrupted = tokio::time::timeout(
+            std::time::Duration::from_millis(50),
+            next_control(&mut reader, &mut line),
+        )
+        .await;
+        assert!(interrupted.is_err());
+
+        shim.write_all(b"\"rows\":24,\"cols\":80}\n").await.unwrap();
+        assert_eq!(
+            next_control(&mut reader, &mut line).await,
+            ShimControl::Resize { rows: 24, cols: 80 }
+        );
+
+        // A line cut off by the shim going away cancels
+        shim.write_all(b"{\"type\":\"sig").await.unwrap();
+        drop(shim);
+        assert_eq!(
+            next_control(&mut reader, &mut line).await,
+            ShimControl::Cancel
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reply_timeout_cancels_on_server() {
+        use carapace_protocol::{Capabilities, MessageCodec, PingPong, WireEncoding};
+        use futures::{SinkExt, StreamExt};
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+        use tokio_util::codec::{FramedRead, FramedWrite};
+
+        // A server that accepts cancellation but never answers the request
+        let server = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = server.local_addr().unwrap().port();
+        let received = tokio::spawn(async move {
+            let (socket, _) = server.accept().await.unwrap();
+            let (read, write) = socket.into_split();
+            let mut frame_read = FramedRead::new(read, MessageCodec::new());
+            let mut frame_write = FramedWrite::new(write, MessageCodec::new());
+            let Some(Ok(Message::Ping(ping))) = frame_read.next().await else {
+                panic!("Expected handshake Ping");
+            };
+            let accepted = Capabilities::supported().accept(&ping.capabilities.unwrap());
+            frame_write
+                .send(Message::Pong(PingPong {
+                    id: ping.id,
+                    timestamp: ping.timestamp,
+                    capabilities: Some(accepted),
+                    agent: None,
+                }))
+                .await
+                .unwrap();
+            let mut received = Vec::new();
+            while let Some(Ok(msg)) = frame_read.next().await {
+                let cancelled = matches!(msg, Message::Cancel(_));
+                received.push(msg);
+                if cancelled {
+                    break;
+                }
+            }
+            received
+        });
+
+        let capabilities = Capabilities {
+            encodings: vec![WireEncoding::Json],
+            cancel: true,
+            ..Default::default()
+        };
+        let connection = Connection::connect_tcp_with_capabilities("127.0.0.1", port, capabilities)
+            .await
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("cli.sock").to_str().unwrap().to_string();
+        let handler = CliHandler::new(
+            socket_path.clone(),
+            Arc::new(Multiplexer::new()),
+            Arc::new(connection),
+        )
+        .with_reply_timeout(Duration::from_millis(200));
+        tokio::spawn(async move { handler.listen().await });
+        while !std::path::Path::new(&socket_path).exists() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // The shim stays connected, so only the timeout ends the wait
+        let mut shim = UnixStream::connect(&socket_path).await.unwrap();
+        shim.write_all(b"{\"tool\":\"echo\",\"argv\":[\"hi\"]}\n")
+            .await
+            .unwrap();
+        let mut accepted = String::new();
+        tokio::io::BufReader::new(&mut shim)
+            .read_line(&mut accepted)
+            .await
+            .unwrap();
+
+        let received = tokio::time::timeout(Duration::from_secs(5), received)
+            .await
+            .expect("server never heard a Cancel")
+            .unwrap();
+        match received.as_slice() {
+            [Message::CliRequest(req), Message::Cancel(cancel)] => assert_eq!(cancel.id, req.id),
+            other => panic!("Expected the request then its Cancel, got {:?}", other),
+        }
+    }
 }
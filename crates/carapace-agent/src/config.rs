@@ -1,3 +1,4 @@
+use carapace_protocol::{Compression, WireEncoding};
 use serde::{Deserialize, Serialize};
 use std::path::Path;
 
@@ -33,6 +34,19 @@ pub struct TcpServerConfig {
     /// Reconnection backoff in ms
     #[serde(default = "default_reconnect_backoff_ms")]
     pub reconnect_backoff_ms: u64,
+
+    /// Frame encoding to request from the server (json, cbor, msgpack)
+    #[serde(default)]
+    pub wire_encoding: WireEncoding,
+
+    /// Compress large frames (zstd), if the server agrees
+    #[serde(default)]
+    pub compression: Option<Compression>,
+
+    /// Name given to the server in the handshake, recorded in its audit log
+    /// (default: the hostname)
+    #[serde(default)]
+    pub agent_name: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -97,6 +111,18 @@ impl AgentConfig {
                     .unwrap_or(8765),
                 reconnect_attempts: 5,
                 reconnect_backoff_ms: 100,
+                wire_encoding: std::env::var("CARAPACE_WIRE_ENCODING")
+                    .ok()
+                    .and_then(|e| e.parse().ok())
+                    .unwrap_or_default(),
+                compression: std::env::var("CARAPACE_WIRE_COMPRESSION")
+                    .ok()
+                    .and_then(|c| c.parse().ok()),
+                agent_name: std::env::var("CARAPACE_AGENT_NAME")
+                    .ok()
+                    .or_else(|| std::fs::read_to_string("/proc/sys/kernel/hostname").ok())
+                    .map(|name| name.trim().to_string())
+                    .filter(|name| !name.is_empty()),
             },
             cli_socket: std::env::var("CARAPACE_CLI_SOCKET")
                 .unwrap_or_else(|_| "/tmp/carapace-agent.sock".to_string()),
@@ -1,4 +1,7 @@
-use carapace_protocol::{Message, MessageCodec};
+use carapace_protocol::chunking::Reassembler;
+use carapace_protocol::{
+    Cancel, Capabilities, ErrorMessage, Message, MessageCodec, PingPong, Signal,
+};
 use futures::{SinkExt, StreamExt};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
@@ -17,19 +20,70 @@ pub struct Connection {
     server_port: u16,
     reconnect_attempts: u32,
     reconnect_backoff_ms: u64,
+    /// Features to request from the server on every (re)connect
+    capabilities: Capabilities,
+    /// Name to give the server in the handshake
+    agent_name: Option<String>,
+    /// Features the server accepted on the current connection
+    negotiated: Arc<Mutex<Capabilities>>,
+    /// Partially received chunked messages
+    reassembler: Arc<Mutex<Reassembler>>,
 }
 
+/// How long to wait for the server's answer to the capability handshake
+const HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
 impl Connection {
     /// Connect to server via TCP
     pub async fn connect_tcp(server_host: &str, server_port: u16) -> Result<Self> {
         Self::connect_tcp_with_config(server_host, server_port, 5, 100).await
     }
 
+    /// Connect to server via TCP and negotiate optional features
+    /// (e.g. a compact wire encoding) during the handshake
+    pub async fn connect_tcp_with_capabilities(
+        server_host: &str,
+        server_port: u16,
+        capabilities: Capabilities,
+    ) -> Result<Self> {
+        Self::connect(server_host, server_port, 5, 100, capabilities, None).await
+    }
+
+    /// Like `connect_tcp_with_capabilities`, also telling the server which
+    /// agent this is
+    pub async fn connect_tcp_as(
+        server_host: &str,
+        server_port: u16,
+        capabilities: Capabilities,
+        agent_name: Option<String>,
+    ) -> Result<Self> {
+        Self::connect(server_host, server_port, 5, 100, capabilities, agent_name).await
+    }
+
     pub async fn connect_tcp_with_config(
         server_host: &str,
         server_port: u16,
         reconnect_attempts: u32,
         reconnect_backoff_ms: u64,
+    ) -> Result<Self> {
+        Self::connect(
+            server_host,
+            server_port,
+            reconnect_attempts,
+            reconnect_backoff_ms,
+            Capabilities::default(),
+            None,
+        )
+        .await
+    }
+
+    async fn connect(
+        server_host: &str,
+        server_port: u16,
+        reconnect_attempts: u32,
+        reconnect_backoff_ms: u64,
+        capabilities: Capabilities,
+        agent_name: Option<String>,
     ) -> Result<Self> {
         let connection = Connection {
             frame_read: Arc::new(Mutex::new(None)),
@@ -40,6 +94,10 @@ impl Connection {
             server_port,
             reconnect_attempts,
             reconnect_backoff_ms,
+            capabilities,
+            agent_name,
+            negotiated: Arc::new(Mutex::new(Capabilities::default())),
+            reassembler: Arc::new(Mutex::new(Reassembler::new())),
         };
 
         // Establish initial connection
@@ -57,6 +115,8 @@ impl Connection {
 
                     *read_lock = Some(frame_read);
                     *write_lock = Some(frame_write);
+                    // Chunks from the previous connection will never complete
+                    self.reassembler.lock().await.clear();
                     self.connected.store(true, Ordering::SeqCst);
                     self.reconnected.notify_waiters();
 
@@ -101,12 +161,85 @@ impl Connection {
             .map_err(|e| AgentError::SSHConnectionRefused(format!("TCP connect failed: {}", e)))?;
 
         let (read, write) = stream.into_split();
-        let frame_read = FramedRead::new(read, MessageCodec);
-        let frame_write = FramedWrite::new(write, MessageCodec);
+        let mut frame_read = FramedRead::new(read, MessageCodec::new());
+        let mut frame_write = FramedWrite::new(write, MessageCodec::new());
+
+        let negotiated = if self.capabilities.is_default() && self.agent_name.is_none() {
+            Capabilities::default()
+        } else {
+            self.handshake(&mut frame_read, &mut frame_write).await?
+        };
+        let codec = frame_write.encoder_mut();
+        codec.set_encoding(negotiated.encoding());
+        codec.set_compression(negotiated.compression());
+        *self.negotiated.lock().await = negotiated;
 
         Ok((frame_read, frame_write))
     }
 
+    /// Offer our capabilities in a Ping, along with our name, and return what
+    /// the server accepted.
+    /// Servers that predate negotiation answer with a plain Pong, which
+    /// keeps the connection on the defaults.
+    async fn handshake(
+        &self,
+        frame_read: &mut FramedRead<tokio::net::tcp::OwnedReadHalf, MessageCodec>,
+        frame_write: &mut FramedWrite<tokio::net::tcp::OwnedWriteHalf, MessageCodec>,
+    ) -> Result<Capabilities> {
+        let ping = Message::Ping(PingPong {
+            id: format!("handshake-{}", uuid::Uuid::new_v4()),
+            timestamp: 0,
+            capabilities: Some(self.capabilities.clone()),
+            agent: self.agent_name.clone(),
+        });
+        frame_write
+            .send(ping)
+            .await
+            .map_err(|e| AgentError::SSHConnectionRefused(format!("Handshake failed: {}", e)))?;
+
+        let reply = tokio::time::timeout(HANDSHAKE_TIMEOUT, frame_read.next())
+            .await
+            .map_err(|_| {
+                AgentError::RequestTimeout("Capability handshake timed out".to_string())
+            })?;
+
+        match reply {
+            Some(Ok(Message::Pong(pong))) => {
+                let accepted = pong.capabilities.unwrap_or_default();
+                if !accepted.is_subset_of(&self.capabilities) {
+                    return Err(AgentError::SSHConnectionRefused(format!(
+                        "Server selected unrequested capabilities: {:?}",
+                        accepted
+                    )));
+                }
+                tracing::info!(
+                    "Negotiated wire encoding: {}, compression: {}",
+                    accepted.encoding(),
+                    accepted
+                        .compression()
+                        .map_or("none".to_string(), |c| c.to_string())
+                );
+                Ok(accepted)
+            }
+            Some(Ok(other)) => Err(AgentError::SSHConnectionRefused(format!(
+                "Unexpected handshake reply: {:?}",
+                other.id()
+            ))),
+            Some(Err(e)) => Err(AgentError::SSHConnectionRefused(format!(
+                "Handshake failed: {}",
+                e
+            ))),
+            None => Err(AgentError::SSHConnectionRefused(
+                "Connection closed during handshake".to_string(),
+            )),
+        }
+    }
+
+    /// Features the server accepted on the current connection
+    pub async fn negotiated(&self) -> Capabilities {
+        self.negotiated.lock().await.clone()
+    }
+
     /// Send a message to the server
     pub async fn send(&self, msg: Message) -> Result<()> {
         let mut write_lock = self.frame_write.lock().await;
@@ -130,25 +263,76 @@ impl Connection {
         }
     }
 
+    /// Ask the server to abandon an in-flight request. Does nothing unless the
+    /// server negotiated cancellation: older servers drop the connection on
+    /// message types they do not know.
+    pub async fn cancel(&self, id: &str) -> Result<()> {
+        if !self.negotiated().await.cancel {
+            tracing::debug!(
+                "Server does not support cancellation, not cancelling {}",
+                id
+            );
+            return Ok(());
+        }
+        tracing::info!("Cancelling request {}", id);
+        self.send(Message::Cancel(Cancel { id: id.to_string() }))
+            .await
+    }
+
+    /// Forward a signal to the process running a CLI request. Does nothing
+    /// unless the server negotiated signal forwarding.
+    pub async fn signal(&self, id: &str, signal: i32) -> Result<()> {
+        if !self.negotiated().await.signals {
+            tracing::debug!("Server does not support signals, not signalling {}", id);
+            return Ok(());
+        }
+        tracing::info!("Forwarding signal {} to request {}", signal, id);
+        self.send(Message::Signal(Signal {
+            id: id.to_string(),
+            signal,
+        }))
+        .await
+    }
+
     /// Receive a message from the server
     pub async fn recv(&self) -> Result<Option<Message>> {
         let mut read_lock = self.frame_read.lock().await;
 
         if let Some(reader) = read_lock.as_mut() {
-            match reader.next().await {
-                Some(Ok(msg)) => Ok(Some(msg)),
-                Some(Err(e)) => {
-                    tracing::error!("Failed to receive message: {}", e);
-                    self.connected.store(false, Ordering::SeqCst);
-                    Err(AgentError::SSHConnectionRefused(format!(
-                        "Recv failed: {}",
-                        e
-                    )))
-                }
-                None => {
-                    self.connected.store(false, Ordering::SeqCst);
-                    Ok(None)
-                }
+            loop {
+                return match reader.next().await {
+                    // Chunks are reassembled here; callers only see whole messages
+                    Some(Ok(Message::Chunk(chunk))) => {
+                        let id = chunk.id.clone();
+                        match self.reassembler.lock().await.push(chunk) {
+                            Ok(Some(msg)) => Ok(Some(msg)),
+                            Ok(None) => continue,
+                            Err(e) => {
+                                // Fail only the affected request, not the connection
+                                tracing::warn!("Failed to reassemble message {}: {}", id, e);
+                                Ok(Some(Message::Error(ErrorMessage {
+                                    id: Some(id),
+                                    code: "chunk_error".to_string(),
+                                    message: format!("Failed to reassemble response: {}", e),
+                                    denial: None,
+                                })))
+                            }
+                        }
+                    }
+                    Some(Ok(msg)) => Ok(Some(msg)),
+                    Some(Err(e)) => {
+                        tracing::error!("Failed to receive message: {}", e);
+                        self.connected.store(false, Ordering::SeqCst);
+                        Err(AgentError::SSHConnectionRefused(format!(
+                            "Recv failed: {}",
+                            e
+                        )))
+                    }
+                    None => {
+                        self.connected.store(false, Ordering::SeqCst);
+                        Ok(None)
+                    }
+                };
             }
         } else {
             Err(AgentError::SSHConnectionRefused(
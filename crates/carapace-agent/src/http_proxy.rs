@@ -9,12 +9,13 @@ use axum::{
     routing::{get, post},
     Router,
 };
-use carapace_protocol::{HttpRequest, Message};
+use carapace_protocol::{HttpRequest, HttpResponse, Message, PayloadEncoding};
 use std::collections::HashMap;
 use std::convert::Infallible;
 use std::sync::Arc;
 use uuid::Uuid;
 
+use crate::cancel::CancelGuard;
 use crate::connection::Connection;
 use crate::error::Result as AgentResult;
 use crate::multiplexer::Multiplexer;
@@ -115,6 +116,7 @@ async fn handle_rpc(
             h
         },
         body: Some(body_without_tool),
+        encoding: PayloadEncoding::Utf8,
     };
 
     // Register waiter for response
@@ -128,6 +130,9 @@ async fn handle_rpc(
         return Err(HttpProxyError::NoResponse);
     }
 
+    // Cancel the upstream request if the client disconnects while we wait
+    let guard = CancelGuard::new(request_id.clone(), multiplexer.clone(), connection.clone());
+
     // Wait for response with 60 second timeout
     let msg = match timeout(tokio::time::Duration::from_secs(60), rx.recv()).await {
         Ok(msg) => msg,
@@ -137,16 +142,15 @@ async fn handle_rpc(
         }
     };
 
+    guard.disarm();
     multiplexer.remove_waiter(&request_id).await;
 
     match msg {
         Some(Message::HttpResponse(resp)) => {
             // Check if this is an SSE response
             if is_sse_response(&resp.headers) {
-                let events = resp
-                    .body
-                    .as_deref()
-                    .unwrap_or("")
+                let body = response_body(&resp)?;
+                let events = String::from_utf8_lossy(&body)
                     .lines()
                     .filter(|line| !line.is_empty())
                     .collect::<Vec<_>>()
@@ -159,7 +163,7 @@ async fn handle_rpc(
                 )
                     .into_response())
             } else {
-                let body = resp.body.unwrap_or_default();
+                let body = response_body(&resp)?;
                 Ok((
                     StatusCode::from_u16(resp.status).unwrap_or(StatusCode::OK),
                     body,
@@ -221,6 +225,7 @@ async fn handle_api_v1_rpc(
             h
         },
         body: Some(body_without_tool),
+        encoding: PayloadEncoding::Utf8,
     };
 
     // Register waiter for response
@@ -234,6 +239,9 @@ async fn handle_api_v1_rpc(
         return Err(HttpProxyError::NoResponse);
     }
 
+    // Cancel the upstream request if the client disconnects while we wait
+    let guard = CancelGuard::new(request_id.clone(), multiplexer.clone(), connection.clone());
+
     // Wait for response with 60 second timeout
     let msg = match timeout(tokio::time::Duration::from_secs(60), rx.recv()).await {
         Ok(msg) => msg,
@@ -243,11 +251,12 @@ async fn handle_api_v1_rpc(
         }
     };
 
+    guard.disarm();
     multiplexer.remove_waiter(&request_id).await;
 
     match msg {
         Some(Message::HttpResponse(resp)) => {
-            let body = resp.body.unwrap_or_default();
+            let body = response_body(&resp)?;
             Ok((
                 StatusCode::from_u16(resp.status).unwrap_or(StatusCode::OK),
                 body,
@@ -272,35 +281,35 @@ async fn handle_http(
         .await
         .map_err(|_| HttpProxyError::InvalidBody)?;
 
-    let body_str = if body_bytes.is_empty() {
-        None
-    } else {
-        Some(String::from_utf8_lossy(&body_bytes).to_string())
-    };
-
     let request_id = Uuid::new_v4().to_string();
 
-    // Extract tool from JSON body if present, and strip "tool" field before forwarding
-    let (tool, final_body) = if let Some(body) = &body_str {
-        if let Ok(mut json) = serde_json::from_str::<serde_json::Value>(body) {
-            let tool = json
-                .get("tool")
-                .and_then(|v| v.as_str())
-                .unwrap_or("unknown")
-                .to_string();
-
-            // Remove "tool" field (it's for Carapace, not the upstream service)
-            if let serde_json::Value::Object(ref mut obj) = json {
-                obj.remove("tool");
-            }
-            let body_without_tool = serde_json::to_string(&json).unwrap_or_else(|_| body.clone());
-
-            (tool, Some(body_without_tool))
-        } else {
-            ("unknown".to_string(), Some(body.clone()))
+    // Extract tool from JSON body if present, and strip "tool" field before forwarding.
+    // Non-JSON bodies are forwarded untouched (base64-encoded if not UTF-8).
+    let json_body = serde_json::from_slice::<serde_json::Value>(&body_bytes).ok();
+    let (tool, final_body, encoding) = if let Some(mut json) = json_body {
+        let tool = json
+            .get("tool")
+            .and_then(|v| v.as_str())
+            .unwrap_or("unknown")
+            .to_string();
+
+        // Remove "tool" field (it's for Carapace, not the upstream service)
+        if let serde_json::Value::Object(ref mut obj) = json {
+            obj.remove("tool");
         }
+        let body_without_tool = serde_json::to_string(&json)
+            .unwrap_or_else(|_| String::from_utf8_lossy(&body_bytes).to_string());
+
+        (tool, Some(body_without_tool), PayloadEncoding::Utf8)
+    } else if body_bytes.is_empty() {
+        ("unknown".to_string(), None, PayloadEncoding::Utf8)
     } else {
-        ("unknown".to_string(), None)
+        let encoding = PayloadEncoding::for_bytes(&[&body_bytes]);
+        (
+            "unknown".to_string(),
+            Some(encoding.encode(&body_bytes)),
+            encoding,
+        )
     };
 
     // Create HttpRequest
@@ -311,6 +320,7 @@ async fn handle_http(
         path,
         headers: HashMap::new(),
         body: final_body,
+        encoding,
     };
 
     // Register waiter for response
@@ -324,6 +334,9 @@ async fn handle_http(
         return Err(HttpProxyError::NoResponse);
     }
 
+    // Cancel the upstream request if the client disconnects while we wait
+    let guard = CancelGuard::new(request_id.clone(), multiplexer.clone(), connection.clone());
+
     // Wait for response with 60 second timeout
     let msg = match timeout(tokio::time::Duration::from_secs(60), rx.recv()).await {
         Ok(msg) => msg,
@@ -333,16 +346,15 @@ async fn handle_http(
         }
     };
 
+    guard.disarm();
     multiplexer.remove_waiter(&request_id).await;
 
     match msg {
         Some(Message::HttpResponse(resp)) => {
             // Check if this is an SSE response
             if is_sse_response(&resp.headers) {
-                let events = resp
-                    .body
-                    .as_deref()
-                    .unwrap_or("")
+                let body = response_body(&resp)?;
+                let events = String::from_utf8_lossy(&body)
                     .lines()
                     .filter(|line| !line.is_empty())
                     .collect::<Vec<_>>()
@@ -355,7 +367,7 @@ async fn handle_http(
                 )
                     .into_response())
             } else {
-                let body = resp.body.unwrap_or_default();
+                let body = response_body(&resp)?;
                 Ok((
                     StatusCode::from_u16(resp.status).unwrap_or(StatusCode::OK),
                     body,
@@ -394,6 +406,7 @@ async fn handle_events(
         path: full_path,
         headers: HashMap::new(),
         body: None,
+        encoding: PayloadEncoding::Utf8,
     };
 
     // Register waiter for streaming responses
@@ -408,8 +421,11 @@ async fn handle_events(
     }
 
     // Create a real-time stream from the multiplexer channel.
-    // Each SseEvent is yielded immediately — no buffering.
-    let stream = sse_stream_from_receiver(rx, multiplexer, request_id);
+    // Each SseEvent is yielded immediately — no buffering. The guard travels
+    // with the stream, so the upstream subscription is cancelled when the
+    // client disconnects and axum drops the stream.
+    let guard = CancelGuard::new(request_id.clone(), multiplexer.clone(), connection);
+    let stream = sse_stream_from_receiver(rx, multiplexer, request_id, guard);
 
     Ok(Sse::new(stream)
         .keep_alive(
@@ -424,36 +440,44 @@ async fn handle_events(
 ///
 /// Yields each SseEvent immediately as it arrives (real-time, no buffering).
 /// Ends when the channel closes, a non-SSE message arrives, or 300s idle timeout.
-/// Cleans up the multiplexer waiter when the stream ends.
+/// Cleans up the multiplexer waiter when the stream ends, and cancels the
+/// upstream request if the stream is dropped before it completes.
 fn sse_stream_from_receiver(
     rx: tokio::sync::mpsc::Receiver<Message>,
     multiplexer: Arc<Multiplexer>,
     request_id: String,
+    guard: CancelGuard,
 ) -> impl futures::stream::Stream<Item = Result<Event, Infallible>> {
     futures::stream::unfold(
-        (rx, Some((multiplexer, request_id))),
-        |(mut rx, cleanup)| async move {
+        (rx, Some((multiplexer, request_id)), Some(guard)),
+        |(mut rx, cleanup, guard)| async move {
             match tokio::time::timeout(tokio::time::Duration::from_secs(300), rx.recv()).await {
                 Ok(Some(Message::SseEvent(evt))) => {
                     tracing::debug!("Streaming SseEvent to client: event={}", evt.event);
                     let event = Event::default().event(evt.event).data(evt.data);
-                    Some((Ok(event), (rx, cleanup)))
+                    Some((Ok(event), (rx, cleanup, guard)))
                 }
                 Ok(Some(Message::HttpResponse(resp))) => {
                     // Fallback: buffered response from old-style server
                     tracing::debug!("Received buffered HttpResponse in SSE stream");
+                    if let Some(guard) = guard {
+                        guard.disarm();
+                    }
                     if let Some((mux, rid)) = cleanup {
                         mux.remove_waiter(&rid).await;
                     }
-                    if let Some(body) = resp.body {
-                        let event = Event::default().data(body);
-                        Some((Ok(event), (rx, None)))
+                    if let Ok(Some(body)) = resp.body_bytes() {
+                        let event = Event::default().data(String::from_utf8_lossy(&body));
+                        Some((Ok(event), (rx, None, None)))
                     } else {
                         None
                     }
                 }
                 Ok(Some(_)) => {
                     tracing::warn!("Unexpected message type in SSE stream");
+                    if let Some(guard) = guard {
+                        guard.disarm();
+                    }
                     if let Some((mux, rid)) = cleanup {
                         mux.remove_waiter(&rid).await;
                     }
@@ -461,12 +485,16 @@ fn sse_stream_from_receiver(
                 }
                 Ok(None) => {
                     tracing::warn!("SSE channel closed (connection lost)");
+                    if let Some(guard) = guard {
+                        guard.disarm();
+                    }
                     if let Some((mux, rid)) = cleanup {
                         mux.remove_waiter(&rid).await;
                     }
                     None
                 }
                 Err(_) => {
+                    // Dropping the still-armed guard cancels the upstream request
                     tracing::warn!("SSE timeout (connection idle for 300s)");
                     if let Some((mux, rid)) = cleanup {
                         mux.remove_waiter(&rid).await;
@@ -483,6 +511,13 @@ async fn handle_check() -> std::result::Result<Response, HttpProxyError> {
     Ok((StatusCode::OK, "OK").into_response())
 }
 
+/// Decode the raw response body (empty if the response has none)
+fn response_body(resp: &HttpResponse) -> std::result::Result<Vec<u8>, HttpProxyError> {
+    resp.body_bytes()
+        .map(|b| b.unwrap_or_default())
+        .map_err(|_| HttpProxyError::InvalidResponse)
+}
+
 /// Detect if response is SSE (Server-Sent Events)
 fn is_sse_response(headers: &HashMap<String, String>) -> bool {
     headers
@@ -496,6 +531,7 @@ fn is_sse_response(headers: &HashMap<String, String>) -> bool {
 enum HttpProxyError {
     InvalidBody,
     MalformedJson,
+    InvalidResponse,
     WrongResponseType,
     NoResponse,
 }
@@ -505,6 +541,9 @@ impl IntoResponse for HttpProxyError {
         let (status, error_message) = match self {
             HttpProxyError::InvalidBody => (StatusCode::BAD_REQUEST, "Invalid request body"),
             HttpProxyError::MalformedJson => (StatusCode::BAD_REQUEST, "Malformed JSON"),
+            HttpProxyError::InvalidResponse => {
+                (StatusCode::BAD_GATEWAY, "Invalid response body from server")
+            }
             HttpProxyError::WrongResponseType => {
                 (StatusCode::INTERNAL_SERVER_ERROR, "Wrong response type")
             }
@@ -563,6 +602,7 @@ async fn handle_fallback(
             h
         },
         body: Some(body_without_tool),
+        encoding: PayloadEncoding::Utf8,
     };
 
     // Register waiter for response
@@ -576,6 +616,9 @@ async fn handle_fallback(
         return Err(HttpProxyError::NoResponse);
     }
 
+    // Cancel the upstream request if the client disconnects while we wait
+    let guard = CancelGuard::new(request_id.clone(), multiplexer.clone(), connection.clone());
+
     // Wait for response with 60 second timeout
     let msg = match timeout(tokio::time::Duration::from_secs(60), rx.recv()).await {
         Ok(msg) => msg,
@@ -585,11 +628,12 @@ async fn handle_fallback(
         }
     };
 
+    guard.disarm();
     multiplexer.remove_waiter(&request_id).await;
 
     match msg {
         Some(Message::HttpResponse(resp)) => {
-            let body = resp.body.unwrap_or_default();
+            let body = response_body(&resp)?;
             Ok((
                 StatusCode::from_u16(resp.status).unwrap_or(StatusCode::OK),
                 body,
@@ -1,3 +1,4 @@
+pub mod cancel;
 pub mod cli_handler;
 pub mod config;
 pub mod connection;
@@ -5,6 +6,7 @@ pub mod error;
 pub mod http_proxy;
 pub mod multiplexer;
 
+pub use cancel::CancelGuard;
 pub use cli_handler::CliHandler;
 pub use connection::Connection;
 pub use error::{AgentError, Result};
@@ -1,5 +1,5 @@
 use carapace_agent::{CliHandler, Connection, HttpProxy, Multiplexer, Result as AgentResult};
-use carapace_protocol::{Message, PingPong};
+use carapace_protocol::{Capabilities, Message, PingPong};
 use std::sync::Arc;
 
 #[tokio::main]
@@ -19,13 +19,37 @@ async fn main() -> AgentResult<()> {
     let config = carapace_agent::config::AgentConfig::from_env();
 
     // Establish TCP connection to server
-    let connection =
-        Arc::new(Connection::connect_tcp(&config.server.host, config.server.port).await?);
+    let connection = Arc::new(
+        Connection::connect_tcp_as(
+            &config.server.host,
+            config.server.port,
+            Capabilities {
+                encodings: vec![config.server.wire_encoding],
+                compression: config.server.compression.into_iter().collect(),
+                chunking: true,
+                cancel: true,
+                signals: true,
+                pty: true,
+            },
+            config.server.agent_name.clone(),
+        )
+        .await?,
+    );
 
     tracing::info!(
-        "TCP connection established to {}:{}",
+        "TCP connection established to {}:{} (wire encoding: {}, compression: {}, chunking: {}, cancel: {}, signals: {}, pty: {})",
         config.server.host,
-        config.server.port
+        config.server.port,
+        connection.negotiated().await.encoding(),
+        connection
+            .negotiated()
+            .await
+            .compression()
+            .map_or("none".to_string(), |c| c.to_string()),
+        connection.negotiated().await.chunking,
+        connection.negotiated().await.cancel,
+        connection.negotiated().await.signals,
+        connection.negotiated().await.pty
     );
 
     // Create multiplexer for request/response matching
@@ -109,6 +133,8 @@ async fn main() -> AgentResult<()> {
                     .duration_since(std::time::UNIX_EPOCH)
                     .unwrap_or_default()
                     .as_secs(),
+                capabilities: None,
+                agent: None,
             });
             if let Err(e) = connection_monitor.send(ping).await {
                 tracing::warn!("Ping failed (connection likely dead): {}", e);
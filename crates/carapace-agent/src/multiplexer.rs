@@ -100,6 +100,7 @@ mod tests {
             id: Some(id),
             code: "test".to_string(),
             message: "test".to_string(),
+            denial: None,
         });
 
         multiplexer.handle_response(resp).await;
@@ -166,6 +167,7 @@ mod tests {
                     id: Some(id),
                     code: "test".to_string(),
                     message: format!("response-{}", i),
+                    denial: None,
                 });
                 m.handle_response(resp).await;
 
@@ -211,6 +213,7 @@ mod tests {
             id: Some("nonexistent".to_string()),
             code: "test".to_string(),
             message: "test".to_string(),
+            denial: None,
         });
 
         // Should not panic
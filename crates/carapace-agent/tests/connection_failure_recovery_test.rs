@@ -3,7 +3,9 @@
 /// Tests resilience to network failures, connection drops, and recovery scenarios.
 /// These are critical for production stability.
 use carapace_agent::{Connection, Multiplexer};
-use carapace_protocol::{CliRequest, CliResponse, Message, MessageCodec, PingPong};
+use carapace_protocol::{
+    CliRequest, CliResponse, Message, MessageCodec, PayloadEncoding, PingPong,
+};
 use futures::{SinkExt, StreamExt};
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -92,6 +94,7 @@ async fn test_multiplexer_cleans_up_on_disconnect() {
         env: HashMap::new(),
         stdin: None,
         cwd: "/".to_string(),
+        tty: None,
     });
 
     connection.send(req).await.expect("Failed to send request");
@@ -129,7 +132,7 @@ async fn test_partial_message_handling() {
     tokio::spawn(async move {
         if let Ok((socket, _)) = listener.accept().await {
             let (reader, _) = socket.into_split();
-            let mut frame_read = FramedRead::new(reader, MessageCodec);
+            let mut frame_read = FramedRead::new(reader, MessageCodec::new());
 
             // Read the incoming request
             if let Some(_result) = frame_read.next().await {
@@ -154,6 +157,7 @@ async fn test_partial_message_handling() {
         env: HashMap::new(),
         stdin: None,
         cwd: "/".to_string(),
+        tty: None,
     });
 
     connection.send(req).await.expect("Failed to send request");
@@ -223,7 +227,7 @@ async fn test_concurrent_requests_survive_connection_drop() {
     tokio::spawn(async move {
         if let Ok((socket, _)) = listener.accept().await {
             let (reader, _) = socket.into_split();
-            let mut frame_read = FramedRead::new(reader, MessageCodec);
+            let mut frame_read = FramedRead::new(reader, MessageCodec::new());
 
             let mut count = 0;
             while let Some(result) = frame_read.next().await {
@@ -259,6 +263,7 @@ async fn test_concurrent_requests_survive_connection_drop() {
                 env: HashMap::new(),
                 stdin: None,
                 cwd: "/".to_string(),
+                tty: None,
             });
 
             // Some might succeed, some might fail due to connection drop
@@ -295,7 +300,7 @@ async fn test_oversized_message_rejection() {
     tokio::spawn(async move {
         if let Ok((socket, _)) = listener.accept().await {
             let (_reader, writer) = socket.into_split();
-            let mut frame_write = FramedWrite::new(writer, MessageCodec);
+            let mut frame_write = FramedWrite::new(writer, MessageCodec::new());
 
             // Try to encode a message with huge argv
             let huge_argv: Vec<String> = (0..100_000).map(|i| format!("arg-{}", i)).collect();
@@ -307,6 +312,7 @@ async fn test_oversized_message_rejection() {
                 env: HashMap::new(),
                 stdin: None,
                 cwd: "/".to_string(),
+                tty: None,
             });
 
             // This might fail or succeed depending on the actual payload size
@@ -357,8 +363,8 @@ async fn test_transient_error_recovery() {
         // Accept first connection
         if let Ok((socket, _)) = listener.accept().await {
             let (reader, writer) = socket.into_split();
-            let mut frame_read = FramedRead::new(reader, MessageCodec);
-            let mut frame_write = FramedWrite::new(writer, MessageCodec);
+            let mut frame_read = FramedRead::new(reader, MessageCodec::new());
+            let mut frame_write = FramedWrite::new(writer, MessageCodec::new());
 
             // Read one message
             if let Some(Ok(_msg)) = frame_read.next().await {
@@ -370,6 +376,7 @@ async fn test_transient_error_recovery() {
                     env: HashMap::new(),
                     stdin: None,
                     cwd: "/".to_string(),
+                    tty: None,
                 });
 
                 if frame_write.send(response).await.is_ok() {
@@ -394,6 +401,7 @@ async fn test_transient_error_recovery() {
         env: HashMap::new(),
         stdin: None,
         cwd: "/".to_string(),
+        tty: None,
     });
 
     connection.send(req).await.expect("Failed to send request");
@@ -475,8 +483,8 @@ async fn test_recv_loop_recovers_after_server_restart() {
     let server1 = tokio::spawn(async move {
         let (socket, _) = listener1.accept().await.unwrap();
         let (reader, writer) = socket.into_split();
-        let mut frame_read = FramedRead::new(reader, MessageCodec);
-        let mut frame_write = FramedWrite::new(writer, MessageCodec);
+        let mut frame_read = FramedRead::new(reader, MessageCodec::new());
+        let mut frame_write = FramedWrite::new(writer, MessageCodec::new());
 
         while let Some(Ok(msg)) = frame_read.next().await {
             match msg {
@@ -486,6 +494,11 @@ async fn test_recv_loop_recovers_after_server_restart() {
                         exit_code: 0,
                         stdout: "phase1".to_string(),
                         stderr: String::new(),
+                        encoding: PayloadEncoding::Utf8,
+                        signal: None,
+                        timed_out: false,
+                        stdout_truncated: false,
+                        stderr_truncated: false,
                     });
                     let _ = frame_write.send(resp).await;
                     let _ = frame_write.flush().await;
@@ -544,6 +557,8 @@ async fn test_recv_loop_recovers_after_server_restart() {
             let ping = Message::Ping(PingPong {
                 id: "test-ping".into(),
                 timestamp: 0,
+                capabilities: None,
+                agent: None,
             });
             let _ = conn_ping.send(ping).await;
         }
@@ -559,6 +574,7 @@ async fn test_recv_loop_recovers_after_server_restart() {
             env: HashMap::new(),
             stdin: None,
             cwd: "/".into(),
+            tty: None,
         }))
         .await
         .unwrap();
@@ -585,8 +601,8 @@ async fn test_recv_loop_recovers_after_server_restart() {
     tokio::spawn(async move {
         let (socket, _) = listener2.accept().await.unwrap();
         let (reader, writer) = socket.into_split();
-        let mut frame_read = FramedRead::new(reader, MessageCodec);
-        let mut frame_write = FramedWrite::new(writer, MessageCodec);
+        let mut frame_read = FramedRead::new(reader, MessageCodec::new());
+        let mut frame_write = FramedWrite::new(writer, MessageCodec::new());
 
         while let Some(Ok(msg)) = frame_read.next().await {
             match msg {
@@ -596,6 +612,11 @@ async fn test_recv_loop_recovers_after_server_restart() {
                         exit_code: 0,
                         stdout: "phase2".to_string(),
                         stderr: String::new(),
+                        encoding: PayloadEncoding::Utf8,
+                        signal: None,
+                        timed_out: false,
+                        stdout_truncated: false,
+                        stderr_truncated: false,
                     });
                     let _ = frame_write.send(resp).await;
                     let _ = frame_write.flush().await;
@@ -626,6 +647,7 @@ async fn test_recv_loop_recovers_after_server_restart() {
             env: HashMap::new(),
             stdin: None,
             cwd: "/".into(),
+            tty: None,
         }))
         .await
         .unwrap();
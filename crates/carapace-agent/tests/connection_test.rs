@@ -10,6 +10,7 @@ fn test_cli_request_creation() {
         env: HashMap::new(),
         stdin: None,
         cwd: "/home/user".to_string(),
+        tty: None,
     };
 
     assert_eq!(req.id, "test-001");
@@ -49,6 +50,7 @@ fn test_cli_request_with_large_argv() {
         env: HashMap::new(),
         stdin: None,
         cwd: "/".to_string(),
+        tty: None,
     };
 
     assert_eq!(req.argv.len(), 10001);
@@ -67,6 +69,7 @@ fn test_cli_request_with_env_vars() {
         env: env.clone(),
         stdin: None,
         cwd: "/".to_string(),
+        tty: None,
     };
 
     assert_eq!(req.env.get("PATH"), Some(&"/usr/bin".to_string()));
@@ -84,6 +87,7 @@ fn test_cli_request_with_stdin() {
         env: HashMap::new(),
         stdin: Some(stdin_data.clone()),
         cwd: "/".to_string(),
+        tty: None,
     };
 
     assert_eq!(req.stdin, Some(stdin_data));
@@ -107,6 +111,7 @@ fn test_cwd_with_special_paths() {
             env: HashMap::new(),
             stdin: None,
             cwd: path.to_string(),
+            tty: None,
         };
 
         assert_eq!(req.cwd, path);
@@ -125,6 +130,7 @@ fn test_tool_name_validation() {
             env: HashMap::new(),
             stdin: None,
             cwd: "/".to_string(),
+            tty: None,
         };
 
         assert_eq!(req.tool, tool);
@@ -145,6 +151,7 @@ fn test_unicode_in_argv_elements() {
         env: HashMap::new(),
         stdin: None,
         cwd: "/".to_string(),
+        tty: None,
     };
 
     assert_eq!(req.argv[3], "Test 中文 🎉 العربية");
@@ -159,6 +166,7 @@ fn test_empty_env_vars() {
         env: HashMap::new(),
         stdin: None,
         cwd: "/".to_string(),
+        tty: None,
     };
 
     assert!(req.env.is_empty());
@@ -173,6 +181,7 @@ fn test_null_bytes_in_argv() {
         env: HashMap::new(),
         stdin: None,
         cwd: "/".to_string(),
+        tty: None,
     };
 
     assert!(req.argv[0].contains('\0'));
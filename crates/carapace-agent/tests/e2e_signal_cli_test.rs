@@ -84,6 +84,7 @@ async fn start_carapace_server(listener_addr: &str, upstream_url: &str) -> Strin
     // Create signal-cli policy with version and send methods allowed
     let http_policy = HttpPolicy {
         upstream: upstream_url.to_string(),
+        mode: None,
         jsonrpc_allow_methods: vec![
             "version".to_string(),
             "send".to_string(),
@@ -92,13 +93,19 @@ async fn start_carapace_server(listener_addr: &str, upstream_url: &str) -> Strin
         jsonrpc_deny_methods: vec![],
         jsonrpc_param_filters: HashMap::new(),
         rate_limit: None,
+        max_concurrent: None,
+        require_approval: None,
         timeout_secs: None,
         audit: Default::default(),
     };
 
     let mut tools = HashMap::new();
     tools.insert("signal-cli".to_string(), ToolPolicy::Http(http_policy));
-    let policy = PolicyConfig { tools };
+    let policy = PolicyConfig {
+        tools,
+        mode: carapace_policy::PolicyMode::Enforce,
+        source: None,
+    };
 
     let http_dispatcher = Arc::new(carapace_server::http_dispatch::HttpDispatcher::with_policy(
         policy,
@@ -106,6 +113,8 @@ async fn start_carapace_server(listener_addr: &str, upstream_url: &str) -> Strin
     let cli_dispatcher = Arc::new(carapace_server::cli_dispatch::CliDispatcher::with_policy(
         PolicyConfig {
             tools: HashMap::new(),
+            mode: carapace_policy::PolicyMode::Enforce,
+            source: None,
         },
     ));
 
@@ -118,8 +127,8 @@ async fn start_carapace_server(listener_addr: &str, upstream_url: &str) -> Strin
 
             tokio::spawn(async move {
                 // Manual implementation of listener loop to have better control
-                let mut frame_read = FramedRead::new(reader, MessageCodec);
-                let mut frame_write = FramedWrite::new(writer, MessageCodec);
+                let mut frame_read = FramedRead::new(reader, MessageCodec::new());
+                let mut frame_write = FramedWrite::new(writer, MessageCodec::new());
 
                 while let Some(result) = frame_read.next().await {
                     if let Ok(msg) = result {
@@ -137,6 +146,7 @@ async fn start_carapace_server(listener_addr: &str, upstream_url: &str) -> Strin
                                             id: Some(req.id),
                                             code: "http_error".to_string(),
                                             message: format!("{}", e),
+                                            denial: None,
                                         }))
                                     }
                                 }
@@ -149,6 +159,7 @@ async fn start_carapace_server(listener_addr: &str, upstream_url: &str) -> Strin
                                             id: Some(req.id),
                                             code: "cli_error".to_string(),
                                             message: format!("{}", e),
+                                            denial: None,
                                         }))
                                     }
                                 }
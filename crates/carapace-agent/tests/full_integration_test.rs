@@ -11,7 +11,7 @@
 /// If it fails, we've reproduced the production issue locally.
 use carapace_agent::{Connection, Multiplexer};
 use carapace_policy::{HttpPolicy, PolicyConfig, ToolPolicy};
-use carapace_protocol::{Message, MessageCodec};
+use carapace_protocol::{Message, MessageCodec, PayloadEncoding};
 use carapace_server::{cli_dispatch::CliDispatcher, http_dispatch::HttpDispatcher};
 use futures::{SinkExt, StreamExt};
 use std::collections::HashMap;
@@ -66,17 +66,24 @@ async fn start_carapace_server(
     // Create policy
     let http_policy = HttpPolicy {
         upstream: upstream.to_string(),
+        mode: None,
         jsonrpc_allow_methods: vec!["version".to_string(), "send".to_string()],
         jsonrpc_deny_methods: vec![],
         jsonrpc_param_filters: HashMap::new(),
         rate_limit: None,
+        max_concurrent: None,
+        require_approval: None,
         timeout_secs: None,
         audit: Default::default(),
     };
 
     let mut tools = HashMap::new();
     tools.insert("signal-cli".to_string(), ToolPolicy::Http(http_policy));
-    let policy = PolicyConfig { tools };
+    let policy = PolicyConfig {
+        tools,
+        mode: carapace_policy::PolicyMode::Enforce,
+        source: None,
+    };
 
     let http_dispatcher = Arc::new(HttpDispatcher::with_policy(policy.clone()));
     let cli_dispatcher = Arc::new(CliDispatcher::with_policy(policy));
@@ -93,8 +100,8 @@ async fn start_carapace_server(
             let cli_dispatcher = cli_dispatcher_clone.clone();
 
             tokio::spawn(async move {
-                let mut frame_read = FramedRead::new(reader, MessageCodec);
-                let mut frame_write = FramedWrite::new(writer, MessageCodec);
+                let mut frame_read = FramedRead::new(reader, MessageCodec::new());
+                let mut frame_write = FramedWrite::new(writer, MessageCodec::new());
 
                 while let Some(result) = frame_read.next().await {
                     match result {
@@ -121,6 +128,7 @@ async fn start_carapace_server(
                                                 id: Some(req.id),
                                                 code: "error".to_string(),
                                                 message: format!("{}", e),
+                                                denial: None,
                                             }))
                                         }
                                     }
@@ -133,6 +141,7 @@ async fn start_carapace_server(
                                                 id: Some(req.id),
                                                 code: "error".to_string(),
                                                 message: format!("{}", e),
+                                                denial: None,
                                             }))
                                         }
                                     }
@@ -246,6 +255,7 @@ async fn test_full_integration_signal_cli() {
             h
         },
         body: Some(r#"{"jsonrpc":"2.0","id":"1","method":"version","params":{}}"#.to_string()),
+        encoding: PayloadEncoding::Utf8,
     };
 
     eprintln!("   Sending HTTP request to server...");
@@ -1,4 +1,4 @@
-use carapace_protocol::{HttpRequest, HttpResponse};
+use carapace_protocol::{HttpRequest, HttpResponse, PayloadEncoding};
 use std::collections::HashMap;
 
 #[test]
@@ -14,6 +14,7 @@ fn test_valid_json_rpc_request() {
             h
         },
         body: Some(r#"{"jsonrpc":"2.0","method":"send","params":{}}"#.to_string()),
+        encoding: PayloadEncoding::Utf8,
     };
 
     assert_eq!(req.method, "POST");
@@ -29,6 +30,7 @@ fn test_malformed_json_in_request_body() {
         path: "/api/v1/rpc".to_string(),
         headers: HashMap::new(),
         body: Some("{broken json".to_string()),
+        encoding: PayloadEncoding::Utf8,
     };
 
     // Should detect that body is not valid JSON
@@ -47,6 +49,7 @@ fn test_http_request_smuggling_attempt() {
         path: "/api/v1/rpc\r\nHost: attacker.com".to_string(),
         headers: HashMap::new(),
         body: None,
+        encoding: PayloadEncoding::Utf8,
     };
 
     // Path should be validated to not contain control characters
@@ -64,6 +67,7 @@ fn test_oversized_request_body() {
         path: "/api".to_string(),
         headers: HashMap::new(),
         body: Some(huge_body),
+        encoding: PayloadEncoding::Utf8,
     };
 
     // Should be able to detect if body exceeds size limit
@@ -79,6 +83,7 @@ fn test_missing_content_type_header() {
         path: "/api".to_string(),
         headers: HashMap::new(),
         body: Some("{}".to_string()),
+        encoding: PayloadEncoding::Utf8,
     };
 
     assert!(!req.headers.contains_key("Content-Type"));
@@ -95,6 +100,7 @@ fn test_http_response_creation() {
             h
         },
         body: Some(r#"{"result":"success"}"#.to_string()),
+        encoding: PayloadEncoding::Utf8,
     };
 
     assert_eq!(resp.status, 200);
@@ -113,6 +119,7 @@ fn test_sse_response_detection() {
             h
         },
         body: None, // SSE uses streaming
+        encoding: PayloadEncoding::Utf8,
     };
 
     let is_sse = resp
@@ -136,6 +143,7 @@ fn test_multiple_concurrent_http_requests() {
             path: "/api".to_string(),
             headers: HashMap::new(),
             body: Some(format!(r#"{{"id":{}}}"#, i)),
+            encoding: PayloadEncoding::Utf8,
         };
         requests.push(req);
     }
@@ -153,6 +161,7 @@ fn test_http_status_codes() {
             status: status as u16,
             headers: HashMap::new(),
             body: None,
+            encoding: PayloadEncoding::Utf8,
         };
 
         assert_eq!(resp.status, status as u16);
@@ -171,6 +180,7 @@ fn test_request_method_types() {
             path: "/api".to_string(),
             headers: HashMap::new(),
             body: None,
+            encoding: PayloadEncoding::Utf8,
         };
 
         assert_eq!(req.method, method);
@@ -186,6 +196,7 @@ fn test_path_with_query_parameters() {
         path: "/api/v1/messages?limit=10&offset=0&sort=date".to_string(),
         headers: HashMap::new(),
         body: None,
+        encoding: PayloadEncoding::Utf8,
     };
 
     assert!(req.path.contains("?"));
@@ -214,6 +225,7 @@ fn test_response_headers_preservation() {
         status: 200,
         headers,
         body: None,
+        encoding: PayloadEncoding::Utf8,
     };
 
     assert_eq!(resp.headers.len(), 3);
@@ -230,6 +242,7 @@ fn test_null_body_vs_empty_body() {
         status: 200,
         headers: HashMap::new(),
         body: None,
+        encoding: PayloadEncoding::Utf8,
     };
 
     let resp_empty = HttpResponse {
@@ -237,6 +250,7 @@ fn test_null_body_vs_empty_body() {
         status: 200,
         headers: HashMap::new(),
         body: Some("".to_string()),
+        encoding: PayloadEncoding::Utf8,
     };
 
     assert!(resp_null.body.is_none());
@@ -251,6 +265,7 @@ fn test_unicode_in_response_body() {
         status: 200,
         headers: HashMap::new(),
         body: Some(r#"{"message":"你好 العربية 🎉"}"#.to_string()),
+        encoding: PayloadEncoding::Utf8,
     };
 
     assert!(resp.body.is_some());
@@ -265,6 +280,7 @@ fn test_large_response_body() {
         status: 200,
         headers: HashMap::new(),
         body: Some(large_body),
+        encoding: PayloadEncoding::Utf8,
     };
 
     assert!(resp.body.is_some());
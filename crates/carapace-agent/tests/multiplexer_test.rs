@@ -7,7 +7,7 @@
 ///
 /// If this doesn't work, HTTP handlers will timeout waiting for responses.
 use carapace_agent::Multiplexer;
-use carapace_protocol::{HttpResponse, Message};
+use carapace_protocol::{HttpResponse, Message, PayloadEncoding};
 use std::collections::HashMap;
 
 #[tokio::test]
@@ -23,6 +23,7 @@ async fn test_multiplexer_registers_waiter() {
         status: 200,
         headers: HashMap::new(),
         body: Some(r#"{"result":"ok"}"#.to_string()),
+        encoding: PayloadEncoding::Utf8,
     };
 
     // Handle response should route it to the waiter
@@ -63,6 +64,7 @@ async fn test_multiplexer_routes_correct_response() {
         status: 200,
         headers: HashMap::new(),
         body: Some("response-2".to_string()),
+        encoding: PayloadEncoding::Utf8,
     };
     multiplexer
         .handle_response(Message::HttpResponse(response_2))
@@ -96,6 +98,7 @@ async fn test_multiplexer_concurrent_requests() {
                 status: 200,
                 headers: HashMap::new(),
                 body: Some(format!("response-{}", i)),
+                encoding: PayloadEncoding::Utf8,
             };
 
             mux.handle_response(Message::HttpResponse(response)).await;
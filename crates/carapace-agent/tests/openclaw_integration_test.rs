@@ -14,7 +14,7 @@
 /// - Real production request/response patterns
 use carapace_agent::{Connection, Multiplexer};
 use carapace_policy::{HttpPolicy, ParamFilter, PolicyConfig, ToolPolicy};
-use carapace_protocol::{Message, MessageCodec};
+use carapace_protocol::{Message, MessageCodec, PayloadEncoding};
 use carapace_server::{cli_dispatch::CliDispatcher, http_dispatch::HttpDispatcher};
 use futures::{SinkExt, StreamExt};
 use std::collections::HashMap;
@@ -224,6 +224,7 @@ async fn start_carapace_server(upstream: &str) -> (String, Arc<HttpDispatcher>)
 
     let http_policy = HttpPolicy {
         upstream: upstream.to_string(),
+        mode: None,
         jsonrpc_allow_methods: vec![
             "send".to_string(),
             "sendTyping".to_string(),
@@ -234,13 +235,19 @@ async fn start_carapace_server(upstream: &str) -> (String, Arc<HttpDispatcher>)
         jsonrpc_deny_methods: vec!["deleteEverything".to_string()],
         jsonrpc_param_filters: param_filters,
         rate_limit: None,
+        max_concurrent: None,
+        require_approval: None,
         timeout_secs: Some(30),
         audit: Default::default(),
     };
 
     let mut tools = HashMap::new();
     tools.insert("signal-cli".to_string(), ToolPolicy::Http(http_policy));
-    let policy = PolicyConfig { tools };
+    let policy = PolicyConfig {
+        tools,
+        mode: carapace_policy::PolicyMode::Enforce,
+        source: None,
+    };
 
     let http_dispatcher = Arc::new(HttpDispatcher::with_policy(policy.clone()));
     let cli_dispatcher = Arc::new(CliDispatcher::with_policy(policy));
@@ -255,8 +262,8 @@ async fn start_carapace_server(upstream: &str) -> (String, Arc<HttpDispatcher>)
             let cli_dispatcher = cli_dispatcher_clone.clone();
 
             tokio::spawn(async move {
-                let mut frame_read = FramedRead::new(reader, MessageCodec);
-                let mut frame_write = FramedWrite::new(writer, MessageCodec);
+                let mut frame_read = FramedRead::new(reader, MessageCodec::new());
+                let mut frame_write = FramedWrite::new(writer, MessageCodec::new());
 
                 while let Some(result) = frame_read.next().await {
                     match result {
@@ -274,6 +281,7 @@ async fn start_carapace_server(upstream: &str) -> (String, Arc<HttpDispatcher>)
                                                 id: Some(req.id),
                                                 code: "error".to_string(),
                                                 message: format!("{}", e),
+                                                denial: None,
                                             }))
                                         }
                                     }
@@ -286,6 +294,7 @@ async fn start_carapace_server(upstream: &str) -> (String, Arc<HttpDispatcher>)
                                                 id: Some(req.id),
                                                 code: "error".to_string(),
                                                 message: format!("{}", e),
+                                                denial: None,
                                             }))
                                         }
                                     }
@@ -365,6 +374,7 @@ async fn test_openclaw_send_message() {
             r#"{"jsonrpc":"2.0","id":"550e8400-e29b-41d4-a716-446655440000","method":"send","params":{"message":"Hello from OpenClaw","recipient":["+12025551234"]}}"#
                 .to_string(),
         ),
+        encoding: PayloadEncoding::Utf8,
     };
 
     eprintln!("Sending message request (UUID ID)...");
@@ -448,6 +458,7 @@ async fn test_openclaw_send_typing_indicator() {
             r#"{"jsonrpc":"2.0","id":"550e8400-e29b-41d4-a716-446655440001","method":"sendTyping","params":{"recipient":["+12025551234"]}}"#
                 .to_string(),
         ),
+        encoding: PayloadEncoding::Utf8,
     };
 
     eprintln!("Sending typing indicator...");
@@ -514,6 +525,7 @@ async fn test_openclaw_receive_events_sse() {
         path: "/api/v1/events?account=%2B12242120288".to_string(),
         headers: HashMap::new(),
         body: None,
+        encoding: PayloadEncoding::Utf8,
     };
 
     eprintln!("Requesting event stream...");
@@ -592,6 +604,7 @@ async fn test_openclaw_blocked_number() {
             r#"{"jsonrpc":"2.0","id":"550e8400-e29b-41d4-a716-446655440002","method":"send","params":{"message":"International number","recipient":["+442071234567"]}}"#
                 .to_string(),
         ),
+        encoding: PayloadEncoding::Utf8,
     };
 
     eprintln!("Sending to blocked number (+44 UK)...");
@@ -605,7 +618,7 @@ async fn test_openclaw_blocked_number() {
     match response_result {
         Ok(Some(Message::Error(err))) => {
             eprintln!("✓ Correctly blocked: {}", err.message);
-            assert!(err.message.contains("Denied") || err.message.contains("not in allow list"));
+            assert!(err.message.contains("denied by param_allow"));
         }
         Ok(Some(msg)) => {
             eprintln!("Unexpected message type: {:?}", msg);
@@ -676,6 +689,7 @@ async fn test_openclaw_concurrent_requests() {
                     r#"{{"jsonrpc":"2.0","id":"{}","method":"version","params":{{}}}}"#,
                     request_id
                 )),
+                encoding: PayloadEncoding::Utf8,
             };
 
             conn.send(Message::HttpRequest(http_req))
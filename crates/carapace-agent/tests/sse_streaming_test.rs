@@ -24,8 +24,8 @@ async fn test_sse_events_are_streamed_not_buffered() {
     tokio::spawn(async move {
         let (socket, _) = server_listener.accept().await.unwrap();
         let (reader, writer) = socket.into_split();
-        let mut frame_read = FramedRead::new(reader, MessageCodec);
-        let mut frame_write = FramedWrite::new(writer, MessageCodec);
+        let mut frame_read = FramedRead::new(reader, MessageCodec::new());
+        let mut frame_write = FramedWrite::new(writer, MessageCodec::new());
 
         while let Some(Ok(msg)) = frame_read.next().await {
             match msg {
@@ -7,16 +7,81 @@
 /// - Missing flush() after send() causes buffered data to stay in the kernel buffer
 /// - Server reads garbage/partial data because the frame is never flushed
 /// - Symptoms: "Frame too large: 1195725856 bytes" (0x47534F4E = "GSON")
+///
+/// Every test runs once per wire encoding: the mock server answers the
+/// capability handshake so the connection switches to the encoding under test.
 use carapace_agent::Connection;
-use carapace_protocol::{CliRequest, HttpRequest, Message, MessageCodec};
-use futures::StreamExt;
+use carapace_protocol::{
+    chunking, Capabilities, CliRequest, CliResponse, Compression, HttpRequest, Message,
+    MessageCodec, PayloadEncoding, PingPong, WireEncoding,
+};
+use futures::{SinkExt, StreamExt};
 use std::collections::HashMap;
+use tokio::io::AsyncReadExt;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpListener;
-use tokio_util::codec::FramedRead;
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+/// Accept one connection and answer the capability handshake (if any)
+async fn accept_framed(
+    listener: TcpListener,
+    requested: Capabilities,
+) -> (
+    FramedRead<OwnedReadHalf, MessageCodec>,
+    FramedWrite<OwnedWriteHalf, MessageCodec>,
+) {
+    let (socket, _) = listener
+        .accept()
+        .await
+        .expect("Failed to accept connection");
+    let (reader, writer) = socket.into_split();
+    let mut frame_read = FramedRead::new(reader, MessageCodec::new());
+    let mut frame_write = FramedWrite::new(writer, MessageCodec::new());
+
+    if !requested.is_default() {
+        let ping = match frame_read.next().await {
+            Some(Ok(Message::Ping(ping))) => ping,
+            other => panic!("Expected handshake Ping, got {:?}", other),
+        };
+        let offer = ping
+            .capabilities
+            .expect("Handshake Ping without capabilities");
+        assert_eq!(offer, requested);
+        let accepted = Capabilities::supported().accept(&offer);
+        frame_write
+            .send(Message::Pong(PingPong {
+                id: ping.id,
+                timestamp: ping.timestamp,
+                capabilities: Some(accepted.clone()),
+                agent: None,
+            }))
+            .await
+            .expect("Failed to send handshake Pong");
+        frame_write.encoder_mut().set_encoding(accepted.encoding());
+        frame_write
+            .encoder_mut()
+            .set_compression(accepted.compression());
+    }
+
+    (frame_read, frame_write)
+}
+
+/// Connect to the mock server, requesting the given capabilities
+async fn connect(server_port: u16, capabilities: Capabilities) -> Connection {
+    Connection::connect_tcp_with_capabilities("127.0.0.1", server_port, capabilities)
+        .await
+        .expect("Failed to connect")
+}
 
 /// Test that Connection::send() properly frames messages
 #[tokio::test]
 async fn test_connection_send_flushes_to_socket() {
+    for encoding in WireEncoding::ALL {
+        test_connection_send_flushes_to_socket_with(encoding).await;
+    }
+}
+
+async fn test_connection_send_flushes_to_socket_with(encoding: WireEncoding) {
     // Start a mock server that receives and validates framed messages
     let listener = TcpListener::bind("127.0.0.1:0")
         .await
@@ -27,12 +92,8 @@ async fn test_connection_send_flushes_to_socket() {
 
     // Spawn server task to receive and validate the message
     let server_handle = tokio::spawn(async move {
-        let (socket, _) = listener
-            .accept()
-            .await
-            .expect("Failed to accept connection");
-        let (reader, _) = socket.into_split();
-        let mut frame_read = FramedRead::new(reader, MessageCodec);
+        let (mut frame_read, _frame_write) =
+            accept_framed(listener, Capabilities::with_encoding(encoding)).await;
 
         // Read the first message
         let msg = frame_read
@@ -52,9 +113,7 @@ async fn test_connection_send_flushes_to_socket() {
     });
 
     // Create a client connection
-    let connection = Connection::connect_tcp("127.0.0.1", server_port)
-        .await
-        .expect("Failed to connect");
+    let connection = connect(server_port, Capabilities::with_encoding(encoding)).await;
 
     // Send a message (this is where the bug would manifest)
     let test_request = Message::CliRequest(CliRequest {
@@ -64,6 +123,7 @@ async fn test_connection_send_flushes_to_socket() {
         env: HashMap::new(),
         stdin: None,
         cwd: "/tmp".to_string(),
+        tty: None,
     });
 
     // This should NOT hang or panic
@@ -79,6 +139,12 @@ async fn test_connection_send_flushes_to_socket() {
 /// Test that multiple messages are properly framed
 #[tokio::test]
 async fn test_connection_send_multiple_messages() {
+    for encoding in WireEncoding::ALL {
+        test_connection_send_multiple_messages_with(encoding).await;
+    }
+}
+
+async fn test_connection_send_multiple_messages_with(encoding: WireEncoding) {
     let listener = TcpListener::bind("127.0.0.1:0")
         .await
         .expect("Failed to bind mock server");
@@ -88,12 +154,8 @@ async fn test_connection_send_multiple_messages() {
 
     // Spawn server task to receive multiple messages
     let server_handle = tokio::spawn(async move {
-        let (socket, _) = listener
-            .accept()
-            .await
-            .expect("Failed to accept connection");
-        let (reader, _) = socket.into_split();
-        let mut frame_read = FramedRead::new(reader, MessageCodec);
+        let (mut frame_read, _frame_write) =
+            accept_framed(listener, Capabilities::with_encoding(encoding)).await;
 
         // Read three messages
         for i in 0..3 {
@@ -118,9 +180,7 @@ async fn test_connection_send_multiple_messages() {
     });
 
     // Create a client connection
-    let connection = Connection::connect_tcp("127.0.0.1", server_port)
-        .await
-        .expect("Failed to connect");
+    let connection = connect(server_port, Capabilities::with_encoding(encoding)).await;
 
     // Send multiple messages in rapid succession
     // If flush() is missing, only the last message might be received
@@ -132,6 +192,7 @@ async fn test_connection_send_multiple_messages() {
             env: HashMap::new(),
             stdin: None,
             cwd: "/".to_string(),
+            tty: None,
         });
 
         connection.send(req).await.unwrap_or_else(|_| {
@@ -151,6 +212,12 @@ async fn test_connection_send_multiple_messages() {
 /// This is the specific message type that was failing in production
 #[tokio::test]
 async fn test_connection_send_http_request() {
+    for encoding in WireEncoding::ALL {
+        test_connection_send_http_request_with(encoding).await;
+    }
+}
+
+async fn test_connection_send_http_request_with(encoding: WireEncoding) {
     let listener = TcpListener::bind("127.0.0.1:0")
         .await
         .expect("Failed to bind mock server");
@@ -160,12 +227,8 @@ async fn test_connection_send_http_request() {
 
     // Spawn server task
     let server_handle = tokio::spawn(async move {
-        let (socket, _) = listener
-            .accept()
-            .await
-            .expect("Failed to accept connection");
-        let (reader, _) = socket.into_split();
-        let mut frame_read = FramedRead::new(reader, MessageCodec);
+        let (mut frame_read, _frame_write) =
+            accept_framed(listener, Capabilities::with_encoding(encoding)).await;
 
         let msg = frame_read
             .next()
@@ -191,9 +254,7 @@ async fn test_connection_send_http_request() {
     });
 
     // Create a client connection
-    let connection = Connection::connect_tcp("127.0.0.1", server_port)
-        .await
-        .expect("Failed to connect");
+    let connection = connect(server_port, Capabilities::with_encoding(encoding)).await;
 
     // Send the HttpRequest that was failing in production
     let http_req = Message::HttpRequest(HttpRequest {
@@ -203,6 +264,7 @@ async fn test_connection_send_http_request() {
         path: "/api/v1/events".to_string(),
         headers: HashMap::new(),
         body: None,
+        encoding: PayloadEncoding::Utf8,
     });
 
     connection
@@ -218,6 +280,12 @@ async fn test_connection_send_http_request() {
 /// Without proper flushing, rapid messages could get stuck in the kernel buffer
 #[tokio::test]
 async fn test_connection_send_rapid_messages() {
+    for encoding in WireEncoding::ALL {
+        test_connection_send_rapid_messages_with(encoding).await;
+    }
+}
+
+async fn test_connection_send_rapid_messages_with(encoding: WireEncoding) {
     let listener = TcpListener::bind("127.0.0.1:0")
         .await
         .expect("Failed to bind mock server");
@@ -229,12 +297,8 @@ async fn test_connection_send_rapid_messages() {
 
     // Spawn server task
     let server_handle = tokio::spawn(async move {
-        let (socket, _) = listener
-            .accept()
-            .await
-            .expect("Failed to accept connection");
-        let (reader, _) = socket.into_split();
-        let mut frame_read = FramedRead::new(reader, MessageCodec);
+        let (mut frame_read, _frame_write) =
+            accept_framed(listener, Capabilities::with_encoding(encoding)).await;
 
         let mut received_count = 0;
         while let Some(result) = frame_read.next().await {
@@ -264,9 +328,7 @@ async fn test_connection_send_rapid_messages() {
     });
 
     // Create a client connection
-    let connection = Connection::connect_tcp("127.0.0.1", server_port)
-        .await
-        .expect("Failed to connect");
+    let connection = connect(server_port, Capabilities::with_encoding(encoding)).await;
 
     // Send many messages rapidly
     for i in 0..message_count {
@@ -277,6 +339,7 @@ async fn test_connection_send_rapid_messages() {
             env: HashMap::new(),
             stdin: None,
             cwd: "/".to_string(),
+            tty: None,
         });
 
         connection
@@ -288,3 +351,236 @@ async fn test_connection_send_rapid_messages() {
     // Wait for server to verify it received all messages
     server_handle.await.expect("Server task failed");
 }
+
+/// Test that frames after the handshake are written in the negotiated encoding
+#[tokio::test]
+async fn test_connection_uses_negotiated_encoding() {
+    for encoding in WireEncoding::ALL {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind mock server");
+        let server_port = listener.local_addr().unwrap().port();
+
+        let server_handle = tokio::spawn(async move {
+            let (frame_read, _frame_write) =
+                accept_framed(listener, Capabilities::with_encoding(encoding)).await;
+            let mut raw = frame_read.into_inner();
+            let mut prefix = [0u8; 4];
+            raw.read_exact(&mut prefix)
+                .await
+                .expect("No frame received");
+            let mut payload = vec![0u8; (u32::from_be_bytes(prefix) & 0x0FFF_FFFF) as usize];
+            raw.read_exact(&mut payload).await.expect("Truncated frame");
+
+            // The encoding lives in bits 28-29 of the prefix
+            let expected_tag = WireEncoding::ALL
+                .iter()
+                .position(|e| *e == encoding)
+                .unwrap() as u32;
+            assert_eq!(u32::from_be_bytes(prefix) >> 28, expected_tag);
+            match encoding
+                .from_slice(&payload)
+                .expect("Failed to decode payload")
+            {
+                Message::CliRequest(req) => assert_eq!(req.id, "negotiated"),
+                other => panic!("Expected CliRequest, got {:?}", other),
+            }
+        });
+
+        let connection = connect(server_port, Capabilities::with_encoding(encoding)).await;
+        assert_eq!(connection.negotiated().await.encoding(), encoding);
+
+        connection
+            .send(Message::CliRequest(CliRequest {
+                id: "negotiated".to_string(),
+                tool: "test".to_string(),
+                argv: vec![],
+                env: HashMap::new(),
+                stdin: None,
+                cwd: "/".to_string(),
+                tty: None,
+            }))
+            .await
+            .expect("Failed to send message");
+
+        server_handle.await.expect("Server task failed");
+    }
+}
+
+/// Test that large frames are compressed once compression is negotiated
+#[tokio::test]
+async fn test_connection_compresses_large_frames() {
+    for encoding in WireEncoding::ALL {
+        let capabilities = Capabilities {
+            encodings: vec![encoding],
+            compression: vec![Compression::Zstd],
+            ..Default::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind mock server");
+        let server_port = listener.local_addr().unwrap().port();
+        let stdin = "line of highly repetitive output\n".repeat(10_000);
+        let expected_stdin = stdin.clone();
+
+        let requested = capabilities.clone();
+        let server_handle = tokio::spawn(async move {
+            let (frame_read, _frame_write) = accept_framed(listener, requested).await;
+            let mut raw = frame_read.into_inner();
+            let mut prefix = [0u8; 4];
+            raw.read_exact(&mut prefix)
+                .await
+                .expect("No frame received");
+            let prefix = u32::from_be_bytes(prefix);
+            assert_ne!(prefix & 0x8000_0000, 0, "Frame should be compressed");
+
+            let length = (prefix & 0x0FFF_FFFF) as usize;
+            assert!(length < expected_stdin.len() / 4);
+            let mut frame = tokio_util::bytes::BytesMut::new();
+            frame.extend_from_slice(&prefix.to_be_bytes());
+            frame.resize(4 + length, 0);
+            raw.read_exact(&mut frame[4..])
+                .await
+                .expect("Truncated frame");
+
+            let msg = tokio_util::codec::Decoder::decode(&mut MessageCodec::new(), &mut frame)
+                .expect("Failed to decode compressed frame")
+                .expect("Incomplete frame");
+            match msg {
+                Message::CliRequest(req) => assert_eq!(req.stdin, Some(expected_stdin)),
+                other => panic!("Expected CliRequest, got {:?}", other),
+            }
+        });
+
+        let connection = connect(server_port, capabilities).await;
+        assert_eq!(
+            connection.negotiated().await.compression(),
+            Some(Compression::Zstd)
+        );
+
+        connection
+            .send(Message::CliRequest(CliRequest {
+                id: "compressed".to_string(),
+                tool: "test".to_string(),
+                argv: vec![],
+                env: HashMap::new(),
+                stdin: Some(stdin),
+                cwd: "/".to_string(),
+                tty: None,
+            }))
+            .await
+            .expect("Failed to send message");
+
+        server_handle.await.expect("Server task failed");
+    }
+}
+
+/// Test that chunked responses are reassembled by Connection::recv()
+#[tokio::test]
+async fn test_connection_reassembles_chunked_response() {
+    for encoding in WireEncoding::ALL {
+        let capabilities = Capabilities {
+            encodings: vec![encoding],
+            chunking: true,
+            ..Default::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind mock server");
+        let server_port = listener.local_addr().unwrap().port();
+        let stdout = "0123456789abcdef".repeat(64 * 1024); // 1 MB
+
+        let requested = capabilities.clone();
+        let response_stdout = stdout.clone();
+        let server_handle = tokio::spawn(async move {
+            let (_frame_read, mut frame_write) = accept_framed(listener, requested).await;
+            let response = Message::CliResponse(CliResponse::from_output(
+                "chunked".to_string(),
+                0,
+                response_stdout.as_bytes(),
+                b"",
+            ));
+            let frames =
+                chunking::split(response, chunking::CHUNK_SIZE, Default::default()).unwrap();
+            assert!(frames.len() > 1);
+            for frame in frames {
+                frame_write.send(frame).await.expect("Failed to send chunk");
+            }
+
+            // A chunk that never started is reported against its request id
+            frame_write
+                .send(Message::Chunk(carapace_protocol::Chunk {
+                    id: "orphan".to_string(),
+                    seq: 1,
+                    total: 2,
+                    encoding: Default::default(),
+                    data: Vec::new(),
+                }))
+                .await
+                .expect("Failed to send chunk");
+            frame_write
+        });
+
+        let connection = connect(server_port, capabilities).await;
+        assert!(connection.negotiated().await.chunking);
+
+        match connection.recv().await.expect("recv failed") {
+            Some(Message::CliResponse(resp)) => {
+                assert_eq!(resp.id, "chunked");
+                assert_eq!(resp.stdout, stdout);
+            }
+            other => panic!("Expected reassembled CliResponse, got {:?}", other),
+        }
+        match connection.recv().await.expect("recv failed") {
+            Some(Message::Error(err)) => {
+                assert_eq!(err.id.as_deref(), Some("orphan"));
+                assert_eq!(err.code, "chunk_error");
+            }
+            other => panic!("Expected chunk error, got {:?}", other),
+        }
+        assert!(connection.is_healthy());
+
+        server_handle.await.expect("Server task failed");
+    }
+}
+
+/// Test that Cancel is sent only when the server negotiated cancellation
+#[tokio::test]
+async fn test_connection_cancel_requires_negotiation() {
+    for cancel in [false, true] {
+        let capabilities = Capabilities {
+            encodings: vec![WireEncoding::Json],
+            cancel,
+            ..Default::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind mock server");
+        let server_port = listener.local_addr().unwrap().port();
+
+        let requested = capabilities.clone();
+        let server_handle = tokio::spawn(async move {
+            let (mut frame_read, _frame_write) = accept_framed(listener, requested).await;
+            frame_read.next().await
+        });
+
+        let connection = connect(server_port, capabilities).await;
+        assert_eq!(connection.negotiated().await.cancel, cancel);
+        connection.cancel("req-1").await.expect("cancel failed");
+        connection
+            .send(Message::Ping(PingPong {
+                id: "after-cancel".to_string(),
+                timestamp: 0,
+                capabilities: None,
+                agent: None,
+            }))
+            .await
+            .expect("Failed to send ping");
+
+        match server_handle.await.expect("Server task failed") {
+            Some(Ok(Message::Cancel(c))) if cancel => assert_eq!(c.id, "req-1"),
+            Some(Ok(Message::Ping(p))) if !cancel => assert_eq!(p.id, "after-cancel"),
+            other => panic!("Unexpected first message (cancel={}): {:?}", cancel, other),
+        }
+    }
+}
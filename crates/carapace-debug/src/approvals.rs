@@ -0,0 +1,117 @@
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::path::PathBuf;
+
+/// List requests waiting for approval
+pub async fn list(host: &str, port: u16, format: &str) -> Result<()> {
+    let url = format!("http://{}:{}/debug/approvals", host, port);
+
+    let data = match reqwest::get(&url).await {
+        Ok(resp) => resp.json::<Value>().await?,
+        Err(e) => {
+            eprintln!("Failed to connect to server at {}: {}", url, e);
+            return Err(anyhow!("Failed to connect to server"));
+        }
+    };
+
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&data)?);
+    } else {
+        print_pending_table(&data);
+    }
+    Ok(())
+}
+
+/// The approver token in `token_file`, or else in the file named by
+/// `CARAPACE_APPROVAL_TOKEN_FILE`
+pub fn read_token(token_file: Option<PathBuf>) -> Result<String> {
+    let path = token_file
+        .or_else(|| std::env::var_os("CARAPACE_APPROVAL_TOKEN_FILE").map(PathBuf::from))
+        .ok_or_else(|| anyhow!("Give --token-file or set CARAPACE_APPROVAL_TOKEN_FILE"))?;
+    let token = std::fs::read_to_string(&path)
+        .map_err(|e| anyhow!("Cannot read token file {}: {}", path.display(), e))?;
+    Ok(token.trim().to_string())
+}
+
+/// Approve or deny a waiting request. The server records whoever `token`
+/// belongs to as the approver.
+pub async fn decide(
+    host: &str,
+    port: u16,
+    approval_id: &str,
+    approved: bool,
+    token: &str,
+    format: &str,
+) -> Result<()> {
+    let action = if approved { "approve" } else { "deny" };
+    let url = format!(
+        "http://{}:{}/debug/approvals/{}/{}",
+        host, port, approval_id, action
+    );
+
+    let resp = reqwest::Client::new()
+        .post(&url)
+        .bearer_auth(token)
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to connect to server at {}: {}", url, e))?;
+    let status = resp.status();
+    let data = resp.json::<Value>().await?;
+
+    if !status.is_success() {
+        let error = data.get("error").and_then(|v| v.as_str()).unwrap_or("");
+        return Err(anyhow!("Failed to {} {}: {}", action, approval_id, error));
+    }
+
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&data)?);
+    } else {
+        println!(
+            "{} {} as {}",
+            if approved { "Approved" } else { "Denied" },
+            approval_id,
+            data.get("approver").and_then(|v| v.as_str()).unwrap_or("")
+        );
+    }
+    Ok(())
+}
+
+fn print_pending_table(data: &Value) {
+    println!("=== Pending Approvals ===");
+
+    let pending = match data.get("pending").and_then(|v| v.as_array()) {
+        Some(pending) if !pending.is_empty() => pending,
+        _ => {
+            println!("No requests waiting for approval");
+            return;
+        }
+    };
+
+    println!(
+        "{:<38} {:<20} {:<15} {:<27} Request",
+        "Approval ID", "Request ID", "Tool", "Requested"
+    );
+    println!("{}", "-".repeat(121));
+
+    for request in pending {
+        let field = |name: &str| request.get(name).and_then(|v| v.as_str()).unwrap_or("");
+        // argv for CLI tools, method and path (and the body) for HTTP tools
+        let summary = match request.get("argv").and_then(|v| v.as_array()) {
+            Some(argv) => argv
+                .iter()
+                .filter_map(|arg| arg.as_str())
+                .collect::<Vec<_>>()
+                .join(" "),
+            None => format!("{} {} {}", field("method"), field("path"), field("body")),
+        };
+
+        println!(
+            "{:<38} {:<20} {:<15} {:<27} {}",
+            field("approval_id"),
+            field("request_id"),
+            field("tool"),
+            field("requested_at"),
+            summary
+        );
+    }
+}
@@ -1,9 +1,65 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use chrono::{Duration, Utc};
-use serde_json::Value;
+use ed25519_dalek::{Signature, SigningKey, Verifier, VerifyingKey};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::Path;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// `prev_hash` of the first entry of a fresh audit log
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Which entries a query, follow or stats run looks at
+pub(crate) struct AuditFilter {
+    tool: Option<String>,
+    action: Option<String>,
+    result: Option<String>,
+    since: Option<chrono::DateTime<Utc>>,
+}
+
+impl AuditFilter {
+    pub(crate) fn new(
+        tool: Option<String>,
+        action: Option<String>,
+        result: Option<String>,
+        since: Option<String>,
+    ) -> Self {
+        AuditFilter {
+            tool,
+            action,
+            result,
+            since: since.as_ref().and_then(|s| parse_time_filter(s)),
+        }
+    }
+
+    pub(crate) fn matches(&self, entry: &Value) -> bool {
+        let field_is = |name: &str, wanted: &Option<String>| match wanted {
+            Some(wanted) => entry.get(name).and_then(|v| v.as_str()) == Some(wanted.as_str()),
+            None => true,
+        };
+        if !field_is("tool", &self.tool)
+            || !field_is("action_type", &self.action)
+            || !field_is("policy_result", &self.result)
+        {
+            return false;
+        }
+
+        match self.since {
+            Some(cutoff) => entry
+                .get("timestamp")
+                .and_then(|v| v.as_str())
+                .and_then(|ts| chrono::DateTime::parse_from_rfc3339(ts).ok())
+                .is_none_or(|ts| ts.with_timezone(&Utc) >= cutoff),
+            None => true,
+        }
+    }
+}
 
 /// Query audit logs with filtering
 #[allow(clippy::too_many_arguments)]
@@ -17,196 +73,294 @@ pub async fn audit(
     format: &str,
     limit: usize,
 ) -> Result<()> {
-    // Parse time filter
-    let cutoff_time = since_filter.as_ref().and_then(|s| parse_time_filter(s));
+    let filter = AuditFilter::new(tool_filter, action_filter, result_filter, since_filter);
 
     if follow {
-        return Err(anyhow::anyhow!(
-            "Follow mode is not implemented. Use: tail -f {}",
-            file.display()
-        ));
-    }
-
-    query_audit_log(
-        file,
-        tool_filter,
-        action_filter,
-        result_filter,
-        cutoff_time,
-        format,
-        limit,
-    )?;
+        return follow_audit_log(file, &filter, format, limit).await;
+    }
+
+    query_audit_log(file, &filter, format, limit)?;
 
     Ok(())
 }
 
-fn query_audit_log(
-    file: &Path,
-    tool_filter: Option<String>,
-    action_filter: Option<String>,
-    result_filter: Option<String>,
-    cutoff_time: Option<chrono::DateTime<Utc>>,
-    format: &str,
-    limit: usize,
-) -> Result<()> {
+/// The last `limit` matching entries, oldest first, read across the
+/// rotated logs and then the current one
+fn recent_entries(file: &Path, filter: &AuditFilter, limit: usize) -> Result<Vec<Value>> {
+    let mut entries = VecDeque::new();
+    for path in chain_files(file) {
+        let reader = BufReader::new(File::open(&path)?);
+        for line in reader.lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    eprintln!("Error reading log file {}: {}", path.display(), e);
+                    break;
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            // Skip malformed JSON lines
+            let Ok(entry) = serde_json::from_str::<Value>(&line) else {
+                continue;
+            };
+            if filter.matches(&entry) {
+                if entries.len() == limit {
+                    entries.pop_front();
+                }
+                entries.push_back(entry);
+            }
+        }
+    }
+    Ok(entries.into())
+}
+
+fn query_audit_log(file: &Path, filter: &AuditFilter, format: &str, limit: usize) -> Result<()> {
     if !file.exists() {
         println!("Audit log file not found: {}", file.display());
         return Ok(());
     }
 
-    let f = File::open(file)?;
-    let reader = BufReader::new(f);
+    let mut entries = recent_entries(file, filter, limit)?;
 
-    let mut entries = Vec::new();
-    let mut count = 0;
+    // Reverse to show newest first
+    entries.reverse();
 
-    for line in reader.lines() {
-        if count >= limit {
-            break;
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+    } else {
+        // Text format with table
+        print_audit_table(&entries);
+    }
+
+    Ok(())
+}
+
+/// Print the last `limit` matching entries, then new ones as they are
+/// written, oldest first. JSON output is one entry per line.
+async fn follow_audit_log(
+    file: &Path,
+    filter: &AuditFilter,
+    format: &str,
+    limit: usize,
+) -> Result<()> {
+    // Start following before reading the backlog, so no line falls between
+    let mut follower = Follower::at_end(file)?;
+
+    let print = |entry: &Value| -> Result<()> {
+        if format == "json" {
+            println!("{}", serde_json::to_string(entry)?);
+        } else {
+            print_audit_row(entry);
         }
+        Ok(())
+    };
 
-        match line {
-            Ok(line) => {
-                if line.trim().is_empty() {
-                    continue;
+    if format != "json" {
+        print_audit_header();
+    }
+    for entry in recent_entries(file, filter, limit)? {
+        print(&entry)?;
+    }
+
+    loop {
+        for line in follower.poll()? {
+            if let Ok(entry) = serde_json::from_str::<Value>(&line) {
+                if filter.matches(&entry) {
+                    print(&entry)?;
                 }
+            }
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    }
+}
 
-                match serde_json::from_str::<Value>(&line) {
-                    Ok(entry) => {
-                        // Apply filters
-                        if let Some(ref tool) = tool_filter {
-                            if entry
-                                .get("tool")
-                                .and_then(|v| v.as_str())
-                                .map(|t| t != tool)
-                                .unwrap_or(true)
-                            {
-                                continue;
-                            }
-                        }
+/// Tails the audit log across rotations. The server rotates by renaming
+/// the log away and starting a new file, so a changed inode means the
+/// file being read is now a rotated one: it is read to its end, then any
+/// rotated out after it, then the new file from its start.
+struct Follower {
+    path: PathBuf,
+    reader: Option<BufReader<File>>,
+    inode: u64,
+    /// Bytes of a line whose newline has not been written yet
+    partial: String,
+}
 
-                        if let Some(ref action) = action_filter {
-                            if entry
-                                .get("action_type")
-                                .and_then(|v| v.as_str())
-                                .map(|a| a != action)
-                                .unwrap_or(true)
-                            {
-                                continue;
-                            }
-                        }
+impl Follower {
+    /// Follow `path` from its current end, or from the start of the file
+    /// once it appears
+    fn at_end(path: &Path) -> Result<Self> {
+        let mut follower = Follower {
+            path: path.to_path_buf(),
+            reader: None,
+            inode: 0,
+            partial: String::new(),
+        };
+        if path.exists() {
+            follower.open()?;
+            if let Some(reader) = follower.reader.as_mut() {
+                reader.seek(SeekFrom::End(0))?;
+            }
+        }
+        Ok(follower)
+    }
 
-                        if let Some(ref result) = result_filter {
-                            if entry
-                                .get("policy_result")
-                                .and_then(|v| v.as_str())
-                                .map(|r| r != result)
-                                .unwrap_or(true)
-                            {
-                                continue;
-                            }
-                        }
+    fn open(&mut self) -> Result<()> {
+        let file = File::open(&self.path)
+            .with_context(|| format!("Failed to open {}", self.path.display()))?;
+        self.inode = file.metadata()?.ino();
+        self.reader = Some(BufReader::new(file));
+        self.partial.clear();
+        Ok(())
+    }
+
+    /// Complete lines written since the last poll
+    fn poll(&mut self) -> Result<Vec<String>> {
+        let mut lines = self.read_lines()?;
 
-                        if let Some(cutoff) = cutoff_time {
-                            if let Some(timestamp_str) =
-                                entry.get("timestamp").and_then(|v| v.as_str())
-                            {
-                                if let Ok(ts) = chrono::DateTime::parse_from_rfc3339(timestamp_str)
-                                {
-                                    if ts.with_timezone(&Utc) < cutoff {
-                                        continue;
-                                    }
-                                }
+        match std::fs::metadata(&self.path) {
+            Ok(meta) if self.reader.is_none() || meta.ino() != self.inode => {
+                // Created or rotated: what was left of the old file was read above
+                if self.reader.is_some() {
+                    for path in self.rotated_since() {
+                        let file = File::open(&path)
+                            .with_context(|| format!("Failed to open {}", path.display()))?;
+                        for line in BufReader::new(file).lines() {
+                            let line = line?;
+                            if !line.is_empty() {
+                                lines.push(line);
                             }
                         }
-
-                        entries.push(entry);
-                        count += 1;
-                    }
-                    Err(_) => {
-                        // Skip malformed JSON lines
-                        continue;
                     }
                 }
+                self.open()?;
+                lines.extend(self.read_lines()?);
             }
-            Err(e) => {
-                eprintln!("Error reading log file: {}", e);
+            Ok(meta) => {
+                // Truncated in place
+                if let Some(reader) = self.reader.as_mut() {
+                    if meta.len() < reader.stream_position()? {
+                        reader.seek(SeekFrom::Start(0))?;
+                        self.partial.clear();
+                        lines.extend(self.read_lines()?);
+                    }
+                }
             }
+            // Between the rename and the new file
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
         }
-    }
 
-    // Reverse to show newest first
-    entries.reverse();
+        Ok(lines)
+    }
 
-    if format == "json" {
-        println!("{}", serde_json::to_string_pretty(&entries)?);
-    } else {
-        // Text format with table
-        print_audit_table(&entries);
+    /// Logs rotated out after the one being read, oldest first: those the
+    /// server wrote in full when it rotated more than once between polls
+    fn rotated_since(&self) -> Vec<PathBuf> {
+        let mut files = chain_files(&self.path);
+        files.pop();
+        let read = files
+            .iter()
+            .position(|path| std::fs::metadata(path).is_ok_and(|meta| meta.ino() == self.inode));
+        match read {
+            Some(index) => files.split_off(index + 1),
+            // Already deleted; the files left cannot be told apart
+            None => Vec::new(),
+        }
     }
 
-    Ok(())
+    fn read_lines(&mut self) -> Result<Vec<String>> {
+        let mut lines = Vec::new();
+        let Some(reader) = self.reader.as_mut() else {
+            return Ok(lines);
+        };
+        loop {
+            let mut chunk = String::new();
+            if reader.read_line(&mut chunk)? == 0 {
+                break;
+            }
+            self.partial.push_str(&chunk);
+            if self.partial.ends_with('\n') {
+                let line = std::mem::take(&mut self.partial);
+                let line = line.trim_end();
+                if !line.is_empty() {
+                    lines.push(line.to_string());
+                }
+            }
+        }
+        Ok(lines)
+    }
 }
 
 fn print_audit_table(entries: &[Value]) {
     println!("=== Audit Log Entries (Most Recent First) ===");
+    print_audit_header();
+
+    for entry in entries {
+        print_audit_row(entry);
+    }
+
+    println!("\nTotal: {} entries", entries.len());
+}
+
+fn print_audit_header() {
     println!(
         "{:<20} {:<12} {:<8} {:<15} {:<40}",
         "Timestamp", "Tool", "Action", "Result", "Details"
     );
     println!("{}", "-".repeat(100));
+}
 
-    for entry in entries {
-        let timestamp = entry
-            .get("timestamp")
-            .and_then(|v| v.as_str())
-            .map(|s| s.split('T').next().unwrap_or(s))
-            .unwrap_or("unknown");
-
-        let tool = entry
-            .get("tool")
-            .and_then(|v| v.as_str())
-            .unwrap_or("unknown");
-
-        let action = entry
-            .get("action_type")
-            .and_then(|v| v.as_str())
-            .unwrap_or("unknown");
-
-        let result = entry
-            .get("policy_result")
-            .and_then(|v| v.as_str())
-            .unwrap_or("unknown");
-
-        // Build details from different fields
-        let details = if let Some(argv) = entry.get("argv").and_then(|v| v.as_array()) {
-            let args: Vec<String> = argv
-                .iter()
-                .filter_map(|v| v.as_str().map(String::from))
-                .collect();
-            args.join(" ").chars().take(40).collect::<String>()
-        } else if let Some(method) = entry.get("method").and_then(|v| v.as_str()) {
-            format!(
-                "{} {}",
-                method,
-                entry.get("path").and_then(|v| v.as_str()).unwrap_or("")
-            )
-            .chars()
-            .take(40)
-            .collect()
-        } else if let Some(msg) = entry.get("error_message").and_then(|v| v.as_str()) {
-            msg.chars().take(40).collect::<String>()
-        } else {
-            "-".to_string()
-        };
+fn print_audit_row(entry: &Value) {
+    let timestamp = entry
+        .get("timestamp")
+        .and_then(|v| v.as_str())
+        .map(|s| s.split('T').next().unwrap_or(s))
+        .unwrap_or("unknown");
 
-        println!(
-            "{:<20} {:<12} {:<8} {:<15} {:<40}",
-            timestamp, tool, action, result, details
-        );
-    }
+    let tool = entry
+        .get("tool")
+        .and_then(|v| v.as_str())
+        .unwrap_or("unknown");
 
-    println!("\nTotal: {} entries", entries.len());
+    let action = entry
+        .get("action_type")
+        .and_then(|v| v.as_str())
+        .unwrap_or("unknown");
+
+    let result = entry
+        .get("policy_result")
+        .and_then(|v| v.as_str())
+        .unwrap_or("unknown");
+
+    // Build details from different fields
+    let details = if let Some(argv) = entry.get("argv").and_then(|v| v.as_array()) {
+        let args: Vec<String> = argv
+            .iter()
+            .filter_map(|v| v.as_str().map(String::from))
+            .collect();
+        args.join(" ").chars().take(40).collect::<String>()
+    } else if let Some(method) = entry.get("method").and_then(|v| v.as_str()) {
+        format!(
+            "{} {}",
+            method,
+            entry.get("path").and_then(|v| v.as_str()).unwrap_or("")
+        )
+        .chars()
+        .take(40)
+        .collect()
+    } else if let Some(msg) = entry.get("error_message").and_then(|v| v.as_str()) {
+        msg.chars().take(40).collect::<String>()
+    } else {
+        "-".to_string()
+    };
+
+    println!(
+        "{:<20} {:<12} {:<8} {:<15} {:<40}",
+        timestamp, tool, action, result, details
+    );
 }
 
 /// Parse time filter like "5m", "1h", "24h"
@@ -229,3 +383,490 @@ fn parse_time_filter(filter: &str) -> Option<chrono::DateTime<Utc>> {
         _ => None,
     }
 }
+
+/// Where a hash chain stops holding
+#[derive(Serialize)]
+struct BrokenLink {
+    file: String,
+    line: usize,
+    reason: String,
+}
+
+/// Result of walking the chain across the current and rotated logs
+#[derive(Serialize, Default)]
+struct ChainReport {
+    files: Vec<String>,
+    entries: usize,
+    /// Lines written before the server chained its log
+    unchained: usize,
+    checkpoints: usize,
+    /// Checkpoints whose signature was checked against a public key
+    verified_checkpoints: usize,
+    /// Entries after the last checkpoint, covered by the chain alone
+    since_checkpoint: usize,
+    /// The first chained entry, when the line it links to is in none of
+    /// the files (a rotated log deleted since), so that link is unchecked
+    unverified_link: Option<UnverifiedLink>,
+    broken: Option<BrokenLink>,
+}
+
+/// A `prev_hash` pointing outside the files read
+#[derive(Serialize)]
+struct UnverifiedLink {
+    file: String,
+    line: usize,
+    prev_hash: String,
+}
+
+/// Verify the hash chain of an audit log and its rotated predecessors,
+/// reporting the first broken link. Exits non-zero if the chain is broken.
+pub fn verify(file: &Path, public_key: Option<String>, format: &str) -> Result<()> {
+    let public_key = public_key.map(|key| parse_public_key(&key)).transpose()?;
+    let report = walk_chain(&chain_paths(file), public_key.as_ref())?;
+
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_chain_report(&report, public_key.is_some());
+    }
+
+    if report.broken.is_some() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Rotated logs oldest first (`audit.N` … `audit.1`), then the current file
+pub(crate) fn chain_files(file: &Path) -> Vec<PathBuf> {
+    chain_paths(file)
+        .into_iter()
+        .filter(|path| path.exists())
+        .collect()
+}
+
+/// Like `chain_files`, but with every index up to the highest on disk, so
+/// that a rotated log missing in between is a path that does not exist
+fn chain_paths(file: &Path) -> Vec<PathBuf> {
+    let stem = file.with_extension("");
+    let prefix = format!(
+        "{}.",
+        stem.file_name().unwrap_or_default().to_string_lossy()
+    );
+    let dir = match stem.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let oldest = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name();
+            name.to_str()?.strip_prefix(&prefix)?.parse::<u32>().ok()
+        })
+        .max()
+        .unwrap_or(0);
+
+    let mut files: Vec<PathBuf> = (1..=oldest)
+        .rev()
+        .map(|i| PathBuf::from(format!("{}.{}", stem.display(), i)))
+        .collect();
+    if file.exists() {
+        files.push(file.to_path_buf());
+    }
+    files
+}
+
+fn walk_chain(files: &[PathBuf], public_key: Option<&VerifyingKey>) -> Result<ChainReport> {
+    let mut report = ChainReport::default();
+    // Hash of the previous line, chained or not
+    let mut head: Option<String> = None;
+    let mut chained = false;
+
+    for path in files {
+        report.files.push(path.display().to_string());
+        if !path.exists() {
+            report.broken = Some(BrokenLink {
+                file: path.display().to_string(),
+                line: 0,
+                reason: "rotated log is missing".to_string(),
+            });
+            return Ok(report);
+        }
+        let reader = BufReader::new(
+            File::open(path).with_context(|| format!("Cannot open {}", path.display()))?,
+        );
+
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let broken = |reason: String| BrokenLink {
+                file: path.display().to_string(),
+                line: index + 1,
+                reason,
+            };
+
+            let entry: Option<Value> = serde_json::from_str(&line).ok();
+            let prev_hash = entry
+                .as_ref()
+                .and_then(|e| e.get("prev_hash"))
+                .and_then(|v| v.as_str());
+
+            match (prev_hash, &head) {
+                (None, _) if !chained && entry.is_some() => {
+                    report.unchained += 1;
+                    head = Some(format!("{:x}", Sha256::digest(line.as_bytes())));
+                    continue;
+                }
+                (None, _) => {
+                    report.broken = Some(broken(match entry {
+                        Some(_) => "entry has no prev_hash".to_string(),
+                        None => "line is not valid JSON".to_string(),
+                    }));
+                    return Ok(report);
+                }
+                (Some(found), Some(expected)) if found != expected => {
+                    report.broken = Some(broken(format!(
+                        "prev_hash {} does not match the previous line ({})",
+                        found, expected
+                    )));
+                    return Ok(report);
+                }
+                // A fresh log starts from the genesis hash
+                (Some(found), None) if found != GENESIS_HASH => {
+                    report.unverified_link = Some(UnverifiedLink {
+                        file: path.display().to_string(),
+                        line: index + 1,
+                        prev_hash: found.to_string(),
+                    });
+                }
+                _ => {}
+            }
+            chained = true;
+
+            let is_checkpoint = entry
+                .as_ref()
+                .and_then(|e| e.get("action_type"))
+                .and_then(|v| v.as_str())
+                == Some("checkpoint");
+            if is_checkpoint {
+                report.checkpoints += 1;
+                report.since_checkpoint = 0;
+                if let Some(key) = public_key {
+                    let signature = entry
+                        .as_ref()
+                        .and_then(|e| e.get("signature"))
+                        .and_then(|v| v.as_str())
+                        .unwrap_or_default();
+                    if !signature_valid(key, prev_hash.unwrap_or_default(), signature) {
+                        report.broken = Some(broken("checkpoint signature is invalid".to_string()));
+                        return Ok(report);
+                    }
+                    report.verified_checkpoints += 1;
+                }
+            } else {
+                report.entries += 1;
+                report.since_checkpoint += 1;
+            }
+
+            head = Some(format!("{:x}", Sha256::digest(line.as_bytes())));
+        }
+    }
+
+    Ok(report)
+}
+
+fn signature_valid(key: &VerifyingKey, prev_hash: &str, signature: &str) -> bool {
+    BASE64
+        .decode(signature)
+        .ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        .is_some_and(|signature| key.verify(prev_hash.as_bytes(), &signature).is_ok())
+}
+
+fn parse_public_key(key: &str) -> Result<VerifyingKey> {
+    let bytes: [u8; 32] = BASE64
+        .decode(key.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| anyhow::anyhow!("Public key must be 32 bytes of base64"))?;
+    Ok(VerifyingKey::from_bytes(&bytes)?)
+}
+
+fn print_chain_report(report: &ChainReport, signatures_checked: bool) {
+    println!("=== Audit Chain ===");
+    for file in &report.files {
+        println!("File: {}", file);
+    }
+    println!("Entries: {}", report.entries);
+    if report.unchained > 0 {
+        println!("Unchained: {} (written before chaining)", report.unchained);
+    }
+    if signatures_checked {
+        println!(
+            "Checkpoints: {} ({} signatures verified)",
+            report.checkpoints, report.verified_checkpoints
+        );
+    } else {
+        println!(
+            "Checkpoints: {} (signatures not checked, pass --public-key)",
+            report.checkpoints
+        );
+    }
+    if report.checkpoints > 0 {
+        println!("After last checkpoint: {} entries", report.since_checkpoint);
+    }
+    if let Some(link) = &report.unverified_link {
+        println!(
+            "Unverified: {} line {} links to a line in none of these files (prev_hash {})",
+            link.file, link.line, link.prev_hash
+        );
+    }
+
+    match &report.broken {
+        Some(broken) if broken.line == 0 => {
+            println!("\nBROKEN at {}: {}", broken.file, broken.reason)
+        }
+        Some(broken) => println!(
+            "\nBROKEN at {} line {}: {}",
+            broken.file, broken.line, broken.reason
+        ),
+        None if report.files.is_empty() => println!("\nNo audit log files found"),
+        None => println!("\nChain intact"),
+    }
+}
+
+/// Create a checkpoint signing key for the server and print its public half
+pub fn keygen(out: &Path, format: &str) -> Result<()> {
+    if out.exists() {
+        return Err(anyhow::anyhow!("{} already exists", out.display()));
+    }
+
+    let mut seed = [0u8; 32];
+    File::open("/dev/urandom")?.read_exact(&mut seed)?;
+    let key = SigningKey::from_bytes(&seed);
+
+    {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(out)?;
+        writeln!(file, "{}", BASE64.encode(seed))?;
+    }
+
+    let public_key = BASE64.encode(key.verifying_key().to_bytes());
+    if format == "json" {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&json!({
+                "key_file": out.display().to_string(),
+                "public_key": public_key,
+            }))?
+        );
+    } else {
+        println!("Signing key written to {}", out.display());
+        println!("Public key: {}", public_key);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::Signer;
+
+    /// A chained log rotated three times (`audit.3` oldest), two entries
+    /// per file and a checkpoint signed by `key` closing `audit.1`
+    fn write_chain(dir: &Path, key: &SigningKey) -> PathBuf {
+        let mut head = GENESIS_HASH.to_string();
+        for (name, n) in [
+            ("audit.3", 0),
+            ("audit.2", 2),
+            ("audit.1", 4),
+            ("audit.log", 6),
+        ] {
+            let mut lines = Vec::new();
+            for id in [n, n + 1] {
+                let entry = json!({"action_type": "cli_request", "request_id": id.to_string()});
+                lines.push(chain(&mut head, entry));
+            }
+            if name == "audit.1" {
+                let signature = BASE64.encode(key.sign(head.as_bytes()).to_bytes());
+                let entry = json!({"action_type": "checkpoint", "signature": signature});
+                lines.push(chain(&mut head, entry));
+            }
+            std::fs::write(dir.join(name), lines.join("\n") + "\n").unwrap();
+        }
+        dir.join("audit.log")
+    }
+
+    /// `entry` as the line after `head`, which moves on to it
+    fn chain(head: &mut String, mut entry: Value) -> String {
+        entry["prev_hash"] = json!(head);
+        let line = entry.to_string();
+        *head = format!("{:x}", Sha256::digest(line.as_bytes()));
+        line
+    }
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn broken(report: &ChainReport) -> (String, usize, String) {
+        let broken = report.broken.as_ref().expect("chain should be broken");
+        let file = Path::new(&broken.file).file_name().unwrap();
+        (
+            file.to_string_lossy().into_owned(),
+            broken.line,
+            broken.reason.clone(),
+        )
+    }
+
+    /// The server's rotation: shift `audit.N` up and rename the log to
+    /// `audit.1`, leaving the writer on the renamed file
+    fn rotate(dir: &Path) {
+        let mut rotated: Vec<u32> = (1..)
+            .take_while(|i| dir.join(format!("audit.{}", i)).exists())
+            .collect();
+        rotated.reverse();
+        for i in rotated {
+            std::fs::rename(
+                dir.join(format!("audit.{}", i)),
+                dir.join(format!("audit.{}", i + 1)),
+            )
+            .unwrap();
+        }
+        std::fs::rename(dir.join("audit.log"), dir.join("audit.1")).unwrap();
+    }
+
+    #[test]
+    fn test_follower_across_rotations() {
+        use std::io::Write;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let append = || {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .unwrap()
+        };
+        let mut writer = append();
+        writer.write_all(b"{\"n\":0}\n").unwrap();
+
+        let mut follower = Follower::at_end(&path).unwrap();
+        let mut seen = Vec::new();
+
+        writer.write_all(b"{\"n\":1}\n{\"n\":").unwrap();
+        seen.extend(follower.poll().unwrap());
+
+        // Rotated mid-line: the writer finishes the line in the old file
+        rotate(dir.path());
+        writer.write_all(b"2}\n{\"n\":3}\n").unwrap();
+        let mut writer = append();
+        writer.write_all(b"{\"n\":4}\n{\"n\"").unwrap();
+        seen.extend(follower.poll().unwrap());
+
+        // Rotated twice between polls
+        rotate(dir.path());
+        writer.write_all(b":5}\n").unwrap();
+        let mut writer = append();
+        writer.write_all(b"{\"n\":6}\n").unwrap();
+        rotate(dir.path());
+        writer.write_all(b"{\"n\":7}\n").unwrap();
+        let mut writer = append();
+        writer.write_all(b"{\"n\":8}\n").unwrap();
+        seen.extend(follower.poll().unwrap());
+        seen.extend(follower.poll().unwrap());
+
+        let expected: Vec<String> = (1..=8).map(|n| format!("{{\"n\":{}}}", n)).collect();
+        assert_eq!(seen, expected);
+    }
+
+    #[test]
+    fn test_intact_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = key(1);
+        let file = write_chain(dir.path(), &key);
+
+        let report = walk_chain(&chain_paths(&file), Some(&key.verifying_key())).unwrap();
+        assert!(report.broken.is_none());
+        assert!(report.unverified_link.is_none());
+        assert_eq!(report.files.len(), 4);
+        assert_eq!(report.entries, 8);
+        assert_eq!(report.verified_checkpoints, 1);
+        assert_eq!(report.since_checkpoint, 2);
+    }
+
+    #[test]
+    fn test_tampered_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = key(1);
+        let file = write_chain(dir.path(), &key);
+        let rotated = dir.path().join("audit.2");
+        let text = std::fs::read_to_string(&rotated).unwrap();
+        std::fs::write(&rotated, text.replacen("\"2\"", "\"9\"", 1)).unwrap();
+
+        let report = walk_chain(&chain_paths(&file), None).unwrap();
+        let (file, line, reason) = broken(&report);
+        assert_eq!((file.as_str(), line), ("audit.2", 2));
+        assert!(reason.contains("does not match"));
+    }
+
+    #[test]
+    fn test_deleted_middle_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = key(1);
+        let file = write_chain(dir.path(), &key);
+        std::fs::remove_file(dir.path().join("audit.2")).unwrap();
+
+        // Files past the gap are still read by queries and stats
+        assert_eq!(chain_files(&file).len(), 3);
+        let report = walk_chain(&chain_paths(&file), None).unwrap();
+        assert_eq!(
+            broken(&report),
+            (
+                "audit.2".to_string(),
+                0,
+                "rotated log is missing".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_deleted_oldest_file_leaves_first_link_unverified() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = key(1);
+        let file = write_chain(dir.path(), &key);
+        std::fs::remove_file(dir.path().join("audit.3")).unwrap();
+
+        let report = walk_chain(&chain_paths(&file), None).unwrap();
+        assert!(report.broken.is_none());
+        let link = report.unverified_link.unwrap();
+        assert!(link.file.ends_with("audit.2"));
+        assert_eq!(link.line, 1);
+    }
+
+    #[test]
+    fn test_bad_checkpoint_signature() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = write_chain(dir.path(), &key(1));
+
+        let report = walk_chain(&chain_paths(&file), Some(&key(2).verifying_key())).unwrap();
+        assert_eq!(
+            broken(&report),
+            (
+                "audit.1".to_string(),
+                3,
+                "checkpoint signature is invalid".to_string()
+            )
+        );
+        // Without a key the signature is not checked
+        let report = walk_chain(&chain_paths(&file), None).unwrap();
+        assert!(report.broken.is_none());
+    }
+}
@@ -0,0 +1,553 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::audit::{chain_files, AuditFilter};
+
+/// Aggregates over the audit log
+#[derive(Serialize, Default)]
+struct AuditStats {
+    entries: usize,
+    first: Option<String>,
+    last: Option<String>,
+    tools: BTreeMap<String, ToolStats>,
+    actions: BTreeMap<String, usize>,
+    /// Policy results of request entries
+    decisions: BTreeMap<String, usize>,
+    /// Reasons given on denials
+    deny_reasons: BTreeMap<String, usize>,
+    buckets: BTreeMap<String, DecisionCounts>,
+    latency: Latency,
+    top_denied: Vec<Denied>,
+    /// Exit codes of CLI runs and interactive sessions
+    exit_codes: BTreeMap<i64, usize>,
+    http_statuses: BTreeMap<i64, usize>,
+    rate_limit_hits: BTreeMap<String, usize>,
+}
+
+#[derive(Serialize, Default)]
+struct ToolStats {
+    #[serde(flatten)]
+    requests: DecisionCounts,
+    latency: Latency,
+}
+
+/// Request entries (`cli` and `http`), by how the policy decided them
+#[derive(Serialize, Default)]
+struct DecisionCounts {
+    requests: usize,
+    allowed: usize,
+    denied: usize,
+}
+
+impl DecisionCounts {
+    fn add(&mut self, entry: &Value) {
+        self.requests += 1;
+        match str_field(entry, "policy_result") {
+            Some("allow") => self.allowed += 1,
+            Some("deny") => self.denied += 1,
+            _ => {}
+        }
+    }
+}
+
+/// Nearest-rank percentiles of `latency_ms`, in milliseconds
+#[derive(Serialize, Default)]
+struct Latency {
+    count: usize,
+    p50: Option<u64>,
+    p90: Option<u64>,
+    p95: Option<u64>,
+    p99: Option<u64>,
+    max: Option<u64>,
+}
+
+impl Latency {
+    fn from_samples(mut samples: Vec<u64>) -> Self {
+        samples.sort_unstable();
+        let percentile = |p: usize| {
+            let rank = (p * samples.len()).div_ceil(100).max(1);
+            samples.get(rank - 1).copied()
+        };
+        Latency {
+            count: samples.len(),
+            p50: percentile(50),
+            p90: percentile(90),
+            p95: percentile(95),
+            p99: percentile(99),
+            max: samples.last().copied(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Denied {
+    pattern: String,
+    count: usize,
+}
+
+fn str_field<'a>(entry: &'a Value, name: &str) -> Option<&'a str> {
+    entry.get(name).and_then(|v| v.as_str())
+}
+
+/// Start of the time bucket a timestamp falls in
+fn bucket_of(timestamp: &DateTime<Utc>, bucket: &str) -> String {
+    match bucket {
+        "hour" => timestamp.format("%Y-%m-%dT%H:00Z").to_string(),
+        "week" => timestamp.format("%G-W%V").to_string(),
+        _ => timestamp.format("%Y-%m-%d").to_string(),
+    }
+}
+
+/// What a denied request asked for: its first arguments when they were
+/// logged, else the rule and pattern that denied it
+fn denied_pattern(entry: &Value) -> String {
+    let tool = str_field(entry, "tool").unwrap_or("unknown");
+    if let Some(argv) = entry.get("argv").and_then(|v| v.as_array()) {
+        let args: Vec<&str> = argv.iter().take(2).filter_map(|v| v.as_str()).collect();
+        return format!("{} {}", tool, args.join(" "));
+    }
+    let decision = entry.get("decision");
+    let rule = decision.and_then(|d| str_field(d, "rule"));
+    let matched = decision.and_then(|d| str_field(d, "matched"));
+    match (rule, matched) {
+        (Some(rule), Some(matched)) => format!("{} {}: {}", tool, rule, matched),
+        (Some(rule), None) => format!("{} {}", tool, rule),
+        _ => format!(
+            "{} {}",
+            tool,
+            str_field(entry, "reason").unwrap_or("unknown")
+        ),
+    }
+}
+
+/// Running totals, turned into `AuditStats` once every entry is in
+struct Aggregate<'a> {
+    stats: AuditStats,
+    bucket: &'a str,
+    latencies: Vec<u64>,
+    tool_latencies: HashMap<String, Vec<u64>>,
+    denied: HashMap<String, usize>,
+}
+
+impl<'a> Aggregate<'a> {
+    fn new(bucket: &'a str) -> Self {
+        Aggregate {
+            stats: AuditStats::default(),
+            bucket,
+            latencies: Vec::new(),
+            tool_latencies: HashMap::new(),
+            denied: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, entry: &Value) {
+        let stats = &mut self.stats;
+        let action = str_field(entry, "action_type").unwrap_or("unknown");
+        stats.entries += 1;
+        *stats.actions.entry(action.to_string()).or_default() += 1;
+        let timestamp = str_field(entry, "timestamp")
+            .and_then(|ts| DateTime::parse_from_rfc3339(ts).ok())
+            .map(|ts| ts.with_timezone(&Utc));
+        if let Some(ts) = str_field(entry, "timestamp") {
+            stats.first.get_or_insert_with(|| ts.to_string());
+            stats.last = Some(ts.to_string());
+        }
+
+        let tool = str_field(entry, "tool").unwrap_or("unknown").to_string();
+        let result = str_field(entry, "policy_result").unwrap_or("");
+        let reason = str_field(entry, "reason");
+
+        if matches!(action, "cli" | "http") {
+            stats
+                .tools
+                .entry(tool.clone())
+                .or_default()
+                .requests
+                .add(entry);
+            *stats.decisions.entry(result.to_string()).or_default() += 1;
+            if let Some(ts) = &timestamp {
+                stats
+                    .buckets
+                    .entry(bucket_of(ts, self.bucket))
+                    .or_default()
+                    .add(entry);
+            }
+            if result == "deny" {
+                *self.denied.entry(denied_pattern(entry)).or_default() += 1;
+            }
+        }
+        if result == "deny" {
+            if let Some(reason) = reason {
+                *stats.deny_reasons.entry(reason.to_string()).or_default() += 1;
+            }
+        }
+        if reason == Some("rate_limit_exceeded") {
+            *stats.rate_limit_hits.entry(tool.clone()).or_default() += 1;
+        }
+
+        // Only the entry closing a request times the whole of it
+        if matches!(action, "cli_response" | "http_response") {
+            if let Some(latency) = entry.get("latency_ms").and_then(|v| v.as_u64()) {
+                self.latencies.push(latency);
+                self.tool_latencies.entry(tool).or_default().push(latency);
+            }
+        }
+        if let Some(code) = entry.get("exit_code").and_then(|v| v.as_i64()) {
+            match action {
+                "cli_response" | "pty_session" => *stats.exit_codes.entry(code).or_default() += 1,
+                "http_response" => *stats.http_statuses.entry(code).or_default() += 1,
+                _ => {}
+            }
+        }
+    }
+
+    /// The stats, with the `top` most denied patterns
+    fn finish(self, top: usize) -> AuditStats {
+        let mut stats = self.stats;
+        stats.latency = Latency::from_samples(self.latencies);
+        for (tool, samples) in self.tool_latencies {
+            stats.tools.entry(tool).or_default().latency = Latency::from_samples(samples);
+        }
+        let mut denied: Vec<Denied> = self
+            .denied
+            .into_iter()
+            .map(|(pattern, count)| Denied { pattern, count })
+            .collect();
+        denied.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then_with(|| a.pattern.cmp(&b.pattern))
+        });
+        denied.truncate(top);
+        stats.top_denied = denied;
+        stats
+    }
+}
+
+/// Aggregate the audit log, rotated files included
+pub fn stats(
+    file: &Path,
+    filter: &AuditFilter,
+    bucket: &str,
+    top: usize,
+    format: &str,
+) -> Result<()> {
+    if !matches!(bucket, "hour" | "day" | "week") {
+        return Err(anyhow!(
+            "Invalid bucket '{}': expected hour, day or week",
+            bucket
+        ));
+    }
+    let files = chain_files(file);
+    if files.is_empty() {
+        println!("Audit log file not found: {}", file.display());
+        return Ok(());
+    }
+
+    let mut aggregate = Aggregate::new(bucket);
+    for path in files {
+        for line in BufReader::new(File::open(&path)?).lines() {
+            let line = line?;
+            let Ok(entry) = serde_json::from_str::<Value>(&line) else {
+                continue;
+            };
+            if str_field(&entry, "action_type") == Some("checkpoint") || !filter.matches(&entry) {
+                continue;
+            }
+            aggregate.add(&entry);
+        }
+    }
+    let stats = aggregate.finish(top);
+
+    match format {
+        "json" => println!("{}", serde_json::to_string_pretty(&stats)?),
+        "csv" => print_stats_csv(&stats),
+        _ => print_stats_text(&stats, bucket),
+    }
+    Ok(())
+}
+
+fn fmt_ms(value: Option<u64>) -> String {
+    value.map_or("-".to_string(), |ms| format!("{}ms", ms))
+}
+
+fn print_stats_text(stats: &AuditStats, bucket: &str) {
+    println!("=== Audit Log Statistics ===");
+    println!(
+        "{} entries, {} to {}",
+        stats.entries,
+        stats.first.as_deref().unwrap_or("-"),
+        stats.last.as_deref().unwrap_or("-")
+    );
+
+    println!("\n--- By tool (most used first) ---");
+    println!(
+        "{:<20} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        "Tool", "Requests", "Allowed", "Denied", "p50", "p95", "p99"
+    );
+    let mut tools: Vec<_> = stats.tools.iter().collect();
+    tools.sort_by_key(|(_, t)| std::cmp::Reverse(t.requests.requests));
+    for (tool, t) in tools {
+        println!(
+            "{:<20} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}",
+            tool,
+            t.requests.requests,
+            t.requests.allowed,
+            t.requests.denied,
+            fmt_ms(t.latency.p50),
+            fmt_ms(t.latency.p95),
+            fmt_ms(t.latency.p99)
+        );
+    }
+
+    println!("\n--- By action ---");
+    for (action, count) in &stats.actions {
+        println!("{:<20} {:>9}", action, count);
+    }
+
+    println!("\n--- By decision ---");
+    for (result, count) in &stats.decisions {
+        println!("{:<20} {:>9}", result, count);
+    }
+    for (reason, count) in &stats.deny_reasons {
+        println!("  deny: {:<32} {:>9}", reason, count);
+    }
+
+    println!("\n--- By {} ---", bucket);
+    println!(
+        "{:<20} {:>9} {:>9} {:>9}",
+        "Bucket", "Requests", "Allowed", "Denied"
+    );
+    for (start, counts) in &stats.buckets {
+        println!(
+            "{:<20} {:>9} {:>9} {:>9}",
+            start, counts.requests, counts.allowed, counts.denied
+        );
+    }
+
+    let l = &stats.latency;
+    println!("\n--- Latency ({} samples) ---", l.count);
+    println!(
+        "p50 {}  p90 {}  p95 {}  p99 {}  max {}",
+        fmt_ms(l.p50),
+        fmt_ms(l.p90),
+        fmt_ms(l.p95),
+        fmt_ms(l.p99),
+        fmt_ms(l.max)
+    );
+
+    println!("\n--- Top denied ---");
+    for denied in &stats.top_denied {
+        println!("{:>6}  {}", denied.count, denied.pattern);
+    }
+
+    println!("\n--- Exit codes ---");
+    for (code, count) in &stats.exit_codes {
+        println!("{:<20} {:>9}", code, count);
+    }
+    if !stats.http_statuses.is_empty() {
+        println!("\n--- HTTP statuses ---");
+        for (status, count) in &stats.http_statuses {
+            println!("{:<20} {:>9}", status, count);
+        }
+    }
+
+    println!("\n--- Rate limit hits ---");
+    for (tool, count) in &stats.rate_limit_hits {
+        println!("{:<20} {:>9}", tool, count);
+    }
+}
+
+/// Quote a CSV field when it needs it
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// One `section,key,metric,value` row per number, for spreadsheets
+fn print_stats_csv(stats: &AuditStats) {
+    let row = |section: &str, key: &str, metric: &str, value: String| {
+        println!(
+            "{},{},{},{}",
+            section,
+            csv_field(key),
+            metric,
+            csv_field(&value)
+        );
+    };
+    let latency_rows = |section: &str, key: &str, l: &Latency| {
+        row(section, key, "latency_count", l.count.to_string());
+        for (metric, value) in [
+            ("latency_p50_ms", l.p50),
+            ("latency_p90_ms", l.p90),
+            ("latency_p95_ms", l.p95),
+            ("latency_p99_ms", l.p99),
+            ("latency_max_ms", l.max),
+        ] {
+            if let Some(value) = value {
+                row(section, key, metric, value.to_string());
+            }
+        }
+    };
+
+    println!("section,key,metric,value");
+    row("total", "", "entries", stats.entries.to_string());
+    for (tool, t) in &stats.tools {
+        row("tool", tool, "requests", t.requests.requests.to_string());
+        row("tool", tool, "allowed", t.requests.allowed.to_string());
+        row("tool", tool, "denied", t.requests.denied.to_string());
+        latency_rows("tool", tool, &t.latency);
+    }
+    for (action, count) in &stats.actions {
+        row("action", action, "entries", count.to_string());
+    }
+    for (result, count) in &stats.decisions {
+        row("decision", result, "requests", count.to_string());
+    }
+    for (reason, count) in &stats.deny_reasons {
+        row("deny_reason", reason, "entries", count.to_string());
+    }
+    for (start, counts) in &stats.buckets {
+        row("bucket", start, "requests", counts.requests.to_string());
+        row("bucket", start, "allowed", counts.allowed.to_string());
+        row("bucket", start, "denied", counts.denied.to_string());
+    }
+    latency_rows("latency", "", &stats.latency);
+    for denied in &stats.top_denied {
+        row(
+            "top_denied",
+            &denied.pattern,
+            "requests",
+            denied.count.to_string(),
+        );
+    }
+    for (code, count) in &stats.exit_codes {
+        row("exit_code", &code.to_string(), "entries", count.to_string());
+    }
+    for (status, count) in &stats.http_statuses {
+        row(
+            "http_status",
+            &status.to_string(),
+            "entries",
+            count.to_string(),
+        );
+    }
+    for (tool, count) in &stats.rate_limit_hits {
+        row("rate_limit", tool, "hits", count.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_latency_percentiles() {
+        let latency = Latency::from_samples((1..=100).rev().collect());
+        assert_eq!(latency.count, 100);
+        assert_eq!(
+            [
+                latency.p50,
+                latency.p90,
+                latency.p95,
+                latency.p99,
+                latency.max
+            ],
+            [Some(50), Some(90), Some(95), Some(99), Some(100)]
+        );
+
+        // Nearest rank: the smallest sample covering the percentile
+        let latency = Latency::from_samples(vec![30, 10, 20]);
+        assert_eq!(
+            [latency.p50, latency.p90, latency.max],
+            [Some(20), Some(30), Some(30)]
+        );
+        let latency = Latency::from_samples(vec![7]);
+        assert_eq!([latency.p50, latency.p99], [Some(7), Some(7)]);
+
+        let latency = Latency::from_samples(Vec::new());
+        assert_eq!(latency.count, 0);
+        assert_eq!(latency.p50, None);
+    }
+
+    #[test]
+    fn test_grouping() {
+        let entries = [
+            json!({"action_type": "cli", "tool": "gh", "policy_result": "allow",
+                   "timestamp": "2026-03-01T10:15:00Z", "latency_ms": 1}),
+            json!({"action_type": "cli_response", "tool": "gh", "policy_result": "allow",
+                   "timestamp": "2026-03-01T10:15:01Z", "latency_ms": 40, "exit_code": 0}),
+            json!({"action_type": "cli", "tool": "gh", "policy_result": "deny",
+                   "timestamp": "2026-03-01T11:00:00Z", "reason": "argv_deny",
+                   "argv": ["pr", "merge", "1"]}),
+            json!({"action_type": "http", "tool": "signal", "policy_result": "allow",
+                   "timestamp": "2026-03-02T09:00:00Z"}),
+            json!({"action_type": "http_response", "tool": "signal", "policy_result": "allow",
+                   "timestamp": "2026-03-02T09:00:01Z", "latency_ms": 200, "exit_code": 200}),
+            json!({"action_type": "cli", "tool": "gh", "policy_result": "deny",
+                   "timestamp": "2026-03-02T09:30:00Z", "reason": "rate_limit_exceeded"}),
+        ];
+        let mut aggregate = Aggregate::new("day");
+        for entry in &entries {
+            aggregate.add(entry);
+        }
+        let stats = aggregate.finish(10);
+
+        assert_eq!(stats.entries, 6);
+        assert_eq!(stats.first.as_deref(), Some("2026-03-01T10:15:00Z"));
+        assert_eq!(stats.last.as_deref(), Some("2026-03-02T09:30:00Z"));
+        let gh = &stats.tools["gh"];
+        assert_eq!(
+            (
+                gh.requests.requests,
+                gh.requests.allowed,
+                gh.requests.denied
+            ),
+            (3, 1, 2)
+        );
+        assert_eq!(stats.tools["signal"].requests.requests, 1);
+        assert_eq!(stats.decisions["allow"], 2);
+        assert_eq!(stats.decisions["deny"], 2);
+        assert_eq!(stats.deny_reasons["argv_deny"], 1);
+        assert_eq!(stats.rate_limit_hits["gh"], 1);
+        let days: Vec<(&str, usize)> = stats
+            .buckets
+            .iter()
+            .map(|(day, counts)| (day.as_str(), counts.requests))
+            .collect();
+        assert_eq!(days, [("2026-03-01", 2), ("2026-03-02", 2)]);
+        assert_eq!(stats.top_denied[0].pattern, "gh pr merge");
+        assert_eq!(stats.exit_codes[&0], 1);
+        assert_eq!(stats.http_statuses[&200], 1);
+
+        // Latency from the response entries only, not the request's own
+        assert_eq!(stats.latency.count, 2);
+        assert_eq!(stats.latency.max, Some(200));
+        assert_eq!(stats.tools["gh"].latency.count, 1);
+        assert_eq!(stats.tools["gh"].latency.p50, Some(40));
+
+        let mut aggregate = Aggregate::new("hour");
+        for entry in &entries {
+            aggregate.add(entry);
+        }
+        let hours: Vec<String> = aggregate.finish(10).buckets.into_keys().collect();
+        assert_eq!(
+            hours,
+            [
+                "2026-03-01T10:00Z",
+                "2026-03-01T11:00Z",
+                "2026-03-02T09:00Z"
+            ]
+        );
+    }
+}
@@ -0,0 +1,177 @@
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use carapace_protocol::capture::{capture_file_prefix, unseal};
+use serde_json::Value;
+use std::path::Path;
+
+/// Decrypt and print the captures of a request id, oldest first. Ids are
+/// only unique within a connection, so there may be several.
+pub fn show(dir: &Path, key_file: &Path, request_id: &str, format: &str) -> Result<()> {
+    let records = load(dir, &load_key(key_file)?, request_id)?;
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&records)?);
+    } else {
+        for (i, record) in records.iter().enumerate() {
+            if i > 0 {
+                println!();
+            }
+            print_capture(record);
+        }
+    }
+    Ok(())
+}
+
+/// Decrypt the captures of a request id, oldest first
+fn load(dir: &Path, key: &[u8; 32], request_id: &str) -> Result<Vec<Value>> {
+    let prefix = format!("{}-", capture_file_prefix(request_id));
+    let mut records = Vec::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Cannot read {}", dir.display()))? {
+        let path = entry?.path();
+        let name = path.file_name().and_then(|name| name.to_str());
+        if !name.is_some_and(|name| name.starts_with(&prefix) && name.ends_with(".cap")) {
+            continue;
+        }
+        let sealed = std::fs::read(&path)?;
+        let plaintext =
+            unseal(key, &sealed).with_context(|| format!("Cannot read {}", path.display()))?;
+        let record: Value = serde_json::from_slice(&plaintext)
+            .with_context(|| format!("Capture {} is not a JSON record", path.display()))?;
+        records.push(record);
+    }
+    if records.is_empty() {
+        return Err(anyhow!(
+            "No capture for request {} in {}",
+            request_id,
+            dir.display()
+        ));
+    }
+    records.sort_by(|a, b| {
+        let timestamp = |record: &Value| {
+            record
+                .get("timestamp")
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+        };
+        timestamp(a).cmp(&timestamp(b))
+    });
+    Ok(records)
+}
+
+fn load_key(path: &Path) -> Result<[u8; 32]> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Cannot read capture key {}", path.display()))?;
+    BASE64
+        .decode(text.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| anyhow!("Capture key {} is not a base64 32-byte key", path.display()))
+}
+
+fn print_capture(record: &Value) {
+    let field = |name: &str| record.get(name).and_then(|v| v.as_str()).unwrap_or("-");
+    println!("=== Capture {} ===", field("request_id"));
+    println!("Tool:      {}", field("tool"));
+    println!("Time:      {}", field("timestamp"));
+    if let Some(session) = record.get("session_id").and_then(|v| v.as_str()) {
+        println!("Session:   {}", session);
+    }
+
+    if let Some(argv) = record.get("argv").and_then(|v| v.as_array()) {
+        let args: Vec<&str> = argv.iter().filter_map(|v| v.as_str()).collect();
+        println!("Argv:      {}", args.join(" "));
+    }
+    if let Some(code) = record.get("exit_code") {
+        println!("Exit code: {}", code);
+    }
+    if let Some(method) = record.get("method").and_then(|v| v.as_str()) {
+        println!("Request:   {} {}", method, field("path"));
+    }
+    if let Some(status) = record.get("status") {
+        println!("Status:    {}", status);
+    }
+
+    print_headers("Request headers", record.get("request_headers"));
+    print_payload("stdin", record.get("stdin"));
+    print_payload("Request body", record.get("request_body"));
+    print_payload("stdout", record.get("stdout"));
+    print_payload("stderr", record.get("stderr"));
+    print_headers("Response headers", record.get("response_headers"));
+    print_payload("Response body", record.get("response_body"));
+
+    if let Some(events) = record.get("sse_events").and_then(|v| v.as_array()) {
+        println!("\n--- SSE events ({}) ---", events.len());
+        for event in events {
+            println!(
+                "{}: {}",
+                event.get("event").and_then(|v| v.as_str()).unwrap_or(""),
+                event.get("data").and_then(|v| v.as_str()).unwrap_or("")
+            );
+        }
+    }
+}
+
+fn print_headers(title: &str, headers: Option<&Value>) {
+    let Some(headers) = headers.and_then(|v| v.as_object()) else {
+        return;
+    };
+    println!("\n--- {} ---", title);
+    for (name, value) in headers {
+        println!("{}: {}", name, value.as_str().unwrap_or(""));
+    }
+}
+
+fn print_payload(title: &str, payload: Option<&Value>) {
+    let Some(payload) = payload else {
+        return;
+    };
+    let data = payload.get("data").and_then(|v| v.as_str()).unwrap_or("");
+    if payload.get("encoding").and_then(|v| v.as_str()) == Some("base64") {
+        println!("\n--- {} (binary, base64) ---", title);
+    } else {
+        println!("\n--- {} ({} bytes) ---", title, data.len());
+    }
+    println!("{}", data.trim_end_matches('\n'));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use carapace_server::capture::{CaptureRecord, CaptureStore, Payload};
+
+    #[test]
+    fn test_reads_what_the_server_stores() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = [7u8; 32];
+        let store = CaptureStore::open(dir.path(), key, 1 << 20).unwrap();
+        for (timestamp, session_id) in [
+            ("2026-10-18T12:00:01Z", "session-b"),
+            ("2026-10-18T12:00:00Z", "session-a"),
+        ] {
+            store
+                .save(&CaptureRecord {
+                    request_id: "1".to_string(),
+                    tool: "gh".to_string(),
+                    timestamp: timestamp.to_string(),
+                    session_id: Some(session_id.to_string()),
+                    argv: Some(vec!["pr".to_string(), "list".to_string()]),
+                    exit_code: Some(0),
+                    stdout: Some(Payload::text(format!("from {}", session_id))),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let key_file = dir.path().join("capture.key");
+        std::fs::write(&key_file, BASE64.encode(key)).unwrap();
+
+        let records = load(dir.path(), &load_key(&key_file).unwrap(), "1").unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["session_id"], "session-a");
+        assert_eq!(records[0]["stdout"]["data"], "from session-a");
+        assert_eq!(records[1]["argv"], serde_json::json!(["pr", "list"]));
+        show(dir.path(), &key_file, "1", "text").unwrap();
+
+        assert!(load(dir.path(), &[8u8; 32], "1").is_err());
+        assert!(show(dir.path(), &key_file, "2", "text").is_err());
+    }
+}
@@ -2,10 +2,15 @@ use anyhow::Result;
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 
+mod approvals;
 mod audit;
+mod audit_stats;
+mod capture;
 mod connections;
 mod health;
 mod policy;
+mod policy_replay;
+mod policy_test;
 
 #[derive(Parser)]
 #[command(name = "carapace-debug")]
@@ -52,34 +57,55 @@ enum Commands {
         format: String,
     },
 
-    /// Query audit logs
+    /// List requests held for approval, or approve or deny one
+    Approvals {
+        #[command(subcommand)]
+        action: Option<ApprovalAction>,
+
+        /// Server host (default: localhost)
+        #[arg(long, default_value = "localhost", global = true)]
+        host: String,
+
+        /// Debug server port (default: 8766)
+        #[arg(long, default_value = "8766", global = true)]
+        port: u16,
+
+        /// Output format: json, text (default: text)
+        #[arg(long, default_value = "text", global = true)]
+        format: String,
+    },
+
+    /// Query audit logs, or verify their hash chain
     Audit {
+        #[command(subcommand)]
+        command: Option<AuditAction>,
+
         /// Audit log file (default: /var/log/carapace/audit.log)
-        #[arg(long, default_value = "/var/log/carapace/audit.log")]
+        #[arg(long, default_value = "/var/log/carapace/audit.log", global = true)]
         file: PathBuf,
 
         /// Filter by tool name
-        #[arg(long)]
+        #[arg(long, global = true)]
         tool: Option<String>,
 
         /// Filter by action type (cli, http)
-        #[arg(long)]
+        #[arg(long, global = true)]
         action: Option<String>,
 
         /// Filter by policy result (allow, deny)
-        #[arg(long)]
+        #[arg(long, global = true)]
         result: Option<String>,
 
         /// Time range: "5m", "1h", "24h" (default: all)
-        #[arg(long)]
+        #[arg(long, global = true)]
         since: Option<String>,
 
-        /// Follow mode: tail new entries
+        /// Keep printing new entries as they are written, across log rotations
         #[arg(long)]
         follow: bool,
 
-        /// Output format: json, text (default: text)
-        #[arg(long, default_value = "text")]
+        /// Output format: json, text, csv for stats (default: text)
+        #[arg(long, default_value = "text", global = true)]
         format: String,
 
         /// Limit number of results
@@ -87,20 +113,153 @@ enum Commands {
         limit: usize,
     },
 
+    /// Read back payloads kept by forensic capture
+    Capture {
+        #[command(subcommand)]
+        command: CaptureAction,
+
+        /// Capture store directory (the server's CARAPACE_CAPTURE_DIR)
+        #[arg(long, default_value = "/var/lib/carapace/captures", global = true)]
+        dir: PathBuf,
+
+        /// Key file (the server's CARAPACE_CAPTURE_KEY)
+        #[arg(long, default_value = "/etc/carapace/capture.key", global = true)]
+        key: PathBuf,
+
+        /// Output format: json, text (default: text)
+        #[arg(long, default_value = "text", global = true)]
+        format: String,
+    },
+
     /// Test policy decisions
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
     Policy {
+        #[command(subcommand)]
+        command: Option<PolicyAction>,
+
         /// Policy file to test
-        policy: PathBuf,
+        #[arg(required = true)]
+        policy: Option<PathBuf>,
 
         /// Request JSON file or inline JSON
-        request: String,
+        #[arg(required = true)]
+        request: Option<String>,
 
-        /// Output format: json, text (default: text)
-        #[arg(long, default_value = "text")]
+        /// Output format: json, text, junit for test (default: text)
+        #[arg(long, default_value = "text", global = true)]
         format: String,
     },
 }
 
+#[derive(Subcommand)]
+enum ApprovalAction {
+    /// List pending requests (default)
+    List,
+
+    /// Let a pending request run
+    Approve {
+        /// Approval ID, as `approvals` lists it
+        approval_id: String,
+
+        /// File holding this operator's approver token (default:
+        /// $CARAPACE_APPROVAL_TOKEN_FILE)
+        #[arg(long)]
+        token_file: Option<PathBuf>,
+    },
+
+    /// Reject a pending request
+    Deny {
+        /// Approval ID, as `approvals` lists it
+        approval_id: String,
+
+        /// File holding this operator's approver token (default:
+        /// $CARAPACE_APPROVAL_TOKEN_FILE)
+        #[arg(long)]
+        token_file: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum AuditAction {
+    /// Check the hash chain across the current and rotated logs
+    Verify {
+        /// Base64 public key for checking signed checkpoints
+        #[arg(long)]
+        public_key: Option<String>,
+    },
+
+    /// Create a key for signing checkpoints (CARAPACE_AUDIT_SIGNING_KEY)
+    Keygen {
+        /// Where to write the private key
+        #[arg(long)]
+        out: PathBuf,
+    },
+
+    /// Summarize the current and rotated logs: counts by tool, action,
+    /// decision and time, latency percentiles, top denials, exit codes
+    Stats {
+        /// Time bucket: hour, day, week
+        #[arg(long, default_value = "day")]
+        bucket: String,
+
+        /// How many denied patterns to list
+        #[arg(long, default_value = "10")]
+        top: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum PolicyAction {
+    /// Re-evaluate recorded requests against a candidate policy and show
+    /// which decisions change; exits 1 if any does, 2 if the replay cannot
+    /// run (an unreadable file, an invalid policy)
+    Replay {
+        /// Candidate policy
+        #[arg(long)]
+        policy: PathBuf,
+
+        /// Audit log to replay, with its rotated files
+        #[arg(long)]
+        audit: Option<PathBuf>,
+
+        /// JSON lines of requests, as `policy` takes them, each with an
+        /// optional "allowed" for the decision it got
+        #[arg(long)]
+        requests: Option<PathBuf>,
+
+        /// Compare with this policy's decisions instead of the recorded ones
+        #[arg(long)]
+        baseline: Option<PathBuf>,
+    },
+
+    /// Run policy test files (<policy>.test.yaml) and report each case;
+    /// exits 1 if any fails, 2 if the tests cannot run (an unreadable or
+    /// invalid test file or policy)
+    Test {
+        /// Test files, or directories to take *.test.yaml files from
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum CaptureAction {
+    /// Decrypt and print what a request sent and got back
+    Show {
+        /// Request ID
+        request_id: String,
+    },
+}
+
+/// Print the error and exit 2, for commands whose exit 1 reports what
+/// they found rather than a failure to run
+fn exit_2_on_error(result: Result<()>) {
+    if let Err(e) = result {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(2);
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
@@ -117,7 +276,58 @@ async fn main() -> Result<()> {
         } => {
             connections::connections(&host, port, watch, &format).await?;
         }
+        Commands::Approvals {
+            action,
+            host,
+            port,
+            format,
+        } => match action.unwrap_or(ApprovalAction::List) {
+            ApprovalAction::List => approvals::list(&host, port, &format).await?,
+            ApprovalAction::Approve {
+                approval_id,
+                token_file,
+            } => {
+                let token = approvals::read_token(token_file)?;
+                approvals::decide(&host, port, &approval_id, true, &token, &format).await?;
+            }
+            ApprovalAction::Deny {
+                approval_id,
+                token_file,
+            } => {
+                let token = approvals::read_token(token_file)?;
+                approvals::decide(&host, port, &approval_id, false, &token, &format).await?;
+            }
+        },
+        Commands::Audit {
+            command: Some(AuditAction::Verify { public_key }),
+            file,
+            format,
+            ..
+        } => {
+            audit::verify(&file, public_key, &format)?;
+        }
+        Commands::Audit {
+            command: Some(AuditAction::Keygen { out }),
+            format,
+            ..
+        } => {
+            audit::keygen(&out, &format)?;
+        }
+        Commands::Audit {
+            command: Some(AuditAction::Stats { bucket, top }),
+            file,
+            tool,
+            action,
+            result,
+            since,
+            format,
+            ..
+        } => {
+            let filter = audit::AuditFilter::new(tool, action, result, since);
+            audit_stats::stats(&file, &filter, &bucket, top, &format)?;
+        }
         Commands::Audit {
+            command: None,
             file,
             tool,
             action,
@@ -129,13 +339,49 @@ async fn main() -> Result<()> {
         } => {
             audit::audit(&file, tool, action, result, since, follow, &format, limit).await?;
         }
+        Commands::Capture {
+            command: CaptureAction::Show { request_id },
+            dir,
+            key,
+            format,
+        } => {
+            capture::show(&dir, &key, &request_id, &format)?;
+        }
+        Commands::Policy {
+            command:
+                Some(PolicyAction::Replay {
+                    policy,
+                    audit,
+                    requests,
+                    baseline,
+                }),
+            format,
+            ..
+        } => {
+            exit_2_on_error(policy_replay::replay(
+                &policy,
+                audit.as_deref(),
+                requests.as_deref(),
+                baseline.as_deref(),
+                &format,
+            ));
+        }
+        Commands::Policy {
+            command: Some(PolicyAction::Test { files }),
+            format,
+            ..
+        } => {
+            exit_2_on_error(policy_test::test(&files, &format));
+        }
         Commands::Policy {
-            policy,
-            request,
+            command: None,
+            policy: Some(policy),
+            request: Some(request),
             format,
         } => {
             policy::policy(&policy, &request, &format).await?;
         }
+        Commands::Policy { .. } => unreachable!("clap requires a policy and a request"),
     }
 
     Ok(())
@@ -1,18 +1,21 @@
 use anyhow::{anyhow, Result};
-use carapace_policy::{ArgvMatcher, PolicyConfig, PolicyValidator};
+use carapace_policy::PolicyConfig;
 use serde_json::json;
 use std::fs;
 use std::path::Path;
 
-/// Test a policy decision without running the full system
-pub async fn policy(policy_file: &Path, request_json: &str, format: &str) -> Result<()> {
-    // Load policy
-    let policy = PolicyConfig::from_file(
+pub(crate) fn load_policy(policy_file: &Path) -> Result<PolicyConfig> {
+    PolicyConfig::from_file(
         policy_file
             .to_str()
             .ok_or_else(|| anyhow!("Invalid policy file path"))?,
     )
-    .map_err(|e| anyhow!("Failed to load policy: {}", e))?;
+    .map_err(|e| anyhow!("Failed to load policy {}: {}", policy_file.display(), e))
+}
+
+/// Test a policy decision without running the full system
+pub async fn policy(policy_file: &Path, request_json: &str, format: &str) -> Result<()> {
+    let policy = load_policy(policy_file)?;
 
     // Parse request JSON
     let request: serde_json::Value = if request_json.starts_with('{') {
@@ -63,62 +66,17 @@ fn test_jsonrpc_method(
         .and_then(|v| v.as_str())
         .ok_or_else(|| anyhow!("Request must have 'method' field"))?;
 
+    // The request doubles as the JSON-RPC body, as the server sees it
     let body = serde_json::to_string(request)?;
-
-    // Get tool policy
-    let tool_config = policy
-        .tools
-        .get(tool)
-        .ok_or_else(|| anyhow!("Tool '{}' not in policy", tool))?;
-
-    let http_policy = match tool_config {
-        carapace_policy::ToolPolicy::Http(policy) => policy,
-        carapace_policy::ToolPolicy::Cli(_) => {
-            return Ok(json!({
-                "allowed": false,
-                "reason": format!("Tool '{}' is CLI-only, cannot handle HTTP request", tool),
-                "tool": tool,
-                "method": method,
-            }))
-        }
-    };
-
-    // Validate method
-    match PolicyValidator::validate_jsonrpc_method(
-        method,
-        &http_policy.jsonrpc_allow_methods,
-        &http_policy.jsonrpc_deny_methods,
-    ) {
-        Ok(()) => {}
-        Err(e) => {
-            return Ok(json!({
-                "allowed": false,
-                "reason": format!("Method denied: {}", e),
-                "tool": tool,
-                "method": method,
-            }))
-        }
-    }
-
-    // Validate params
-    match PolicyValidator::validate_jsonrpc_params(
-        method,
-        &body,
-        &http_policy.jsonrpc_param_filters,
-    ) {
-        Ok(()) => Ok(json!({
-            "allowed": true,
-            "reason": "Both method and params passed policy validation",
-            "tool": tool,
-            "method": method,
-        })),
-        Err(e) => Ok(json!({
-            "allowed": false,
-            "reason": format!("Params denied: {}", e),
-            "tool": tool,
-            "method": method,
-        })),
-    }
+    let decision = policy.decide_http(tool, Some(body.as_bytes()));
+
+    Ok(json!({
+        "allowed": decision.allowed,
+        "reason": decision.to_string(),
+        "tool": tool,
+        "method": method,
+        "decision": decision,
+    }))
 }
 
 fn test_cli_argv(policy: &PolicyConfig, request: &serde_json::Value) -> Result<serde_json::Value> {
@@ -137,45 +95,15 @@ fn test_cli_argv(policy: &PolicyConfig, request: &serde_json::Value) -> Result<s
         .filter_map(|v| v.as_str().map(|s| s.to_string()))
         .collect();
 
-    // Get tool policy
-    let tool_config = policy
-        .tools
-        .get(tool)
-        .ok_or_else(|| anyhow!("Tool '{}' not in policy", tool))?;
-
-    let cli_policy = match tool_config {
-        carapace_policy::ToolPolicy::Cli(policy) => policy,
-        carapace_policy::ToolPolicy::Http(_) => {
-            return Ok(json!({
-                "allowed": false,
-                "reason": format!("Tool '{}' is HTTP-only, cannot handle CLI request", tool),
-                "tool": tool,
-                "argv": argv_strings,
-            }))
-        }
-    };
+    let decision = policy.decide_cli(tool, &argv_strings);
 
-    // Validate argv using ArgvMatcher
-    match ArgvMatcher::new(
-        cli_policy.argv_allow_patterns.clone(),
-        cli_policy.argv_deny_patterns.clone(),
-    ) {
-        Ok(matcher) => {
-            let allowed = matcher.matches(&argv_strings);
-            Ok(json!({
-                "allowed": allowed,
-                "reason": if allowed { "Argv passed policy validation" } else { "Argv denied by policy" },
-                "tool": tool,
-                "argv": argv_strings,
-            }))
-        }
-        Err(e) => Ok(json!({
-            "allowed": false,
-            "reason": format!("Error creating matcher: {}", e),
-            "tool": tool,
-            "argv": argv_strings,
-        })),
-    }
+    Ok(json!({
+        "allowed": decision.allowed,
+        "reason": decision.to_string(),
+        "tool": tool,
+        "argv": argv_strings,
+        "decision": decision,
+    }))
 }
 
 fn print_policy_result(result: &serde_json::Value) {
@@ -202,6 +130,23 @@ fn print_policy_result(result: &serde_json::Value) {
     );
     println!("Reason: {}", reason);
 
+    if let Some(decision) = result.get("decision") {
+        let field = |name: &str| decision.get(name).and_then(|v| v.as_str());
+        if let Some(rule) = field("rule") {
+            println!("Rule: {}", rule);
+        }
+        if let Some(matched) = field("matched") {
+            println!("Matched: {}", matched);
+        }
+        if let Some(location) = decision.get("location") {
+            println!(
+                "Location: {}:{}",
+                location.get("file").and_then(|v| v.as_str()).unwrap_or(""),
+                location.get("line").and_then(|v| v.as_u64()).unwrap_or(0)
+            );
+        }
+    }
+
     if let Some(method) = result.get("method").and_then(|v| v.as_str()) {
         println!("Method: {}", method);
     }
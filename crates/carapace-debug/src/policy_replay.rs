@@ -0,0 +1,403 @@
+use anyhow::{anyhow, Result};
+use carapace_policy::{Decision, PolicyConfig};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use crate::audit::chain_files;
+use crate::policy::load_policy;
+
+/// What the server logs in place of a redacted value
+const REDACTED: &str = "[REDACTED]";
+
+/// What a recorded request asked for, as the policy sees it
+enum Recorded {
+    Cli(Vec<String>),
+    /// JSON-RPC body
+    Http(Vec<u8>),
+}
+
+/// A request worth replaying, with the decision it got at the time
+struct Replayable {
+    request_id: Option<String>,
+    tool: String,
+    recorded: Recorded,
+    allowed: Option<bool>,
+}
+
+impl Replayable {
+    /// How the request reads in the report: the argv, or the JSON-RPC
+    /// method and params
+    fn describe(&self) -> String {
+        match &self.recorded {
+            Recorded::Cli(argv) => format!("{} {}", self.tool, argv.join(" ")),
+            Recorded::Http(body) => {
+                let body: Value = serde_json::from_slice(body).unwrap_or(Value::Null);
+                let method = body.get("method").and_then(|v| v.as_str()).unwrap_or("-");
+                match body.get("params") {
+                    Some(params) => format!("{} {} {}", self.tool, method, params),
+                    None => format!("{} {}", self.tool, method),
+                }
+            }
+        }
+    }
+}
+
+/// Requests whose decision changed, grouped when they are identical
+#[derive(Serialize)]
+struct Change {
+    tool: String,
+    request: String,
+    count: usize,
+    /// First request ID with this change, when recorded
+    request_id: Option<String>,
+    /// The candidate policy's decision
+    decision: Decision,
+}
+
+#[derive(Serialize, Default)]
+struct ReplayReport {
+    policy: String,
+    replayed: usize,
+    unchanged: usize,
+    /// Entries that could not be replayed, by why not
+    skipped: BTreeMap<String, usize>,
+    newly_denied: Vec<Change>,
+    newly_allowed: Vec<Change>,
+}
+
+/// Re-evaluate recorded requests against a candidate policy and report the
+/// decisions that change. Exits non-zero if any does.
+pub fn replay(
+    policy_file: &Path,
+    audit_file: Option<&Path>,
+    requests_file: Option<&Path>,
+    baseline_file: Option<&Path>,
+    format: &str,
+) -> Result<()> {
+    if audit_file.is_none() && requests_file.is_none() {
+        return Err(anyhow!("Nothing to replay: give --audit or --requests"));
+    }
+    let candidate = load_policy(policy_file)?;
+    let baseline = baseline_file.map(load_policy).transpose()?;
+
+    let mut inputs: Vec<PathBuf> = Vec::new();
+    if let Some(audit_file) = audit_file {
+        let files = chain_files(audit_file);
+        if files.is_empty() {
+            return Err(anyhow!("Audit log not found: {}", audit_file.display()));
+        }
+        inputs.extend(files);
+    }
+    inputs.extend(requests_file.map(Path::to_path_buf));
+
+    let mut report = ReplayReport {
+        policy: policy_file.display().to_string(),
+        ..Default::default()
+    };
+    let mut changes: BTreeMap<(bool, String, String), Change> = BTreeMap::new();
+    for path in inputs {
+        let reader = BufReader::new(
+            File::open(&path).map_err(|e| anyhow!("Cannot read {}: {}", path.display(), e))?,
+        );
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let Ok(record) = serde_json::from_str::<Value>(&line) else {
+                *report.skipped.entry("not JSON".to_string()).or_default() += 1;
+                continue;
+            };
+            let mut request = match replayable(&record) {
+                Ok(Some(request)) => request,
+                Ok(None) => continue,
+                Err(reason) => {
+                    *report.skipped.entry(reason.to_string()).or_default() += 1;
+                    continue;
+                }
+            };
+            if let Some(baseline) = &baseline {
+                request.allowed = Some(decide(baseline, &request).allowed);
+            }
+            let Some(before) = request.allowed else {
+                *report
+                    .skipped
+                    .entry("no recorded decision (give --baseline)".to_string())
+                    .or_default() += 1;
+                continue;
+            };
+
+            report.replayed += 1;
+            let decision = decide(&candidate, &request);
+            if decision.allowed == before {
+                report.unchanged += 1;
+                continue;
+            }
+            let description = request.describe();
+            let key = (decision.allowed, description.clone(), decision.to_string());
+            changes
+                .entry(key)
+                .or_insert_with(|| Change {
+                    tool: request.tool.clone(),
+                    request: description,
+                    count: 0,
+                    request_id: request.request_id.clone(),
+                    decision,
+                })
+                .count += 1;
+        }
+    }
+
+    for ((now_allowed, _, _), change) in changes {
+        if now_allowed {
+            report.newly_allowed.push(change);
+        } else {
+            report.newly_denied.push(change);
+        }
+    }
+    report
+        .newly_denied
+        .sort_by_key(|c| std::cmp::Reverse(c.count));
+    report
+        .newly_allowed
+        .sort_by_key(|c| std::cmp::Reverse(c.count));
+
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_replay_report(&report);
+    }
+
+    if !report.newly_denied.is_empty() || !report.newly_allowed.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn decide(policy: &PolicyConfig, request: &Replayable) -> Decision {
+    match &request.recorded {
+        Recorded::Cli(argv) => policy.decide_cli(&request.tool, argv),
+        Recorded::Http(body) => policy.decide_http(&request.tool, Some(body)),
+    }
+}
+
+/// The request in an audit entry or a request record. `None` for audit
+/// entries that record no request (responses, approvals, ...); an error
+/// naming what is missing for those that cannot be replayed.
+fn replayable(record: &Value) -> std::result::Result<Option<Replayable>, &'static str> {
+    let action = record.get("action_type").and_then(|v| v.as_str());
+    if action.is_some_and(|action| action != "cli" && action != "http") {
+        return Ok(None);
+    }
+    let tool = record
+        .get("tool")
+        .and_then(|v| v.as_str())
+        .ok_or("no tool")?
+        .to_string();
+    let request_id = record
+        .get("request_id")
+        .or_else(|| record.get("id"))
+        .and_then(|v| v.as_str())
+        .map(String::from);
+    let argv = record.get("argv").and_then(|v| v.as_array()).map(|argv| {
+        argv.iter()
+            .filter_map(|v| v.as_str().map(String::from))
+            .collect::<Vec<_>>()
+    });
+
+    let (recorded, allowed) = match action {
+        // Audit log entries. Tools that do not log argv or bodies, HTTP
+        // denials and CLI denials logged by servers that left out their
+        // argv leave nothing to replay, and redacted values would be
+        // judged as the placeholder rather than what was sent.
+        Some("cli") => {
+            let argv = argv.ok_or("cli entry without argv")?;
+            if argv.iter().any(|arg| arg.contains(REDACTED)) {
+                return Err("cli entry with redacted argv");
+            }
+            (Recorded::Cli(argv), recorded_decision(record))
+        }
+        Some("http") => {
+            let body = record
+                .get("body")
+                .filter(|body| !body.is_null())
+                .ok_or("http entry without body")?;
+            let body = body_bytes(body);
+            if String::from_utf8_lossy(&body).contains(REDACTED) {
+                return Err("http entry with redacted body");
+            }
+            (Recorded::Http(body), recorded_decision(record))
+        }
+        Some(_) => unreachable!("only cli and http entries get here"),
+        // Request records, as `carapace-debug policy` takes them
+        None => {
+            let recorded = if let Some(argv) = argv {
+                Recorded::Cli(argv)
+            } else if let Some(body) = record.get("body") {
+                Recorded::Http(body_bytes(body))
+            } else if let Some(method) = record.get("method") {
+                let mut body = json!({"jsonrpc": "2.0", "method": method, "id": 1});
+                if let Some(params) = record.get("params") {
+                    body["params"] = params.clone();
+                }
+                Recorded::Http(body_bytes(&body))
+            } else {
+                return Err("request without argv, body or method");
+            };
+            (recorded, record.get("allowed").and_then(|v| v.as_bool()))
+        }
+    };
+
+    Ok(Some(Replayable {
+        request_id,
+        tool,
+        recorded,
+        allowed,
+    }))
+}
+
+/// The live policy's verdict on an audited request. It is recorded with the
+/// entry; older entries only have the result.
+fn recorded_decision(entry: &Value) -> Option<bool> {
+    entry
+        .get("decision")
+        .and_then(|d| d.get("allowed"))
+        .and_then(|v| v.as_bool())
+        .or_else(
+            || match entry.get("policy_result").and_then(|v| v.as_str()) {
+                Some("allow") => Some(true),
+                Some("deny") => Some(false),
+                _ => None,
+            },
+        )
+}
+
+/// A body as the server received it: strings as they are, JSON re-encoded
+fn body_bytes(body: &Value) -> Vec<u8> {
+    match body {
+        Value::String(text) => text.as_bytes().to_vec(),
+        other => other.to_string().into_bytes(),
+    }
+}
+
+fn print_replay_report(report: &ReplayReport) {
+    println!("=== Policy Replay: {} ===", report.policy);
+    println!("Replayed:  {}", report.replayed);
+    println!("Unchanged: {}", report.unchanged);
+    let skipped: usize = report.skipped.values().sum();
+    if skipped > 0 {
+        println!("Skipped:   {}", skipped);
+        for (reason, count) in &report.skipped {
+            println!("  {:<40} {:>6}", reason, count);
+        }
+    }
+
+    for (title, changes) in [
+        ("Previously allowed, now denied", &report.newly_denied),
+        ("Previously denied, now allowed", &report.newly_allowed),
+    ] {
+        let total: usize = changes.iter().map(|c| c.count).sum();
+        println!("\n--- {} ({}) ---", title, total);
+        for change in changes {
+            println!("{:>6}  {}", change.count, change.request);
+            let mut detail = format!("        {}", change.decision);
+            if let Some(id) = &change.request_id {
+                detail.push_str(&format!(" [e.g. {}]", id));
+            }
+            println!("{}", detail);
+        }
+    }
+
+    if report.newly_denied.is_empty() && report.newly_allowed.is_empty() {
+        println!("\nNo decisions changed");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(request: &Replayable) -> &[String] {
+        match &request.recorded {
+            Recorded::Cli(argv) => argv,
+            Recorded::Http(_) => panic!("expected a CLI request"),
+        }
+    }
+
+    #[test]
+    fn test_audit_entries() {
+        let entry = json!({"action_type": "cli", "tool": "gh", "request_id": "r1",
+                           "argv": ["pr", "list"], "decision": {"allowed": true}});
+        let request = replayable(&entry).unwrap().unwrap();
+        assert_eq!(argv(&request), ["pr", "list"]);
+        assert_eq!(request.request_id.as_deref(), Some("r1"));
+        assert_eq!(request.allowed, Some(true));
+
+        let entry = json!({"action_type": "http", "tool": "rpc", "policy_result": "allow",
+                           "body": {"jsonrpc": "2.0", "method": "send", "id": 1}});
+        let request = replayable(&entry).unwrap().unwrap();
+        assert_eq!(request.describe(), "rpc send");
+        assert_eq!(request.allowed, Some(true));
+
+        let entry = json!({"action_type": "cli", "tool": "gh", "policy_result": "deny",
+                           "argv": ["repo", "delete"], "decision": {"allowed": false}});
+        let request = replayable(&entry).unwrap().unwrap();
+        assert_eq!(argv(&request), ["repo", "delete"]);
+        assert_eq!(request.allowed, Some(false));
+
+        // Entries that close a request are not requests themselves
+        let entry = json!({"action_type": "cli_response", "tool": "gh"});
+        assert!(replayable(&entry).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_unreplayable_entries_are_skipped() {
+        // A denial from a server that did not log denied argv
+        let denied = json!({"action_type": "cli", "tool": "gh", "policy_result": "deny"});
+        assert_eq!(replayable(&denied).err(), Some("cli entry without argv"));
+        let unlogged = json!({"action_type": "http", "tool": "rpc", "body": null});
+        assert_eq!(replayable(&unlogged).err(), Some("http entry without body"));
+
+        let redacted = json!({"action_type": "cli", "tool": "op",
+                              "argv": ["signin", "--password", "[REDACTED]"]});
+        assert_eq!(
+            replayable(&redacted).err(),
+            Some("cli entry with redacted argv")
+        );
+        let redacted = json!({"action_type": "cli", "tool": "curl",
+                              "argv": ["-H", "Authorization: [REDACTED]"]});
+        assert_eq!(
+            replayable(&redacted).err(),
+            Some("cli entry with redacted argv")
+        );
+        let redacted = json!({"action_type": "http", "tool": "rpc",
+                              "body": {"method": "login", "params": {"token": "[REDACTED]"}}});
+        assert_eq!(
+            replayable(&redacted).err(),
+            Some("http entry with redacted body")
+        );
+    }
+
+    #[test]
+    fn test_request_records() {
+        let record =
+            json!({"tool": "rpc", "method": "send", "params": {"to": "+1"}, "allowed": false});
+        let request = replayable(&record).unwrap().unwrap();
+        assert_eq!(request.describe(), r#"rpc send {"to":"+1"}"#);
+        assert_eq!(request.allowed, Some(false));
+
+        let record = json!({"tool": "gh", "argv": ["pr", "merge"]});
+        let request = replayable(&record).unwrap().unwrap();
+        assert_eq!(argv(&request), ["pr", "merge"]);
+        assert_eq!(request.allowed, None);
+
+        assert_eq!(
+            replayable(&json!({"tool": "gh"})).err(),
+            Some("request without argv, body or method")
+        );
+    }
+}
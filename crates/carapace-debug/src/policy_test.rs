@@ -0,0 +1,166 @@
+use anyhow::{anyhow, Result};
+use carapace_policy::{CaseResult, PolicyTestFile};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::policy::load_policy;
+
+/// The results of one test file
+#[derive(Serialize)]
+struct FileReport {
+    file: String,
+    policy: String,
+    passed: usize,
+    failed: usize,
+    cases: Vec<CaseResult>,
+    #[serde(skip)]
+    seconds: f64,
+}
+
+/// Run policy test files and report each case. Exits non-zero if any fails.
+pub fn test(paths: &[PathBuf], format: &str) -> Result<()> {
+    let mut reports = Vec::new();
+    for file in test_files(paths)? {
+        let started = Instant::now();
+        let tests = PolicyTestFile::from_file(&file)?;
+        let policy_file = tests.policy_path(&file)?;
+        let policy = load_policy(&policy_file)?;
+        let cases = tests.run(&policy);
+        let passed = cases.iter().filter(|case| case.passed).count();
+        reports.push(FileReport {
+            file: file.display().to_string(),
+            policy: policy_file.display().to_string(),
+            passed,
+            failed: cases.len() - passed,
+            cases,
+            seconds: started.elapsed().as_secs_f64(),
+        });
+    }
+
+    match format {
+        "json" => println!("{}", serde_json::to_string_pretty(&reports)?),
+        "junit" => print_junit(&reports),
+        _ => print_results(&reports),
+    }
+
+    if reports.iter().any(|report| report.failed > 0) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// The files given, with directories replaced by the test files in them
+fn test_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+        let mut found: Vec<PathBuf> = std::fs::read_dir(path)
+            .map_err(|e| anyhow!("Cannot read {}: {}", path.display(), e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|file| is_test_file(file))
+            .collect();
+        if found.is_empty() {
+            return Err(anyhow!("No *.test.yaml files in {}", path.display()));
+        }
+        found.sort();
+        files.extend(found);
+    }
+    Ok(files)
+}
+
+fn is_test_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.ends_with(".test.yaml") || name.ends_with(".test.yml"))
+}
+
+fn print_results(reports: &[FileReport]) {
+    for report in reports {
+        println!("{} ({})", report.file, report.policy);
+        for case in &report.cases {
+            if case.passed {
+                println!("  ok    {}", case.name);
+                continue;
+            }
+            println!("  FAIL  {}", case.name);
+            for failure in &case.failures {
+                println!("          {}", failure);
+            }
+            if let Some(decision) = &case.decision {
+                println!("          decision: {}", decision);
+            }
+        }
+    }
+
+    let passed: usize = reports.iter().map(|r| r.passed).sum();
+    let failed: usize = reports.iter().map(|r| r.failed).sum();
+    println!(
+        "\ntest result: {}. {} passed; {} failed",
+        if failed == 0 { "ok" } else { "FAILED" },
+        passed,
+        failed
+    );
+}
+
+/// JUnit XML, one suite per test file, as CI systems read it
+fn print_junit(reports: &[FileReport]) {
+    let tests: usize = reports.iter().map(|r| r.cases.len()).sum();
+    let failures: usize = reports.iter().map(|r| r.failed).sum();
+    println!(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    println!(
+        r#"<testsuites name="carapace-policy" tests="{}" failures="{}">"#,
+        tests, failures
+    );
+    for report in reports {
+        println!(
+            r#"  <testsuite name="{}" tests="{}" failures="{}" time="{:.3}">"#,
+            xml_escape(&report.file),
+            report.cases.len(),
+            report.failed,
+            report.seconds
+        );
+        for case in &report.cases {
+            let open = format!(
+                r#"    <testcase name="{}" classname="{}""#,
+                xml_escape(&case.name),
+                xml_escape(&report.policy)
+            );
+            if case.passed {
+                println!("{}/>", open);
+                continue;
+            }
+            let mut detail = case.failures.join("\n");
+            if let Some(decision) = &case.decision {
+                detail.push_str(&format!("\ndecision: {}", decision));
+            }
+            println!("{}>", open);
+            println!(
+                r#"      <failure message="{}">{}</failure>"#,
+                xml_escape(&case.failures.join("; ")),
+                xml_escape(&detail)
+            );
+            println!("    </testcase>");
+        }
+        println!("  </testsuite>");
+    }
+    println!("</testsuites>");
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -1,10 +1,25 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::num::NonZeroU32;
 use std::path::Path;
 
+use crate::decision::PolicySource;
+use crate::error::PolicyError;
+use crate::matcher::ArgvMatcher;
+use crate::validator::PolicyValidator;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PolicyConfig {
     pub tools: HashMap<String, ToolPolicy>,
+
+    /// Mode of tools that don't set their own
+    #[serde(default)]
+    pub mode: PolicyMode,
+
+    /// The file the policy was loaded from, to locate the rules behind
+    /// decisions
+    #[serde(skip)]
+    pub source: Option<PolicySource>,
 }
 
 impl PolicyConfig {
@@ -16,11 +31,36 @@ impl PolicyConfig {
         }
 
         let content = std::fs::read_to_string(path)?;
-        serde_yaml::from_str(&content)
-            .map_err(|e| format!("Failed to parse policy YAML: {}", e).into())
+        let mut policy: PolicyConfig = serde_yaml::from_str(&content)
+            .map_err(|e| format!("Failed to parse policy YAML: {}", e))?;
+        policy.source = Some(PolicySource::new(path.display().to_string(), content));
+        Ok(policy)
+    }
+
+    /// Whether the rules of `tool` are enforced or only audited
+    pub fn mode(&self, tool: &str) -> PolicyMode {
+        let tool_mode = match self.tools.get(tool) {
+            Some(ToolPolicy::Cli(policy)) => policy.mode,
+            Some(ToolPolicy::Http(policy)) => policy.mode,
+            None => None,
+        };
+        tool_mode.unwrap_or(self.mode)
     }
 }
 
+/// How rule violations are handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyMode {
+    /// Deny requests that break a rule
+    #[default]
+    Enforce,
+    /// Let them through, but audit the denial that enforcing would produce
+    AuditOnly,
+}
+
+// Policies are loaded once at startup, so the size difference doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum ToolPolicy {
@@ -34,12 +74,21 @@ pub enum ToolPolicy {
 pub struct CliPolicy {
     pub binary: String,
 
+    /// Overrides the policy-wide mode for this tool
+    #[serde(default)]
+    pub mode: Option<PolicyMode>,
+
     #[serde(default)]
     pub argv_allow_patterns: Vec<String>,
 
     #[serde(default)]
     pub argv_deny_patterns: Vec<String>,
 
+    /// Glob patterns for client environment variables passed to the tool.
+    /// Everything else, and always the built-in `DENIED_ENV_VARS`, is dropped.
+    #[serde(default)]
+    pub env_passthrough: Vec<String>,
+
     #[serde(default)]
     pub env_inject: HashMap<String, String>,
 
@@ -49,14 +98,164 @@ pub struct CliPolicy {
     #[serde(default = "default_timeout")]
     pub timeout_secs: u64,
 
+    /// Most stdout bytes returned to the client; unlimited if unset
+    #[serde(default)]
+    pub max_stdout_bytes: Option<u64>,
+
+    /// Most stderr bytes returned to the client; unlimited if unset
+    #[serde(default)]
+    pub max_stderr_bytes: Option<u64>,
+
+    /// What to do when output exceeds its limit
+    #[serde(default)]
+    pub on_output_limit: OutputLimitAction,
+
+    /// Most requests for this tool running at once; more wait in the queue.
+    /// Zero is rejected when the policy loads: no request could ever run.
+    #[serde(default)]
+    pub max_concurrent: Option<NonZeroU32>,
+
+    /// Allowed requests that an operator must approve before they run
+    #[serde(default)]
+    pub require_approval: Option<ApprovalRule>,
+
+    /// Run in a pseudo-terminal when the client has one, for tools with
+    /// interactive prompts (e.g. `op signin`)
+    #[serde(default)]
+    pub interactive: bool,
+
+    /// Confinement for the spawned process; none by default
+    #[serde(default)]
+    pub sandbox: Option<SandboxPolicy>,
+
     #[serde(default)]
     pub audit: AuditConfig,
 }
 
+/// Handling of CLI output beyond `max_stdout_bytes` / `max_stderr_bytes`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputLimitAction {
+    /// Keep the command running, but discard output past the limit
+    #[default]
+    Truncate,
+    /// Kill the command's process group as soon as a limit is reached
+    Kill,
+}
+
+impl OutputLimitAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputLimitAction::Truncate => "truncate",
+            OutputLimitAction::Kill => "kill",
+        }
+    }
+}
+
+/// Restrictions applied to a CLI tool's process between fork and exec
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SandboxPolicy {
+    /// Run as this user id (requires the server to run as root)
+    #[serde(default)]
+    pub uid: Option<u32>,
+
+    /// Run as this group id; supplementary groups are dropped
+    #[serde(default)]
+    pub gid: Option<u32>,
+
+    /// Start from an empty environment instead of the server's
+    #[serde(default)]
+    pub clear_env: bool,
+
+    /// Server environment variables kept when `clear_env` is set.
+    /// Request and `env_inject` variables are always passed.
+    #[serde(default)]
+    pub env_allow: Vec<String>,
+
+    #[serde(default)]
+    pub rlimits: RlimitPolicy,
+
+    /// Set PR_SET_NO_NEW_PRIVS, so setuid binaries cannot gain privileges.
+    /// Always set when a seccomp profile is configured.
+    #[serde(default)]
+    pub no_new_privs: bool,
+
+    #[serde(default)]
+    pub seccomp: Option<SeccompPolicy>,
+
+    #[serde(default)]
+    pub namespaces: NamespacePolicy,
+
+    /// Paths bind-mounted into the tool's mount namespace
+    #[serde(default)]
+    pub bind_mounts: Vec<BindMount>,
+}
+
+/// Resource limits (setrlimit); unset limits are inherited from the server
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RlimitPolicy {
+    /// CPU time in seconds (RLIMIT_CPU)
+    #[serde(default)]
+    pub cpu_secs: Option<u64>,
+
+    /// Address space in bytes (RLIMIT_AS)
+    #[serde(default)]
+    pub memory_bytes: Option<u64>,
+
+    /// Largest file the tool may write, in bytes (RLIMIT_FSIZE)
+    #[serde(default)]
+    pub file_size_bytes: Option<u64>,
+
+    /// Processes per user id (RLIMIT_NPROC); not enforced for root
+    #[serde(default)]
+    pub max_processes: Option<u64>,
+}
+
+/// Syscalls that fail with EPERM inside the sandbox
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeccompPolicy {
+    /// Deny the built-in set of dangerous syscalls (ptrace, mount, bpf,
+    /// kernel module loading, ...)
+    #[serde(default = "default_deny_dangerous")]
+    pub deny_dangerous: bool,
+
+    /// Additional syscalls to deny, by name
+    #[serde(default)]
+    pub deny_syscalls: Vec<String>,
+}
+
+/// Linux namespaces to create for the tool (require the server to run as root)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NamespacePolicy {
+    /// Private mount namespace; needed for `bind_mounts`
+    #[serde(default)]
+    pub mount: bool,
+
+    /// Empty network namespace: only an unconfigured loopback device
+    #[serde(default)]
+    pub network: bool,
+
+    /// Private PID namespace; with `mount`, /proc is remounted to match
+    #[serde(default)]
+    pub pid: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BindMount {
+    pub source: String,
+    pub target: String,
+    #[serde(default)]
+    pub read_only: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HttpPolicy {
     pub upstream: String,
 
+    /// Overrides the policy-wide mode for this tool
+    #[serde(default)]
+    pub mode: Option<PolicyMode>,
+
     #[serde(default)]
     pub jsonrpc_allow_methods: Vec<String>,
 
@@ -69,6 +268,15 @@ pub struct HttpPolicy {
     #[serde(default)]
     pub rate_limit: Option<RateLimit>,
 
+    /// Most requests for this tool running at once; more wait in the queue.
+    /// Zero is rejected when the policy loads: no request could ever run.
+    #[serde(default)]
+    pub max_concurrent: Option<NonZeroU32>,
+
+    /// Allowed requests that an operator must approve before they are sent
+    #[serde(default)]
+    pub require_approval: Option<ApprovalRule>,
+
     #[serde(default)]
     pub timeout_secs: Option<u64>,
 
@@ -91,13 +299,50 @@ pub struct ParamFilter {
     pub deny_patterns: Vec<String>,
 }
 
+/// Requests that pass the policy but are held until an operator approves
+/// or denies them. A request with no decision in time is denied.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApprovalRule {
+    /// CLI tools: argv glob patterns, matched like `argv_allow_patterns`
+    #[serde(default)]
+    pub argv_patterns: Vec<String>,
+
+    /// HTTP tools: JSON-RPC methods that always need approval
+    #[serde(default)]
+    pub jsonrpc_methods: Vec<String>,
+
+    /// HTTP tools: JSON-RPC calls whose params fail these filters need
+    /// approval (e.g. `send` to a recipient outside `allow_patterns`)
+    #[serde(default)]
+    pub jsonrpc_param_filters: HashMap<String, ParamFilter>,
+
+    /// Seconds to wait for a decision
+    #[serde(default = "default_approval_timeout")]
+    pub timeout_secs: u64,
+}
+
+impl ApprovalRule {
+    /// True if a CLI request with this argv needs approval
+    pub fn matches_argv(&self, argv: &[String]) -> Result<bool, PolicyError> {
+        let matcher = ArgvMatcher::new(self.argv_patterns.clone(), vec![])?;
+        Ok(matcher.matches(argv))
+    }
+
+    /// True if a JSON-RPC call with this method and body needs approval
+    pub fn matches_jsonrpc(&self, method: &str, body: &str) -> bool {
+        self.jsonrpc_methods.iter().any(|m| m == method)
+            || PolicyValidator::validate_jsonrpc_params(method, body, &self.jsonrpc_param_filters)
+                .is_err()
+    }
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct RateLimit {
     pub max_requests: u32,
     pub window_secs: u64,
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuditConfig {
     #[serde(default = "default_audit_enabled")]
     pub enabled: bool,
@@ -110,6 +355,15 @@ pub struct AuditConfig {
 
     #[serde(default)]
     pub redact_patterns: Vec<String>,
+
+    /// Directory for transcripts of interactive sessions (terminal output only)
+    #[serde(default)]
+    pub transcript_dir: Option<String>,
+
+    /// Keep full payloads (argv, stdin, output, bodies, SSE events) in the
+    /// server's encrypted capture store, if it has one
+    #[serde(default)]
+    pub capture: bool,
 }
 
 fn default_timeout() -> u64 {
@@ -120,6 +374,28 @@ fn default_audit_enabled() -> bool {
     true
 }
 
+/// Same as an empty `audit:` section, so tools without one are logged
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig {
+            enabled: default_audit_enabled(),
+            log_argv: false,
+            log_body: false,
+            redact_patterns: Vec::new(),
+            transcript_dir: None,
+            capture: false,
+        }
+    }
+}
+
+fn default_deny_dangerous() -> bool {
+    true
+}
+
+fn default_approval_timeout() -> u64 {
+    25
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -170,6 +446,74 @@ tools:
         assert!(config.tools.contains_key("signal-cli"));
     }
 
+    #[test]
+    fn test_require_approval() {
+        let yaml = r#"
+tools:
+  op:
+    type: cli
+    binary: /usr/bin/op
+    argv_allow_patterns: ["item get *"]
+    require_approval:
+      argv_patterns: ["item get * --vault prod*"]
+  signal-cli:
+    type: http
+    upstream: "http://127.0.0.1:18080"
+    require_approval:
+      jsonrpc_methods: [deleteAccount]
+      jsonrpc_param_filters:
+        send:
+          field: recipient
+          allow_patterns: ["+1555*"]
+      timeout_secs: 60
+"#;
+
+        let config: PolicyConfig = serde_yaml::from_str(yaml).expect("parse failed");
+        let Some(ToolPolicy::Cli(op)) = config.tools.get("op") else {
+            panic!("op should be a CLI tool");
+        };
+        let rule = op.require_approval.as_ref().unwrap();
+        assert_eq!(rule.timeout_secs, 25);
+        let argv = |s: &str| s.split(' ').map(String::from).collect::<Vec<_>>();
+        assert!(rule
+            .matches_argv(&argv("item get db --vault prod"))
+            .unwrap());
+        assert!(!rule.matches_argv(&argv("item get db --vault dev")).unwrap());
+
+        let Some(ToolPolicy::Http(signal)) = config.tools.get("signal-cli") else {
+            panic!("signal-cli should be an HTTP tool");
+        };
+        let rule = signal.require_approval.as_ref().unwrap();
+        assert_eq!(rule.timeout_secs, 60);
+        let send = |to: &str| format!(r#"{{"method":"send","params":{{"recipient":"{}"}}}}"#, to);
+        assert!(!rule.matches_jsonrpc("send", &send("+15551234")));
+        assert!(rule.matches_jsonrpc("send", &send("+4930123")));
+        assert!(rule.matches_jsonrpc("deleteAccount", "{}"));
+        assert!(!rule.matches_jsonrpc("receive", "{}"));
+    }
+
+    #[test]
+    fn test_policy_mode() {
+        let yaml = r#"
+mode: audit_only
+tools:
+  gh:
+    type: cli
+    binary: /usr/bin/gh
+  op:
+    type: cli
+    binary: /usr/bin/op
+    mode: enforce
+"#;
+
+        let config: PolicyConfig = serde_yaml::from_str(yaml).expect("parse failed");
+        assert_eq!(config.mode("gh"), PolicyMode::AuditOnly);
+        assert_eq!(config.mode("op"), PolicyMode::Enforce);
+
+        let config: PolicyConfig = serde_yaml::from_str("tools: {}").expect("parse failed");
+        assert_eq!(config.mode("gh"), PolicyMode::Enforce);
+    }
+
     #[test]
     fn test_missing_required_fields() {
         let yaml = r#"
@@ -182,6 +526,26 @@ tools:
         assert!(result.is_err(), "Should fail with missing required fields");
     }
 
+    #[test]
+    fn test_zero_max_concurrent_rejected() {
+        for tool in [
+            "type: cli\n    binary: /bin/echo",
+            "type: http\n    upstream: http://127.0.0.1:1",
+        ] {
+            let yaml = format!("tools:\n  t:\n    {}\n    max_concurrent: 0\n", tool);
+            let result: Result<PolicyConfig, _> = serde_yaml::from_str(&yaml);
+            assert!(result.is_err(), "Should reject max_concurrent: 0");
+
+            let yaml = yaml.replace("max_concurrent: 0", "max_concurrent: 2");
+            let policy: PolicyConfig = serde_yaml::from_str(&yaml).unwrap();
+            let max_concurrent = match &policy.tools["t"] {
+                ToolPolicy::Cli(policy) => policy.max_concurrent,
+                ToolPolicy::Http(policy) => policy.max_concurrent,
+            };
+            assert_eq!(max_concurrent.map(NonZeroU32::get), Some(2));
+        }
+    }
+
     #[test]
     fn test_invalid_tool_type() {
         let yaml = r#"
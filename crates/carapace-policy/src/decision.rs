@@ -0,0 +1,595 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::config::{CliPolicy, HttpPolicy, PolicyConfig, PolicyMode, ToolPolicy};
+use crate::matcher::{ArgvMatch, ArgvMatcher};
+use crate::validator::{ParamMatch, PolicyValidator};
+
+/// `Decision::matched` of a denial because no allow pattern or method matched
+pub const NO_ALLOW_MATCHED: &str = "no allow matched";
+
+/// The outcome of checking a request against a policy, with the rule that
+/// decided it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Decision {
+    pub tool: String,
+    pub allowed: bool,
+    pub rule: RuleKind,
+    /// The pattern, method or argument that decided, or `NO_ALLOW_MATCHED`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matched: Option<String>,
+    /// Where the rule is in the policy file, if it was loaded from one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<RuleLocation>,
+}
+
+/// The kind of rule behind a decision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleKind {
+    /// The tool is not in the policy
+    UnknownTool,
+    /// A CLI request for an HTTP tool or the reverse; `matched` is the
+    /// tool's type
+    WrongToolType,
+    ArgvAllow,
+    ArgvDeny,
+    /// An argument contains shell metacharacters; `matched` is the argument
+    ShellChars,
+    JsonrpcAllowMethod,
+    JsonrpcDenyMethod,
+    ParamAllow,
+    ParamDeny,
+    /// The filtered param is missing; `matched` is its name
+    ParamMissing,
+    /// A pattern of the tool's policy does not compile
+    InvalidPattern,
+    /// No rule applies to the request, e.g. an HTTP request that is not a
+    /// JSON-RPC call
+    NoRule,
+}
+
+impl RuleKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RuleKind::UnknownTool => "unknown_tool",
+            RuleKind::WrongToolType => "wrong_tool_type",
+            RuleKind::ArgvAllow => "argv_allow",
+            RuleKind::ArgvDeny => "argv_deny",
+            RuleKind::ShellChars => "shell_chars",
+            RuleKind::JsonrpcAllowMethod => "jsonrpc_allow_method",
+            RuleKind::JsonrpcDenyMethod => "jsonrpc_deny_method",
+            RuleKind::ParamAllow => "param_allow",
+            RuleKind::ParamDeny => "param_deny",
+            RuleKind::ParamMissing => "param_missing",
+            RuleKind::InvalidPattern => "invalid_pattern",
+            RuleKind::NoRule => "no_rule",
+        }
+    }
+}
+
+impl fmt::Display for RuleKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Position of a rule in a policy file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuleLocation {
+    pub file: String,
+    /// 1-based
+    pub line: usize,
+}
+
+impl fmt::Display for RuleLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.rule {
+            RuleKind::UnknownTool => write!(f, "Tool '{}' not in policy", self.tool)?,
+            RuleKind::WrongToolType => write!(
+                f,
+                "Tool '{}' is {}-only, cannot handle this request",
+                self.tool,
+                self.matched.as_deref().unwrap_or("").to_uppercase()
+            )?,
+            rule => {
+                let verdict = if self.allowed { "allowed" } else { "denied" };
+                write!(f, "Tool '{}' {} by {}", self.tool, verdict, rule)?;
+                match self.matched.as_deref() {
+                    Some(NO_ALLOW_MATCHED) => write!(f, ": {}", NO_ALLOW_MATCHED)?,
+                    Some(matched) => write!(f, ": '{}'", matched)?,
+                    None => {}
+                }
+            }
+        }
+        if let Some(location) = &self.location {
+            write!(f, " ({})", location)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Decision {}
+
+/// The text a policy was parsed from, to locate rules in it
+#[derive(Debug, Clone)]
+pub struct PolicySource {
+    pub path: String,
+    text: String,
+}
+
+impl PolicySource {
+    pub fn new(path: impl Into<String>, text: impl Into<String>) -> Self {
+        PolicySource {
+            path: path.into(),
+            text: text.into(),
+        }
+    }
+
+    /// The line of the mapping entry reached through `keys`, or, with a
+    /// `needle`, of the first line in that entry mentioning it
+    fn locate(&self, keys: &[&str], needle: Option<&str>) -> Option<RuleLocation> {
+        let lines: Vec<&str> = self.text.lines().collect();
+        let (mut start, mut end, mut indent) = (0, lines.len(), None);
+        for key in keys {
+            let (line, key_indent) = find_key(&lines[start..end], key, indent)?;
+            start += line;
+            end = block_end(&lines, start, key_indent);
+            indent = Some(key_indent);
+        }
+
+        let line = match needle {
+            Some(needle) => (start..end).find(|&i| lines[i].contains(needle))?,
+            None => start,
+        };
+        Some(RuleLocation {
+            file: self.path.clone(),
+            line: line + 1,
+        })
+    }
+}
+
+/// Index and indentation of `key` among the direct children of a block
+/// whose own key is indented by `parent`
+fn find_key(lines: &[&str], key: &str, parent: Option<usize>) -> Option<(usize, usize)> {
+    let mut child = None;
+    for (i, line) in lines.iter().enumerate() {
+        let content = line.trim_start();
+        if content.is_empty() || content.starts_with('#') {
+            continue;
+        }
+        let indent = line.len() - content.len();
+        if parent.is_some_and(|parent| indent <= parent) {
+            continue;
+        }
+        // Direct children share the indentation of the first one
+        if *child.get_or_insert(indent) != indent {
+            continue;
+        }
+        let name = content.split(':').next().unwrap_or("").trim();
+        if name.trim_matches(|c| c == '"' || c == '\'') == key {
+            return Some((i, indent));
+        }
+    }
+    None
+}
+
+/// End (exclusive) of the block opened by the key on line `start`
+fn block_end(lines: &[&str], start: usize, indent: usize) -> usize {
+    lines[start + 1..]
+        .iter()
+        .position(|line| {
+            let content = line.trim_start();
+            !content.is_empty() && !content.starts_with('#') && line.len() - content.len() <= indent
+        })
+        .map_or(lines.len(), |end| start + 1 + end)
+}
+
+impl PolicyConfig {
+    /// Decide a CLI request for `tool` with this argv
+    pub fn decide_cli(&self, tool: &str, argv: &[String]) -> Decision {
+        match self.tools.get(tool) {
+            Some(ToolPolicy::Cli(cli_policy)) => self.decide_argv(tool, cli_policy, argv),
+            Some(ToolPolicy::Http(_)) => self.decision(
+                tool,
+                false,
+                RuleKind::WrongToolType,
+                Some("http"),
+                &["type"],
+                None,
+            ),
+            None => self.decision(tool, false, RuleKind::UnknownTool, None, &[], None),
+        }
+    }
+
+    /// Decide an HTTP request for `tool` with this (decoded) body
+    pub fn decide_http(&self, tool: &str, body: Option<&[u8]>) -> Decision {
+        match self.tools.get(tool) {
+            Some(ToolPolicy::Http(http_policy)) => self.decide_jsonrpc(tool, http_policy, body),
+            Some(ToolPolicy::Cli(_)) => self.decision(
+                tool,
+                false,
+                RuleKind::WrongToolType,
+                Some("cli"),
+                &["type"],
+                None,
+            ),
+            None => self.decision(tool, false, RuleKind::UnknownTool, None, &[], None),
+        }
+    }
+
+    /// Whether `decision` is enforced: denials of requests the tool cannot
+    /// handle always are, rule violations unless the tool is audit-only
+    pub fn enforces(&self, decision: &Decision) -> bool {
+        !decision.allowed
+            && (matches!(
+                decision.rule,
+                RuleKind::UnknownTool | RuleKind::WrongToolType
+            ) || self.mode(&decision.tool) == PolicyMode::Enforce)
+    }
+
+    fn decide_argv(&self, tool: &str, cli_policy: &CliPolicy, argv: &[String]) -> Decision {
+        let matcher = match ArgvMatcher::new(
+            cli_policy.argv_allow_patterns.clone(),
+            cli_policy.argv_deny_patterns.clone(),
+        ) {
+            Ok(matcher) => matcher,
+            Err(e) => {
+                let error = e.to_string();
+                return self.decision(
+                    tool,
+                    false,
+                    RuleKind::InvalidPattern,
+                    Some(&error),
+                    &[],
+                    None,
+                );
+            }
+        };
+
+        let allowed_by = match matcher.check(argv) {
+            ArgvMatch::Denied(pattern) => {
+                return self.decision(
+                    tool,
+                    false,
+                    RuleKind::ArgvDeny,
+                    Some(pattern),
+                    &["argv_deny_patterns"],
+                    Some(pattern),
+                );
+            }
+            ArgvMatch::NoAllow => {
+                return self.decision(
+                    tool,
+                    false,
+                    RuleKind::ArgvAllow,
+                    Some(NO_ALLOW_MATCHED),
+                    &["argv_allow_patterns"],
+                    None,
+                );
+            }
+            ArgvMatch::Allowed(pattern) => pattern,
+        };
+
+        // Check for shell injection attempts in argv
+        if let Some(arg) = argv
+            .iter()
+            .find(|arg| PolicyValidator::has_dangerous_shell_chars(arg))
+        {
+            return self.decision(tool, false, RuleKind::ShellChars, Some(arg), &[], None);
+        }
+
+        self.decision(
+            tool,
+            true,
+            RuleKind::ArgvAllow,
+            Some(allowed_by),
+            &["argv_allow_patterns"],
+            Some(allowed_by),
+        )
+    }
+
+    fn decide_jsonrpc(
+        &self,
+        tool: &str,
+        http_policy: &HttpPolicy,
+        body: Option<&[u8]>,
+    ) -> Decision {
+        // Only JSON-RPC calls are subject to method and param rules
+        let Some(body) = body.and_then(|b| std::str::from_utf8(b).ok()) else {
+            return self.decision(tool, true, RuleKind::NoRule, None, &[], None);
+        };
+        let Some(method) = serde_json::from_str::<serde_json::Value>(body)
+            .ok()
+            .and_then(|json| json.get("method")?.as_str().map(str::to_string))
+        else {
+            return self.decision(tool, true, RuleKind::NoRule, None, &[], None);
+        };
+
+        // Deny takes precedence
+        if http_policy.jsonrpc_deny_methods.contains(&method) {
+            return self.decision(
+                tool,
+                false,
+                RuleKind::JsonrpcDenyMethod,
+                Some(&method),
+                &["jsonrpc_deny_methods"],
+                Some(&method),
+            );
+        }
+        let mut decision = if http_policy.jsonrpc_allow_methods.is_empty() {
+            self.decision(tool, true, RuleKind::NoRule, None, &[], None)
+        } else if http_policy.jsonrpc_allow_methods.contains(&method) {
+            self.decision(
+                tool,
+                true,
+                RuleKind::JsonrpcAllowMethod,
+                Some(&method),
+                &["jsonrpc_allow_methods"],
+                Some(&method),
+            )
+        } else {
+            return self.decision(
+                tool,
+                false,
+                RuleKind::JsonrpcAllowMethod,
+                Some(NO_ALLOW_MATCHED),
+                &["jsonrpc_allow_methods"],
+                None,
+            );
+        };
+
+        // Validate params (e.g., phone numbers)
+        if let Some(filter) = http_policy.jsonrpc_param_filters.get(&method) {
+            let filter_keys = ["jsonrpc_param_filters", method.as_str()];
+            let value = match PolicyValidator::param_value(body, filter) {
+                Ok(value) => value,
+                Err(_) => {
+                    let keys = [filter_keys[0], filter_keys[1], "field"];
+                    return self.decision(
+                        tool,
+                        false,
+                        RuleKind::ParamMissing,
+                        Some(&filter.field),
+                        &keys,
+                        None,
+                    );
+                }
+            };
+            decision = match PolicyValidator::match_param(&value, filter) {
+                Ok(ParamMatch::Denied(pattern)) => {
+                    let keys = [filter_keys[0], filter_keys[1], "deny_patterns"];
+                    self.decision(
+                        tool,
+                        false,
+                        RuleKind::ParamDeny,
+                        Some(pattern),
+                        &keys,
+                        Some(pattern),
+                    )
+                }
+                Ok(ParamMatch::NoAllow) => {
+                    let keys = [filter_keys[0], filter_keys[1], "allow_patterns"];
+                    self.decision(
+                        tool,
+                        false,
+                        RuleKind::ParamAllow,
+                        Some(NO_ALLOW_MATCHED),
+                        &keys,
+                        None,
+                    )
+                }
+                Ok(ParamMatch::Allowed(Some(pattern))) => {
+                    let keys = [filter_keys[0], filter_keys[1], "allow_patterns"];
+                    self.decision(
+                        tool,
+                        true,
+                        RuleKind::ParamAllow,
+                        Some(pattern),
+                        &keys,
+                        Some(pattern),
+                    )
+                }
+                Ok(ParamMatch::Allowed(None)) => decision,
+                Err(e) => {
+                    let error = e.to_string();
+                    self.decision(
+                        tool,
+                        false,
+                        RuleKind::InvalidPattern,
+                        Some(&error),
+                        &filter_keys,
+                        None,
+                    )
+                }
+            };
+        }
+
+        decision
+    }
+
+    /// A decision by the rule under `keys` of the tool's policy
+    fn decision(
+        &self,
+        tool: &str,
+        allowed: bool,
+        rule: RuleKind,
+        matched: Option<&str>,
+        keys: &[&str],
+        needle: Option<&str>,
+    ) -> Decision {
+        let location = self.source.as_ref().filter(|_| rule != RuleKind::NoRule);
+        let location = location.and_then(|source| {
+            let mut path = vec!["tools"];
+            if rule != RuleKind::UnknownTool {
+                path.push(tool);
+                path.extend_from_slice(keys);
+            }
+            source.locate(&path, needle)
+        });
+        Decision {
+            tool: tool.to_string(),
+            allowed,
+            rule,
+            matched: matched.map(str::to_string),
+            location,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = r#"
+tools:
+  gh:
+    type: cli
+    binary: /usr/bin/gh
+    argv_allow_patterns:
+      - "pr list*"
+      - "issue view *"
+    argv_deny_patterns:
+      - "* --token *"
+  signal-cli:
+    type: http
+    upstream: "http://127.0.0.1:18080"
+    jsonrpc_allow_methods: [send, receive]
+    jsonrpc_deny_methods:
+      - deleteAccount
+    jsonrpc_param_filters:
+      send:
+        field: recipient
+        allow_patterns:
+          - "+1555*"
+"#;
+
+    fn policy() -> PolicyConfig {
+        let mut policy: PolicyConfig = serde_yaml::from_str(POLICY).expect("parse failed");
+        policy.source = Some(PolicySource::new("policy.yaml", POLICY));
+        policy
+    }
+
+    fn argv(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn location(line: usize) -> Option<RuleLocation> {
+        Some(RuleLocation {
+            file: "policy.yaml".to_string(),
+            line,
+        })
+    }
+
+    #[test]
+    fn test_cli_decisions() {
+        let policy = policy();
+
+        let decision = policy.decide_cli("gh", &argv(&["issue", "view", "1"]));
+        assert!(decision.allowed);
+        assert_eq!(decision.rule, RuleKind::ArgvAllow);
+        assert_eq!(decision.matched.as_deref(), Some("issue view *"));
+        assert_eq!(decision.location, location(8));
+
+        let decision = policy.decide_cli("gh", &argv(&["pr", "list", "--token", "x"]));
+        assert!(!decision.allowed);
+        assert_eq!(decision.rule, RuleKind::ArgvDeny);
+        assert_eq!(decision.location, location(10));
+        assert_eq!(
+            decision.to_string(),
+            "Tool 'gh' denied by argv_deny: '* --token *' (policy.yaml:10)"
+        );
+
+        let decision = policy.decide_cli("gh", &argv(&["repo", "delete"]));
+        assert_eq!(decision.rule, RuleKind::ArgvAllow);
+        assert_eq!(decision.matched.as_deref(), Some(NO_ALLOW_MATCHED));
+        assert_eq!(decision.location, location(6));
+
+        let decision = policy.decide_cli("gh", &argv(&["pr", "list", ";ls"]));
+        assert_eq!(decision.rule, RuleKind::ShellChars);
+        assert_eq!(decision.matched.as_deref(), Some(";ls"));
+
+        let decision = policy.decide_cli("signal-cli", &argv(&["send"]));
+        assert_eq!(decision.rule, RuleKind::WrongToolType);
+        assert_eq!(decision.location, location(12));
+
+        let decision = policy.decide_cli("curl", &argv(&["example.com"]));
+        assert_eq!(decision.rule, RuleKind::UnknownTool);
+        assert_eq!(
+            decision.to_string(),
+            "Tool 'curl' not in policy (policy.yaml:2)"
+        );
+    }
+
+    #[test]
+    fn test_http_decisions() {
+        let policy = policy();
+        let call = |method: &str, recipient: &str| {
+            let body = format!(
+                r#"{{"jsonrpc":"2.0","method":"{}","params":{{"recipient":"{}"}}}}"#,
+                method, recipient
+            );
+            policy.decide_http("signal-cli", Some(body.as_bytes()))
+        };
+
+        let decision = call("send", "+15551234");
+        assert!(decision.allowed);
+        assert_eq!(decision.rule, RuleKind::ParamAllow);
+        assert_eq!(decision.location, location(21));
+
+        let decision = call("send", "+4930123");
+        assert!(!decision.allowed);
+        assert_eq!(decision.rule, RuleKind::ParamAllow);
+        assert_eq!(decision.matched.as_deref(), Some(NO_ALLOW_MATCHED));
+        assert_eq!(decision.location, location(20));
+
+        let decision = call("receive", "");
+        assert!(decision.allowed);
+        assert_eq!(decision.rule, RuleKind::JsonrpcAllowMethod);
+        assert_eq!(decision.location, location(14));
+
+        let decision = call("deleteAccount", "");
+        assert_eq!(decision.rule, RuleKind::JsonrpcDenyMethod);
+        assert_eq!(decision.location, location(16));
+
+        let decision = call("listGroups", "");
+        assert!(!decision.allowed);
+        assert_eq!(decision.rule, RuleKind::JsonrpcAllowMethod);
+
+        let decision = policy.decide_http("signal-cli", Some(b"not json"));
+        assert!(decision.allowed);
+        assert_eq!(decision.rule, RuleKind::NoRule);
+        assert_eq!(decision.location, None);
+    }
+
+    #[test]
+    fn test_enforces() {
+        let mut policy = policy();
+        let denied = policy.decide_cli("gh", &argv(&["repo", "delete"]));
+        let unknown = policy.decide_cli("curl", &argv(&[]));
+        assert!(policy.enforces(&denied));
+
+        // Audit-only still rejects requests the policy cannot handle
+        policy.mode = PolicyMode::AuditOnly;
+        assert!(!policy.enforces(&denied));
+        assert!(policy.enforces(&unknown));
+        assert!(!policy.enforces(&policy.decide_cli("gh", &argv(&["pr", "list"]))));
+    }
+
+    #[test]
+    fn test_no_location_without_source() {
+        let policy: PolicyConfig = serde_yaml::from_str(POLICY).expect("parse failed");
+        let decision = policy.decide_cli("gh", &argv(&["repo", "delete"]));
+        assert_eq!(decision.location, None);
+        assert_eq!(
+            decision.to_string(),
+            "Tool 'gh' denied by argv_allow: no allow matched"
+        );
+    }
+}
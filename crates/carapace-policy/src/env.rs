@@ -0,0 +1,152 @@
+use crate::error::PolicyError;
+use glob::Pattern;
+use std::collections::HashMap;
+
+/// Variables a client may never set on the host, whatever the policy says:
+/// they change what gets loaded or executed (dynamic linker, interpreter and
+/// shell hooks, helper commands), where configuration and trust come from,
+/// or where traffic goes.
+pub const DENIED_ENV_VARS: &[&str] = &[
+    "LD_*",
+    "DYLD_*",
+    "PATH",
+    "HOME",
+    "XDG_CONFIG_HOME",
+    "IFS",
+    "ENV",
+    "BASH_ENV",
+    "BASH_FUNC_*",
+    "SHELLOPTS",
+    "BASHOPTS",
+    "PS4",
+    "PROMPT_COMMAND",
+    "SHELL",
+    "EDITOR",
+    "VISUAL",
+    "PAGER",
+    "BROWSER",
+    "GIT_*",
+    "SSH_ASKPASS",
+    "SSH_AUTH_SOCK",
+    "PYTHONPATH",
+    "PYTHONHOME",
+    "PYTHONSTARTUP",
+    "PERL5LIB",
+    "PERL5OPT",
+    "PERLLIB",
+    "RUBYLIB",
+    "RUBYOPT",
+    "NODE_OPTIONS",
+    "NODE_PATH",
+    "JAVA_TOOL_OPTIONS",
+    "_JAVA_OPTIONS",
+    "SSL_CERT_FILE",
+    "SSL_CERT_DIR",
+    "CURL_CA_BUNDLE",
+    "REQUESTS_CA_BUNDLE",
+    "NODE_EXTRA_CA_CERTS",
+    "HTTP_PROXY",
+    "HTTPS_PROXY",
+    "ALL_PROXY",
+    "NO_PROXY",
+    "http_proxy",
+    "https_proxy",
+    "all_proxy",
+    "no_proxy",
+];
+
+/// Decides which client environment variables reach a tool: those matching
+/// one of the passthrough patterns, unless they are in `DENIED_ENV_VARS`.
+pub struct EnvFilter {
+    allow: Vec<Pattern>,
+    deny: Vec<Pattern>,
+}
+
+impl EnvFilter {
+    pub fn new(passthrough: &[String]) -> Result<Self, PolicyError> {
+        Ok(Self {
+            allow: compile(passthrough.iter().map(String::as_str))?,
+            deny: compile(DENIED_ENV_VARS.iter().copied())?,
+        })
+    }
+
+    /// True if the variable is in the built-in deny set
+    pub fn is_denied(&self, name: &str) -> bool {
+        self.deny.iter().any(|pattern| pattern.matches(name))
+    }
+
+    pub fn allows(&self, name: &str) -> bool {
+        !self.is_denied(name) && self.allow.iter().any(|pattern| pattern.matches(name))
+    }
+
+    /// Split `env` into the variables passed through and the sorted names
+    /// of those dropped
+    pub fn apply(&self, env: &HashMap<String, String>) -> (HashMap<String, String>, Vec<String>) {
+        let mut passed = HashMap::new();
+        let mut dropped = Vec::new();
+        for (name, value) in env {
+            if self.allows(name) {
+                passed.insert(name.clone(), value.clone());
+            } else {
+                dropped.push(name.clone());
+            }
+        }
+        dropped.sort();
+        (passed, dropped)
+    }
+}
+
+fn compile<'a>(patterns: impl Iterator<Item = &'a str>) -> Result<Vec<Pattern>, PolicyError> {
+    patterns
+        .map(|p| Pattern::new(p).map_err(|e| PolicyError::InvalidPattern(e.to_string())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passthrough_patterns() {
+        let filter = EnvFilter::new(&["GH_*".to_string(), "LANG".to_string()]).unwrap();
+        assert!(filter.allows("GH_HOST"));
+        assert!(filter.allows("LANG"));
+        assert!(!filter.allows("LC_ALL"));
+        assert!(!filter.allows("AWS_SECRET_ACCESS_KEY"));
+    }
+
+    #[test]
+    fn test_builtin_deny_wins() {
+        let filter = EnvFilter::new(&["*".to_string()]).unwrap();
+        assert!(filter.allows("TERM"));
+        for name in [
+            "LD_PRELOAD",
+            "PATH",
+            "GIT_SSH_COMMAND",
+            "BASH_ENV",
+            "https_proxy",
+        ] {
+            assert!(filter.is_denied(name), "{} should be denied", name);
+            assert!(!filter.allows(name));
+        }
+    }
+
+    #[test]
+    fn test_apply_reports_dropped_names() {
+        let filter = EnvFilter::new(&["TERM".to_string()]).unwrap();
+        let env = HashMap::from([
+            ("TERM".to_string(), "xterm".to_string()),
+            ("PATH".to_string(), "/tmp/evil".to_string()),
+            ("LD_PRELOAD".to_string(), "/tmp/evil.so".to_string()),
+        ]);
+        let (passed, dropped) = filter.apply(&env);
+        assert_eq!(passed.len(), 1);
+        assert_eq!(passed["TERM"], "xterm");
+        assert_eq!(dropped, ["LD_PRELOAD", "PATH"]);
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        assert!(EnvFilter::new(&["[".to_string()]).is_err());
+    }
+}
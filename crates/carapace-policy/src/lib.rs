@@ -1,11 +1,19 @@
 pub mod config;
+pub mod decision;
+pub mod env;
 pub mod error;
 pub mod matcher;
+pub mod testing;
 pub mod validator;
 
 pub use config::{
-    AuditConfig, CliPolicy, HttpPolicy, ParamFilter, PolicyConfig, RateLimit, ToolPolicy,
+    ApprovalRule, AuditConfig, BindMount, CliPolicy, HttpPolicy, NamespacePolicy,
+    OutputLimitAction, ParamFilter, PolicyConfig, PolicyMode, RateLimit, RlimitPolicy,
+    SandboxPolicy, SeccompPolicy, ToolPolicy,
 };
+pub use decision::{Decision, PolicySource, RuleKind, RuleLocation, NO_ALLOW_MATCHED};
+pub use env::{EnvFilter, DENIED_ENV_VARS};
 pub use error::PolicyError;
-pub use matcher::ArgvMatcher;
-pub use validator::PolicyValidator;
+pub use matcher::{ArgvMatch, ArgvMatcher};
+pub use testing::{CaseResult, Expectation, PolicyTestCase, PolicyTestFile};
+pub use validator::{ParamMatch, PolicyValidator};
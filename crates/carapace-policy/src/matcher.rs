@@ -13,6 +13,26 @@ enum GlobPattern {
     Regex(Regex),
 }
 
+impl GlobPattern {
+    fn as_str(&self) -> &str {
+        match self {
+            GlobPattern::Simple(p) => p.as_str(),
+            GlobPattern::Regex(r) => r.as_str(),
+        }
+    }
+}
+
+/// Outcome of matching argv, with the pattern that decided it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgvMatch<'a> {
+    /// A deny pattern matched
+    Denied(&'a str),
+    /// No deny pattern matched, and this allow pattern did
+    Allowed(&'a str),
+    /// Neither a deny nor an allow pattern matched
+    NoAllow,
+}
+
 impl ArgvMatcher {
     pub fn new(
         allow_patterns: Vec<String>,
@@ -42,23 +62,29 @@ impl ArgvMatcher {
 
     /// Match argv against allow/deny patterns
     pub fn matches(&self, argv: &[String]) -> bool {
+        matches!(self.check(argv), ArgvMatch::Allowed(_))
+    }
+
+    /// Match argv against allow/deny patterns, reporting the pattern that
+    /// decided
+    pub fn check(&self, argv: &[String]) -> ArgvMatch<'_> {
         let argv_str = argv.join(" ");
 
         // Check deny patterns first - they take precedence
         for pattern in &self.deny_patterns {
             if self.pattern_matches(&argv_str, pattern) {
-                return false;
+                return ArgvMatch::Denied(pattern.as_str());
             }
         }
 
         // Check allow patterns
         for pattern in &self.allow_patterns {
             if self.pattern_matches(&argv_str, pattern) {
-                return true;
+                return ArgvMatch::Allowed(pattern.as_str());
             }
         }
 
-        false
+        ArgvMatch::NoAllow
     }
 
     fn pattern_matches(&self, input: &str, pattern: &GlobPattern) -> bool {
@@ -99,6 +125,28 @@ mod tests {
         ]));
     }
 
+    #[test]
+    fn test_check_reports_deciding_pattern() {
+        let matcher = ArgvMatcher::new(
+            vec!["pr *".to_string(), "*".to_string()],
+            vec!["* --token *".to_string()],
+        )
+        .expect("matcher creation failed");
+
+        assert_eq!(
+            matcher.check(&["pr".to_string(), "list".to_string()]),
+            ArgvMatch::Allowed("pr *")
+        );
+        assert_eq!(
+            matcher.check(&["pr".to_string(), "--token".to_string(), "x".to_string()]),
+            ArgvMatch::Denied("* --token *")
+        );
+
+        let matcher =
+            ArgvMatcher::new(vec!["pr *".to_string()], vec![]).expect("matcher creation failed");
+        assert_eq!(matcher.check(&["issue".to_string()]), ArgvMatch::NoAllow);
+    }
+
     #[test]
     fn test_empty_argv() {
         let matcher = ArgvMatcher::new(vec!["anything".to_string()], vec![])
@@ -0,0 +1,334 @@
+//! Policy test files: requests with the decision a policy should make on
+//! them, checked the way the server checks requests, without running
+//! anything.
+//!
+//! ```yaml
+//! policy: 1password.yaml        # default: this file's name without .test
+//! cases:
+//!   - name: items can be listed
+//!     tool: op
+//!     argv: [item, list]
+//!     expect: allow
+//!   - tool: op
+//!     argv: [item, delete, x]
+//!     expect: deny
+//!     rule: argv_deny           # optional
+//!     matched: "item delete *"  # optional
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::config::PolicyConfig;
+use crate::decision::{Decision, RuleKind};
+use crate::error::PolicyError;
+
+/// A list of cases for one policy
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyTestFile {
+    /// The policy under test, relative to the test file
+    #[serde(default)]
+    pub policy: Option<String>,
+    pub cases: Vec<PolicyTestCase>,
+}
+
+/// A request and the decision expected on it. CLI requests give `argv`;
+/// HTTP requests give `method` and `params` of a JSON-RPC call, or a raw
+/// `body`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyTestCase {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub tool: String,
+    #[serde(default)]
+    pub argv: Option<Vec<String>>,
+    #[serde(default)]
+    pub method: Option<String>,
+    #[serde(default)]
+    pub params: Option<serde_json::Value>,
+    #[serde(default)]
+    pub body: Option<String>,
+    pub expect: Expectation,
+    /// Rule expected to decide
+    #[serde(default)]
+    pub rule: Option<RuleKind>,
+    /// Pattern, method or argument expected to decide
+    #[serde(default)]
+    pub matched: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Expectation {
+    Allow,
+    Deny,
+}
+
+/// The outcome of one case
+#[derive(Debug, Clone, Serialize)]
+pub struct CaseResult {
+    pub name: String,
+    pub passed: bool,
+    /// The policy's decision, unless the case could not be evaluated
+    pub decision: Option<Decision>,
+    /// How the decision differs from what was expected
+    pub failures: Vec<String>,
+}
+
+impl PolicyTestFile {
+    pub fn from_file(path: &Path) -> Result<Self, PolicyError> {
+        let content = std::fs::read_to_string(path)?;
+        serde_yaml::from_str(&content)
+            .map_err(|e| PolicyError::YamlError(format!("{}: {}", path.display(), e)))
+    }
+
+    /// The policy `test_file` is for: its `policy`, else the file next to
+    /// it with `.test` dropped from the name
+    pub fn policy_path(&self, test_file: &Path) -> Result<PathBuf, PolicyError> {
+        let dir = test_file.parent().unwrap_or(Path::new(""));
+        if let Some(policy) = &self.policy {
+            return Ok(dir.join(policy));
+        }
+        let name = test_file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        [".test.yaml", ".test.yml"]
+            .iter()
+            .find_map(|suffix| name.strip_suffix(suffix))
+            .map(|stem| dir.join(format!("{}.yaml", stem)))
+            .ok_or_else(|| {
+                PolicyError::ConfigError(format!(
+                    "{}: set `policy`, or name the file <policy>.test.yaml",
+                    test_file.display()
+                ))
+            })
+    }
+
+    pub fn run(&self, policy: &PolicyConfig) -> Vec<CaseResult> {
+        self.cases.iter().map(|case| case.run(policy)).collect()
+    }
+}
+
+impl PolicyTestCase {
+    /// The case's name, or else the request it makes
+    pub fn name(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        match (&self.argv, &self.method) {
+            (Some(argv), _) => format!("{} {}", self.tool, argv.join(" ")),
+            (None, Some(method)) => format!("{} {}", self.tool, method),
+            (None, None) => format!("{} (body)", self.tool),
+        }
+    }
+
+    /// The policy's decision on the request, as the CLI or HTTP dispatcher
+    /// makes it
+    pub fn decide(&self, policy: &PolicyConfig) -> Result<Decision, PolicyError> {
+        if let Some(argv) = &self.argv {
+            return Ok(policy.decide_cli(&self.tool, argv));
+        }
+        let body = match (&self.method, &self.body) {
+            (Some(method), None) => {
+                let mut call = serde_json::json!({"jsonrpc": "2.0", "method": method, "id": 1});
+                if let Some(params) = &self.params {
+                    call["params"] = params.clone();
+                }
+                call.to_string()
+            }
+            (None, Some(body)) => body.clone(),
+            _ => {
+                return Err(PolicyError::ConfigError(
+                    "a case needs `argv`, `method` or `body` (only one of the last two)"
+                        .to_string(),
+                ))
+            }
+        };
+        Ok(policy.decide_http(&self.tool, Some(body.as_bytes())))
+    }
+
+    pub fn run(&self, policy: &PolicyConfig) -> CaseResult {
+        let name = self.name();
+        let decision = match self.decide(policy) {
+            Ok(decision) => decision,
+            Err(e) => {
+                return CaseResult {
+                    name,
+                    passed: false,
+                    decision: None,
+                    failures: vec![e.to_string()],
+                }
+            }
+        };
+
+        let mut failures = Vec::new();
+        // Requests go through unless the policy enforces the denial, as in
+        // audit-only mode
+        let outcome = if policy.enforces(&decision) {
+            Expectation::Deny
+        } else {
+            Expectation::Allow
+        };
+        if outcome != self.expect {
+            failures.push(format!("expected {:?}, got {:?}", self.expect, outcome).to_lowercase());
+        }
+        if let Some(rule) = self.rule {
+            if rule != decision.rule {
+                failures.push(format!("expected rule {}, got {}", rule, decision.rule));
+            }
+        }
+        if let Some(matched) = &self.matched {
+            if Some(matched) != decision.matched.as_ref() {
+                failures.push(format!(
+                    "expected match '{}', got {}",
+                    matched,
+                    decision
+                        .matched
+                        .as_deref()
+                        .map_or("none".to_string(), |m| format!("'{}'", m))
+                ));
+            }
+        }
+
+        CaseResult {
+            name,
+            passed: failures.is_empty(),
+            decision: Some(decision),
+            failures,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = r#"
+mode: enforce
+tools:
+  gh:
+    type: cli
+    binary: /usr/bin/gh
+    argv_allow_patterns: ["pr *"]
+    argv_deny_patterns: ["pr merge *"]
+  rpc:
+    type: http
+    upstream: "http://127.0.0.1:1"
+    jsonrpc_allow_methods: [send]
+    jsonrpc_param_filters:
+      send:
+        field: recipient
+        allow_patterns: ["+1*"]
+        deny_patterns: []
+  shadow:
+    type: cli
+    binary: /usr/bin/shadow
+    mode: audit_only
+    argv_allow_patterns: ["status"]
+"#;
+
+    #[test]
+    fn test_cases_pass_and_fail() {
+        let policy: PolicyConfig = serde_yaml::from_str(POLICY).unwrap();
+        let tests: PolicyTestFile = serde_yaml::from_str(
+            r#"
+cases:
+  - tool: gh
+    argv: [pr, list]
+    expect: allow
+    rule: argv_allow
+  - name: merging is denied
+    tool: gh
+    argv: [pr, merge, "1"]
+    expect: deny
+    rule: argv_deny
+    matched: "pr merge *"
+  - tool: rpc
+    method: send
+    params: {recipient: ["+44123"]}
+    expect: deny
+    rule: param_allow
+  - tool: shadow
+    argv: [rm]
+    expect: allow
+  - tool: gh
+    argv: [issue, list]
+    expect: allow
+    matched: "issue *"
+  - tool: rpc
+    expect: allow
+"#,
+        )
+        .unwrap();
+
+        let results = tests.run(&policy);
+        let passed: Vec<bool> = results.iter().map(|r| r.passed).collect();
+        assert_eq!(passed, [true, true, true, true, false, false]);
+        assert_eq!(results[0].name, "gh pr list");
+        assert_eq!(results[1].name, "merging is denied");
+        assert_eq!(
+            results[4].failures,
+            [
+                "expected allow, got deny",
+                "expected match 'issue *', got 'no allow matched'"
+            ]
+        );
+        assert!(results[5].decision.is_none());
+    }
+
+    #[test]
+    fn test_policy_path() {
+        let tests = PolicyTestFile {
+            policy: None,
+            cases: Vec::new(),
+        };
+        assert_eq!(
+            tests
+                .policy_path(Path::new("examples/policies/op.test.yaml"))
+                .unwrap(),
+            Path::new("examples/policies/op.yaml")
+        );
+        assert!(tests.policy_path(Path::new("cases.yaml")).is_err());
+
+        let tests = PolicyTestFile {
+            policy: Some("../op.yaml".to_string()),
+            cases: Vec::new(),
+        };
+        assert_eq!(
+            tests.policy_path(Path::new("tests/op.yaml")).unwrap(),
+            Path::new("tests/../op.yaml")
+        );
+    }
+
+    #[test]
+    fn test_example_policies_pass_their_tests() {
+        let dir = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../examples/policies"
+        ));
+        let mut files = 0;
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if !path.to_string_lossy().ends_with(".test.yaml") {
+                continue;
+            }
+            files += 1;
+            let tests = PolicyTestFile::from_file(&path).unwrap();
+            let policy_path = tests.policy_path(&path).unwrap();
+            let policy = PolicyConfig::from_file(policy_path.to_str().unwrap()).unwrap();
+            for result in tests.run(&policy) {
+                assert!(
+                    result.passed,
+                    "{}: {}: {:?}",
+                    path.display(),
+                    result.name,
+                    result.failures
+                );
+            }
+        }
+        assert!(files >= 3);
+    }
+}
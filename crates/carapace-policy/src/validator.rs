@@ -3,6 +3,17 @@ use crate::error::PolicyError;
 use glob::Pattern;
 use std::collections::HashMap;
 
+/// Outcome of matching a JSON-RPC param, with the pattern that decided it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamMatch<'a> {
+    /// A deny pattern matched
+    Denied(&'a str),
+    /// This allow pattern matched, or the filter has no allow patterns
+    Allowed(Option<&'a str>),
+    /// The filter has allow patterns, but none matched
+    NoAllow,
+}
+
 /// Validator for request-specific policy validation
 pub struct PolicyValidator;
 
@@ -39,82 +50,93 @@ impl PolicyValidator {
         filters: &HashMap<String, ParamFilter>,
     ) -> Result<(), PolicyError> {
         // Check if this method has param filters
-        if let Some(filter) = filters.get(method) {
-            // Parse JSON body
-            let json: serde_json::Value = serde_json::from_str(body)
-                .map_err(|e| PolicyError::Violation(format!("Invalid JSON: {}", e)))?;
-
-            // Extract params object
-            let params = json.get("params").ok_or_else(|| {
-                PolicyError::Violation("Missing params field in JSON-RPC request".to_string())
-            })?;
+        let Some(filter) = filters.get(method) else {
+            return Ok(());
+        };
+
+        let field_value = Self::param_value(body, filter)?;
+        match Self::match_param(&field_value, filter)? {
+            ParamMatch::Denied(pattern) => Err(PolicyError::Violation(format!(
+                "Param '{}' value '{}' matches deny pattern '{}'",
+                filter.field, field_value, pattern
+            ))),
+            ParamMatch::NoAllow => Err(PolicyError::Violation(format!(
+                "Param '{}' value '{}' not in allow list",
+                filter.field, field_value
+            ))),
+            ParamMatch::Allowed(_) => Ok(()),
+        }
+    }
 
-            // Extract the field to filter on (handle both string and array values)
-            let field_value = params
-                .get(&filter.field)
-                .and_then(|v| {
-                    // Try as string first
-                    if let Some(s) = v.as_str() {
-                        return Some(s.to_string());
-                    }
-                    // If it's an array, take the first element as a string
-                    if let Some(arr) = v.as_array() {
-                        if let Some(first) = arr.first() {
-                            if let Some(s) = first.as_str() {
-                                return Some(s.to_string());
-                            }
+    /// The value of the filtered field in a JSON-RPC body's params
+    pub fn param_value(body: &str, filter: &ParamFilter) -> Result<String, PolicyError> {
+        // Parse JSON body
+        let json: serde_json::Value = serde_json::from_str(body)
+            .map_err(|e| PolicyError::Violation(format!("Invalid JSON: {}", e)))?;
+
+        // Extract params object
+        let params = json.get("params").ok_or_else(|| {
+            PolicyError::Violation("Missing params field in JSON-RPC request".to_string())
+        })?;
+
+        // Extract the field to filter on (handle both string and array values)
+        params
+            .get(&filter.field)
+            .and_then(|v| {
+                // Try as string first
+                if let Some(s) = v.as_str() {
+                    return Some(s.to_string());
+                }
+                // If it's an array, take the first element as a string
+                if let Some(arr) = v.as_array() {
+                    if let Some(first) = arr.first() {
+                        if let Some(s) = first.as_str() {
+                            return Some(s.to_string());
                         }
                     }
-                    None
-                })
-                .ok_or_else(|| {
-                    PolicyError::Violation(format!(
-                        "Missing or invalid field '{}' in params",
-                        filter.field
-                    ))
-                })?;
-
-            // Check deny patterns first (deny-first semantics)
-            for pattern_str in &filter.deny_patterns {
-                let pattern = Pattern::new(pattern_str).map_err(|e| {
-                    PolicyError::Violation(format!("Invalid deny pattern '{}': {}", pattern_str, e))
-                })?;
-
-                if pattern.matches(&field_value) {
-                    return Err(PolicyError::Violation(format!(
-                        "Param '{}' value '{}' matches deny pattern '{}'",
-                        filter.field, field_value, pattern_str
-                    )));
                 }
+                None
+            })
+            .ok_or_else(|| {
+                PolicyError::Violation(format!(
+                    "Missing or invalid field '{}' in params",
+                    filter.field
+                ))
+            })
+    }
+
+    /// Match a param value against the filter's patterns, reporting the
+    /// pattern that decided
+    pub fn match_param<'a>(
+        value: &str,
+        filter: &'a ParamFilter,
+    ) -> Result<ParamMatch<'a>, PolicyError> {
+        // Check deny patterns first (deny-first semantics)
+        for pattern_str in &filter.deny_patterns {
+            let pattern = Pattern::new(pattern_str).map_err(|e| {
+                PolicyError::Violation(format!("Invalid deny pattern '{}': {}", pattern_str, e))
+            })?;
+
+            if pattern.matches(value) {
+                return Ok(ParamMatch::Denied(pattern_str));
             }
+        }
 
-            // If allow patterns exist, check them (whitelist mode)
-            if !filter.allow_patterns.is_empty() {
-                let mut allowed = false;
-                for pattern_str in &filter.allow_patterns {
-                    let pattern = Pattern::new(pattern_str).map_err(|e| {
-                        PolicyError::Violation(format!(
-                            "Invalid allow pattern '{}': {}",
-                            pattern_str, e
-                        ))
-                    })?;
-
-                    if pattern.matches(&field_value) {
-                        allowed = true;
-                        break;
-                    }
-                }
+        // If allow patterns exist, check them (whitelist mode)
+        if filter.allow_patterns.is_empty() {
+            return Ok(ParamMatch::Allowed(None));
+        }
+        for pattern_str in &filter.allow_patterns {
+            let pattern = Pattern::new(pattern_str).map_err(|e| {
+                PolicyError::Violation(format!("Invalid allow pattern '{}': {}", pattern_str, e))
+            })?;
 
-                if !allowed {
-                    return Err(PolicyError::Violation(format!(
-                        "Param '{}' value '{}' not in allow list",
-                        filter.field, field_value
-                    )));
-                }
+            if pattern.matches(value) {
+                return Ok(ParamMatch::Allowed(Some(pattern_str)));
             }
         }
 
-        Ok(())
+        Ok(ParamMatch::NoAllow)
     }
 
     /// Validate binary path doesn't have traversal attempts
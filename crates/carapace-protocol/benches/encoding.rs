@@ -0,0 +1,85 @@
+//! Compare wire encodings on 1 MB payloads.
+//!
+//! Run with `cargo bench -p carapace-protocol --bench encoding`.
+
+use bytes::BytesMut;
+use carapace_protocol::{CliResponse, HttpResponse, Message, MessageCodec, WireEncoding};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::collections::HashMap;
+use tokio_util::codec::{Decoder, Encoder};
+
+const ONE_MB: usize = 1024 * 1024;
+
+/// Text output, e.g. `gh api` pagination dumps
+fn text_output() -> Message {
+    let line = "{\"number\":1234,\"title\":\"Fix flaky test\",\"state\":\"open\"}\n";
+    let stdout = line.repeat(ONE_MB / line.len());
+    Message::CliResponse(CliResponse::from_output(
+        "bench-text".to_string(),
+        0,
+        stdout.as_bytes(),
+        b"",
+    ))
+}
+
+/// Binary output, e.g. `op document get`, which travels base64-encoded
+fn binary_output() -> Message {
+    let stdout: Vec<u8> = (0..ONE_MB).map(|i| (i * 31 % 251) as u8).collect();
+    Message::CliResponse(CliResponse::from_output(
+        "bench-binary".to_string(),
+        0,
+        &stdout,
+        b"",
+    ))
+}
+
+/// Large JSON-RPC response body
+fn http_output() -> Message {
+    let item = "{\"envelope\":{\"source\":\"+15555550123\",\"timestamp\":1700000000000}},";
+    let body = format!("[{}]", item.repeat(ONE_MB / item.len()));
+    Message::HttpResponse(HttpResponse {
+        id: "bench-http".to_string(),
+        status: 200,
+        headers: HashMap::new(),
+        body: Some(body),
+        encoding: Default::default(),
+    })
+}
+
+fn bench_encodings(c: &mut Criterion) {
+    let payloads = [
+        ("cli_text", text_output()),
+        ("cli_binary", binary_output()),
+        ("http_json", http_output()),
+    ];
+
+    for (name, msg) in &payloads {
+        let mut group = c.benchmark_group(format!("roundtrip_{}", name));
+        group.throughput(Throughput::Bytes(ONE_MB as u64));
+
+        for encoding in WireEncoding::ALL {
+            let mut codec = MessageCodec::with_encoding(encoding);
+            let mut frame = BytesMut::new();
+            codec.encode(msg.clone(), &mut frame).unwrap();
+            println!("{}/{}: {} byte frame", name, encoding, frame.len());
+
+            group.bench_with_input(BenchmarkId::new("encode", encoding), msg, |b, msg| {
+                b.iter(|| {
+                    let mut buf = BytesMut::new();
+                    codec.encode(msg.clone(), &mut buf).unwrap();
+                    buf
+                })
+            });
+            group.bench_with_input(BenchmarkId::new("decode", encoding), &frame, |b, frame| {
+                b.iter(|| {
+                    let mut buf = frame.clone();
+                    codec.decode(&mut buf).unwrap().unwrap()
+                })
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_encodings);
+criterion_main!(benches);
@@ -0,0 +1,103 @@
+//! Capture file format, shared by the server, which writes forensic
+//! captures, and `carapace-debug`, which reads them back.
+//!
+//! A capture file is `MAGIC || nonce || ciphertext`: the JSON record sealed
+//! with ChaCha20-Poly1305 under the store key, with a random 96-bit nonce
+//! and `MAGIC` as associated data. It is named
+//! `<capture_file_prefix(request_id)>-<uuid>.cap`.
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use sha2::{Digest, Sha256};
+
+use crate::error::CaptureError;
+
+/// Start of every capture file, naming the format version
+pub const MAGIC: &[u8; 8] = b"CRPCAP02";
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Start of the file names of a request's captures, which end in
+/// `-<uuid>.cap`. Request ids come from the client, so they are hashed
+/// rather than used as paths.
+pub fn capture_file_prefix(request_id: &str) -> String {
+    format!("{:x}", Sha256::digest(request_id.as_bytes()))
+}
+
+/// Encrypt and authenticate `plaintext` under `key`
+pub fn seal(key: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>, CaptureError> {
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad: MAGIC,
+            },
+        )
+        .map_err(|_| CaptureError::Encrypt)?;
+
+    let mut out = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+/// Check and decrypt what `seal` produced
+pub fn unseal(key: &[u8; 32], sealed: &[u8]) -> Result<Vec<u8>, CaptureError> {
+    let header = MAGIC.len() + NONCE_LEN;
+    if sealed.len() < header + TAG_LEN || &sealed[..MAGIC.len()] != MAGIC {
+        return Err(CaptureError::NotACapture);
+    }
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(
+            Nonce::from_slice(&sealed[MAGIC.len()..header]),
+            Payload {
+                msg: &sealed[header..],
+                aad: MAGIC,
+            },
+        )
+        .map_err(|_| CaptureError::Unauthenticated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_round_trip_and_tamper() {
+        let key = [3u8; 32];
+        let plaintext = b"the secret output of a tool, longer than one block".to_vec();
+        let sealed = seal(&key, &plaintext).unwrap();
+        assert!(!sealed
+            .windows(10)
+            .any(|w| plaintext.windows(10).any(|p| p == w)));
+        assert_eq!(unseal(&key, &sealed).unwrap(), plaintext);
+
+        assert!(matches!(
+            unseal(&[4u8; 32], &sealed),
+            Err(CaptureError::Unauthenticated)
+        ));
+        let mut tampered = sealed.clone();
+        tampered[MAGIC.len() + NONCE_LEN + 3] ^= 1;
+        assert!(unseal(&key, &tampered).is_err());
+        assert!(unseal(&key, &sealed[..sealed.len() - 1]).is_err());
+        assert!(matches!(
+            unseal(&key, b"CRPCAP01 an older format"),
+            Err(CaptureError::NotACapture)
+        ));
+        // A fresh nonce every time
+        assert_ne!(seal(&key, &plaintext).unwrap(), sealed);
+    }
+
+    #[test]
+    fn test_file_prefix_hides_the_request_id() {
+        let prefix = capture_file_prefix("../../etc/passwd");
+        assert_eq!(prefix.len(), 64);
+        assert!(prefix.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(prefix, capture_file_prefix("../../etc/passwd"));
+        assert_ne!(prefix, capture_file_prefix("../../etc/shadow"));
+    }
+}
@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+
+use crate::error::ProtocolError;
+use crate::framing::WireEncoding;
+use crate::messages::{Chunk, Message, RequestId};
+
+/// Serialized messages larger than this are split into chunks. Small enough
+/// that a Ping or another response can be written between two chunks
+/// without a noticeable delay.
+pub const CHUNK_SIZE: usize = 256 * 1024;
+
+/// Largest message a `Reassembler` accepts by default (1 GB)
+pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024 * 1024;
+
+/// Most partially received messages a `Reassembler` tracks by default
+pub const MAX_PENDING_MESSAGES: usize = 64;
+
+/// Split a message into `Chunk` messages of at most `chunk_size` bytes of
+/// the message serialized with `encoding`, the encoding of the frames they
+/// go out in. Messages that already fit are returned unchanged.
+pub fn split(
+    msg: Message,
+    chunk_size: usize,
+    encoding: WireEncoding,
+) -> Result<Vec<Message>, ProtocolError> {
+    let bytes = encoding.to_vec(&msg)?;
+    if bytes.len() <= chunk_size {
+        return Ok(vec![msg]);
+    }
+
+    let id = msg
+        .id()
+        .ok_or_else(|| ProtocolError::InvalidMessage("Cannot chunk a message without id".into()))?
+        .to_string();
+    let total = bytes.len().div_ceil(chunk_size) as u32;
+
+    Ok(bytes
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(seq, data)| {
+            Message::Chunk(Chunk {
+                id: id.clone(),
+                seq: seq as u32,
+                total,
+                encoding,
+                data: data.to_vec(),
+            })
+        })
+        .collect())
+}
+
+struct PartialMessage {
+    total: u32,
+    encoding: WireEncoding,
+    next_seq: u32,
+    data: Vec<u8>,
+}
+
+/// Rebuilds chunked messages, enforcing limits on message size and on the
+/// number of messages in flight so a misbehaving peer cannot exhaust memory.
+pub struct Reassembler {
+    pending: HashMap<RequestId, PartialMessage>,
+    max_message_size: usize,
+    max_pending: usize,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self::with_limits(MAX_MESSAGE_SIZE, MAX_PENDING_MESSAGES)
+    }
+
+    pub fn with_limits(max_message_size: usize, max_pending: usize) -> Self {
+        Reassembler {
+            pending: HashMap::new(),
+            max_message_size,
+            max_pending,
+        }
+    }
+
+    /// Add a chunk. Returns the original message once its last chunk arrives.
+    ///
+    /// On error the partial message is discarded; later chunks with the same
+    /// id are rejected as out of sequence.
+    pub fn push(&mut self, chunk: Chunk) -> Result<Option<Message>, ProtocolError> {
+        let result = self.try_push(&chunk);
+        if result.is_err() {
+            self.pending.remove(&chunk.id);
+        }
+        result
+    }
+
+    fn try_push(&mut self, chunk: &Chunk) -> Result<Option<Message>, ProtocolError> {
+        if chunk.total == 0 || chunk.seq >= chunk.total {
+            return Err(ProtocolError::InvalidMessage(format!(
+                "Invalid chunk {}/{} for {}",
+                chunk.seq, chunk.total, chunk.id
+            )));
+        }
+
+        if chunk.seq == 0 {
+            if self.pending.contains_key(&chunk.id) {
+                return Err(ProtocolError::InvalidMessage(format!(
+                    "Duplicate chunked message: {}",
+                    chunk.id
+                )));
+            }
+            if self.pending.len() >= self.max_pending {
+                return Err(ProtocolError::InvalidMessage(format!(
+                    "Too many chunked messages in flight (max {})",
+                    self.max_pending
+                )));
+            }
+            self.pending.insert(
+                chunk.id.clone(),
+                PartialMessage {
+                    total: chunk.total,
+                    encoding: chunk.encoding,
+                    next_seq: 0,
+                    data: Vec::new(),
+                },
+            );
+        }
+
+        let partial = self.pending.get_mut(&chunk.id).ok_or_else(|| {
+            ProtocolError::InvalidMessage(format!("Chunk out of sequence for {}", chunk.id))
+        })?;
+        if chunk.seq != partial.next_seq || chunk.total != partial.total {
+            return Err(ProtocolError::InvalidMessage(format!(
+                "Chunk out of sequence for {}: got {}/{}, expected {}/{}",
+                chunk.id, chunk.seq, chunk.total, partial.next_seq, partial.total
+            )));
+        }
+        if chunk.encoding != partial.encoding {
+            return Err(ProtocolError::InvalidMessage(format!(
+                "Chunk encoding changed for {}: {} after {}",
+                chunk.id, chunk.encoding, partial.encoding
+            )));
+        }
+
+        if partial.data.len() + chunk.data.len() > self.max_message_size {
+            return Err(ProtocolError::FrameTooLarge(
+                (partial.data.len() + chunk.data.len()).min(u32::MAX as usize) as u32,
+            ));
+        }
+        partial.data.extend_from_slice(&chunk.data);
+        partial.next_seq += 1;
+
+        if partial.next_seq < partial.total {
+            return Ok(None);
+        }
+
+        let partial = self
+            .pending
+            .remove(&chunk.id)
+            .expect("partial message present");
+        Ok(Some(partial.encoding.from_slice(&partial.data)?))
+    }
+
+    /// Drop all partial messages (e.g. after the connection was replaced)
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Number of partially received messages
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{CliResponse, ErrorMessage};
+
+    fn large_response(id: &str, size: usize) -> Message {
+        Message::CliResponse(CliResponse::from_output(
+            id.to_string(),
+            0,
+            "x".repeat(size).as_bytes(),
+            b"",
+        ))
+    }
+
+    fn chunks(msg: Message, chunk_size: usize) -> Vec<Chunk> {
+        split(msg, chunk_size, WireEncoding::Json)
+            .unwrap()
+            .into_iter()
+            .map(|m| match m {
+                Message::Chunk(c) => c,
+                other => panic!("Expected Chunk, got {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_small_message_not_split() {
+        let msg = Message::Error(ErrorMessage {
+            id: Some("e".to_string()),
+            code: "x".to_string(),
+            message: "small".to_string(),
+            denial: None,
+        });
+        let frames = split(msg, CHUNK_SIZE, WireEncoding::Json).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert!(matches!(frames[0], Message::Error(_)));
+    }
+
+    #[test]
+    fn test_split_and_reassemble() {
+        let parts = chunks(large_response("big", 10_000), 1000);
+        assert!(parts.len() > 1);
+        assert!(parts.iter().all(|c| c.total as usize == parts.len()));
+
+        let mut reassembler = Reassembler::new();
+        let last = parts.len() - 1;
+        for (i, chunk) in parts.into_iter().enumerate() {
+            let result = reassembler.push(chunk).unwrap();
+            if i < last {
+                assert!(result.is_none());
+            } else {
+                match result {
+                    Some(Message::CliResponse(resp)) => assert_eq!(resp.stdout.len(), 10_000),
+                    other => panic!("Expected CliResponse, got {:?}", other),
+                }
+            }
+        }
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn test_chunks_use_the_wire_encoding() {
+        use crate::framing::MessageCodec;
+        use bytes::BytesMut;
+        use tokio_util::codec::{Decoder, Encoder};
+
+        for encoding in WireEncoding::ALL {
+            let msg = large_response("enc", 20_000);
+            let size = encoding.to_vec(&msg).unwrap().len();
+            let parts = split(msg, 4000, encoding).unwrap();
+            assert_eq!(parts.len(), size.div_ceil(4000));
+
+            let mut codec = MessageCodec::with_encoding(encoding);
+            let mut reassembler = Reassembler::new();
+            let mut framed = 0;
+            let mut done = None;
+            for part in parts {
+                let mut buf = BytesMut::new();
+                codec.encode(part, &mut buf).unwrap();
+                framed += buf.len();
+                match codec.decode(&mut buf).unwrap() {
+                    Some(Message::Chunk(chunk)) => {
+                        assert_eq!(chunk.encoding, encoding);
+                        done = reassembler.push(chunk).unwrap();
+                    }
+                    other => panic!("Expected Chunk, got {:?}", other),
+                }
+            }
+            match done {
+                Some(Message::CliResponse(resp)) => assert_eq!(resp.stdout.len(), 20_000),
+                other => panic!("Expected CliResponse, got {:?}", other),
+            }
+            // Binary encodings carry the chunk bytes as they are
+            if encoding != WireEncoding::Json {
+                assert!(framed < size + 1000, "{}: {} > {}", encoding, framed, size);
+            }
+        }
+    }
+
+    #[test]
+    fn test_interleaved_messages() {
+        let a = chunks(large_response("a", 5000), 1000);
+        let b = chunks(large_response("b", 5000), 1000);
+
+        let mut reassembler = Reassembler::new();
+        let mut done = Vec::new();
+        for (ca, cb) in a.into_iter().zip(b) {
+            done.extend(reassembler.push(ca).unwrap());
+            done.extend(reassembler.push(cb).unwrap());
+        }
+        let ids: Vec<_> = done.iter().filter_map(|m| m.id()).collect();
+        assert_eq!(ids, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_out_of_sequence_rejected() {
+        let mut parts = chunks(large_response("seq", 5000), 1000);
+        let mut reassembler = Reassembler::new();
+        reassembler.push(parts.remove(0)).unwrap();
+        assert!(reassembler.push(parts.remove(1)).is_err());
+        // The partial message was discarded
+        assert_eq!(reassembler.pending(), 0);
+        assert!(reassembler.push(parts.remove(0)).is_err());
+    }
+
+    #[test]
+    fn test_message_size_limit() {
+        let parts = chunks(large_response("huge", 5000), 1000);
+        let mut reassembler = Reassembler::with_limits(2500, MAX_PENDING_MESSAGES);
+        let result: Result<Vec<_>, _> = parts.into_iter().map(|c| reassembler.push(c)).collect();
+        assert!(matches!(result, Err(ProtocolError::FrameTooLarge(_))));
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn test_pending_limit() {
+        let mut reassembler = Reassembler::with_limits(MAX_MESSAGE_SIZE, 2);
+        for id in ["a", "b"] {
+            let first = chunks(large_response(id, 5000), 1000).remove(0);
+            assert!(reassembler.push(first).unwrap().is_none());
+        }
+        let third = chunks(large_response("c", 5000), 1000).remove(0);
+        assert!(reassembler.push(third).is_err());
+        assert_eq!(reassembler.pending(), 2);
+    }
+}
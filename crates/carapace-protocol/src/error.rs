@@ -19,6 +19,18 @@ pub enum ProtocolError {
     InvalidMessage(String),
 }
 
+#[derive(Error, Debug)]
+pub enum CaptureError {
+    #[error("Not a capture file")]
+    NotACapture,
+
+    #[error("Cannot encrypt capture")]
+    Encrypt,
+
+    #[error("Capture does not authenticate: wrong key or tampered file")]
+    Unauthenticated,
+}
+
 #[derive(Error, Debug)]
 pub enum FrameError {
     #[error("IO error: {0}")]
@@ -1,12 +1,246 @@
+use crate::error::ProtocolError;
 use crate::messages::Message;
 use bytes::{Buf, BufMut, BytesMut};
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::io;
+use std::str::FromStr;
 use tokio_util::codec::{Decoder, Encoder};
 
 /// Maximum frame size: 100 MB (prevents DoS from giant messages)
 const MAX_FRAME_SIZE: u32 = 100 * 1024 * 1024;
 
-pub struct MessageCodec;
+/// Low bits of the length prefix carry the payload length
+const LENGTH_MASK: u32 = 0x0FFF_FFFF;
+
+/// Bits 28-29 of the length prefix carry the payload encoding.
+/// Plain JSON frames leave them clear, so they stay byte-identical to
+/// frames written by peers that predate encodings.
+const ENCODING_SHIFT: u32 = 28;
+const ENCODING_MASK: u32 = 0b11 << ENCODING_SHIFT;
+
+/// Bit 31 of the length prefix marks a zstd-compressed payload; the length
+/// then counts compressed bytes
+const COMPRESSED_FLAG: u32 = 1 << 31;
+
+/// Flag bits that are not assigned yet; frames using them are rejected
+const RESERVED_MASK: u32 = !(LENGTH_MASK | ENCODING_MASK | COMPRESSED_FLAG);
+
+/// Payloads smaller than this are never compressed (not worth the CPU)
+pub const COMPRESSION_THRESHOLD: usize = 64 * 1024;
+
+/// zstd level: favours speed, large text outputs still shrink several-fold
+const ZSTD_LEVEL: i32 = 3;
+
+/// Serialization format of a frame payload.
+///
+/// JSON is the default. CBOR and MessagePack are compact binary formats that
+/// are cheaper for large outputs; they are only written once both peers have
+/// agreed on them (see `Capabilities`). Every frame records its own encoding,
+/// so decoders accept all of them regardless of what they write.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WireEncoding {
+    #[default]
+    Json,
+    Cbor,
+    #[serde(rename = "msgpack")]
+    MessagePack,
+}
+
+impl WireEncoding {
+    /// All supported encodings, JSON first
+    pub const ALL: [WireEncoding; 3] = [
+        WireEncoding::Json,
+        WireEncoding::Cbor,
+        WireEncoding::MessagePack,
+    ];
+
+    pub fn is_json(&self) -> bool {
+        *self == WireEncoding::Json
+    }
+
+    fn tag(self) -> u32 {
+        match self {
+            WireEncoding::Json => 0,
+            WireEncoding::Cbor => 1,
+            WireEncoding::MessagePack => 2,
+        }
+    }
+
+    fn from_tag(tag: u32) -> Option<Self> {
+        match tag {
+            0 => Some(WireEncoding::Json),
+            1 => Some(WireEncoding::Cbor),
+            2 => Some(WireEncoding::MessagePack),
+            _ => None,
+        }
+    }
+
+    /// Serialize a message into a frame payload
+    pub fn to_vec(self, msg: &Message) -> io::Result<Vec<u8>> {
+        match self {
+            WireEncoding::Json => Ok(serde_json::to_vec(msg)?),
+            WireEncoding::Cbor => {
+                let mut buf = Vec::new();
+                ciborium::into_writer(msg, &mut buf)
+                    .map_err(|e| invalid_data(format!("CBOR encode failed: {}", e)))?;
+                Ok(buf)
+            }
+            // Named fields are required: `Message` is internally tagged
+            WireEncoding::MessagePack => rmp_serde::to_vec_named(msg)
+                .map_err(|e| invalid_data(format!("MessagePack encode failed: {}", e))),
+        }
+    }
+
+    /// Deserialize a frame payload into a message
+    pub fn from_slice(self, payload: &[u8]) -> io::Result<Message> {
+        match self {
+            WireEncoding::Json => Ok(serde_json::from_slice(payload)?),
+            WireEncoding::Cbor => ciborium::from_reader(payload)
+                .map_err(|e| invalid_data(format!("CBOR decode failed: {}", e))),
+            WireEncoding::MessagePack => rmp_serde::from_slice(payload)
+                .map_err(|e| invalid_data(format!("MessagePack decode failed: {}", e))),
+        }
+    }
+}
+
+impl fmt::Display for WireEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireEncoding::Json => write!(f, "json"),
+            WireEncoding::Cbor => write!(f, "cbor"),
+            WireEncoding::MessagePack => write!(f, "msgpack"),
+        }
+    }
+}
+
+impl FromStr for WireEncoding {
+    type Err = ProtocolError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(WireEncoding::Json),
+            "cbor" => Ok(WireEncoding::Cbor),
+            "msgpack" | "messagepack" => Ok(WireEncoding::MessagePack),
+            other => Err(ProtocolError::InvalidMessage(format!(
+                "Unknown wire encoding: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// Per-frame compression, applied to payloads above `COMPRESSION_THRESHOLD`.
+///
+/// Like encodings, compression is only written once both peers have agreed
+/// on it, and compressed frames are flagged so decoders always accept them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    Zstd,
+}
+
+impl Compression {
+    fn compress(self, payload: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::Zstd => zstd::bulk::compress(payload, ZSTD_LEVEL),
+        }
+    }
+
+    /// Decompress a payload, refusing to produce more than `MAX_FRAME_SIZE`
+    /// bytes so a small frame cannot expand into a decompression bomb
+    fn decompress(self, payload: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::Zstd => {
+                let decoder = zstd::stream::read::Decoder::new(payload)?;
+                let mut out = Vec::new();
+                io::Read::read_to_end(
+                    &mut io::Read::take(decoder, MAX_FRAME_SIZE as u64 + 1),
+                    &mut out,
+                )?;
+                if out.len() > MAX_FRAME_SIZE as usize {
+                    return Err(invalid_data(format!(
+                        "Decompressed frame exceeds {} bytes",
+                        MAX_FRAME_SIZE
+                    )));
+                }
+                Ok(out)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = ProtocolError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "zstd" => Ok(Compression::Zstd),
+            other => Err(ProtocolError::InvalidMessage(format!(
+                "Unknown compression: {}",
+                other
+            ))),
+        }
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Length-prefixed message codec.
+///
+/// Encodes outgoing frames with the connection's current `WireEncoding` (and
+/// compression, if any) and decodes incoming frames in whatever encoding and
+/// compression they are tagged with.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessageCodec {
+    encoding: WireEncoding,
+    compression: Option<Compression>,
+}
+
+impl MessageCodec {
+    /// JSON codec (the default for every new connection)
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_encoding(encoding: WireEncoding) -> Self {
+        MessageCodec {
+            encoding,
+            compression: None,
+        }
+    }
+
+    /// Encoding used for outgoing frames
+    pub fn encoding(&self) -> WireEncoding {
+        self.encoding
+    }
+
+    /// Switch the encoding used for outgoing frames (after negotiation)
+    pub fn set_encoding(&mut self, encoding: WireEncoding) {
+        self.encoding = encoding;
+    }
+
+    /// Compression applied to large outgoing frames
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    /// Enable or disable compression of outgoing frames (after negotiation)
+    pub fn set_compression(&mut self, compression: Option<Compression>) {
+        self.compression = compression;
+    }
+}
 
 pub struct FrameError(pub String);
 
@@ -22,7 +256,17 @@ impl Decoder for MessageCodec {
         // Read the length prefix (big-endian u32)
         let mut length_bytes = [0u8; 4];
         length_bytes.copy_from_slice(&src[..4]);
-        let length = u32::from_be_bytes(length_bytes);
+        let prefix = u32::from_be_bytes(length_bytes);
+
+        if prefix & RESERVED_MASK != 0 {
+            return Err(invalid_data(format!(
+                "Unsupported frame flags: {:#010x}",
+                prefix & RESERVED_MASK
+            )));
+        }
+        let encoding = WireEncoding::from_tag((prefix & ENCODING_MASK) >> ENCODING_SHIFT)
+            .ok_or_else(|| invalid_data(format!("Unknown frame encoding in {:#010x}", prefix)))?;
+        let length = prefix & LENGTH_MASK;
 
         // Enforce maximum frame size
         if length > MAX_FRAME_SIZE {
@@ -43,8 +287,11 @@ impl Decoder for MessageCodec {
         // Extract the payload
         let payload = src.split_to(length as usize);
 
-        // Deserialize JSON
-        let msg: Message = serde_json::from_slice(&payload)?;
+        let msg = if prefix & COMPRESSED_FLAG != 0 {
+            encoding.from_slice(&Compression::Zstd.decompress(&payload)?)?
+        } else {
+            encoding.from_slice(&payload)?
+        };
         Ok(Some(msg))
     }
 }
@@ -53,19 +300,33 @@ impl Encoder<Message> for MessageCodec {
     type Error = io::Error;
 
     fn encode(&mut self, msg: Message, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let json = serde_json::to_vec(&msg)?;
+        let payload = self.encoding.to_vec(&msg)?;
 
-        let length = json.len() as u32;
-        if length > MAX_FRAME_SIZE {
+        if payload.len() > MAX_FRAME_SIZE as usize {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
-                format!("Payload too large: {} bytes", length),
+                format!("Payload too large: {} bytes", payload.len()),
             ));
         }
 
-        dst.reserve(4 + json.len());
-        dst.put_u32(length);
-        dst.put_slice(&json);
+        let mut flags = self.encoding.tag() << ENCODING_SHIFT;
+        let payload = match self.compression {
+            Some(compression) if payload.len() >= COMPRESSION_THRESHOLD => {
+                let compressed = compression.compress(&payload)?;
+                // Incompressible data (already-compressed downloads) goes as-is
+                if compressed.len() < payload.len() {
+                    flags |= COMPRESSED_FLAG;
+                    compressed
+                } else {
+                    payload
+                }
+            }
+            _ => payload,
+        };
+
+        dst.reserve(4 + payload.len());
+        dst.put_u32(payload.len() as u32 | flags);
+        dst.put_slice(&payload);
         Ok(())
     }
 }
@@ -84,58 +345,63 @@ mod tests {
             env: HashMap::new(),
             stdin: None,
             cwd: "/".to_string(),
+            tty: None,
         })
     }
 
     #[test]
     fn test_framing_encode_decode_roundtrip() {
-        let mut codec = MessageCodec;
-        let msg = create_cli_request("test-001");
+        for encoding in WireEncoding::ALL {
+            let mut codec = MessageCodec::with_encoding(encoding);
+            let msg = create_cli_request("test-001");
 
-        let mut buffer = BytesMut::new();
-        codec
-            .encode(msg.clone(), &mut buffer)
-            .expect("encode failed");
+            let mut buffer = BytesMut::new();
+            codec
+                .encode(msg.clone(), &mut buffer)
+                .expect("encode failed");
 
-        let decoded = codec
-            .decode(&mut buffer)
-            .expect("decode failed")
-            .expect("no message");
-
-        match (&msg, &decoded) {
-            (Message::CliRequest(orig), Message::CliRequest(dec)) => {
-                assert_eq!(orig.id, dec.id);
-                assert_eq!(orig.tool, dec.tool);
-                assert_eq!(orig.argv, dec.argv);
+            let decoded = codec
+                .decode(&mut buffer)
+                .expect("decode failed")
+                .expect("no message");
+
+            match (&msg, &decoded) {
+                (Message::CliRequest(orig), Message::CliRequest(dec)) => {
+                    assert_eq!(orig.id, dec.id);
+                    assert_eq!(orig.tool, dec.tool);
+                    assert_eq!(orig.argv, dec.argv);
+                }
+                _ => panic!("Message type mismatch"),
             }
-            _ => panic!("Message type mismatch"),
         }
     }
 
     #[test]
     fn test_framing_partial_read() {
-        let mut codec = MessageCodec;
-        let msg = create_cli_request("test-002");
+        for encoding in WireEncoding::ALL {
+            let mut codec = MessageCodec::with_encoding(encoding);
+            let msg = create_cli_request("test-002");
 
-        let mut buffer = BytesMut::new();
-        codec.encode(msg, &mut buffer).expect("encode failed");
+            let mut buffer = BytesMut::new();
+            codec.encode(msg, &mut buffer).expect("encode failed");
 
-        let _total_len = buffer.len();
+            let _total_len = buffer.len();
 
-        // Split buffer - keep only first 4 bytes (the length prefix)
-        let mut partial = buffer.split_to(4);
-        let result = codec.decode(&mut partial).expect("decode should not error");
-        assert!(result.is_none(), "Should return None when incomplete");
+            // Split buffer - keep only first 4 bytes (the length prefix)
+            let mut partial = buffer.split_to(4);
+            let result = codec.decode(&mut partial).expect("decode should not error");
+            assert!(result.is_none(), "Should return None when incomplete");
 
-        // Add more data but still incomplete
-        partial.extend_from_slice(&buffer[..10]);
-        let result = codec.decode(&mut partial).expect("decode should not error");
-        assert!(result.is_none(), "Should still return None when incomplete");
+            // Add more data but still incomplete
+            partial.extend_from_slice(&buffer[..10]);
+            let result = codec.decode(&mut partial).expect("decode should not error");
+            assert!(result.is_none(), "Should still return None when incomplete");
 
-        // Add the rest
-        partial.extend_from_slice(&buffer[10..]);
-        let result = codec.decode(&mut partial).expect("decode should not error");
-        assert!(result.is_some(), "Should decode when complete");
+            // Add the rest
+            partial.extend_from_slice(&buffer[10..]);
+            let result = codec.decode(&mut partial).expect("decode should not error");
+            assert!(result.is_some(), "Should decode when complete");
+        }
     }
 
     #[test]
@@ -143,7 +409,7 @@ mod tests {
         let test_sizes = vec![1, 10, 100, 1000, 10_000, 100_000];
 
         for size in test_sizes {
-            let mut codec = MessageCodec;
+            let mut codec = MessageCodec::new();
 
             let mut argv = vec!["arg0".to_string()];
             argv.extend((0..size).map(|i| format!("arg-{}", i)));
@@ -155,6 +421,7 @@ mod tests {
                 env: HashMap::new(),
                 stdin: None,
                 cwd: "/".to_string(),
+                tty: None,
             });
 
             let mut buffer = BytesMut::new();
@@ -172,7 +439,7 @@ mod tests {
 
     #[test]
     fn test_frame_size_limit() {
-        let mut codec = MessageCodec;
+        let mut codec = MessageCodec::new();
 
         // Create a message that would exceed the limit
         let huge_argv = vec!["x".repeat(MAX_FRAME_SIZE as usize + 1)];
@@ -184,6 +451,7 @@ mod tests {
             env: HashMap::new(),
             stdin: None,
             cwd: "/".to_string(),
+            tty: None,
         });
 
         let mut buffer = BytesMut::new();
@@ -207,7 +475,7 @@ mod tests {
         buffer.put_u32(huge_length);
         buffer.put_slice(b"some data");
 
-        let mut codec = MessageCodec;
+        let mut codec = MessageCodec::new();
         let result = codec.decode(&mut buffer);
 
         assert!(
@@ -218,7 +486,7 @@ mod tests {
 
     #[test]
     fn test_concurrent_encoding() {
-        let mut codec = MessageCodec;
+        let mut codec = MessageCodec::new();
 
         for i in 0..10 {
             let msg = create_cli_request(&format!("concurrent-{}", i));
@@ -236,7 +504,7 @@ mod tests {
         let mut buffer = BytesMut::new();
         buffer.put_u32(0); // Zero-length payload
 
-        let mut codec = MessageCodec;
+        let mut codec = MessageCodec::new();
         let result = codec.decode(&mut buffer);
 
         // Should fail because empty JSON isn't valid
@@ -248,94 +516,107 @@ mod tests {
 
     #[test]
     fn test_multiple_messages_in_buffer() {
-        let mut codec = MessageCodec;
+        for encoding in WireEncoding::ALL {
+            let mut codec = MessageCodec::with_encoding(encoding);
 
-        let msg1 = create_cli_request("first");
-        let msg2 = create_cli_request("second");
+            let msg1 = create_cli_request("first");
+            let msg2 = create_cli_request("second");
 
-        let mut buffer = BytesMut::new();
-        codec.encode(msg1, &mut buffer).expect("encode 1 failed");
-        codec.encode(msg2, &mut buffer).expect("encode 2 failed");
+            let mut buffer = BytesMut::new();
+            codec.encode(msg1, &mut buffer).expect("encode 1 failed");
+            codec.encode(msg2, &mut buffer).expect("encode 2 failed");
 
-        // Decode first
-        let decoded1 = codec
-            .decode(&mut buffer)
-            .expect("decode 1 failed")
-            .expect("no message 1");
-        assert!(matches!(decoded1, Message::CliRequest(ref r) if r.id == "first"));
+            // Decode first
+            let decoded1 = codec
+                .decode(&mut buffer)
+                .expect("decode 1 failed")
+                .expect("no message 1");
+            assert!(matches!(decoded1, Message::CliRequest(ref r) if r.id == "first"));
 
-        // Decode second
-        let decoded2 = codec
-            .decode(&mut buffer)
-            .expect("decode 2 failed")
-            .expect("no message 2");
-        assert!(matches!(decoded2, Message::CliRequest(ref r) if r.id == "second"));
+            // Decode second
+            let decoded2 = codec
+                .decode(&mut buffer)
+                .expect("decode 2 failed")
+                .expect("no message 2");
+            assert!(matches!(decoded2, Message::CliRequest(ref r) if r.id == "second"));
 
-        // Buffer should be empty now
-        assert_eq!(buffer.len(), 0);
+            // Buffer should be empty now
+            assert_eq!(buffer.len(), 0);
+        }
     }
 
     #[test]
     fn test_all_message_types_framing() {
-        let mut codec = MessageCodec;
-
-        let messages = vec![
-            Message::CliRequest(CliRequest {
-                id: "cli-req".to_string(),
-                tool: "gh".to_string(),
-                argv: vec![],
-                env: HashMap::new(),
-                stdin: None,
-                cwd: "/".to_string(),
-            }),
-            Message::CliResponse(CliResponse {
-                id: "cli-res".to_string(),
-                exit_code: 0,
-                stdout: "output".to_string(),
-                stderr: "".to_string(),
-            }),
-            Message::HttpRequest(HttpRequest {
-                id: "http-req".to_string(),
-                tool: "signal-cli".to_string(),
-                method: "POST".to_string(),
-                path: "/api".to_string(),
-                headers: HashMap::new(),
-                body: None,
-            }),
-            Message::HttpResponse(HttpResponse {
-                id: "http-res".to_string(),
-                status: 200,
-                headers: HashMap::new(),
-                body: Some("{}".to_string()),
-            }),
-            Message::Error(ErrorMessage {
-                id: Some("err".to_string()),
-                code: "DENIED".to_string(),
-                message: "Policy denied this request".to_string(),
-            }),
-        ];
-
-        for msg in messages {
-            let mut buffer = BytesMut::new();
-            codec
-                .encode(msg.clone(), &mut buffer)
-                .expect("encode failed");
-
-            let decoded = codec
-                .decode(&mut buffer)
-                .expect("decode failed")
-                .expect("no message");
-
-            // Just verify it decoded to the same type
-            match (&msg, &decoded) {
-                (Message::CliRequest(_), Message::CliRequest(_)) => {}
-                (Message::CliResponse(_), Message::CliResponse(_)) => {}
-                (Message::HttpRequest(_), Message::HttpRequest(_)) => {}
-                (Message::HttpResponse(_), Message::HttpResponse(_)) => {}
-                (Message::Error(_), Message::Error(_)) => {}
-                (Message::Ping(_), Message::Ping(_)) => {}
-                (Message::Pong(_), Message::Pong(_)) => {}
-                _ => panic!("Type mismatch"),
+        for encoding in WireEncoding::ALL {
+            let mut codec = MessageCodec::with_encoding(encoding);
+
+            let messages = vec![
+                Message::CliRequest(CliRequest {
+                    id: "cli-req".to_string(),
+                    tool: "gh".to_string(),
+                    argv: vec![],
+                    env: HashMap::new(),
+                    stdin: None,
+                    cwd: "/".to_string(),
+                    tty: None,
+                }),
+                Message::CliResponse(CliResponse {
+                    id: "cli-res".to_string(),
+                    exit_code: 0,
+                    stdout: "output".to_string(),
+                    stderr: "".to_string(),
+                    encoding: PayloadEncoding::Utf8,
+                    signal: None,
+                    timed_out: false,
+                    stdout_truncated: false,
+                    stderr_truncated: false,
+                }),
+                Message::HttpRequest(HttpRequest {
+                    id: "http-req".to_string(),
+                    tool: "signal-cli".to_string(),
+                    method: "POST".to_string(),
+                    path: "/api".to_string(),
+                    headers: HashMap::new(),
+                    body: None,
+                    encoding: PayloadEncoding::Utf8,
+                }),
+                Message::HttpResponse(HttpResponse {
+                    id: "http-res".to_string(),
+                    status: 200,
+                    headers: HashMap::new(),
+                    body: Some("{}".to_string()),
+                    encoding: PayloadEncoding::Utf8,
+                }),
+                Message::Error(ErrorMessage {
+                    id: Some("err".to_string()),
+                    code: "DENIED".to_string(),
+                    message: "Policy denied this request".to_string(),
+                    denial: None,
+                }),
+            ];
+
+            for msg in messages {
+                let mut buffer = BytesMut::new();
+                codec
+                    .encode(msg.clone(), &mut buffer)
+                    .expect("encode failed");
+
+                let decoded = codec
+                    .decode(&mut buffer)
+                    .expect("decode failed")
+                    .expect("no message");
+
+                // Just verify it decoded to the same type
+                match (&msg, &decoded) {
+                    (Message::CliRequest(_), Message::CliRequest(_)) => {}
+                    (Message::CliResponse(_), Message::CliResponse(_)) => {}
+                    (Message::HttpRequest(_), Message::HttpRequest(_)) => {}
+                    (Message::HttpResponse(_), Message::HttpResponse(_)) => {}
+                    (Message::Error(_), Message::Error(_)) => {}
+                    (Message::Ping(_), Message::Ping(_)) => {}
+                    (Message::Pong(_), Message::Pong(_)) => {}
+                    _ => panic!("Type mismatch"),
+                }
             }
         }
     }
@@ -348,7 +629,7 @@ mod tests {
         buffer.put_u32(invalid_json.len() as u32);
         buffer.put_slice(invalid_json);
 
-        let mut codec = MessageCodec;
+        let mut codec = MessageCodec::new();
         let result = codec.decode(&mut buffer);
 
         // Should error on deserialization
@@ -357,32 +638,181 @@ mod tests {
 
     #[test]
     fn test_truncated_frames_at_various_offsets() {
-        let mut codec = MessageCodec;
-        let msg = create_cli_request("truncate-test");
+        for encoding in WireEncoding::ALL {
+            let mut codec = MessageCodec::with_encoding(encoding);
+            let msg = create_cli_request("truncate-test");
+
+            let mut full_buffer = BytesMut::new();
+            codec.encode(msg, &mut full_buffer).expect("encode failed");
+
+            let full_data = full_buffer.to_vec();
+
+            // Test truncation at various points
+            for truncate_at in 1..full_data.len() {
+                let mut truncated = BytesMut::from(&full_data[..truncate_at]);
+                let result = codec.decode(&mut truncated);
+
+                // Should either return None or error, but not panic
+                match result {
+                    Ok(Some(_)) => {
+                        // This is fine if the truncation point happened to be valid
+                        // (unlikely but possible for very short messages)
+                    }
+                    Ok(None) => {
+                        // This is expected - incomplete frame
+                    }
+                    Err(_) => {
+                        // This is also fine - invalid data
+                    }
+                }
+            }
+        }
+    }
 
-        let mut full_buffer = BytesMut::new();
-        codec.encode(msg, &mut full_buffer).expect("encode failed");
+    #[test]
+    fn test_json_frames_carry_no_flags() {
+        // JSON frames must stay readable by peers that predate encodings
+        let mut codec = MessageCodec::new();
+        let mut buffer = BytesMut::new();
+        codec
+            .encode(create_cli_request("legacy"), &mut buffer)
+            .expect("encode failed");
 
-        let full_data = full_buffer.to_vec();
+        let prefix = u32::from_be_bytes(buffer[..4].try_into().unwrap());
+        assert_eq!(prefix as usize, buffer.len() - 4);
+        assert!(serde_json::from_slice::<Message>(&buffer[4..]).is_ok());
+    }
+
+    #[test]
+    fn test_decoder_accepts_any_encoding() {
+        // Frames are self-describing, so a JSON codec reads CBOR and MessagePack
+        let mut reader = MessageCodec::new();
+        for encoding in WireEncoding::ALL {
+            let mut writer = MessageCodec::with_encoding(encoding);
+            let mut buffer = BytesMut::new();
+            writer
+                .encode(create_cli_request("mixed"), &mut buffer)
+                .expect("encode failed");
 
-        // Test truncation at various points
-        for truncate_at in 1..full_data.len() {
-            let mut truncated = BytesMut::from(&full_data[..truncate_at]);
-            let result = codec.decode(&mut truncated);
+            let decoded = reader
+                .decode(&mut buffer)
+                .expect("decode failed")
+                .expect("no message");
+            assert!(matches!(decoded, Message::CliRequest(ref r) if r.id == "mixed"));
+        }
+    }
 
-            // Should either return None or error, but not panic
-            match result {
-                Ok(Some(_)) => {
-                    // This is fine if the truncation point happened to be valid
-                    // (unlikely but possible for very short messages)
-                }
-                Ok(None) => {
-                    // This is expected - incomplete frame
-                }
-                Err(_) => {
-                    // This is also fine - invalid data
-                }
+    #[test]
+    fn test_unknown_encoding_tag_rejected() {
+        let mut buffer = BytesMut::new();
+        buffer.put_u32(2 | (3 << ENCODING_SHIFT));
+        buffer.put_slice(b"{}");
+
+        let mut codec = MessageCodec::new();
+        assert!(codec.decode(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn test_reserved_flag_bits_rejected() {
+        let mut buffer = BytesMut::new();
+        buffer.put_u32(2 | 0x4000_0000);
+        buffer.put_slice(b"{}");
+
+        let mut codec = MessageCodec::new();
+        assert!(codec.decode(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn test_wire_encoding_from_str() {
+        for encoding in WireEncoding::ALL {
+            assert_eq!(
+                encoding.to_string().parse::<WireEncoding>().unwrap(),
+                encoding
+            );
+        }
+        assert_eq!(
+            "MessagePack".parse::<WireEncoding>().unwrap(),
+            WireEncoding::MessagePack
+        );
+        assert!("xml".parse::<WireEncoding>().is_err());
+    }
+
+    #[test]
+    fn test_compression_roundtrip_above_threshold() {
+        for encoding in WireEncoding::ALL {
+            let mut codec = MessageCodec::with_encoding(encoding);
+            codec.set_compression(Some(Compression::Zstd));
+
+            let stdout = "{\"number\":1,\"state\":\"open\"}\n".repeat(20_000);
+            let msg = Message::CliResponse(CliResponse::from_output(
+                "compressed".to_string(),
+                0,
+                stdout.as_bytes(),
+                b"",
+            ));
+
+            let mut buffer = BytesMut::new();
+            codec.encode(msg, &mut buffer).expect("encode failed");
+
+            let prefix = u32::from_be_bytes(buffer[..4].try_into().unwrap());
+            assert_ne!(
+                prefix & COMPRESSED_FLAG,
+                0,
+                "large frame should be compressed"
+            );
+            assert!(buffer.len() < stdout.len() / 4);
+
+            // Decoding does not depend on the reader's own settings
+            let decoded = MessageCodec::new()
+                .decode(&mut buffer)
+                .expect("decode failed")
+                .expect("no message");
+            match decoded {
+                Message::CliResponse(resp) => assert_eq!(resp.stdout, stdout),
+                other => panic!("Expected CliResponse, got {:?}", other),
             }
         }
     }
+
+    #[test]
+    fn test_small_frames_not_compressed() {
+        let mut codec = MessageCodec::new();
+        codec.set_compression(Some(Compression::Zstd));
+
+        let mut buffer = BytesMut::new();
+        codec
+            .encode(create_cli_request("small"), &mut buffer)
+            .expect("encode failed");
+
+        let prefix = u32::from_be_bytes(buffer[..4].try_into().unwrap());
+        assert_eq!(prefix & COMPRESSED_FLAG, 0);
+    }
+
+    #[test]
+    fn test_decompression_bomb_rejected() {
+        // ~100 KB of zstd that expands past MAX_FRAME_SIZE
+        let spaces = io::Read::take(io::repeat(b' '), MAX_FRAME_SIZE as u64 + 1);
+        let bomb = zstd::stream::encode_all(spaces, 1).unwrap();
+        assert!(bomb.len() < MAX_FRAME_SIZE as usize);
+
+        let mut buffer = BytesMut::new();
+        buffer.put_u32(bomb.len() as u32 | COMPRESSED_FLAG);
+        buffer.put_slice(&bomb);
+
+        let mut codec = MessageCodec::new();
+        let err = codec
+            .decode(&mut buffer)
+            .expect_err("bomb should be rejected");
+        assert!(err.to_string().contains("Decompressed frame exceeds"));
+    }
+
+    #[test]
+    fn test_corrupt_compressed_frame_rejected() {
+        let mut buffer = BytesMut::new();
+        buffer.put_u32(4 | COMPRESSED_FLAG);
+        buffer.put_slice(b"nope");
+
+        let mut codec = MessageCodec::new();
+        assert!(codec.decode(&mut buffer).is_err());
+    }
 }
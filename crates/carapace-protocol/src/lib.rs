@@ -1,9 +1,13 @@
+pub mod capture;
+pub mod chunking;
 pub mod error;
 pub mod framing;
 pub mod messages;
 
-pub use error::ProtocolError;
-pub use framing::{FrameError, MessageCodec};
+pub use error::{CaptureError, ProtocolError};
+pub use framing::{Compression, FrameError, MessageCodec, WireEncoding, COMPRESSION_THRESHOLD};
 pub use messages::{
-    CliRequest, CliResponse, ErrorMessage, HttpRequest, HttpResponse, Message, PingPong, SseEvent,
+    Cancel, Capabilities, Chunk, CliRequest, CliResponse, ErrorMessage, HttpRequest, HttpResponse,
+    Message, PayloadEncoding, PingPong, PolicyDenial, PtyData, PtyResize, Signal, SseEvent,
+    TtySize,
 };
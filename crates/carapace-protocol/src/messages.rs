@@ -1,6 +1,11 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+use crate::error::ProtocolError;
+use crate::framing::{Compression, WireEncoding};
+
 /// Unique request/response identifier
 pub type RequestId = String;
 
@@ -15,6 +20,11 @@ pub enum Message {
     Error(ErrorMessage),
     Ping(PingPong),
     Pong(PingPong),
+    Chunk(Chunk),
+    Cancel(Cancel),
+    Signal(Signal),
+    PtyData(PtyData),
+    PtyResize(PtyResize),
 }
 
 impl Message {
@@ -28,6 +38,11 @@ impl Message {
             Message::Error(err) => err.id.as_deref(),
             Message::Ping(p) => Some(&p.id),
             Message::Pong(p) => Some(&p.id),
+            Message::Chunk(c) => Some(&c.id),
+            Message::Cancel(c) => Some(&c.id),
+            Message::Signal(s) => Some(&s.id),
+            Message::PtyData(d) => Some(&d.id),
+            Message::PtyResize(r) => Some(&r.id),
         }
     }
 }
@@ -42,6 +57,64 @@ pub struct CliRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stdin: Option<String>,
     pub cwd: String,
+    /// Size of the client's terminal, if it has one. Tools whose policy is
+    /// `interactive` then run in a pseudo-terminal.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tty: Option<TtySize>,
+}
+
+/// Terminal window size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TtySize {
+    pub rows: u16,
+    pub cols: u16,
+}
+
+/// How a payload string (stdout, stderr, HTTP body) is encoded on the wire.
+///
+/// Payloads that are valid UTF-8 travel as-is and omit the tag entirely, so
+/// peers that predate this field keep working. Anything else is base64-encoded
+/// and tagged, which keeps attachments, `op document get` output and gzip
+/// bodies intact instead of passing them through `from_utf8_lossy`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadEncoding {
+    #[default]
+    Utf8,
+    Base64,
+}
+
+impl PayloadEncoding {
+    /// Pick the encoding that carries every part losslessly
+    pub fn for_bytes(parts: &[&[u8]]) -> Self {
+        if parts.iter().all(|p| std::str::from_utf8(p).is_ok()) {
+            PayloadEncoding::Utf8
+        } else {
+            PayloadEncoding::Base64
+        }
+    }
+
+    /// Encode raw bytes into a payload string
+    pub fn encode(&self, bytes: &[u8]) -> String {
+        match self {
+            PayloadEncoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            PayloadEncoding::Base64 => BASE64.encode(bytes),
+        }
+    }
+
+    /// Decode a payload string back into raw bytes
+    pub fn decode(&self, payload: &str) -> Result<Vec<u8>, ProtocolError> {
+        match self {
+            PayloadEncoding::Utf8 => Ok(payload.as_bytes().to_vec()),
+            PayloadEncoding::Base64 => BASE64.decode(payload).map_err(|e| {
+                ProtocolError::InvalidMessage(format!("Invalid base64 payload: {}", e))
+            }),
+        }
+    }
+
+    pub fn is_utf8(&self) -> bool {
+        *self == PayloadEncoding::Utf8
+    }
 }
 
 /// CLI tool response
@@ -51,6 +124,49 @@ pub struct CliResponse {
     pub exit_code: i32,
     pub stdout: String,
     pub stderr: String,
+    /// Encoding shared by `stdout` and `stderr`
+    #[serde(default, skip_serializing_if = "PayloadEncoding::is_utf8")]
+    pub encoding: PayloadEncoding,
+    /// Signal that terminated the process, if it did not exit normally
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+    /// The process was killed for exceeding the policy timeout
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub timed_out: bool,
+    /// `stdout` was cut off at the policy's `max_stdout_bytes`
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub stdout_truncated: bool,
+    /// `stderr` was cut off at the policy's `max_stderr_bytes`
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub stderr_truncated: bool,
+}
+
+impl CliResponse {
+    /// Build a response from raw process output, base64-encoding it if needed
+    pub fn from_output(id: RequestId, exit_code: i32, stdout: &[u8], stderr: &[u8]) -> Self {
+        let encoding = PayloadEncoding::for_bytes(&[stdout, stderr]);
+        CliResponse {
+            id,
+            exit_code,
+            stdout: encoding.encode(stdout),
+            stderr: encoding.encode(stderr),
+            encoding,
+            signal: None,
+            timed_out: false,
+            stdout_truncated: false,
+            stderr_truncated: false,
+        }
+    }
+
+    /// Raw stdout bytes
+    pub fn stdout_bytes(&self) -> Result<Vec<u8>, ProtocolError> {
+        self.encoding.decode(&self.stdout)
+    }
+
+    /// Raw stderr bytes
+    pub fn stderr_bytes(&self) -> Result<Vec<u8>, ProtocolError> {
+        self.encoding.decode(&self.stderr)
+    }
 }
 
 /// HTTP request to proxy
@@ -63,6 +179,19 @@ pub struct HttpRequest {
     pub headers: HashMap<String, String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub body: Option<String>,
+    /// Encoding of `body`
+    #[serde(default, skip_serializing_if = "PayloadEncoding::is_utf8")]
+    pub encoding: PayloadEncoding,
+}
+
+impl HttpRequest {
+    /// Raw body bytes, if the request has a body
+    pub fn body_bytes(&self) -> Result<Option<Vec<u8>>, ProtocolError> {
+        self.body
+            .as_deref()
+            .map(|b| self.encoding.decode(b))
+            .transpose()
+    }
 }
 
 /// HTTP response
@@ -73,6 +202,19 @@ pub struct HttpResponse {
     pub headers: HashMap<String, String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub body: Option<String>,
+    /// Encoding of `body`
+    #[serde(default, skip_serializing_if = "PayloadEncoding::is_utf8")]
+    pub encoding: PayloadEncoding,
+}
+
+impl HttpResponse {
+    /// Raw body bytes, if the response has a body
+    pub fn body_bytes(&self) -> Result<Option<Vec<u8>>, ProtocolError> {
+        self.body
+            .as_deref()
+            .map(|b| self.encoding.decode(b))
+            .transpose()
+    }
 }
 
 /// Server-sent event from upstream (streamed incrementally for SSE endpoints)
@@ -90,6 +232,149 @@ pub struct ErrorMessage {
     pub id: Option<RequestId>,
     pub code: String,
     pub message: String,
+    /// The policy rule behind a `policy_denied` error, as far as the server
+    /// discloses it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub denial: Option<PolicyDenial>,
+}
+
+/// The policy rule that denied a request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyDenial {
+    /// Kind of rule, e.g. `argv_deny` or `jsonrpc_allow_method`
+    pub rule: String,
+    /// The pattern, method or argument that matched, or "no allow matched"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matched: Option<String>,
+    /// Position of the rule in the server's policy file, as `file:line`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+}
+
+/// One piece of a message too large to send as a single frame.
+///
+/// The sender serializes the whole message in the connection's wire encoding
+/// and splits the bytes into `total` chunks sent in order; the receiver
+/// concatenates them and decodes the original message (see
+/// `chunking::Reassembler`). Chunks of different messages may interleave
+/// with each other and with other frames.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chunk {
+    /// Id of the chunked message
+    pub id: RequestId,
+    /// Zero-based position of this chunk
+    pub seq: u32,
+    /// Number of chunks that make up the message
+    pub total: u32,
+    /// Encoding the message was serialized with
+    #[serde(default, skip_serializing_if = "WireEncoding::is_json")]
+    pub encoding: WireEncoding,
+    /// Slice of the serialized message: base64 in JSON frames, raw bytes in
+    /// CBOR and MessagePack frames
+    #[serde(with = "chunk_data")]
+    pub data: Vec<u8>,
+}
+
+mod chunk_data {
+    use super::BASE64;
+    use base64::Engine;
+    use serde::de::{self, SeqAccess, Visitor};
+    use serde::{Deserializer, Serializer};
+    use std::fmt;
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&BASE64.encode(data))
+        } else {
+            serializer.serialize_bytes(data)
+        }
+    }
+
+    // `Message` is internally tagged, so the data arrives buffered and is
+    // told apart by its type rather than by the format
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        deserializer.deserialize_any(DataVisitor)
+    }
+
+    struct DataVisitor;
+
+    impl<'de> Visitor<'de> for DataVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("bytes or a base64 string")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Vec<u8>, E> {
+            BASE64
+                .decode(v)
+                .map_err(|e| E::custom(format!("Invalid chunk data: {}", e)))
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(v)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            let mut data = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(byte) = seq.next_element()? {
+                data.push(byte);
+            }
+            Ok(data)
+        }
+    }
+}
+
+/// Abandon an in-flight request: the server kills the process group or
+/// aborts the upstream HTTP request. Only sent if the server negotiated
+/// `Capabilities::cancel`, since older servers drop the connection on
+/// unknown message types.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cancel {
+    pub id: RequestId,
+}
+
+/// Deliver a signal to the process group running a CLI request. Only sent
+/// if the server negotiated `Capabilities::signals`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Signal {
+    pub id: RequestId,
+    pub signal: i32,
+}
+
+/// Terminal data of an interactive session: keystrokes from the client,
+/// output from the server. Ends with the session's `CliResponse`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PtyData {
+    pub id: RequestId,
+    /// Base64-encoded bytes
+    pub data: String,
+}
+
+impl PtyData {
+    pub fn new(id: RequestId, data: &[u8]) -> Self {
+        PtyData {
+            id,
+            data: BASE64.encode(data),
+        }
+    }
+
+    /// Raw terminal bytes
+    pub fn bytes(&self) -> Result<Vec<u8>, ProtocolError> {
+        PayloadEncoding::Base64.decode(&self.data)
+    }
+}
+
+/// The client's terminal window was resized
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PtyResize {
+    pub id: RequestId,
+    pub rows: u16,
+    pub cols: u16,
 }
 
 /// Ping/Pong keepalive message
@@ -97,6 +382,117 @@ pub struct ErrorMessage {
 pub struct PingPong {
     pub id: RequestId,
     pub timestamp: u64,
+    /// Feature negotiation, only present on the first Ping/Pong of a connection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<Capabilities>,
+    /// Name the agent goes by, only present on its handshake Ping. Self-reported:
+    /// it labels audit entries but is not authenticated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<String>,
+}
+
+/// Optional connection features, negotiated once per connection.
+///
+/// The agent offers what it wants in a `Ping` right after connecting and the
+/// server answers with the subset it accepted in the matching `Pong`. Servers
+/// that predate negotiation answer with a plain `Pong`, which leaves the
+/// connection on the defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// Frame encodings in order of preference; a `Pong` holds the one picked
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub encodings: Vec<WireEncoding>,
+    /// Frame compression algorithms in order of preference; a `Pong` holds
+    /// the one picked, or nothing if compression stays off
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub compression: Vec<Compression>,
+    /// Large responses may be split into `Chunk` messages
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub chunking: bool,
+    /// In-flight requests may be abandoned with `Cancel`
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub cancel: bool,
+    /// Signals may be forwarded to running CLI requests with `Signal`
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub signals: bool,
+    /// Interactive CLI requests may run in a pseudo-terminal
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub pty: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+impl Capabilities {
+    /// Everything this build supports
+    pub fn supported() -> Self {
+        Capabilities {
+            encodings: WireEncoding::ALL.to_vec(),
+            compression: vec![Compression::Zstd],
+            chunking: true,
+            cancel: true,
+            signals: true,
+            pty: true,
+        }
+    }
+
+    /// Ask for a specific frame encoding
+    pub fn with_encoding(encoding: WireEncoding) -> Self {
+        Capabilities {
+            encodings: vec![encoding],
+            ..Default::default()
+        }
+    }
+
+    /// Answer an offer: the first offered option of each kind we also support
+    pub fn accept(&self, offer: &Capabilities) -> Capabilities {
+        Capabilities {
+            encodings: first_common(&offer.encodings, &self.encodings),
+            compression: first_common(&offer.compression, &self.compression),
+            chunking: offer.chunking && self.chunking,
+            cancel: offer.cancel && self.cancel,
+            signals: offer.signals && self.signals,
+            pty: offer.pty && self.pty,
+        }
+    }
+
+    /// The selected frame encoding (JSON unless another one was agreed)
+    pub fn encoding(&self) -> WireEncoding {
+        self.encodings.first().copied().unwrap_or_default()
+    }
+
+    /// The selected frame compression, if any
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression.first().copied()
+    }
+
+    /// True when nothing beyond the defaults is requested, so no handshake is needed
+    pub fn is_default(&self) -> bool {
+        self.encoding() == WireEncoding::Json
+            && self.compression.is_empty()
+            && !self.chunking
+            && !self.cancel
+            && !self.signals
+            && !self.pty
+    }
+
+    /// True if every selection in `self` is something `offer` asked for
+    pub fn is_subset_of(&self, offer: &Capabilities) -> bool {
+        self.encodings.iter().all(|e| offer.encodings.contains(e))
+            && self
+                .compression
+                .iter()
+                .all(|c| offer.compression.contains(c))
+    }
+}
+
+fn first_common<T: Copy + PartialEq>(offered: &[T], supported: &[T]) -> Vec<T> {
+    offered
+        .iter()
+        .find(|o| supported.contains(o))
+        .map(|o| vec![*o])
+        .unwrap_or_default()
 }
 
 #[cfg(test)]
@@ -112,6 +508,7 @@ mod tests {
             env: HashMap::new(),
             stdin: None,
             cwd: "/home/user".to_string(),
+            tty: None,
         };
 
         let json = serde_json::to_string(&req).expect("serialization failed");
@@ -131,6 +528,7 @@ mod tests {
             env: HashMap::new(),
             stdin: Some("POST data".to_string()),
             cwd: "/tmp".to_string(),
+            tty: None,
         };
 
         let json = serde_json::to_string(&req).expect("serialization failed");
@@ -147,6 +545,11 @@ mod tests {
             exit_code: 0,
             stdout: "response".to_string(),
             stderr: "".to_string(),
+            encoding: PayloadEncoding::Utf8,
+            signal: None,
+            timed_out: false,
+            stdout_truncated: false,
+            stderr_truncated: false,
         };
 
         let json = serde_json::to_string(&resp).expect("serialization failed");
@@ -169,6 +572,7 @@ mod tests {
             path: "/api/v1/rpc".to_string(),
             headers,
             body: Some(r#"{"jsonrpc":"2.0","method":"send"}"#.to_string()),
+            encoding: PayloadEncoding::Utf8,
         };
 
         let json = serde_json::to_string(&req).expect("serialization failed");
@@ -188,6 +592,7 @@ mod tests {
             env: HashMap::new(),
             stdin: None,
             cwd: "/".to_string(),
+            tty: None,
         });
 
         let json = serde_json::to_string(&cli_req).expect("serialization failed");
@@ -206,6 +611,7 @@ mod tests {
             env: HashMap::new(),
             stdin: None,
             cwd: "/".to_string(),
+            tty: None,
         });
 
         assert_eq!(msg.id(), Some("req-123"));
@@ -250,6 +656,11 @@ mod tests {
             exit_code: 0,
             stdout: large_output.clone(),
             stderr: "".to_string(),
+            encoding: PayloadEncoding::Utf8,
+            signal: None,
+            timed_out: false,
+            stdout_truncated: false,
+            stderr_truncated: false,
         };
 
         let json = serde_json::to_string(&resp).expect("serialization failed");
@@ -273,6 +684,7 @@ mod tests {
             env: HashMap::new(),
             stdin: None,
             cwd: "/".to_string(),
+            tty: None,
         };
 
         let json = serde_json::to_string(&req).expect("serialization failed");
@@ -288,6 +700,11 @@ mod tests {
             exit_code: -1,
             stdout: "".to_string(),
             stderr: "Error".to_string(),
+            encoding: PayloadEncoding::Utf8,
+            signal: None,
+            timed_out: false,
+            stdout_truncated: false,
+            stderr_truncated: false,
         };
 
         let json = serde_json::to_string(&resp).expect("serialization failed");
@@ -323,4 +740,129 @@ mod tests {
         let result: Result<CliResponse, _> = serde_json::from_str(malformed);
         assert!(result.is_err(), "Should fail with wrong types");
     }
+
+    #[test]
+    fn test_utf8_output_omits_encoding_tag() {
+        let resp = CliResponse::from_output("utf8".to_string(), 0, b"hello\n", b"");
+
+        assert_eq!(resp.encoding, PayloadEncoding::Utf8);
+        assert_eq!(resp.stdout, "hello\n");
+
+        // Legacy peers must see exactly the old wire format
+        let json = serde_json::to_string(&resp).expect("serialization failed");
+        assert!(!json.contains("encoding"));
+    }
+
+    #[test]
+    fn test_binary_output_roundtrip() {
+        let stdout = vec![0x89, b'P', b'N', b'G', 0x00, 0xff, 0xfe];
+        let resp = CliResponse::from_output("binary".to_string(), 0, &stdout, b"warning\n");
+
+        assert_eq!(resp.encoding, PayloadEncoding::Base64);
+
+        let json = serde_json::to_string(&resp).expect("serialization failed");
+        assert!(json.contains(r#""encoding":"base64""#));
+
+        let deserialized: CliResponse =
+            serde_json::from_str(&json).expect("deserialization failed");
+        assert_eq!(deserialized.stdout_bytes().unwrap(), stdout);
+        assert_eq!(deserialized.stderr_bytes().unwrap(), b"warning\n");
+    }
+
+    #[test]
+    fn test_termination_details_roundtrip() {
+        let plain = CliResponse::from_output("ok".to_string(), 0, b"", b"");
+        let json = serde_json::to_string(&plain).expect("serialization failed");
+        assert!(!json.contains("signal"));
+        assert!(!json.contains("timed_out"));
+        assert!(!json.contains("truncated"));
+
+        let mut killed = CliResponse::from_output("killed".to_string(), -1, b"", b"");
+        killed.signal = Some(9);
+        killed.timed_out = true;
+        killed.stdout_truncated = true;
+        let json = serde_json::to_string(&killed).expect("serialization failed");
+        let deserialized: CliResponse =
+            serde_json::from_str(&json).expect("deserialization failed");
+        assert_eq!(deserialized.signal, Some(9));
+        assert!(deserialized.timed_out);
+        assert!(deserialized.stdout_truncated);
+        assert!(!deserialized.stderr_truncated);
+    }
+
+    #[test]
+    fn test_pty_messages_roundtrip() {
+        let data = Message::PtyData(PtyData::new("pty-1".to_string(), b"\x1b[1mPassword: "));
+        let json = serde_json::to_string(&data).expect("serialization failed");
+        assert!(json.contains(r#""type":"pty_data""#));
+        match serde_json::from_str::<Message>(&json).unwrap() {
+            Message::PtyData(d) => assert_eq!(d.bytes().unwrap(), b"\x1b[1mPassword: "),
+            other => panic!("Expected PtyData, got {:?}", other),
+        }
+
+        let json = r#"{"type":"pty_resize","id":"pty-1","rows":40,"cols":120}"#;
+        match serde_json::from_str::<Message>(json).unwrap() {
+            Message::PtyResize(r) => assert_eq!((r.rows, r.cols), (40, 120)),
+            other => panic!("Expected PtyResize, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_legacy_payload_defaults_to_utf8() {
+        let json = r#"{"id":"old","status":200,"headers":{},"body":"plain"}"#;
+        let resp: HttpResponse = serde_json::from_str(json).expect("deserialization failed");
+
+        assert_eq!(resp.encoding, PayloadEncoding::Utf8);
+        assert_eq!(resp.body_bytes().unwrap(), Some(b"plain".to_vec()));
+    }
+
+    #[test]
+    fn test_invalid_base64_payload_rejected() {
+        let resp = HttpResponse {
+            id: "bad".to_string(),
+            status: 200,
+            headers: HashMap::new(),
+            body: Some("not base64!!".to_string()),
+            encoding: PayloadEncoding::Base64,
+        };
+
+        assert!(resp.body_bytes().is_err());
+    }
+
+    #[test]
+    fn test_capabilities_accept_picks_first_supported() {
+        let offer = Capabilities {
+            encodings: vec![WireEncoding::MessagePack, WireEncoding::Cbor],
+            compression: vec![Compression::Zstd],
+            chunking: true,
+            cancel: true,
+            signals: true,
+            pty: true,
+        };
+        let server = Capabilities {
+            encodings: vec![WireEncoding::Json, WireEncoding::Cbor],
+            compression: vec![],
+            chunking: false,
+            cancel: true,
+            signals: false,
+            pty: false,
+        };
+
+        let accepted = server.accept(&offer);
+        assert_eq!(accepted.encoding(), WireEncoding::Cbor);
+        assert_eq!(accepted.compression(), None);
+        assert!(!accepted.chunking);
+        assert!(accepted.cancel);
+        assert!(!accepted.signals);
+        assert!(accepted.is_subset_of(&offer));
+    }
+
+    #[test]
+    fn test_legacy_ping_has_no_capabilities() {
+        let json = r#"{"type":"ping","id":"p1","timestamp":7}"#;
+        match serde_json::from_str::<Message>(json).unwrap() {
+            Message::Ping(ping) => assert!(ping.capabilities.is_none()),
+            other => panic!("Expected Ping, got {:?}", other),
+        }
+    }
 }
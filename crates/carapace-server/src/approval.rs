@@ -0,0 +1,392 @@
+use carapace_protocol::messages::RequestId;
+use carapace_protocol::{CliRequest, HttpRequest};
+use chrono::Utc;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use crate::audit::AuditLogger;
+use crate::error::{Result, ServerError};
+
+/// Most body bytes shown to the operator
+const MAX_BODY_PREVIEW: usize = 4096;
+
+/// A request held until an operator decides on it. Request ids are only
+/// unique within a connection, so operators decide by `approval_id`, which
+/// the server picks.
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingApproval {
+    pub approval_id: String,
+    pub request_id: RequestId,
+    /// The agent connection the request arrived on
+    pub session_id: Option<String>,
+    pub tool: String,
+    pub argv: Option<Vec<String>>,
+    pub method: Option<String>,
+    pub path: Option<String>,
+    /// Start of the request body, for HTTP requests
+    pub body: Option<String>,
+    pub requested_at: String,
+    pub timeout_secs: u64,
+}
+
+impl PendingApproval {
+    /// `audit` redacts the argv the way the audit log does, since anyone
+    /// who can reach the debug server can list it
+    pub fn cli(
+        req: &CliRequest,
+        session_id: Option<String>,
+        timeout_secs: u64,
+        audit: &AuditLogger,
+    ) -> Self {
+        PendingApproval {
+            approval_id: Uuid::new_v4().to_string(),
+            request_id: req.id.clone(),
+            session_id,
+            tool: req.tool.clone(),
+            argv: Some(audit.redact_sensitive_args(&req.tool, &req.argv)),
+            method: None,
+            path: None,
+            body: None,
+            requested_at: Utc::now().to_rfc3339(),
+            timeout_secs,
+        }
+    }
+
+    /// The body is redacted whole before it is cut to the preview, so
+    /// JSON keys are still recognized
+    pub fn http(
+        req: &HttpRequest,
+        session_id: Option<String>,
+        timeout_secs: u64,
+        audit: &AuditLogger,
+    ) -> Self {
+        let body = req.body_bytes().ok().flatten().map(|body| {
+            let mut text = audit.redact_text(&req.tool, &String::from_utf8_lossy(&body));
+            if text.len() > MAX_BODY_PREVIEW {
+                let mut end = MAX_BODY_PREVIEW;
+                while !text.is_char_boundary(end) {
+                    end -= 1;
+                }
+                text.truncate(end);
+            }
+            text
+        });
+        PendingApproval {
+            approval_id: Uuid::new_v4().to_string(),
+            request_id: req.id.clone(),
+            session_id,
+            tool: req.tool.clone(),
+            argv: None,
+            method: Some(req.method.clone()),
+            path: Some(req.path.clone()),
+            body,
+            requested_at: Utc::now().to_rfc3339(),
+            timeout_secs,
+        }
+    }
+}
+
+/// How an approval request ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApprovalOutcome {
+    Approved { approver: String },
+    Denied { approver: String },
+    TimedOut,
+}
+
+/// Requests waiting for an operator, shared by all connections of a server
+/// and keyed by approval id. Operators decide through the debug server's
+/// `/debug/approvals` endpoints.
+pub struct ApprovalQueue {
+    pending: Mutex<HashMap<String, (PendingApproval, oneshot::Sender<ApprovalOutcome>)>>,
+}
+
+impl ApprovalQueue {
+    pub fn new() -> Self {
+        ApprovalQueue {
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Hold a request until an operator decides on it or `timeout` passes.
+    /// Dropping the future withdraws the request.
+    pub async fn request(&self, pending: PendingApproval, timeout: Duration) -> ApprovalOutcome {
+        let (tx, rx) = oneshot::channel();
+        let id = pending.approval_id.clone();
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(id.clone(), (pending, tx));
+        let _withdraw = Withdraw { queue: self, id };
+
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(outcome)) => outcome,
+            _ => ApprovalOutcome::TimedOut,
+        }
+    }
+
+    /// Requests waiting for a decision, oldest first
+    pub fn pending(&self) -> Vec<PendingApproval> {
+        let mut pending: Vec<PendingApproval> = self
+            .pending
+            .lock()
+            .unwrap()
+            .values()
+            .map(|(pending, _)| pending.clone())
+            .collect();
+        pending.sort_by(|a, b| a.requested_at.cmp(&b.requested_at));
+        pending
+    }
+
+    /// Approve or deny a waiting request by its approval id. Returns false
+    /// if there is no such request (never held, already decided, timed out
+    /// or cancelled).
+    pub fn decide(&self, approval_id: &str, approved: bool, approver: &str) -> bool {
+        let Some((_, tx)) = self.pending.lock().unwrap().remove(approval_id) else {
+            return false;
+        };
+        let approver = approver.to_string();
+        let outcome = if approved {
+            ApprovalOutcome::Approved { approver }
+        } else {
+            ApprovalOutcome::Denied { approver }
+        };
+        tx.send(outcome).is_ok()
+    }
+}
+
+impl Default for ApprovalQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Shortest token accepted in an approvers file
+const MIN_TOKEN_LEN: usize = 16;
+
+/// Operators allowed to decide on held requests, each with a secret token.
+/// Loaded from a file of `<name> <token>` lines; blank lines and lines
+/// starting with `#` are skipped.
+pub struct Approvers {
+    tokens: Vec<(String, String)>,
+}
+
+impl Approvers {
+    pub fn load(path: &str) -> Result<Self> {
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            ServerError::ConfigError(format!("Cannot read approvers file {}: {}", path, e))
+        })?;
+        Self::parse(&text)
+            .map_err(|e| ServerError::ConfigError(format!("Approvers file {}: {}", path, e)))
+    }
+
+    pub(crate) fn parse(text: &str) -> std::result::Result<Self, String> {
+        let mut tokens = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, token) = line
+                .split_once(char::is_whitespace)
+                .map(|(name, token)| (name, token.trim()))
+                .ok_or_else(|| format!("line {}: expected `<name> <token>`", n + 1))?;
+            if token.len() < MIN_TOKEN_LEN {
+                return Err(format!(
+                    "line {}: token for {} is shorter than {} characters",
+                    n + 1,
+                    name,
+                    MIN_TOKEN_LEN
+                ));
+            }
+            tokens.push((name.to_string(), token.to_string()));
+        }
+        if tokens.is_empty() {
+            return Err("no approvers".to_string());
+        }
+        Ok(Approvers { tokens })
+    }
+
+    /// The approver holding `token`. Every token is compared in full, so
+    /// the time taken does not reveal how much of a guess was right.
+    pub fn identify(&self, token: &str) -> Option<&str> {
+        let mut found = None;
+        for (name, expected) in &self.tokens {
+            if constant_time_eq(expected.as_bytes(), token.as_bytes()) {
+                found = Some(name.as_str());
+            }
+        }
+        found
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Removes a request from the queue once nobody waits for it
+struct Withdraw<'a> {
+    queue: &'a ApprovalQueue,
+    /// Approval id of the request
+    id: String,
+}
+
+impl Drop for Withdraw<'_> {
+    fn drop(&mut self) {
+        self.queue.pending.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn request(id: &str, session_id: &str) -> PendingApproval {
+        PendingApproval::cli(
+            &CliRequest {
+                id: id.to_string(),
+                tool: "op".to_string(),
+                argv: vec!["item".to_string(), "get".to_string()],
+                env: HashMap::new(),
+                stdin: None,
+                cwd: "/tmp".to_string(),
+                tty: None,
+            },
+            Some(session_id.to_string()),
+            25,
+            &AuditLogger::new(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_approve_and_deny() {
+        let queue = Arc::new(ApprovalQueue::new());
+
+        // Two connections holding requests with the same id
+        let (first, second) = (request("1", "session-a"), request("1", "session-b"));
+        let (first_id, second_id) = (first.approval_id.clone(), second.approval_id.clone());
+        assert_ne!(first_id, second_id);
+        let waiting = queue.clone();
+        let first =
+            tokio::spawn(async move { waiting.request(first, Duration::from_secs(5)).await });
+        let waiting = queue.clone();
+        let second =
+            tokio::spawn(async move { waiting.request(second, Duration::from_secs(5)).await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(queue.pending().len(), 2);
+
+        assert!(!queue.decide("1", true, "alice"));
+        assert!(queue.decide(&first_id, true, "alice"));
+        assert!(queue.decide(&second_id, false, "bob"));
+        assert!(!queue.decide(&second_id, true, "bob"));
+        assert_eq!(
+            first.await.unwrap(),
+            ApprovalOutcome::Approved {
+                approver: "alice".to_string()
+            }
+        );
+        assert_eq!(
+            second.await.unwrap(),
+            ApprovalOutcome::Denied {
+                approver: "bob".to_string()
+            }
+        );
+        assert!(queue.pending().is_empty());
+    }
+
+    #[test]
+    fn test_pending_requests_are_redacted() {
+        let audit = AuditLogger::new();
+        let cli = CliRequest {
+            id: "1".to_string(),
+            tool: "gh".to_string(),
+            argv: vec!["auth".to_string(), "--token".to_string(), "abc".to_string()],
+            env: HashMap::new(),
+            stdin: None,
+            cwd: "/tmp".to_string(),
+            tty: None,
+        };
+        let pending = PendingApproval::cli(&cli, None, 25, &audit);
+        assert_eq!(pending.argv.unwrap(), vec!["auth", "--token", "[REDACTED]"]);
+
+        // A long body is redacted as JSON before it is cut to the preview
+        let body = serde_json::json!({
+            "filler": "x".repeat(2 * MAX_BODY_PREVIEW),
+            "Authorization": "Bearer abc",
+        });
+        let http = HttpRequest {
+            id: "2".to_string(),
+            tool: "api".to_string(),
+            method: "POST".to_string(),
+            path: "/rpc".to_string(),
+            headers: HashMap::new(),
+            body: Some(body.to_string()),
+            encoding: Default::default(),
+        };
+        let pending = PendingApproval::http(&http, None, 25, &audit);
+        let preview = pending.body.unwrap();
+        assert!(preview.len() <= MAX_BODY_PREVIEW);
+        assert!(!preview.contains("Bearer abc"));
+        assert!(PendingApproval::http(
+            &HttpRequest {
+                body: Some(r#"{"Authorization":"Bearer abc"}"#.to_string()),
+                ..http
+            },
+            None,
+            25,
+            &audit
+        )
+        .body
+        .unwrap()
+        .contains("[REDACTED]"));
+    }
+
+    #[test]
+    fn test_approvers_file() {
+        let approvers = Approvers::parse(
+            "# operators\nalice 0123456789abcdef0123\n\nbob   fedcba9876543210fedc\n",
+        )
+        .unwrap();
+        assert_eq!(approvers.identify("0123456789abcdef0123"), Some("alice"));
+        assert_eq!(approvers.identify("fedcba9876543210fedc"), Some("bob"));
+        assert_eq!(approvers.identify("0123456789abcdef012"), None);
+        assert_eq!(approvers.identify(""), None);
+
+        assert!(Approvers::parse("alice short").is_err());
+        assert!(Approvers::parse("alice").is_err());
+        assert!(Approvers::parse("# nobody\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_timeout_and_withdrawal() {
+        let queue = ApprovalQueue::new();
+        let late = request("late", "session-a");
+        let late_id = late.approval_id.clone();
+        let outcome = queue.request(late, Duration::from_millis(50)).await;
+        assert_eq!(outcome, ApprovalOutcome::TimedOut);
+        assert!(!queue.decide(&late_id, true, "alice"));
+
+        // A cancelled request leaves the queue, taking only its own entry
+        let _ = tokio::time::timeout(Duration::from_millis(50), async {
+            tokio::join!(
+                queue.request(request("gone", "session-a"), Duration::from_secs(5)),
+                async {
+                    let _ = tokio::time::timeout(
+                        Duration::from_millis(10),
+                        queue.request(request("gone", "session-b"), Duration::from_secs(5)),
+                    )
+                    .await;
+                    assert_eq!(queue.pending().len(), 1);
+                    assert_eq!(queue.pending()[0].session_id.as_deref(), Some("session-a"));
+                }
+            )
+        })
+        .await;
+        assert!(queue.pending().is_empty());
+    }
+}
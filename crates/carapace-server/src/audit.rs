@@ -1,41 +1,263 @@
+use crate::audit_sink::AuditSink;
+use crate::capture::{CaptureRecord, CaptureStore, CapturedEvent, Payload};
+use crate::error::{Result, ServerError};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use carapace_policy::{Decision, PolicyConfig, ToolPolicy};
+use carapace_protocol::{CliRequest, CliResponse, HttpRequest, HttpResponse, Message, SseEvent};
 use chrono::Utc;
+use ed25519_dalek::{Signer, SigningKey};
 use serde::Serialize;
-use std::fs::{self, OpenOptions};
-use std::io::Write;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
 use std::path::Path;
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tokio::runtime::RuntimeFlavor;
+
+/// Most entries the writer takes off its queue before flushing
+const MAX_BATCH: usize = 256;
+
+/// `prev_hash` of the first entry of a fresh audit log
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
 
 /// Structured audit log entry
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct AuditLogEntry {
     pub timestamp: String,
     pub request_id: String,
     pub tool: String,
+    /// Agent connection the request arrived on
+    pub session_id: Option<String>,
+    /// Address the agent connected from
+    pub peer: Option<String>,
+    /// Name the agent gave in its handshake
+    pub agent: Option<String>,
     pub action_type: String,   // "cli" or "http"
     pub policy_result: String, // "allow" or "deny"
     pub reason: Option<String>,
     pub argv: Option<Vec<String>>,
+    /// JSON request body, redacted, for tools that log bodies
+    pub body: Option<serde_json::Value>,
     pub method: Option<String>,
     pub path: Option<String>,
     pub exit_code: Option<i32>,
     pub stdout_length: Option<usize>,
     pub stderr_length: Option<usize>,
     pub latency_ms: Option<u64>,
+    pub transcript: Option<String>,
+    /// Names (never values) of client environment variables withheld from the tool
+    pub env_dropped: Option<Vec<String>>,
+    /// Operator who approved or denied a held request
+    pub approver: Option<String>,
+    /// Policy decision on the request and the rule behind it; on entries for
+    /// a decision that was not enforced, that decision
+    pub decision: Option<Decision>,
+}
+
+/// One agent connection. Entries for every request made over it share its
+/// `session_id`.
+#[derive(Debug, Clone)]
+pub struct AuditSession {
+    pub session_id: String,
+    /// Address the agent connected from, when known
+    pub peer: Option<String>,
+    /// Name the agent gave in its handshake. Self-reported, so it labels
+    /// entries but proves nothing.
+    pub agent: Option<String>,
+}
+
+impl AuditSession {
+    pub fn new(peer: Option<String>) -> Self {
+        Self {
+            session_id: uuid::Uuid::new_v4().to_string(),
+            peer,
+            agent: None,
+        }
+    }
+}
+
+/// Everything known about a request, so that each entry logged for it,
+/// from the request to its response, stands on its own
+#[derive(Debug, Clone, Default)]
+pub struct AuditContext {
+    pub request_id: String,
+    pub tool: String,
+    pub session: Option<AuditSession>,
+    pub argv: Option<Vec<String>>,
+    pub method: Option<String>,
+    pub path: Option<String>,
+    /// The live policy's decision, once evaluated
+    pub decision: Option<Decision>,
+}
+
+impl AuditContext {
+    /// Context for a request outside any agent connection
+    pub fn new(request_id: &str, tool: &str) -> Self {
+        Self {
+            request_id: request_id.to_string(),
+            tool: tool.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn cli(session: &AuditSession, req: &CliRequest) -> Self {
+        Self {
+            request_id: req.id.clone(),
+            tool: req.tool.clone(),
+            session: Some(session.clone()),
+            argv: Some(req.argv.clone()),
+            ..Default::default()
+        }
+    }
+
+    pub fn http(session: &AuditSession, req: &HttpRequest) -> Self {
+        Self {
+            request_id: req.id.clone(),
+            tool: req.tool.clone(),
+            session: Some(session.clone()),
+            method: Some(req.method.clone()),
+            path: Some(req.path.clone()),
+            ..Default::default()
+        }
+    }
+
+    /// Context for a message. Only CLI and HTTP requests are audited, so
+    /// other messages get an empty one.
+    pub fn for_message(session: &AuditSession, msg: &Message) -> Self {
+        match msg {
+            Message::CliRequest(req) => Self::cli(session, req),
+            Message::HttpRequest(req) => Self::http(session, req),
+            _ => Self {
+                session: Some(session.clone()),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// An entry as written to the log file, linked to the line before it
+#[derive(Serialize)]
+struct ChainedEntry<'a> {
+    #[serde(flatten)]
+    entry: &'a AuditLogEntry,
+    prev_hash: &'a str,
+}
+
+/// Signed statement that the chain up to `prev_hash` is intact
+#[derive(Serialize)]
+struct Checkpoint<'a> {
+    timestamp: String,
+    action_type: &'static str,
+    prev_hash: &'a str,
+    /// Base64 Ed25519 signature over the `prev_hash` string
+    signature: String,
+}
+
+/// Key and interval for signed checkpoints
+#[derive(Clone)]
+struct CheckpointSigner {
+    key: SigningKey,
+    interval: u64,
+}
+
+/// What happens to an entry when the writer's queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AuditBackpressure {
+    /// Wait for room, so requests slow down rather than go unaudited
+    #[default]
+    Block,
+    /// Drop the entry and count it
+    Drop,
+}
+
+impl std::str::FromStr for AuditBackpressure {
+    type Err = ServerError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "block" => Ok(AuditBackpressure::Block),
+            "drop" => Ok(AuditBackpressure::Drop),
+            _ => Err(ServerError::ConfigError(format!(
+                "Invalid audit backpressure '{}': expected block or drop",
+                s
+            ))),
+        }
+    }
+}
+
+/// When the writer fsyncs the log file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AuditFsync {
+    /// Leave it to the OS
+    Never,
+    /// After every batch of entries
+    #[default]
+    Batch,
+    /// At most once per interval, and before flush returns
+    Interval(Duration),
+}
+
+impl std::str::FromStr for AuditFsync {
+    type Err = ServerError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "never" => Ok(AuditFsync::Never),
+            "batch" => Ok(AuditFsync::Batch),
+            _ => match s.parse::<u64>() {
+                Ok(secs) if secs > 0 => Ok(AuditFsync::Interval(Duration::from_secs(secs))),
+                _ => Err(ServerError::ConfigError(format!(
+                    "Invalid audit fsync '{}': expected never, batch or a number of seconds",
+                    s
+                ))),
+            },
+        }
+    }
+}
+
+/// Work for the writer thread
+enum WriterCommand {
+    Entry(Box<AuditLogEntry>),
+    /// Write out everything queued so far, then acknowledge
+    Flush(mpsc::Sender<()>),
+}
+
+/// The logger's end of its writer thread
+struct WriterHandle {
+    tx: SyncSender<WriterCommand>,
+    thread: JoinHandle<()>,
 }
 
 /// Audit logging system with structured JSON output and persistence
 pub struct AuditLogger {
     enabled: bool,
     log_argv: bool,
-    #[allow(dead_code)]
     log_body: bool,
     redact_patterns: Vec<String>,
+    /// Each tool's `audit` settings from the policy
+    tools: HashMap<String, carapace_policy::AuditConfig>,
     log_file: Option<String>,
     max_size_bytes: u64,
     keep_logs: u32,
-    #[allow(dead_code)]
-    current_size: Arc<Mutex<u64>>,
+    signer: Option<CheckpointSigner>,
+    backpressure: AuditBackpressure,
+    queue_capacity: usize,
+    fsync: AuditFsync,
+    /// Export destinations besides `log_file`; each gets a thread of its
+    /// own when the writer starts
+    sinks: Mutex<Vec<Box<dyn AuditSink>>>,
+    /// Started on the first entry written to `log_file` or a sink
+    writer: OnceLock<WriterHandle>,
+    /// Shared with the export sinks' threads, which drop entries too
+    dropped: Arc<AtomicU64>,
+    /// Where full payloads of tools with `audit.capture` go
+    capture: Option<Arc<CaptureStore>>,
 }
 
 impl AuditLogger {
@@ -43,17 +265,25 @@ impl AuditLogger {
         AuditLogger {
             enabled: true,
             log_argv: true,
-            log_body: false,
+            log_body: true,
             redact_patterns: vec![
                 "--token".to_string(),
                 "--password".to_string(),
                 "--secret".to_string(),
                 "Authorization".to_string(),
             ],
+            tools: HashMap::new(),
             log_file: None,
             max_size_bytes: 100 * 1024 * 1024, // 100MB
             keep_logs: 10,
-            current_size: Arc::new(Mutex::new(0)),
+            signer: None,
+            backpressure: AuditBackpressure::default(),
+            queue_capacity: 4096,
+            fsync: AuditFsync::default(),
+            sinks: Mutex::new(Vec::new()),
+            writer: OnceLock::new(),
+            dropped: Arc::new(AtomicU64::new(0)),
+            capture: None,
         }
     }
 
@@ -75,151 +305,427 @@ impl AuditLogger {
                 "--secret".to_string(),
                 "Authorization".to_string(),
             ],
+            tools: HashMap::new(),
             log_file,
             max_size_bytes,
             keep_logs,
-            current_size: Arc::new(Mutex::new(0)),
+            signer: None,
+            backpressure: AuditBackpressure::default(),
+            queue_capacity: 4096,
+            fsync: AuditFsync::default(),
+            sinks: Mutex::new(Vec::new()),
+            writer: OnceLock::new(),
+            dropped: Arc::new(AtomicU64::new(0)),
+            capture: None,
         }
     }
 
-    /// Log a CLI request
-    pub fn log_cli_request(
-        &self,
-        request_id: &str,
-        tool: &str,
-        argv: &[String],
-        allowed: bool,
-        reason: Option<&str>,
-    ) {
-        if !self.enabled {
-            return;
-        }
+    /// Apply each tool's `audit` settings from the policy: whether it is
+    /// logged at all, whether argv and bodies are, and what to redact
+    pub fn with_policy(mut self, policy: &PolicyConfig) -> Self {
+        self.tools = policy
+            .tools
+            .iter()
+            .map(|(name, tool)| {
+                let audit = match tool {
+                    ToolPolicy::Cli(policy) => &policy.audit,
+                    ToolPolicy::Http(policy) => &policy.audit,
+                };
+                (name.clone(), audit.clone())
+            })
+            .collect();
+        self
+    }
 
-        let redacted_argv = if allowed && self.log_argv {
-            Some(self.redact_sensitive_args(argv))
-        } else {
-            None
-        };
+    /// Sign a checkpoint into the log file every `interval` entries
+    pub fn with_signing_key(mut self, key: SigningKey, interval: u64) -> Self {
+        self.signer = Some(CheckpointSigner {
+            key,
+            interval: interval.max(1),
+        });
+        self
+    }
 
-        let entry = AuditLogEntry {
-            timestamp: Utc::now().to_rfc3339(),
-            request_id: request_id.to_string(),
-            tool: tool.to_string(),
-            action_type: "cli".to_string(),
-            policy_result: if allowed {
-                "allow".to_string()
-            } else {
-                "deny".to_string()
-            },
-            reason: reason.map(|s| s.to_string()),
-            argv: redacted_argv,
-            method: None,
-            path: None,
-            exit_code: None,
-            stdout_length: None,
-            stderr_length: None,
-            latency_ms: None,
-        };
+    /// Bound the writer's queue and choose what happens when it is full
+    pub fn with_queue(mut self, capacity: usize, backpressure: AuditBackpressure) -> Self {
+        self.queue_capacity = capacity.max(1);
+        self.backpressure = backpressure;
+        self
+    }
+
+    /// Choose when the writer fsyncs the log file
+    pub fn with_fsync(mut self, fsync: AuditFsync) -> Self {
+        self.fsync = fsync;
+        self
+    }
 
-        self.emit_log_entry(&entry);
+    /// Also send every entry to `sink`. The sink runs on its own thread
+    /// behind a queue as long as the writer's, and entries that find that
+    /// queue full are dropped, so a slow collector cannot hold up the log
+    /// file or the server.
+    pub fn with_sink(mut self, sink: Box<dyn AuditSink>) -> Self {
+        self.sinks.get_mut().unwrap().push(sink);
+        self
     }
 
-    /// Log a CLI response
+    /// Keep full payloads of tools whose policy sets `audit.capture` in `store`
+    pub fn with_capture(mut self, store: CaptureStore) -> Self {
+        self.capture = Some(Arc::new(store));
+        self
+    }
+
+    /// Entries dropped because the writer's queue, or an export sink's,
+    /// was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Block until every entry logged so far is written out (and synced,
+    /// unless fsync is `never`). Call on graceful shutdown.
+    pub fn flush(&self) {
+        if let Some(writer) = self.writer.get() {
+            let (ack_tx, ack_rx) = mpsc::channel();
+            if writer.tx.send(WriterCommand::Flush(ack_tx)).is_ok() {
+                let _ = ack_rx.recv();
+            }
+        }
+    }
+
+    /// Log a CLI request, with the policy decision on it once evaluated
+    pub fn log_cli_request(&self, ctx: &AuditContext, allowed: bool, reason: Option<&str>) {
+        if !self.enabled_for(&ctx.tool) {
+            return;
+        }
+
+        let mut entry = self.entry(ctx, "cli", if allowed { "allow" } else { "deny" });
+        entry.reason = reason.map(|s| s.to_string());
+        // Denied requests keep their argv here too, so a candidate policy
+        // can be replayed against them
+        entry.argv = self.logged_argv(ctx);
+        self.emit_log_entry(entry);
+    }
+
+    /// Log the end of a CLI request that ran
     pub fn log_cli_response(
         &self,
-        request_id: &str,
+        ctx: &AuditContext,
         exit_code: i32,
         stdout_len: usize,
         stderr_len: usize,
         latency_ms: u64,
     ) {
-        if !self.enabled {
+        if !self.enabled_for(&ctx.tool) {
             return;
         }
 
-        let entry = AuditLogEntry {
-            timestamp: Utc::now().to_rfc3339(),
-            request_id: request_id.to_string(),
-            tool: String::new(),
-            action_type: "cli_response".to_string(),
-            policy_result: String::new(),
-            reason: None,
-            argv: None,
-            method: None,
-            path: None,
-            exit_code: Some(exit_code),
-            stdout_length: Some(stdout_len),
-            stderr_length: Some(stderr_len),
-            latency_ms: Some(latency_ms),
-        };
-
-        self.emit_log_entry(&entry);
+        let mut entry = self.entry(ctx, "cli_response", "allow");
+        entry.exit_code = Some(exit_code);
+        entry.stdout_length = Some(stdout_len);
+        entry.stderr_length = Some(stderr_len);
+        entry.latency_ms = Some(latency_ms);
+        self.emit_log_entry(entry);
     }
 
-    /// Log an HTTP request
+    /// Log an HTTP request, with the policy decision on it once evaluated.
+    /// Only this entry carries the body, for tools that log bodies.
     pub fn log_http_request(
         &self,
-        request_id: &str,
-        tool: &str,
-        method: &str,
-        path: &str,
+        ctx: &AuditContext,
+        req: &HttpRequest,
         allowed: bool,
         reason: Option<&str>,
     ) {
-        if !self.enabled {
+        if !self.enabled_for(&ctx.tool) {
             return;
         }
 
-        let entry = AuditLogEntry {
-            timestamp: Utc::now().to_rfc3339(),
-            request_id: request_id.to_string(),
-            tool: tool.to_string(),
-            action_type: "http".to_string(),
-            policy_result: if allowed {
-                "allow".to_string()
-            } else {
-                "deny".to_string()
-            },
-            reason: reason.map(|s| s.to_string()),
-            argv: None,
-            method: Some(method.to_string()),
-            path: Some(path.to_string()),
-            exit_code: None,
-            stdout_length: None,
-            stderr_length: None,
-            latency_ms: None,
-        };
+        let mut entry = self.entry(ctx, "http", if allowed { "allow" } else { "deny" });
+        entry.reason = reason.map(|s| s.to_string());
+        if allowed && self.logs_body(&ctx.tool) {
+            entry.body = self.redacted_body(req);
+        }
+        self.emit_log_entry(entry);
+    }
+
+    /// Log the end of an HTTP request that was proxied. `status` is the
+    /// upstream's, or 500 if the request failed.
+    pub fn log_http_response(&self, ctx: &AuditContext, status: u16, latency_ms: u64) {
+        if !self.enabled_for(&ctx.tool) {
+            return;
+        }
 
-        self.emit_log_entry(&entry);
+        let mut entry = self.entry(ctx, "http_response", "allow");
+        entry.exit_code = Some(status as i32);
+        entry.latency_ms = Some(latency_ms);
+        self.emit_log_entry(entry);
     }
 
-    /// Log an HTTP response
-    pub fn log_http_response(&self, request_id: &str, status: u16, latency_ms: u64) {
-        if !self.enabled {
+    /// Log a request abandoned by the client before it completed
+    pub fn log_cancellation(&self, ctx: &AuditContext, latency_ms: u64) {
+        if !self.enabled_for(&ctx.tool) {
             return;
         }
 
-        let entry = AuditLogEntry {
+        let mut entry = self.entry(ctx, "cancelled", "allow");
+        entry.reason = Some("cancelled_by_client".to_string());
+        entry.latency_ms = Some(latency_ms);
+        self.emit_log_entry(entry);
+    }
+
+    /// Log the decision on a request held for approval. `reason` is set
+    /// when it was denied: "approval_denied" or "approval_timeout".
+    pub fn log_approval(
+        &self,
+        ctx: &AuditContext,
+        approver: Option<&str>,
+        reason: Option<&str>,
+        latency_ms: u64,
+    ) {
+        if !self.enabled_for(&ctx.tool) {
+            return;
+        }
+
+        let result = if reason.is_none() { "allow" } else { "deny" };
+        let mut entry = self.entry(ctx, "approval", result);
+        entry.reason = reason.map(|s| s.to_string());
+        entry.latency_ms = Some(latency_ms);
+        entry.approver = approver.map(|s| s.to_string());
+        self.emit_log_entry(entry);
+    }
+
+    /// Log a request let through although `decision` denies it, because its
+    /// tool's policy is in audit-only mode
+    pub fn log_audit_only_denial(&self, ctx: &AuditContext, decision: &Decision) {
+        if !self.enabled_for(&ctx.tool) {
+            return;
+        }
+
+        let mut entry = self.entry(ctx, "audit_only", "deny");
+        entry.reason = Some("not_enforced".to_string());
+        entry.decision = Some(decision.clone());
+        self.emit_log_entry(entry);
+    }
+
+    /// Log a request the candidate policy decides differently from the live
+    /// one. `policy_result` is the candidate's decision; `decision` is that
+    /// of whichever policy denies.
+    pub fn log_candidate_disagreement(
+        &self,
+        ctx: &AuditContext,
+        live: &Decision,
+        candidate: &Decision,
+    ) {
+        if !self.enabled_for(&ctx.tool) {
+            return;
+        }
+
+        let result = if candidate.allowed { "allow" } else { "deny" };
+        let mut entry = self.entry(ctx, "candidate_policy", result);
+        entry.reason = Some("candidate_disagrees".to_string());
+        entry.decision = Some(if candidate.allowed { live } else { candidate }.clone());
+        self.emit_log_entry(entry);
+    }
+
+    /// Log the end of an interactive session
+    pub fn log_pty_session(
+        &self,
+        ctx: &AuditContext,
+        exit_code: i32,
+        duration_ms: u64,
+        transcript: Option<&Path>,
+    ) {
+        if !self.enabled_for(&ctx.tool) {
+            return;
+        }
+
+        let mut entry = self.entry(ctx, "pty_session", "allow");
+        entry.exit_code = Some(exit_code);
+        entry.latency_ms = Some(duration_ms);
+        entry.transcript = transcript.map(|p| p.to_string_lossy().to_string());
+        self.emit_log_entry(entry);
+    }
+
+    /// Log output discarded for exceeding the tool's limits. `action` is
+    /// what the policy did about it: "truncate" or "kill".
+    pub fn log_output_truncated(&self, ctx: &AuditContext, streams: &[&str], action: &str) {
+        if !self.enabled_for(&ctx.tool) {
+            return;
+        }
+
+        let mut entry = self.entry(ctx, "output_truncated", action);
+        entry.reason = Some(format!("{} limit exceeded", streams.join(", ")));
+        self.emit_log_entry(entry);
+    }
+
+    /// Log client environment variables dropped by the tool's env policy
+    pub fn log_env_dropped(&self, ctx: &AuditContext, names: &[String]) {
+        if !self.enabled_for(&ctx.tool) {
+            return;
+        }
+
+        let mut entry = self.entry(ctx, "env_dropped", "deny");
+        entry.reason = Some("env_not_allowed".to_string());
+        entry.env_dropped = Some(names.to_vec());
+        self.emit_log_entry(entry);
+    }
+
+    /// Whether requests to `tool` have their payloads captured
+    pub fn captures(&self, tool: &str) -> bool {
+        self.capture.is_some() && self.tools.get(tool).is_some_and(|audit| audit.capture)
+    }
+
+    /// Capture what a CLI request sent and what it printed
+    pub fn capture_cli(&self, ctx: &AuditContext, req: &CliRequest, resp: &CliResponse) {
+        if !self.captures(&ctx.tool) {
+            return;
+        }
+
+        let output = |stream: &str| match resp.encoding.decode(stream) {
+            Ok(bytes) => self.redacted_payload(&ctx.tool, &bytes),
+            Err(_) => Payload::text(String::new()),
+        };
+        let mut record = self.capture_record(ctx);
+        record.argv = Some(self.redact_sensitive_args(&ctx.tool, &req.argv));
+        record.stdin = req
+            .stdin
+            .as_ref()
+            .map(|stdin| self.redacted_payload(&ctx.tool, stdin.as_bytes()));
+        record.exit_code = Some(resp.exit_code);
+        record.stdout = Some(output(&resp.stdout));
+        record.stderr = Some(output(&resp.stderr));
+        self.store_capture(record);
+    }
+
+    /// Capture an HTTP request and its response, which is either `resp` or,
+    /// for a streamed one, `events`
+    pub fn capture_http(
+        &self,
+        ctx: &AuditContext,
+        req: &HttpRequest,
+        resp: Option<&HttpResponse>,
+        events: &[SseEvent],
+    ) {
+        if !self.captures(&ctx.tool) {
+            return;
+        }
+
+        let mut record = self.capture_record(ctx);
+        record.method = Some(req.method.clone());
+        record.path = Some(req.path.clone());
+        record.request_headers = Some(self.redacted_headers(&ctx.tool, &req.headers));
+        record.request_body = req
+            .body_bytes()
+            .ok()
+            .flatten()
+            .map(|body| self.redacted_payload(&ctx.tool, &body));
+        if let Some(resp) = resp {
+            record.status = Some(resp.status);
+            record.response_headers = Some(self.redacted_headers(&ctx.tool, &resp.headers));
+            record.response_body = resp
+                .body_bytes()
+                .ok()
+                .flatten()
+                .map(|body| self.redacted_payload(&ctx.tool, &body));
+        }
+        record.sse_events = events
+            .iter()
+            .map(|event| CapturedEvent {
+                event: event.event.clone(),
+                data: self.redact_text(&ctx.tool, &event.data),
+            })
+            .collect();
+        self.store_capture(record);
+    }
+
+    fn capture_record(&self, ctx: &AuditContext) -> CaptureRecord {
+        CaptureRecord {
+            request_id: ctx.request_id.clone(),
+            tool: ctx.tool.clone(),
             timestamp: Utc::now().to_rfc3339(),
-            request_id: request_id.to_string(),
-            tool: String::new(),
-            action_type: "http_response".to_string(),
-            policy_result: format!("status_{}", status),
-            reason: None,
-            argv: None,
-            method: None,
-            path: None,
-            exit_code: Some(status as i32),
-            stdout_length: None,
-            stderr_length: None,
-            latency_ms: Some(latency_ms),
+            session_id: ctx.session.as_ref().map(|s| s.session_id.clone()),
+            ..Default::default()
+        }
+    }
+
+    /// Write a capture off the async runtime, when there is one
+    fn store_capture(&self, record: CaptureRecord) {
+        let Some(store) = self.capture.clone() else {
+            return;
+        };
+        let save = move || {
+            if let Err(e) = store.save(&record) {
+                tracing::warn!("Failed to capture request {}: {}", record.request_id, e);
+            }
         };
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(save)),
+            Err(_) => save(),
+        }
+    }
+
+    /// An entry about the request in `ctx`, carrying its session, what it
+    /// asked for and the policy decision on it. Argv is left out of entries
+    /// for denied requests.
+    fn entry(&self, ctx: &AuditContext, action_type: &str, policy_result: &str) -> AuditLogEntry {
+        let argv = match policy_result {
+            "deny" => None,
+            _ => self.logged_argv(ctx),
+        };
+
+        AuditLogEntry {
+            timestamp: Utc::now().to_rfc3339(),
+            request_id: ctx.request_id.clone(),
+            tool: ctx.tool.clone(),
+            session_id: ctx.session.as_ref().map(|s| s.session_id.clone()),
+            peer: ctx.session.as_ref().and_then(|s| s.peer.clone()),
+            agent: ctx.session.as_ref().and_then(|s| s.agent.clone()),
+            action_type: action_type.to_string(),
+            policy_result: policy_result.to_string(),
+            argv,
+            method: ctx.method.clone(),
+            path: ctx.path.clone(),
+            decision: ctx.decision.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// The request's argv, redacted, if its tool logs argv
+    fn logged_argv(&self, ctx: &AuditContext) -> Option<Vec<String>> {
+        ctx.argv
+            .as_ref()
+            .filter(|_| self.logs_argv(&ctx.tool))
+            .map(|argv| self.redact_sensitive_args(&ctx.tool, argv))
+    }
+
+    /// Whether entries for `tool` are logged at all
+    fn enabled_for(&self, tool: &str) -> bool {
+        self.enabled && self.tools.get(tool).is_none_or(|audit| audit.enabled)
+    }
+
+    /// Tools in the policy log argv only when their settings ask for it
+    fn logs_argv(&self, tool: &str) -> bool {
+        self.log_argv && self.tools.get(tool).is_none_or(|audit| audit.log_argv)
+    }
 
-        self.emit_log_entry(&entry);
+    fn logs_body(&self, tool: &str) -> bool {
+        self.log_body && self.tools.get(tool).is_some_and(|audit| audit.log_body)
+    }
+
+    /// The built-in patterns plus the tool's own
+    fn redact_patterns<'a>(&'a self, tool: &str) -> impl Iterator<Item = &'a str> {
+        let tool_patterns = self
+            .tools
+            .get(tool)
+            .map(|audit| audit.redact_patterns.as_slice())
+            .unwrap_or_default();
+        self.redact_patterns
+            .iter()
+            .chain(tool_patterns)
+            .map(String::as_str)
     }
 
     /// Redact sensitive arguments (tokens, passwords, etc.)
-    fn redact_sensitive_args(&self, argv: &[String]) -> Vec<String> {
+    pub fn redact_sensitive_args(&self, tool: &str, argv: &[String]) -> Vec<String> {
         let mut result = Vec::new();
         let mut skip_next = false;
 
@@ -231,13 +737,9 @@ impl AuditLogger {
             }
 
             // Check if this is a sensitive flag
-            let mut is_sensitive = false;
-            for pattern in &self.redact_patterns {
-                if arg.contains(pattern.as_str()) {
-                    is_sensitive = true;
-                    break;
-                }
-            }
+            let is_sensitive = self
+                .redact_patterns(tool)
+                .any(|pattern| arg.contains(pattern));
 
             if is_sensitive {
                 result.push(arg.clone());
@@ -253,57 +755,445 @@ impl AuditLogger {
         result
     }
 
-    /// Emit log entry as structured JSON to stdout/logs and optionally to file
-    fn emit_log_entry(&self, entry: &AuditLogEntry) {
-        if let Ok(json) = serde_json::to_string(entry) {
-            // Always log to tracing
-            tracing::info!("AUDIT: {}", json);
+    /// The request body as JSON, with the values of sensitive keys
+    /// redacted. Bodies that are not JSON are not logged.
+    fn redacted_body(&self, req: &HttpRequest) -> Option<serde_json::Value> {
+        let bytes = req.body_bytes().ok()??;
+        let mut body = serde_json::from_slice(&bytes).ok()?;
+        self.redact_json(&req.tool, &mut body);
+        Some(body)
+    }
 
-            // Also write to file if configured
-            if let Some(log_file) = &self.log_file {
-                let _ = self.write_to_file(&json, log_file);
+    fn redact_json(&self, tool: &str, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    if self
+                        .redact_patterns(tool)
+                        .any(|pattern| key.contains(pattern))
+                    {
+                        *value = serde_json::Value::String("[REDACTED]".to_string());
+                    } else {
+                        self.redact_json(tool, value);
+                    }
+                }
             }
+            serde_json::Value::Array(items) => {
+                for item in items {
+                    self.redact_json(tool, item);
+                }
+            }
+            _ => {}
         }
     }
 
-    /// Write log entry to file with rotation
-    fn write_to_file(&self, json: &str, log_file: &str) -> std::io::Result<()> {
-        let log_entry = format!("{}\n", json);
-        let entry_size = log_entry.len() as u64;
+    /// Redact a payload as text; binary payloads are kept as they are
+    fn redacted_payload(&self, tool: &str, bytes: &[u8]) -> Payload {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => Payload::text(self.redact_text(tool, text)),
+            Err(_) => Payload::from_bytes(bytes),
+        }
+    }
 
-        // Check if we need to rotate
-        let current_size = std::fs::metadata(log_file)
-            .ok()
-            .map(|m| m.len())
-            .unwrap_or(0);
+    /// JSON has the values of sensitive keys redacted. Other text has the
+    /// rest of each line redacted after a sensitive pattern, which covers
+    /// `--token abc`, `password=abc` and `Authorization: Bearer abc`.
+    pub fn redact_text(&self, tool: &str, text: &str) -> String {
+        if let Ok(mut json) = serde_json::from_str::<serde_json::Value>(text) {
+            if json.is_object() || json.is_array() {
+                self.redact_json(tool, &mut json);
+                return json.to_string();
+            }
+        }
 
-        if current_size + entry_size > self.max_size_bytes {
-            self.rotate_logs(log_file)?;
+        let mut redacted = String::with_capacity(text.len());
+        for line in text.split_inclusive('\n') {
+            let found = self
+                .redact_patterns(tool)
+                .filter_map(|pattern| line.find(pattern).map(|at| at + pattern.len()))
+                .min();
+            let Some(end) = found else {
+                redacted.push_str(line);
+                continue;
+            };
+            let (kept, rest) = line.split_at(end);
+            let value = rest.trim_start_matches([' ', '=', ':', '"', '\'']);
+            let value_start = end + (rest.len() - value.len());
+            if value.trim_end().is_empty() {
+                redacted.push_str(line);
+                continue;
+            }
+            redacted.push_str(kept);
+            redacted.push_str(&line[end..value_start]);
+            redacted.push_str("[REDACTED]");
+            if line.ends_with('\n') {
+                redacted.push('\n');
+            }
+        }
+        redacted
+    }
+
+    fn redacted_headers(
+        &self,
+        tool: &str,
+        headers: &HashMap<String, String>,
+    ) -> BTreeMap<String, String> {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                let sensitive = self
+                    .redact_patterns(tool)
+                    .any(|pattern| name.eq_ignore_ascii_case(pattern) || name.contains(pattern));
+                let value = if sensitive {
+                    "[REDACTED]".to_string()
+                } else {
+                    value.clone()
+                };
+                (name.clone(), value)
+            })
+            .collect()
+    }
+
+    /// Emit log entry as structured JSON to stdout/logs and optionally to
+    /// file and sinks. These are written by the writer thread, so callers
+    /// on async tasks never touch the disk or network.
+    fn emit_log_entry(&self, entry: AuditLogEntry) {
+        if self.log_file.is_none() {
+            if let Ok(json) = serde_json::to_string(&entry) {
+                tracing::info!("AUDIT: {}", json);
+            }
+            if self.writer.get().is_none() && self.sinks.lock().unwrap().is_empty() {
+                return;
+            }
+        }
+
+        let writer = self.writer.get_or_init(|| self.spawn_writer());
+        let command = WriterCommand::Entry(Box::new(entry));
+        match self.backpressure {
+            AuditBackpressure::Block => {
+                // Entries are logged from async tasks. On a multi-threaded
+                // runtime, waiting for room hands this worker's other tasks
+                // to another worker; elsewhere it just blocks, as the writer
+                // drains the queue on its own thread.
+                let on_multi_thread = tokio::runtime::Handle::try_current()
+                    .is_ok_and(|handle| handle.runtime_flavor() == RuntimeFlavor::MultiThread);
+                if on_multi_thread {
+                    let _ = tokio::task::block_in_place(|| writer.tx.send(command));
+                } else {
+                    let _ = writer.tx.send(command);
+                }
+            }
+            AuditBackpressure::Drop => {
+                if let Err(TrySendError::Full(_)) = writer.tx.try_send(command) {
+                    let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                    if dropped == 1 || dropped.is_multiple_of(1000) {
+                        tracing::warn!("Audit queue full: {} entries dropped so far", dropped);
+                    }
+                }
+            }
         }
+    }
+
+    fn spawn_writer(&self) -> WriterHandle {
+        let (tx, rx) = mpsc::sync_channel(self.queue_capacity);
+        let mut sinks: Vec<Box<dyn AuditSink>> = std::mem::take(&mut *self.sinks.lock().unwrap())
+            .into_iter()
+            .map(|sink| {
+                Box::new(QueuedSink::spawn(
+                    sink,
+                    self.queue_capacity,
+                    self.dropped.clone(),
+                )) as Box<dyn AuditSink>
+            })
+            .collect();
+        if let Some(log_file) = &self.log_file {
+            let file_sink = FileSink {
+                log_file: log_file.clone(),
+                max_size_bytes: self.max_size_bytes,
+                keep_logs: self.keep_logs,
+                fsync: self.fsync,
+                signer: self.signer.clone(),
+                file: None,
+                size: 0,
+                head: None,
+                since_checkpoint: 0,
+                unsynced: false,
+                synced_at: Instant::now(),
+            };
+            sinks.insert(0, Box::new(file_sink));
+        }
+        let writer = AuditWriter { sinks };
+        let thread = std::thread::Builder::new()
+            .name("carapace-audit".to_string())
+            .spawn(move || writer.run(rx))
+            .expect("failed to spawn audit writer thread");
+        WriterHandle { tx, thread }
+    }
+}
 
-        // Append to log file
-        let mut file = OpenOptions::new()
+impl Drop for AuditLogger {
+    fn drop(&mut self) {
+        // Closing the queue lets the writer drain it and exit
+        if let Some(WriterHandle { tx, thread }) = self.writer.take() {
+            drop(tx);
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Runs on the writer thread and hands each queued entry to every sink,
+/// in the order the entries were queued
+struct AuditWriter {
+    sinks: Vec<Box<dyn AuditSink>>,
+}
+
+impl AuditWriter {
+    fn run(mut self, rx: Receiver<WriterCommand>) {
+        loop {
+            // Wake up for sinks holding entries back, even if nothing arrives
+            let deadline = self.sinks.iter().filter_map(|sink| sink.deadline()).min();
+            let first = match deadline {
+                Some(deadline) => {
+                    match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                        Ok(command) => command,
+                        Err(RecvTimeoutError::Timeout) => {
+                            self.finish_batch(false);
+                            continue;
+                        }
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+                None => match rx.recv() {
+                    Ok(command) => command,
+                    Err(_) => break,
+                },
+            };
+
+            let mut acks = Vec::new();
+            let mut command = Some(first);
+            let mut taken = 0;
+            while let Some(next) = command.take() {
+                match next {
+                    WriterCommand::Entry(entry) => {
+                        for sink in &mut self.sinks {
+                            if let Err(e) = sink.write(&entry) {
+                                tracing::warn!(
+                                    "Failed to write audit entry to {}: {}",
+                                    sink.name(),
+                                    e
+                                );
+                            }
+                        }
+                    }
+                    WriterCommand::Flush(ack) => acks.push(ack),
+                }
+                taken += 1;
+                if taken < MAX_BATCH {
+                    command = rx.try_recv().ok();
+                }
+            }
+
+            self.finish_batch(!acks.is_empty());
+            for ack in acks {
+                let _ = ack.send(());
+            }
+        }
+
+        // Queue closed: the logger is gone or the server is shutting down
+        self.finish_batch(true);
+    }
+
+    fn finish_batch(&mut self, force: bool) {
+        for sink in &mut self.sinks {
+            if let Err(e) = sink.flush(force) {
+                tracing::warn!("Failed to flush audit entries to {}: {}", sink.name(), e);
+            }
+        }
+    }
+}
+
+/// An export sink on a thread of its own, fed through a bounded queue.
+/// The writer thread never waits for it except on a forced flush.
+struct QueuedSink {
+    name: String,
+    tx: Option<SyncSender<WriterCommand>>,
+    thread: Option<JoinHandle<()>>,
+    /// Entries this sink dropped, for spacing out warnings
+    dropped: u64,
+    /// The logger's count of dropped entries
+    total_dropped: Arc<AtomicU64>,
+}
+
+impl QueuedSink {
+    fn spawn(sink: Box<dyn AuditSink>, capacity: usize, total_dropped: Arc<AtomicU64>) -> Self {
+        let name = sink.name();
+        let (tx, rx) = mpsc::sync_channel(capacity);
+        let writer = AuditWriter { sinks: vec![sink] };
+        let thread = std::thread::Builder::new()
+            .name("carapace-audit-sink".to_string())
+            .spawn(move || writer.run(rx))
+            .expect("failed to spawn audit sink thread");
+        QueuedSink {
+            name,
+            tx: Some(tx),
+            thread: Some(thread),
+            dropped: 0,
+            total_dropped,
+        }
+    }
+}
+
+impl AuditSink for QueuedSink {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn write(&mut self, entry: &AuditLogEntry) -> std::io::Result<()> {
+        let Some(tx) = &self.tx else {
+            return Ok(());
+        };
+        match tx.try_send(WriterCommand::Entry(Box::new(entry.clone()))) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                self.total_dropped.fetch_add(1, Ordering::Relaxed);
+                if self.dropped == 1 || self.dropped.is_multiple_of(1000) {
+                    tracing::warn!(
+                        "Queue for {} full: {} entries dropped so far",
+                        self.name,
+                        self.dropped
+                    );
+                }
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                Err(std::io::Error::other("sink thread has exited"))
+            }
+        }
+    }
+
+    /// Only a forced flush waits, for the sink to take everything queued
+    fn flush(&mut self, force: bool) -> std::io::Result<()> {
+        let Some(tx) = self.tx.as_ref().filter(|_| force) else {
+            return Ok(());
+        };
+        let (ack_tx, ack_rx) = mpsc::channel();
+        if tx.send(WriterCommand::Flush(ack_tx)).is_ok() {
+            let _ = ack_rx.recv();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for QueuedSink {
+    fn drop(&mut self) {
+        // As for the writer: closing the queue lets the thread drain it
+        drop(self.tx.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Sole owner of the log file: chains, batches, rotates and syncs entries
+struct FileSink {
+    log_file: String,
+    max_size_bytes: u64,
+    keep_logs: u32,
+    fsync: AuditFsync,
+    signer: Option<CheckpointSigner>,
+    file: Option<BufWriter<File>>,
+    size: u64,
+    /// Hash of the last line written; read back from disk on start
+    head: Option<String>,
+    since_checkpoint: u64,
+    unsynced: bool,
+    synced_at: Instant,
+}
+
+impl FileSink {
+    /// Link the entry to the previous line and append it, followed by a
+    /// signed checkpoint when one is due
+    fn append(&mut self, entry: &AuditLogEntry) -> std::io::Result<()> {
+        let prev_hash = match &self.head {
+            Some(head) => head.clone(),
+            None => read_chain_head(&self.log_file)?,
+        };
+        let json = serde_json::to_string(&ChainedEntry {
+            entry,
+            prev_hash: &prev_hash,
+        })?;
+        tracing::info!("AUDIT: {}", json);
+        self.write_line(&json)?;
+        self.since_checkpoint += 1;
+
+        if let Some(signer) = &self.signer {
+            if self.since_checkpoint >= signer.interval {
+                let head = self.head.clone().unwrap_or_default();
+                let checkpoint = serde_json::to_string(&Checkpoint {
+                    timestamp: Utc::now().to_rfc3339(),
+                    action_type: "checkpoint",
+                    prev_hash: &head,
+                    signature: BASE64.encode(signer.key.sign(head.as_bytes()).to_bytes()),
+                })?;
+                tracing::info!("AUDIT: {}", checkpoint);
+                self.write_line(&checkpoint)?;
+                self.since_checkpoint = 0;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Append one line, rotating first if it would not fit
+    fn write_line(&mut self, json: &str) -> std::io::Result<()> {
+        let entry_size = json.len() as u64 + 1;
+        if self.file.is_none() {
+            self.open()?;
+        }
+        if self.size > 0 && self.size + entry_size > self.max_size_bytes {
+            self.rotate_logs()?;
+            self.open()?;
+        }
+
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(json.as_bytes())?;
+            file.write_all(b"\n")?;
+        }
+        self.size += entry_size;
+        self.head = Some(line_hash(json));
+        self.unsynced = true;
+        Ok(())
+    }
+
+    fn open(&mut self) -> std::io::Result<()> {
+        let file = OpenOptions::new()
             .create(true)
             .append(true)
-            .open(log_file)?;
-
-        file.write_all(log_entry.as_bytes())?;
+            .open(&self.log_file)?;
+        self.size = file.metadata()?.len();
+        self.file = Some(BufWriter::new(file));
         Ok(())
     }
 
-    /// Rotate logs when max size is exceeded
-    fn rotate_logs(&self, log_file: &str) -> std::io::Result<()> {
-        let path = Path::new(log_file);
+    /// Rotate logs when max size is exceeded. The chain head stays in
+    /// memory, so the first entry of the new file links to the last line
+    /// of the one just rotated out.
+    fn rotate_logs(&mut self) -> std::io::Result<()> {
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+        }
+        let log_file = self.log_file.as_str();
 
         // Rotate existing logs: shift .1 → .2, .0 → .1, current → .0
         for i in (0..self.keep_logs).rev() {
             let old_name = if i == 0 {
                 log_file.to_string()
             } else {
-                format!("{}.{}", path.with_extension("").to_string_lossy(), i)
+                rotated_path(log_file, i)
             };
 
-            let new_name = format!("{}.{}", path.with_extension("").to_string_lossy(), i + 1);
+            let new_name = rotated_path(log_file, i + 1);
 
             if Path::new(&old_name).exists() {
                 if i + 1 < self.keep_logs {
@@ -321,6 +1211,107 @@ impl AuditLogger {
     }
 }
 
+impl AuditSink for FileSink {
+    fn name(&self) -> String {
+        format!("audit log {}", self.log_file)
+    }
+
+    fn write(&mut self, entry: &AuditLogEntry) -> std::io::Result<()> {
+        self.append(entry)
+    }
+
+    /// Push the batch to the file and sync it as the fsync policy says;
+    /// `force` syncs regardless of the interval (flush and shutdown)
+    fn flush(&mut self, force: bool) -> std::io::Result<()> {
+        let Some(file) = self.file.as_mut() else {
+            return Ok(());
+        };
+        file.flush()?;
+
+        let sync = match self.fsync {
+            AuditFsync::Never => false,
+            AuditFsync::Batch => true,
+            AuditFsync::Interval(interval) => force || self.synced_at.elapsed() >= interval,
+        };
+        if sync && self.unsynced {
+            self.unsynced = false;
+            self.synced_at = Instant::now();
+            file.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// With interval fsync, when unsynced entries are due to be synced
+    fn deadline(&self) -> Option<Instant> {
+        match self.fsync {
+            AuditFsync::Interval(interval) if self.unsynced => Some(self.synced_at + interval),
+            _ => None,
+        }
+    }
+}
+
+/// Hash of the last line on disk, so a restarted server continues the
+/// chain. Right after a rotation the current file is empty and the last
+/// line is in the newest rotated file.
+fn read_chain_head(log_file: &str) -> std::io::Result<String> {
+    for path in [log_file.to_string(), rotated_path(log_file, 1)] {
+        match File::open(&path) {
+            Ok(file) => {
+                let mut last = None;
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    if !line.is_empty() {
+                        last = Some(line);
+                    }
+                }
+                if let Some(line) = last {
+                    return Ok(line_hash(&line));
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(GENESIS_HASH.to_string())
+}
+
+/// Name of the `index`th rotated log: `audit.log` → `audit.1`
+fn rotated_path(log_file: &str, index: u32) -> String {
+    format!(
+        "{}.{}",
+        Path::new(log_file).with_extension("").to_string_lossy(),
+        index
+    )
+}
+
+/// Hex SHA-256 of a log line, without its newline
+pub fn line_hash(line: &str) -> String {
+    format!("{:x}", Sha256::digest(line.as_bytes()))
+}
+
+/// Load a checkpoint signing key: a file holding the base64 32-byte seed
+pub fn load_signing_key(path: &str) -> Result<SigningKey> {
+    let text = fs::read_to_string(path).map_err(|e| {
+        ServerError::ConfigError(format!("Cannot read audit signing key {}: {}", path, e))
+    })?;
+    let seed: [u8; 32] = BASE64
+        .decode(text.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| {
+            ServerError::ConfigError(format!(
+                "Audit signing key {} is not a base64 32-byte seed",
+                path
+            ))
+        })?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// Base64 public half of a checkpoint key, as `carapace-debug audit verify` takes it
+pub fn public_key_base64(key: &SigningKey) -> String {
+    BASE64.encode(key.verifying_key().to_bytes())
+}
+
 impl Default for AuditLogger {
     fn default() -> Self {
         Self::new()
@@ -347,7 +1338,7 @@ mod tests {
             "list".to_string(),
         ];
 
-        let redacted = logger.redact_sensitive_args(&argv);
+        let redacted = logger.redact_sensitive_args("gh", &argv);
         assert_eq!(redacted[0], "gh");
         assert_eq!(redacted[1], "--token");
         assert_eq!(redacted[2], "[REDACTED]");
@@ -369,20 +1360,566 @@ mod tests {
             timestamp: "2026-02-12T10:00:00Z".to_string(),
             request_id: "req-1".to_string(),
             tool: "gh".to_string(),
+            session_id: None,
+            peer: None,
+            agent: None,
             action_type: "cli".to_string(),
             policy_result: "allow".to_string(),
             reason: None,
             argv: Some(vec!["pr".to_string(), "list".to_string()]),
+            body: None,
             method: None,
             path: None,
             exit_code: None,
             stdout_length: None,
             stderr_length: None,
             latency_ms: None,
+            transcript: None,
+            env_dropped: None,
+            approver: None,
+            decision: None,
         };
 
         let json = serde_json::to_string(&entry).expect("serialization failed");
         assert!(json.contains("\"tool\":\"gh\""));
         assert!(json.contains("\"policy_result\":\"allow\""));
     }
+
+    /// Every line of `paths`, in order
+    fn read_lines(paths: &[String]) -> Vec<String> {
+        paths
+            .iter()
+            .flat_map(|path| {
+                fs::read_to_string(path)
+                    .unwrap()
+                    .lines()
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    fn prev_hash(line: &str) -> String {
+        let value: serde_json::Value = serde_json::from_str(line).unwrap();
+        value["prev_hash"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_hash_chain_carries_over_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_file = dir.path().join("audit.log").to_string_lossy().to_string();
+        let logger = AuditLogger::with_config(true, true, false, Some(log_file.clone()), 600, 10);
+
+        for i in 0..6 {
+            logger.log_env_dropped(
+                &AuditContext::new(&format!("req-{}", i), "gh"),
+                &["GH_TOKEN".to_string()],
+            );
+        }
+        logger.flush();
+        let rotated = rotated_path(&log_file, 1);
+        assert!(Path::new(&rotated).exists(), "expected a rotation");
+
+        let mut paths: Vec<String> = (1..10)
+            .rev()
+            .map(|i| rotated_path(&log_file, i))
+            .filter(|path| Path::new(path).exists())
+            .collect();
+        paths.push(log_file.clone());
+        let lines = read_lines(&paths);
+        assert_eq!(lines.len(), 6);
+        assert_eq!(prev_hash(&lines[0]), GENESIS_HASH);
+        for pair in lines.windows(2) {
+            assert_eq!(prev_hash(&pair[1]), line_hash(&pair[0]));
+        }
+
+        // A new logger picks the chain up from disk
+        let restarted =
+            AuditLogger::with_config(true, true, false, Some(log_file.clone()), 600, 10);
+        restarted.log_env_dropped(&AuditContext::new("req-6", "gh"), &["GH_TOKEN".to_string()]);
+        restarted.flush();
+        let last = read_lines(&[log_file]).pop().unwrap();
+        assert_eq!(prev_hash(&last), line_hash(lines.last().unwrap()));
+    }
+
+    #[test]
+    fn test_signed_checkpoints() {
+        use ed25519_dalek::{Signature, Verifier};
+
+        let dir = tempfile::tempdir().unwrap();
+        let log_file = dir.path().join("audit.log").to_string_lossy().to_string();
+        let key = SigningKey::from_bytes(&[7u8; 32]);
+        let public_key = key.verifying_key();
+        let logger = AuditLogger::with_config(
+            true,
+            true,
+            false,
+            Some(log_file.clone()),
+            100 * 1024 * 1024,
+            10,
+        )
+        .with_signing_key(key, 2);
+
+        for i in 0..5 {
+            logger.log_env_dropped(
+                &AuditContext::new(&format!("req-{}", i), "gh"),
+                &["GH_TOKEN".to_string()],
+            );
+        }
+
+        logger.flush();
+        let lines = read_lines(&[log_file]);
+        assert_eq!(lines.len(), 7);
+        let checkpoints: Vec<usize> = lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| line.contains(r#""action_type":"checkpoint""#))
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(checkpoints, vec![2, 5]);
+
+        for i in checkpoints {
+            let value: serde_json::Value = serde_json::from_str(&lines[i]).unwrap();
+            let head = value["prev_hash"].as_str().unwrap();
+            assert_eq!(head, line_hash(&lines[i - 1]));
+            let signature = BASE64.decode(value["signature"].as_str().unwrap()).unwrap();
+            let signature = Signature::from_slice(&signature).unwrap();
+            assert!(public_key.verify(head.as_bytes(), &signature).is_ok());
+        }
+        assert_eq!(prev_hash(&lines[3]), line_hash(&lines[2]));
+    }
+
+    #[test]
+    fn test_load_signing_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.key");
+        fs::write(&path, format!("{}\n", BASE64.encode([7u8; 32]))).unwrap();
+        let key = load_signing_key(&path.to_string_lossy()).unwrap();
+        assert_eq!(key.to_bytes(), [7u8; 32]);
+
+        fs::write(&path, "not a key").unwrap();
+        assert!(load_signing_key(&path.to_string_lossy()).is_err());
+    }
+
+    #[test]
+    fn test_concurrent_writers_keep_one_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_file = dir.path().join("audit.log").to_string_lossy().to_string();
+        let logger = std::sync::Arc::new(
+            AuditLogger::with_config(true, true, false, Some(log_file.clone()), 4096, 100)
+                .with_queue(8, AuditBackpressure::Block)
+                .with_fsync(AuditFsync::Never),
+        );
+
+        let threads: Vec<_> = (0..4)
+            .map(|t| {
+                let logger = logger.clone();
+                std::thread::spawn(move || {
+                    for i in 0..50 {
+                        logger.log_env_dropped(
+                            &AuditContext::new(&format!("req-{}-{}", t, i), "gh"),
+                            &[],
+                        );
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        logger.flush();
+
+        let mut paths: Vec<String> = (1..100)
+            .rev()
+            .map(|i| rotated_path(&log_file, i))
+            .filter(|path| Path::new(path).exists())
+            .collect();
+        assert!(!paths.is_empty(), "expected rotations");
+        paths.push(log_file);
+        let lines = read_lines(&paths);
+        assert_eq!(lines.len(), 200);
+        assert_eq!(prev_hash(&lines[0]), GENESIS_HASH);
+        for pair in lines.windows(2) {
+            assert_eq!(prev_hash(&pair[1]), line_hash(&pair[0]));
+        }
+        assert_eq!(logger.dropped(), 0);
+    }
+
+    #[test]
+    fn test_writer_settings_parse() {
+        assert_eq!(
+            "drop".parse::<AuditBackpressure>().unwrap(),
+            AuditBackpressure::Drop
+        );
+        assert!("wait".parse::<AuditBackpressure>().is_err());
+        assert_eq!("never".parse::<AuditFsync>().unwrap(), AuditFsync::Never);
+        assert_eq!(
+            "5".parse::<AuditFsync>().unwrap(),
+            AuditFsync::Interval(Duration::from_secs(5))
+        );
+        assert!("0".parse::<AuditFsync>().is_err());
+    }
+
+    #[test]
+    fn test_per_tool_settings() {
+        let policy: PolicyConfig = serde_yaml::from_str(
+            r#"
+tools:
+  gh:
+    type: cli
+    binary: /usr/bin/gh
+    argv_allow_patterns: ["*"]
+    audit:
+      log_argv: true
+      redact_patterns: ["--body"]
+  op:
+    type: cli
+    binary: /usr/bin/op
+    argv_allow_patterns: ["*"]
+  noisy:
+    type: cli
+    binary: /usr/bin/noisy
+    argv_allow_patterns: ["*"]
+    audit:
+      enabled: false
+"#,
+        )
+        .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let log_file = dir.path().join("audit.log").to_string_lossy().to_string();
+        let logger = AuditLogger::with_config(
+            true,
+            true,
+            true,
+            Some(log_file.clone()),
+            100 * 1024 * 1024,
+            10,
+        )
+        .with_policy(&policy);
+
+        let cli = |id: &str, tool: &str, args: &[&str]| AuditContext {
+            argv: Some(args.iter().map(|a| a.to_string()).collect()),
+            ..AuditContext::new(id, tool)
+        };
+        logger.log_cli_request(&cli("1", "gh", &["pr", "--body", "secret"]), true, None);
+        logger.log_cli_request(&cli("2", "op", &["read", "op://vault/item"]), true, None);
+        let denied = cli("4", "gh", &["pr", "merge", "--body", "secret"]);
+        logger.log_cli_request(&denied, false, Some("argv_deny"));
+        let noisy = cli("3", "noisy", &["run"]);
+        logger.log_cli_request(&noisy, true, None);
+        logger.log_cli_response(&noisy, 0, 0, 0, 1);
+        logger.flush();
+
+        let entries: Vec<serde_json::Value> = read_lines(&[log_file])
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries[0]["argv"],
+            serde_json::json!(["pr", "--body", "[REDACTED]"])
+        );
+        // Tools in the policy log argv only when they ask for it
+        assert_eq!(entries[1]["tool"], "op");
+        assert!(entries[1]["argv"].is_null());
+        // Denials keep their argv, so policy changes can be replayed on them
+        assert_eq!(entries[2]["policy_result"], "deny");
+        assert_eq!(
+            entries[2]["argv"],
+            serde_json::json!(["pr", "merge", "--body", "[REDACTED]"])
+        );
+    }
+
+    #[test]
+    fn test_body_redaction_from_example_policy() {
+        let mut policy = PolicyConfig::from_file(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../examples/policies/signal-cli.yaml"
+        ))
+        .unwrap();
+        let Some(ToolPolicy::Http(signal)) = policy.tools.get_mut("signal-cli") else {
+            panic!("signal-cli should be an HTTP tool");
+        };
+        signal.audit.log_body = true;
+
+        let dir = tempfile::tempdir().unwrap();
+        let log_file = dir.path().join("audit.log").to_string_lossy().to_string();
+        let logger = AuditLogger::with_config(
+            true,
+            true,
+            true,
+            Some(log_file.clone()),
+            100 * 1024 * 1024,
+            10,
+        )
+        .with_policy(&policy);
+
+        let body = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "send",
+            "params": {"recipient": ["+15551234567"], "message": "hi"},
+            "id": 1
+        });
+        let req = HttpRequest {
+            id: "req-1".to_string(),
+            tool: "signal-cli".to_string(),
+            method: "POST".to_string(),
+            path: "/api/v1/rpc".to_string(),
+            headers: HashMap::new(),
+            body: Some(body.to_string()),
+            encoding: Default::default(),
+        };
+        let ctx = AuditContext::http(&AuditSession::new(None), &req);
+        logger.log_http_request(&ctx, &req, true, None);
+        logger.log_http_request(&ctx, &req, false, Some("param_allow"));
+        logger.flush();
+
+        let entries: Vec<serde_json::Value> = read_lines(&[log_file])
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries[0]["body"]["method"], "send");
+        assert_eq!(entries[0]["body"]["params"]["recipient"], "[REDACTED]");
+        assert_eq!(entries[0]["body"]["params"]["message"], "hi");
+        assert!(entries[1]["body"].is_null());
+    }
+
+    #[test]
+    fn test_capture_redacts_and_respects_policy() {
+        let policy: PolicyConfig = serde_yaml::from_str(
+            r#"
+tools:
+  gh:
+    type: cli
+    binary: /usr/bin/gh
+    argv_allow_patterns: ["*"]
+    audit:
+      capture: true
+      redact_patterns: ["--body"]
+  op:
+    type: cli
+    binary: /usr/bin/op
+    argv_allow_patterns: ["*"]
+  api:
+    type: http
+    upstream: "http://127.0.0.1:1"
+    audit:
+      capture: true
+      redact_patterns: ["token"]
+"#,
+        )
+        .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let store = CaptureStore::open(dir.path(), [5u8; 32], 1 << 20).unwrap();
+        let logger = AuditLogger::new().with_policy(&policy).with_capture(store);
+        assert!(logger.captures("gh"));
+        assert!(!logger.captures("op"));
+
+        let request = |tool: &str, id: &str| CliRequest {
+            id: id.to_string(),
+            tool: tool.to_string(),
+            argv: vec!["pr", "create", "--body", "secret"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            env: HashMap::new(),
+            stdin: Some("--token abc123\nplain\n".to_string()),
+            cwd: "/".to_string(),
+            tty: None,
+        };
+        let response = CliResponse {
+            id: "cli-1".to_string(),
+            exit_code: 0,
+            stdout: r#"{"url":"https://example.com","--body":"secret"}"#.to_string(),
+            stderr: "Authorization: Bearer xyz\nfine\n".to_string(),
+            encoding: Default::default(),
+            signal: None,
+            timed_out: false,
+            stdout_truncated: false,
+            stderr_truncated: false,
+        };
+        for (tool, id) in [("gh", "cli-1"), ("op", "cli-2")] {
+            let req = request(tool, id);
+            let ctx = AuditContext::cli(&AuditSession::new(None), &req);
+            logger.capture_cli(&ctx, &req, &response);
+        }
+
+        let store = logger.capture.as_ref().unwrap();
+        let record = store.load("cli-1").unwrap().remove(0);
+        assert_eq!(
+            record.argv.unwrap(),
+            vec!["pr", "create", "--body", "[REDACTED]"]
+        );
+        assert_eq!(record.stdin.unwrap().data, "--token [REDACTED]\nplain\n");
+        let stdout: serde_json::Value = serde_json::from_str(&record.stdout.unwrap().data).unwrap();
+        assert_eq!(stdout["--body"], "[REDACTED]");
+        assert_eq!(stdout["url"], "https://example.com");
+        assert_eq!(
+            record.stderr.unwrap().data,
+            "Authorization: [REDACTED]\nfine\n"
+        );
+        assert!(store.load("cli-2").unwrap().is_empty());
+
+        let req = HttpRequest {
+            id: "http-1".to_string(),
+            tool: "api".to_string(),
+            method: "GET".to_string(),
+            path: "/events".to_string(),
+            headers: HashMap::from([
+                ("Authorization".to_string(), "Bearer xyz".to_string()),
+                ("Accept".to_string(), "text/event-stream".to_string()),
+            ]),
+            body: None,
+            encoding: Default::default(),
+        };
+        let events = [SseEvent {
+            id: "http-1".to_string(),
+            tool: "api".to_string(),
+            event: "update".to_string(),
+            data: r#"{"token":"t0k","n":1}"#.to_string(),
+        }];
+        let ctx = AuditContext::http(&AuditSession::new(None), &req);
+        logger.capture_http(&ctx, &req, None, &events);
+
+        let record = store.load("http-1").unwrap().remove(0);
+        let headers = record.request_headers.unwrap();
+        assert_eq!(headers["Authorization"], "[REDACTED]");
+        assert_eq!(headers["Accept"], "text/event-stream");
+        assert_eq!(record.sse_events.len(), 1);
+        assert_eq!(record.sse_events[0].event, "update");
+        let data: serde_json::Value = serde_json::from_str(&record.sse_events[0].data).unwrap();
+        assert_eq!(data, serde_json::json!({"token": "[REDACTED]", "n": 1}));
+    }
+
+    /// Sink that takes entries only once `open` is set
+    struct StalledSink {
+        open: Arc<std::sync::atomic::AtomicBool>,
+        received: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl AuditSink for StalledSink {
+        fn name(&self) -> String {
+            "stalled".to_string()
+        }
+
+        fn write(&mut self, entry: &AuditLogEntry) -> std::io::Result<()> {
+            while !self.open.load(Ordering::SeqCst) {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            self.received.lock().unwrap().push(entry.request_id.clone());
+            Ok(())
+        }
+
+        fn flush(&mut self, _force: bool) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_stalled_sink_does_not_hold_up_the_log_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_file = dir.path().join("audit.log").to_string_lossy().to_string();
+        let open = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let logger =
+            AuditLogger::with_config(true, true, false, Some(log_file.clone()), 1 << 20, 2)
+                .with_queue(4, AuditBackpressure::Block)
+                .with_fsync(AuditFsync::Never)
+                .with_sink(Box::new(StalledSink {
+                    open: open.clone(),
+                    received: received.clone(),
+                }));
+
+        for i in 0..20 {
+            logger.log_env_dropped(&AuditContext::new(&format!("req-{}", i), "gh"), &[]);
+        }
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while fs::read_to_string(&log_file)
+            .unwrap_or_default()
+            .lines()
+            .count()
+            < 20
+        {
+            assert!(Instant::now() < deadline, "log file held up by the sink");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        // The sink holds one entry and its queue four; the rest are dropped
+        let dropped = logger.dropped();
+        assert!((15..=16).contains(&dropped), "dropped {}", dropped);
+        open.store(true, Ordering::SeqCst);
+        logger.flush();
+        assert_eq!(received.lock().unwrap().len() as u64, 20 - dropped);
+        assert_eq!(received.lock().unwrap()[0], "req-0");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_full_queue_leaves_runtime_running() {
+        let open = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let logger = Arc::new(
+            AuditLogger::new()
+                .with_queue(1, AuditBackpressure::Block)
+                .with_sink(Box::new(StalledSink {
+                    open: open.clone(),
+                    received: received.clone(),
+                })),
+        );
+
+        // The sink takes the first entry and stalls; a flush then holds up
+        // the writer, so the next entries fill its queue
+        logger.log_env_dropped(&AuditContext::new("req-0", "gh"), &[]);
+        let flushing = {
+            let logger = logger.clone();
+            std::thread::spawn(move || logger.flush())
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let logging = {
+            let logger = logger.clone();
+            tokio::spawn(async move {
+                for i in 1..4 {
+                    logger.log_env_dropped(&AuditContext::new(&format!("req-{}", i), "gh"), &[]);
+                }
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!logging.is_finished());
+
+        // The only worker is waiting on the queue, yet other tasks still run
+        let other = tokio::spawn(async { 42 });
+        let answer = tokio::time::timeout(Duration::from_secs(5), other).await;
+        assert_eq!(answer.expect("runtime starved").unwrap(), 42);
+
+        open.store(true, Ordering::SeqCst);
+        logging.await.unwrap();
+        flushing.join().unwrap();
+        logger.flush();
+        // Every entry got past the writer's queue; the sink's own queue
+        // may have dropped some while it was stalled
+        let received = received.lock().unwrap();
+        assert_eq!(received.len() as u64 + logger.dropped(), 4);
+        assert_eq!(received[0], "req-0");
+    }
+
+    #[test]
+    fn test_sinks_without_log_file() {
+        use crate::audit_sink::SyslogSink;
+        use crate::config::SyslogTransport;
+
+        let collector = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = collector.local_addr().unwrap().to_string();
+        let logger = AuditLogger::new().with_sink(Box::new(SyslogSink::new(
+            SyslogTransport::Udp,
+            address,
+            4,
+            "carapace".to_string(),
+        )));
+        logger.log_env_dropped(&AuditContext::new("req-1", "gh"), &["GH_TOKEN".to_string()]);
+        logger.flush();
+
+        let mut buf = [0u8; 4096];
+        let len = collector.recv(&mut buf).unwrap();
+        let message = String::from_utf8_lossy(&buf[..len]).to_string();
+        assert!(message.contains(" env_dropped [carapace@32473 tool=\"gh\" request_id=\"req-1\""));
+    }
 }
@@ -0,0 +1,840 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+use std::time::{Duration, Instant};
+
+use crate::audit::AuditLogEntry;
+use crate::config::{AuditSinkConfig, SyslogTransport};
+use crate::error::{Result, ServerError};
+
+/// Structured data ID of syslog entries, under the enterprise number
+/// reserved for documentation (RFC 5612)
+const SD_ID: &str = "carapace@32473";
+
+/// Longest wait between webhook retries or syslog reconnects
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(300);
+
+/// How long a syslog collector gets to accept a TCP connection or a write
+const SYSLOG_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Destination for audit entries. Sinks run on audit writer threads, never
+/// on the server's async tasks, and get every entry in the order it was
+/// logged.
+pub trait AuditSink: Send {
+    /// Names the sink in warnings about failed writes
+    fn name(&self) -> String;
+
+    /// Take one entry
+    fn write(&mut self, entry: &AuditLogEntry) -> io::Result<()>;
+
+    /// Push out what the sink holds back, at the end of each batch of
+    /// entries. `force` is set on flush and shutdown, when nothing should
+    /// be held back.
+    fn flush(&mut self, force: bool) -> io::Result<()>;
+
+    /// When the sink wants `flush` called even if no entries arrive
+    fn deadline(&self) -> Option<Instant> {
+        None
+    }
+}
+
+/// Build the sink described by a server config entry
+pub fn build_sink(config: &AuditSinkConfig) -> Result<Box<dyn AuditSink>> {
+    Ok(match config {
+        AuditSinkConfig::Syslog {
+            transport,
+            address,
+            facility,
+            app_name,
+        } => Box::new(SyslogSink::new(
+            *transport,
+            address.clone(),
+            parse_facility(facility)?,
+            app_name.clone(),
+        )),
+        AuditSinkConfig::Journald { socket } => Box::new(JournaldSink::new(socket.clone())),
+        AuditSinkConfig::Webhook {
+            url,
+            headers,
+            batch_size,
+            flush_interval_secs,
+            timeout_secs,
+            spool_file,
+            max_spool_bytes,
+        } => Box::new(WebhookSink {
+            url: url.clone(),
+            headers: headers.clone(),
+            batch_size: (*batch_size).max(1),
+            flush_interval: Duration::from_secs(*flush_interval_secs),
+            timeout: Duration::from_secs(*timeout_secs),
+            spool_file: spool_file.clone(),
+            max_spool_bytes: *max_spool_bytes,
+            client: None,
+            pending: Vec::new(),
+            oldest: None,
+            retry_at: None,
+            backoff: Duration::from_secs(1),
+        }),
+    })
+}
+
+/// Syslog facility code from its name
+fn parse_facility(name: &str) -> Result<u8> {
+    const FACILITIES: [&str; 12] = [
+        "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron",
+        "authpriv", "ftp",
+    ];
+    if let Some(code) = FACILITIES.iter().position(|f| *f == name) {
+        return Ok(code as u8);
+    }
+    match name
+        .strip_prefix("local")
+        .and_then(|n| n.parse::<u8>().ok())
+    {
+        Some(n) if n <= 7 => Ok(16 + n),
+        _ => Err(ServerError::ConfigError(format!(
+            "Invalid syslog facility '{}': expected e.g. auth, authpriv, daemon or local0-local7",
+            name
+        ))),
+    }
+}
+
+/// Syslog severity of an entry: warning for denials, notice otherwise
+fn severity(entry: &AuditLogEntry) -> u8 {
+    if entry.policy_result == "deny" {
+        4
+    } else {
+        5
+    }
+}
+
+fn hostname() -> Option<String> {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+/// RFC 5424 syslog over UDP, TCP or a unix datagram socket. The
+/// connection is made on the first entry and remade after a failure; after
+/// a failed connect, entries are dropped until the backoff has passed.
+pub struct SyslogSink {
+    transport: SyslogTransport,
+    address: String,
+    facility: u8,
+    app_name: String,
+    hostname: String,
+    conn: Option<SyslogConn>,
+    /// When to try connecting again after a failure
+    retry_at: Option<Instant>,
+    backoff: Duration,
+}
+
+enum SyslogConn {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    Unix(UnixDatagram),
+}
+
+impl SyslogSink {
+    pub fn new(
+        transport: SyslogTransport,
+        address: String,
+        facility: u8,
+        app_name: String,
+    ) -> Self {
+        SyslogSink {
+            transport,
+            address,
+            facility,
+            app_name,
+            hostname: hostname().unwrap_or_else(|| "-".to_string()),
+            conn: None,
+            retry_at: None,
+            backoff: Duration::from_secs(1),
+        }
+    }
+
+    fn connect(&self) -> io::Result<SyslogConn> {
+        match self.transport {
+            SyslogTransport::Udp => {
+                let addr = self.address.to_socket_addrs()?.next().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "address did not resolve")
+                })?;
+                let local = if addr.is_ipv6() {
+                    "[::]:0"
+                } else {
+                    "0.0.0.0:0"
+                };
+                let socket = UdpSocket::bind(local)?;
+                socket.connect(addr)?;
+                Ok(SyslogConn::Udp(socket))
+            }
+            SyslogTransport::Tcp => {
+                let mut last_error = None;
+                for addr in self.address.to_socket_addrs()? {
+                    match TcpStream::connect_timeout(&addr, SYSLOG_TIMEOUT) {
+                        Ok(stream) => {
+                            stream.set_write_timeout(Some(SYSLOG_TIMEOUT))?;
+                            return Ok(SyslogConn::Tcp(stream));
+                        }
+                        Err(e) => last_error = Some(e),
+                    }
+                }
+                Err(last_error.unwrap_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "address did not resolve")
+                }))
+            }
+            SyslogTransport::Unix => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(&self.address)?;
+                Ok(SyslogConn::Unix(socket))
+            }
+        }
+    }
+
+    /// The entry as an RFC 5424 message, with the JSON entry as its text
+    fn format(&self, entry: &AuditLogEntry) -> io::Result<String> {
+        // RFC 5424 timestamps have at most microseconds
+        let timestamp = DateTime::parse_from_rfc3339(&entry.timestamp)
+            .map(|t| t.with_timezone(&Utc))
+            .unwrap_or_else(|_| Utc::now())
+            .to_rfc3339_opts(SecondsFormat::Micros, true);
+
+        let mut params = vec![
+            ("tool", entry.tool.as_str()),
+            ("request_id", entry.request_id.as_str()),
+            ("result", entry.policy_result.as_str()),
+        ];
+        if let Some(reason) = &entry.reason {
+            params.push(("reason", reason));
+        }
+        if let Some(session_id) = &entry.session_id {
+            params.push(("session_id", session_id));
+        }
+        let params: String = params
+            .iter()
+            .map(|(name, value)| format!(" {}=\"{}\"", name, escape_param(value)))
+            .collect();
+
+        Ok(format!(
+            "<{}>1 {} {} {} {} {} [{}{}] {}",
+            self.facility as u16 * 8 + severity(entry) as u16,
+            timestamp,
+            self.hostname,
+            self.app_name,
+            std::process::id(),
+            entry.action_type,
+            SD_ID,
+            params,
+            serde_json::to_string(entry)?
+        ))
+    }
+
+    /// Connect unless still backing off from the last failure
+    fn reconnect(&mut self) -> io::Result<()> {
+        if let Some(retry_at) = self.retry_at {
+            let now = Instant::now();
+            if now < retry_at {
+                return Err(io::Error::other(format!(
+                    "not connected, retrying in {}s",
+                    (retry_at - now).as_secs() + 1
+                )));
+            }
+        }
+        match self.connect() {
+            Ok(conn) => {
+                self.conn = Some(conn);
+                self.retry_at = None;
+                self.backoff = Duration::from_secs(1);
+                Ok(())
+            }
+            Err(e) => {
+                self.retry_at = Some(Instant::now() + self.backoff);
+                self.backoff = (self.backoff * 2).min(MAX_RETRY_BACKOFF);
+                Err(e)
+            }
+        }
+    }
+
+    fn send(&mut self, message: &str) -> io::Result<()> {
+        if self.conn.is_none() {
+            self.reconnect()?;
+        }
+        let result = match self.conn.as_mut() {
+            Some(SyslogConn::Udp(socket)) => socket.send(message.as_bytes()).map(|_| ()),
+            Some(SyslogConn::Unix(socket)) => socket.send(message.as_bytes()).map(|_| ()),
+            Some(SyslogConn::Tcp(stream)) => {
+                // Octet counting, so messages may contain newlines
+                let frame = format!("{} {}", message.len(), message);
+                stream.write_all(frame.as_bytes())
+            }
+            None => Ok(()),
+        };
+        if result.is_err() {
+            self.conn = None;
+        }
+        result
+    }
+}
+
+/// Escape `"`, `\\` and `]` in a structured data parameter value
+fn escape_param(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl AuditSink for SyslogSink {
+    fn name(&self) -> String {
+        format!("syslog {}", self.address)
+    }
+
+    fn write(&mut self, entry: &AuditLogEntry) -> io::Result<()> {
+        let message = self.format(entry)?;
+        // A collector that restarted drops TCP connections; reconnect once
+        let connected = self.conn.is_some();
+        match self.send(&message) {
+            Err(_) if connected && self.transport == SyslogTransport::Tcp => self.send(&message),
+            result => result,
+        }
+    }
+
+    fn flush(&mut self, _force: bool) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Structured fields written to the systemd journal's native socket
+pub struct JournaldSink {
+    socket_path: String,
+    socket: Option<UnixDatagram>,
+}
+
+impl JournaldSink {
+    pub fn new(socket_path: String) -> Self {
+        JournaldSink {
+            socket_path,
+            socket: None,
+        }
+    }
+
+    /// The entry as journal fields, with `CARAPACE_ENTRY` holding all of it
+    fn fields(entry: &AuditLogEntry) -> io::Result<Vec<(&'static str, String)>> {
+        let mut fields = vec![
+            (
+                "MESSAGE",
+                format!(
+                    "{} {} {} request {}",
+                    entry.action_type, entry.tool, entry.policy_result, entry.request_id
+                ),
+            ),
+            ("PRIORITY", severity(entry).to_string()),
+            ("SYSLOG_IDENTIFIER", "carapace-server".to_string()),
+            ("CARAPACE_ACTION", entry.action_type.clone()),
+            ("CARAPACE_TOOL", entry.tool.clone()),
+            ("CARAPACE_REQUEST_ID", entry.request_id.clone()),
+            ("CARAPACE_RESULT", entry.policy_result.clone()),
+        ];
+        if let Some(reason) = &entry.reason {
+            fields.push(("CARAPACE_REASON", reason.clone()));
+        }
+        if let Some(session_id) = &entry.session_id {
+            fields.push(("CARAPACE_SESSION_ID", session_id.clone()));
+        }
+        if let Some(agent) = &entry.agent {
+            fields.push(("CARAPACE_AGENT", agent.clone()));
+        }
+        fields.push(("CARAPACE_ENTRY", serde_json::to_string(entry)?));
+        Ok(fields)
+    }
+}
+
+/// Encode fields in the journal's native protocol: `NAME=value` lines, or
+/// a length-prefixed value for values with newlines
+fn journal_payload(fields: &[(&str, String)]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    for (name, value) in fields {
+        payload.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            payload.push(b'\n');
+            payload.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            payload.push(b'=');
+        }
+        payload.extend_from_slice(value.as_bytes());
+        payload.push(b'\n');
+    }
+    payload
+}
+
+impl AuditSink for JournaldSink {
+    fn name(&self) -> String {
+        format!("journald {}", self.socket_path)
+    }
+
+    fn write(&mut self, entry: &AuditLogEntry) -> io::Result<()> {
+        let payload = journal_payload(&Self::fields(entry)?);
+        let socket = match self.socket.take() {
+            Some(socket) => socket,
+            None => UnixDatagram::unbound()?,
+        };
+        socket.send_to(&payload, &self.socket_path)?;
+        self.socket = Some(socket);
+        Ok(())
+    }
+
+    fn flush(&mut self, _force: bool) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// POSTs entries in batches as a JSON array. Batches that cannot be
+/// delivered go to the spool file, and are sent first on the next retry,
+/// which backs off exponentially.
+pub struct WebhookSink {
+    url: String,
+    headers: HashMap<String, String>,
+    batch_size: usize,
+    flush_interval: Duration,
+    timeout: Duration,
+    spool_file: Option<String>,
+    max_spool_bytes: u64,
+    /// Built on the writer thread: the blocking client must not be
+    /// created inside the server's async runtime
+    client: Option<reqwest::blocking::Client>,
+    /// Serialized entries of the batch being filled
+    pending: Vec<String>,
+    /// When the first entry of the batch arrived
+    oldest: Option<Instant>,
+    /// Set after a failed delivery; nothing is sent before it
+    retry_at: Option<Instant>,
+    backoff: Duration,
+}
+
+impl WebhookSink {
+    /// Deliver the spool and the pending batch, or spool the batch
+    fn deliver(&mut self) -> io::Result<()> {
+        let batch = std::mem::take(&mut self.pending);
+        self.oldest = None;
+
+        let mut entries = self.read_spool()?;
+        let spooled = entries.len();
+        entries.extend(batch.iter().cloned());
+        if entries.is_empty() {
+            // Nothing left to retry, e.g. the failed batch was dropped for
+            // want of a spool: stop asking to be woken for it
+            self.retry_at = None;
+            self.backoff = Duration::from_secs(1);
+            return Ok(());
+        }
+
+        match self.post(&entries) {
+            Ok(()) => {
+                if spooled > 0 {
+                    if let Some(spool_file) = &self.spool_file {
+                        fs::remove_file(spool_file)?;
+                    }
+                    tracing::info!(
+                        "Delivered {} spooled audit entries to {}",
+                        spooled,
+                        self.url
+                    );
+                }
+                self.retry_at = None;
+                self.backoff = Duration::from_secs(1);
+                Ok(())
+            }
+            Err(e) => {
+                self.retry_at = Some(Instant::now() + self.backoff);
+                self.backoff = (self.backoff * 2).min(MAX_RETRY_BACKOFF);
+                self.spool(&batch)?;
+                Err(e)
+            }
+        }
+    }
+
+    fn post(&mut self, entries: &[String]) -> io::Result<()> {
+        if self.client.is_none() {
+            let client = reqwest::blocking::Client::builder()
+                .timeout(self.timeout)
+                .build()
+                .map_err(io::Error::other)?;
+            self.client = Some(client);
+        }
+        let Some(client) = &self.client else {
+            return Ok(());
+        };
+
+        let mut request = client
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .body(format!("[{}]", entries.join(",")));
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let response = request.send().map_err(io::Error::other)?;
+        if !response.status().is_success() {
+            return Err(io::Error::other(format!(
+                "{} returned {}",
+                self.url,
+                response.status()
+            )));
+        }
+        Ok(())
+    }
+
+    fn read_spool(&self) -> io::Result<Vec<String>> {
+        let Some(spool_file) = &self.spool_file else {
+            return Ok(Vec::new());
+        };
+        match fs::File::open(spool_file) {
+            Ok(file) => BufReader::new(file)
+                .lines()
+                .filter(|line| !matches!(line, Ok(line) if line.is_empty()))
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Keep an undelivered batch for the next retry, or drop it if there
+    /// is no spool or it is full
+    fn spool(&self, batch: &[String]) -> io::Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let Some(spool_file) = &self.spool_file else {
+            tracing::warn!(
+                "Dropped {} audit entries for {}: no spool",
+                batch.len(),
+                self.url
+            );
+            return Ok(());
+        };
+        let size = fs::metadata(spool_file).map(|m| m.len()).unwrap_or(0);
+        let added: u64 = batch.iter().map(|line| line.len() as u64 + 1).sum();
+        if size + added > self.max_spool_bytes {
+            tracing::warn!(
+                "Dropped {} audit entries for {}: spool {} is full",
+                batch.len(),
+                self.url,
+                spool_file
+            );
+            return Ok(());
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(spool_file)?;
+        for line in batch {
+            writeln!(file, "{}", line)?;
+        }
+        file.sync_data()
+    }
+}
+
+impl AuditSink for WebhookSink {
+    fn name(&self) -> String {
+        format!("webhook {}", self.url)
+    }
+
+    fn write(&mut self, entry: &AuditLogEntry) -> io::Result<()> {
+        self.pending.push(serde_json::to_string(entry)?);
+        self.oldest.get_or_insert_with(Instant::now);
+        Ok(())
+    }
+
+    fn flush(&mut self, force: bool) -> io::Result<()> {
+        let Some(oldest) = self.oldest else {
+            // Nothing new, but the spool may be due for a retry
+            return match self.retry_at {
+                Some(retry_at) if force || Instant::now() >= retry_at => self.deliver(),
+                _ => Ok(()),
+            };
+        };
+
+        let due = force
+            || self.pending.len() >= self.batch_size
+            || oldest.elapsed() >= self.flush_interval;
+        if !due {
+            return Ok(());
+        }
+        match self.retry_at {
+            // Still backing off: straight to the spool
+            Some(retry_at) if !force && Instant::now() < retry_at => {
+                let batch = std::mem::take(&mut self.pending);
+                self.oldest = None;
+                self.spool(&batch)
+            }
+            _ => self.deliver(),
+        }
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        let flush_at = self.oldest.map(|oldest| oldest + self.flush_interval);
+        match (flush_at, self.retry_at) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn entry(request_id: &str, policy_result: &str) -> AuditLogEntry {
+        AuditLogEntry {
+            timestamp: "2026-02-12T10:00:00.123456789+00:00".to_string(),
+            request_id: request_id.to_string(),
+            tool: "gh".to_string(),
+            session_id: Some("session-1".to_string()),
+            action_type: "cli".to_string(),
+            policy_result: policy_result.to_string(),
+            reason: (policy_result == "deny").then(|| "argv_deny".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_facility() {
+        assert_eq!(parse_facility("auth").unwrap(), 4);
+        assert_eq!(parse_facility("authpriv").unwrap(), 10);
+        assert_eq!(parse_facility("local7").unwrap(), 23);
+        assert!(parse_facility("local8").is_err());
+        assert!(parse_facility("security").is_err());
+    }
+
+    #[test]
+    fn test_syslog_udp_and_unix() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = collector.local_addr().unwrap().to_string();
+        let mut sink = SyslogSink::new(SyslogTransport::Udp, address, 4, "carapace".to_string());
+        sink.write(&entry("req-1", "deny")).unwrap();
+
+        let mut buf = [0u8; 4096];
+        let len = collector.recv(&mut buf).unwrap();
+        let message = String::from_utf8_lossy(&buf[..len]).to_string();
+        // auth.warning, microsecond timestamp, action as MSGID
+        assert!(
+            message.starts_with("<36>1 2026-02-12T10:00:00.123456Z "),
+            "{}",
+            message
+        );
+        assert!(message.contains(" carapace "));
+        assert!(message.contains(
+            " cli [carapace@32473 tool=\"gh\" request_id=\"req-1\" result=\"deny\" reason=\"argv_deny\" session_id=\"session-1\"] {"
+        ));
+        let json = &message[message.find("] {").unwrap() + 2..];
+        let parsed: serde_json::Value = serde_json::from_str(json).unwrap();
+        assert_eq!(parsed["request_id"], "req-1");
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.sock");
+        let collector = UnixDatagram::bind(&path).unwrap();
+        let mut sink = SyslogSink::new(
+            SyslogTransport::Unix,
+            path.to_string_lossy().to_string(),
+            16,
+            "carapace".to_string(),
+        );
+        sink.write(&entry("req-2", "allow")).unwrap();
+        let len = collector.recv(&mut buf).unwrap();
+        // local0.notice
+        assert!(String::from_utf8_lossy(&buf[..len]).starts_with("<133>1 "));
+    }
+
+    #[test]
+    fn test_syslog_tcp_octet_counting() {
+        let collector = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = collector.local_addr().unwrap().to_string();
+        let mut sink = SyslogSink::new(SyslogTransport::Tcp, address, 4, "carapace".to_string());
+        sink.write(&entry("req-1", "allow")).unwrap();
+        sink.write(&entry("req-2", "allow")).unwrap();
+        drop(sink);
+
+        let (mut stream, _) = collector.accept().unwrap();
+        let mut received = String::new();
+        stream.read_to_string(&mut received).unwrap();
+
+        let mut frames = Vec::new();
+        let mut rest = received.as_str();
+        while let Some((len, tail)) = rest.split_once(' ') {
+            let len: usize = len.parse().unwrap();
+            frames.push(&tail[..len]);
+            rest = &tail[len..];
+        }
+        assert_eq!(frames.len(), 2);
+        assert!(frames[1].contains("request_id=\"req-2\""));
+    }
+
+    #[test]
+    fn test_syslog_reconnect_backs_off() {
+        let collector = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = collector.local_addr().unwrap();
+        drop(collector);
+        let mut sink = SyslogSink::new(
+            SyslogTransport::Tcp,
+            address.to_string(),
+            4,
+            "carapace".to_string(),
+        );
+
+        // Refused, then not even tried until the backoff has passed
+        let refused = sink.write(&entry("req-1", "allow")).unwrap_err();
+        assert_eq!(refused.kind(), io::ErrorKind::ConnectionRefused);
+        assert_eq!(sink.backoff, Duration::from_secs(2));
+        let waiting = sink.write(&entry("req-2", "allow")).unwrap_err();
+        assert!(waiting.to_string().contains("retrying in"));
+        assert_eq!(sink.backoff, Duration::from_secs(2));
+
+        let collector = TcpListener::bind(address).unwrap();
+        sink.retry_at = Some(Instant::now());
+        sink.write(&entry("req-3", "allow")).unwrap();
+        assert!(sink.retry_at.is_none());
+        assert_eq!(sink.backoff, Duration::from_secs(1));
+        drop(sink);
+
+        let (mut stream, _) = collector.accept().unwrap();
+        let mut received = String::new();
+        stream.read_to_string(&mut received).unwrap();
+        assert!(received.contains("request_id=\"req-3\""));
+        assert!(!received.contains("req-2"));
+    }
+
+    #[test]
+    fn test_journald_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.sock");
+        let journal = UnixDatagram::bind(&path).unwrap();
+        let mut sink = JournaldSink::new(path.to_string_lossy().to_string());
+        sink.write(&entry("req-1", "deny")).unwrap();
+
+        let mut buf = [0u8; 8192];
+        let len = journal.recv(&mut buf).unwrap();
+        let payload = String::from_utf8_lossy(&buf[..len]).to_string();
+        let fields: HashMap<&str, &str> = payload
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .collect();
+        assert_eq!(fields["MESSAGE"], "cli gh deny request req-1");
+        assert_eq!(fields["PRIORITY"], "4");
+        assert_eq!(fields["CARAPACE_TOOL"], "gh");
+        assert_eq!(fields["CARAPACE_REASON"], "argv_deny");
+        assert_eq!(fields["CARAPACE_SESSION_ID"], "session-1");
+        let parsed: serde_json::Value = serde_json::from_str(fields["CARAPACE_ENTRY"]).unwrap();
+        assert_eq!(parsed["request_id"], "req-1");
+
+        let payload = journal_payload(&[("NOTE", "two\nlines".to_string())]);
+        assert_eq!(&payload[..5], b"NOTE\n");
+        assert_eq!(payload[5..13], 9u64.to_le_bytes());
+        assert_eq!(&payload[13..], b"two\nlines\n");
+    }
+
+    /// Stand-in webhook endpoint: answers each POST with the next status
+    /// and hands back the request bodies
+    fn webhook_endpoint(statuses: Vec<u16>) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/ingest", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0u8; length];
+                reader.read_exact(&mut body).unwrap();
+                bodies.push(String::from_utf8(body).unwrap());
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {} X\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+            }
+            bodies
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn test_webhook_batches_spools_and_retries() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool_file = dir.path().join("webhook.jsonl");
+        let (url, endpoint) = webhook_endpoint(vec![503, 200]);
+        let config: AuditSinkConfig = serde_yaml::from_str(&format!(
+            "{{type: webhook, url: \"{}\", batch_size: 2, spool_file: \"{}\"}}",
+            url,
+            spool_file.display()
+        ))
+        .unwrap();
+        let mut sink = build_sink(&config).unwrap();
+
+        // Not a full batch yet, and not due
+        sink.write(&entry("req-1", "allow")).unwrap();
+        sink.flush(false).unwrap();
+        assert!(sink.deadline().is_some());
+
+        // Full batch, but the endpoint fails: it waits in the spool
+        sink.write(&entry("req-2", "allow")).unwrap();
+        assert!(sink.flush(false).is_err());
+        assert_eq!(fs::read_to_string(&spool_file).unwrap().lines().count(), 2);
+
+        // The retry sends the spool first, then empties it
+        sink.write(&entry("req-3", "deny")).unwrap();
+        sink.flush(true).unwrap();
+        assert!(!spool_file.exists());
+        assert!(sink.deadline().is_none());
+
+        let bodies = endpoint.join().unwrap();
+        let delivered: Vec<serde_json::Value> = serde_json::from_str(&bodies[1]).unwrap();
+        let ids: Vec<&str> = delivered
+            .iter()
+            .map(|e| e["request_id"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids, ["req-1", "req-2", "req-3"]);
+    }
+
+    #[test]
+    fn test_webhook_without_spool_stops_retrying() {
+        let (url, endpoint) = webhook_endpoint(vec![503]);
+        let config: AuditSinkConfig =
+            serde_yaml::from_str(&format!("{{type: webhook, url: \"{}\"}}", url)).unwrap();
+        let mut sink = build_sink(&config).unwrap();
+
+        sink.write(&entry("req-1", "allow")).unwrap();
+        assert!(sink.flush(true).is_err());
+        endpoint.join().unwrap();
+        // The batch is dropped, but the retry is still scheduled
+        assert!(sink.deadline().is_some());
+
+        // At the retry there is nothing to send, so the sink goes quiet
+        // rather than leaving a deadline in the past for the writer
+        sink.flush(true).unwrap();
+        assert!(sink.deadline().is_none());
+    }
+}
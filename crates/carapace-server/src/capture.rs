@@ -0,0 +1,294 @@
+//! Forensic capture store: full request and response payloads of tools whose
+//! policy sets `audit.capture`, one encrypted file per request, kept apart
+//! from the audit log. Request ids are only unique within a connection, so
+//! a capture never replaces another with the same id.
+//!
+//! The file format lives in `carapace_protocol::capture`, which
+//! `carapace-debug` reads captures with.
+
+use crate::error::{Result, ServerError};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use carapace_protocol::capture::{capture_file_prefix, seal, unseal};
+use carapace_protocol::PayloadEncoding;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// A payload as the protocol carries it: text, or base64 when not UTF-8
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Payload {
+    pub data: String,
+    #[serde(default, skip_serializing_if = "PayloadEncoding::is_utf8")]
+    pub encoding: PayloadEncoding,
+}
+
+impl Payload {
+    pub fn text(data: String) -> Self {
+        Payload {
+            data,
+            encoding: PayloadEncoding::Utf8,
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => Payload::text(text.to_string()),
+            Err(_) => Payload {
+                data: BASE64.encode(bytes),
+                encoding: PayloadEncoding::Base64,
+            },
+        }
+    }
+}
+
+/// One event of a streamed (SSE) response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CapturedEvent {
+    pub event: String,
+    pub data: String,
+}
+
+/// Everything a request sent and got back, after redaction
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    pub request_id: String,
+    pub tool: String,
+    pub timestamp: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub argv: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<Payload>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdout: Option<Payload>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stderr: Option<Payload>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_headers: Option<BTreeMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_body: Option<Payload>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_headers: Option<BTreeMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_body: Option<Payload>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sse_events: Vec<CapturedEvent>,
+}
+
+/// Encrypted captures in one directory, the oldest removed once they
+/// take up more than `max_bytes`
+pub struct CaptureStore {
+    dir: PathBuf,
+    key: [u8; 32],
+    max_bytes: u64,
+    /// Stored files, oldest first, and their total size
+    index: Mutex<(VecDeque<(PathBuf, u64)>, u64)>,
+}
+
+impl CaptureStore {
+    /// Open (creating it if needed) the store in `dir`
+    pub fn open(dir: impl Into<PathBuf>, key: [u8; 32], max_bytes: u64) -> Result<Self> {
+        let dir = dir.into();
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&dir)?;
+
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "cap") {
+                let meta = entry.metadata()?;
+                files.push((meta.modified()?, path, meta.len()));
+            }
+        }
+        files.sort();
+        let total = files.iter().map(|(_, _, len)| len).sum();
+        let files = files
+            .into_iter()
+            .map(|(_, path, len)| (path, len))
+            .collect();
+
+        Ok(CaptureStore {
+            dir,
+            key,
+            max_bytes,
+            index: Mutex::new((files, total)),
+        })
+    }
+
+    /// Files of the captures with `request_id`, in no particular order
+    pub fn paths_for(&self, request_id: &str) -> Result<Vec<PathBuf>> {
+        let prefix = format!("{}-", capture_file_prefix(request_id));
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let name = path.file_name().and_then(|name| name.to_str());
+            if name.is_some_and(|name| name.starts_with(&prefix) && name.ends_with(".cap")) {
+                paths.push(path);
+            }
+        }
+        Ok(paths)
+    }
+
+    /// Encrypt and store `record` in a file of its own, then remove the
+    /// oldest captures over the size cap
+    pub fn save(&self, record: &CaptureRecord) -> Result<()> {
+        let sealed = seal(&self.key, &serde_json::to_vec(record)?)
+            .map_err(|e| ServerError::Other(e.to_string()))?;
+        let size = sealed.len() as u64;
+        if size > self.max_bytes {
+            return Err(ServerError::Other(format!(
+                "Capture of {} is {} bytes, over the store's cap of {}",
+                record.request_id, size, self.max_bytes
+            )));
+        }
+
+        let path = self.dir.join(format!(
+            "{}-{}.cap",
+            capture_file_prefix(&record.request_id),
+            uuid::Uuid::new_v4().simple()
+        ));
+        let tmp = path.with_extension("tmp");
+        {
+            let mut file = fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(0o600)
+                .open(&tmp)?;
+            file.write_all(&sealed)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &path)?;
+
+        let mut index = self.index.lock().unwrap();
+        let (files, total) = &mut *index;
+        files.push_back((path, size));
+        *total += size;
+        while *total > self.max_bytes {
+            let Some((oldest, len)) = files.pop_front() else {
+                break;
+            };
+            if let Err(e) = fs::remove_file(&oldest) {
+                tracing::warn!("Cannot remove capture {}: {}", oldest.display(), e);
+            }
+            *total -= len;
+        }
+        Ok(())
+    }
+
+    /// Every capture stored for `request_id`, oldest first
+    pub fn load(&self, request_id: &str) -> Result<Vec<CaptureRecord>> {
+        let mut records = Vec::new();
+        for path in self.paths_for(request_id)? {
+            let mut sealed = Vec::new();
+            File::open(&path)?.read_to_end(&mut sealed)?;
+            let plaintext =
+                unseal(&self.key, &sealed).map_err(|e| ServerError::Other(e.to_string()))?;
+            records.push(serde_json::from_slice::<CaptureRecord>(&plaintext)?);
+        }
+        records.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+        Ok(records)
+    }
+}
+
+/// Load a capture key: a file holding a base64 32-byte key
+pub fn load_capture_key(path: &str) -> Result<[u8; 32]> {
+    let text = fs::read_to_string(path).map_err(|e| {
+        ServerError::ConfigError(format!("Cannot read capture key {}: {}", path, e))
+    })?;
+    BASE64
+        .decode(text.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| {
+            ServerError::ConfigError(format!("Capture key {} is not a base64 32-byte key", path))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: &str, stdout: &str) -> CaptureRecord {
+        CaptureRecord {
+            request_id: id.to_string(),
+            tool: "gh".to_string(),
+            timestamp: "2026-10-18T12:00:00Z".to_string(),
+            argv: Some(vec!["pr".to_string(), "list".to_string()]),
+            exit_code: Some(0),
+            stdout: Some(Payload::text(stdout.to_string())),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_store_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = CaptureStore::open(dir.path().join("captures"), [9u8; 32], 1 << 20).unwrap();
+        let saved = record("../../etc/passwd", "hello");
+        store.save(&saved).unwrap();
+
+        let paths = store.paths_for("../../etc/passwd").unwrap();
+        assert_eq!(paths.len(), 1);
+        assert!(paths[0].starts_with(dir.path().join("captures")));
+        assert!(!fs::read_to_string(&paths[0])
+            .unwrap_or_default()
+            .contains("hello"));
+        assert_eq!(store.load("../../etc/passwd").unwrap(), vec![saved]);
+        assert!(store.load("other").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_store_keeps_captures_with_the_same_request_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = CaptureStore::open(dir.path(), [9u8; 32], 1 << 20).unwrap();
+        let mut first = record("1", "from the first connection");
+        first.session_id = Some("session-a".to_string());
+        let mut second = record("1", "from the second connection");
+        second.session_id = Some("session-b".to_string());
+        second.timestamp = "2026-10-18T12:00:01Z".to_string();
+        store.save(&second).unwrap();
+        store.save(&first).unwrap();
+
+        assert_eq!(store.load("1").unwrap(), vec![first, second]);
+    }
+
+    #[test]
+    fn test_store_removes_oldest_over_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = [1u8; 32];
+        let one = seal(&key, &serde_json::to_vec(&record("a", "x")).unwrap())
+            .unwrap()
+            .len() as u64;
+        let store = CaptureStore::open(dir.path(), key, one * 2).unwrap();
+        for id in ["a", "b", "c"] {
+            store.save(&record(id, "x")).unwrap();
+        }
+        assert!(store.load("a").unwrap().is_empty());
+        assert_eq!(store.load("b").unwrap().len(), 1);
+        assert_eq!(store.load("c").unwrap().len(), 1);
+
+        // Reopening counts what is already stored
+        let reopened = CaptureStore::open(dir.path(), key, one * 2).unwrap();
+        reopened.save(&record("d", "x")).unwrap();
+        assert_eq!(reopened.load("d").unwrap().len(), 1);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+}
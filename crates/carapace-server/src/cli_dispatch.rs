@@ -1,115 +1,369 @@
-use carapace_policy::{ArgvMatcher, PolicyConfig, PolicyValidator};
-use carapace_protocol::{CliRequest, CliResponse};
+use carapace_policy::{
+    ApprovalRule, CliPolicy, Decision, EnvFilter, OutputLimitAction, PolicyConfig, PolicyValidator,
+};
+use carapace_protocol::messages::RequestId;
+use carapace_protocol::{CliRequest, CliResponse, Message};
 use std::collections::HashMap;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+
+use crate::audit::{AuditContext, AuditLogger};
+use crate::pty::{Pty, PtyInput, Session};
+use crate::sandbox::Sandbox;
+
+/// Signals a client may forward to a running command
+const FORWARDED_SIGNALS: [i32; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+/// A running request: the connection it came on (its audit session id, or
+/// empty outside any connection) and its id. Clients choose request ids, so
+/// ids alone would let one agent reach another's processes.
+type RunKey = (String, RequestId);
+
+fn run_key(owner: &str, id: &str) -> RunKey {
+    (owner.to_string(), id.to_string())
+}
 
 /// Handles CLI command execution with policy enforcement
 pub struct CliDispatcher {
     policy: PolicyConfig,
+    /// Policy evaluated next to the live one; only disagreements are logged
+    candidate: Option<PolicyConfig>,
+    audit_logger: Arc<AuditLogger>,
+    /// Process group of each running command
+    running: Mutex<HashMap<RunKey, u32>>,
+    /// Input channels of running interactive sessions
+    sessions: Mutex<HashMap<RunKey, tokio::sync::mpsc::UnboundedSender<PtyInput>>>,
+}
+
+/// Result of an interactive session
+pub struct InteractiveSession {
+    pub response: CliResponse,
+    /// Where the session's terminal output was recorded, if anywhere
+    pub transcript: Option<PathBuf>,
+}
+
+/// Output of a finished command
+struct CommandOutput {
+    output: std::process::Output,
+    /// The command was killed for exceeding its timeout
+    timed_out: bool,
+    /// Output past the policy's limits was discarded
+    stdout_truncated: bool,
+    stderr_truncated: bool,
 }
 
 impl CliDispatcher {
     pub fn new() -> Self {
+        Self::with_policy(PolicyConfig {
+            tools: HashMap::new(),
+            mode: carapace_policy::PolicyMode::Enforce,
+            source: None,
+        })
+    }
+
+    pub fn with_policy(policy: PolicyConfig) -> Self {
         CliDispatcher {
-            policy: PolicyConfig {
-                tools: HashMap::new(),
-            },
+            policy,
+            candidate: None,
+            audit_logger: Arc::new(AuditLogger::new()),
+            running: Mutex::new(HashMap::new()),
+            sessions: Mutex::new(HashMap::new()),
         }
     }
 
-    pub fn with_policy(policy: PolicyConfig) -> Self {
-        CliDispatcher { policy }
+    /// Evaluate requests against `candidate` too, and audit where it
+    /// decides differently from the live policy
+    pub fn with_candidate_policy(mut self, candidate: PolicyConfig) -> Self {
+        self.candidate = Some(candidate);
+        self
+    }
+
+    /// Where decisions that are not enforced get audited
+    pub fn with_audit_logger(mut self, audit_logger: Arc<AuditLogger>) -> Self {
+        self.audit_logger = audit_logger;
+        self
+    }
+
+    /// Forward a signal to the process group of a request `owner` is
+    /// running. Returns false if there is no such request or the signal is
+    /// not one clients may send.
+    pub fn signal(&self, owner: &str, id: &str, signal: i32) -> bool {
+        if !FORWARDED_SIGNALS.contains(&signal) {
+            tracing::warn!("Refusing to forward signal {} to request {}", signal, id);
+            return false;
+        }
+        let Some(pgid) = self
+            .running
+            .lock()
+            .unwrap()
+            .get(&run_key(owner, id))
+            .copied()
+        else {
+            return false;
+        };
+        // SAFETY: killpg has no memory-safety preconditions; the group id is
+        // the child's pid because it was spawned with process_group(0) or
+        // as a session leader
+        unsafe { libc::killpg(pgid as libc::pid_t, signal) == 0 }
     }
 
     /// Dispatch a CLI request, validate against policy, and execute
     pub async fn dispatch_cli(&self, req: CliRequest) -> anyhow::Result<CliResponse> {
-        // Check if tool is allowed in policy
-        let tool_config = self
-            .policy
-            .tools
-            .get(&req.tool)
-            .ok_or_else(|| anyhow::anyhow!("Tool '{}' not in policy", req.tool))?;
-
-        // Get CLI policy
-        let cli_policy = match tool_config {
-            carapace_policy::ToolPolicy::Cli(policy) => policy,
-            carapace_policy::ToolPolicy::Http(_) => {
+        self.dispatch_cli_with_cancel("", req, CancellationToken::new())
+            .await
+    }
+
+    /// Like `dispatch_cli`, for a request `owner` sent, and kills the
+    /// process group when `cancel` fires
+    pub async fn dispatch_cli_with_cancel(
+        &self,
+        owner: &str,
+        req: CliRequest,
+        cancel: CancellationToken,
+    ) -> anyhow::Result<CliResponse> {
+        let (cli_policy, merged_env) = self.authorize(&req)?;
+
+        // Execute the command with policy timeout
+        let CommandOutput {
+            output,
+            timed_out,
+            stdout_truncated,
+            stderr_truncated,
+        } = self
+            .execute_command(
+                &run_key(owner, &req.id),
+                cli_policy,
+                &req.argv,
+                &merged_env,
+                &cancel,
+            )
+            .await?;
+
+        // Non-UTF-8 output (binary documents, attachments) is base64-encoded
+        let mut response = CliResponse::from_output(
+            req.id,
+            output.status.code().unwrap_or(-1),
+            &output.stdout,
+            &output.stderr,
+        );
+        response.signal = output.status.signal();
+        response.timed_out = timed_out;
+        response.stdout_truncated = stdout_truncated;
+        response.stderr_truncated = stderr_truncated;
+        Ok(response)
+    }
+
+    /// True if the request gets a pseudo-terminal: the client has a terminal
+    /// and the tool's policy is interactive
+    pub fn is_interactive(&self, req: &CliRequest) -> bool {
+        req.tty.is_some()
+            && matches!(
+                self.policy.tools.get(&req.tool),
+                Some(carapace_policy::ToolPolicy::Cli(policy)) if policy.interactive
+            )
+    }
+
+    /// Run an interactive request `owner` sent in a pseudo-terminal.
+    /// Terminal output is streamed to `output` as `PtyData`; client input
+    /// arrives via `pty_input`. The process group is killed when `cancel`
+    /// fires.
+    pub async fn dispatch_interactive(
+        &self,
+        owner: &str,
+        req: CliRequest,
+        output: tokio::sync::mpsc::UnboundedSender<Message>,
+        cancel: CancellationToken,
+    ) -> anyhow::Result<InteractiveSession> {
+        let (cli_policy, merged_env) = self.authorize(&req)?;
+        let size = match req.tty {
+            Some(size) if cli_policy.interactive => size,
+            _ => {
                 return Err(anyhow::anyhow!(
-                    "Tool '{}' is HTTP-only, cannot handle CLI request",
+                    "Tool '{}' does not allow interactive sessions",
                     req.tool
                 ))
             }
         };
 
-        // Validate argv against allow/deny patterns
-        let matcher = ArgvMatcher::new(
-            cli_policy.argv_allow_patterns.clone(),
-            cli_policy.argv_deny_patterns.clone(),
-        )?;
-
-        if !matcher.matches(&req.argv) {
-            return Err(anyhow::anyhow!(
-                "CLI request denied by policy: argv={:?}",
-                req.argv
-            ));
+        let transcript = cli_policy.audit.transcript_dir.as_ref().map(|dir| {
+            let timestamp = chrono::Utc::now().format("%Y%m%dT%H%M%S");
+            Path::new(dir).join(format!("{}-{}-{}.log", timestamp, req.tool, req.id))
+        });
+
+        let sandbox = prepare_sandbox(cli_policy)?;
+        let cmd = build_command(&cli_policy.binary, &req.argv, &merged_env, sandbox.as_ref());
+        let (mut child, master) = Pty::open(size)?.spawn(cmd, sandbox)?;
+        let (input_tx, input_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let key = run_key(owner, &req.id);
+        if let Some(pid) = child.id() {
+            self.running.lock().unwrap().insert(key.clone(), pid);
         }
+        self.sessions.lock().unwrap().insert(key.clone(), input_tx);
 
-        // Validate binary path
-        PolicyValidator::validate_binary_path(&cli_policy.binary)?;
+        let session = Session {
+            id: req.id.clone(),
+            master,
+            input: input_rx,
+            output,
+            transcript: transcript.clone(),
+            timeout: Duration::from_secs(cli_policy.timeout_secs),
+        };
+        let result = session.run(&mut child, &cancel).await;
 
-        // Check for shell injection attempts in argv
-        for arg in &req.argv {
-            if PolicyValidator::has_dangerous_shell_chars(arg) {
-                return Err(anyhow::anyhow!(
-                    "Shell injection detected in argument: {}",
-                    arg
-                ));
+        self.sessions.lock().unwrap().remove(&key);
+        self.running.lock().unwrap().remove(&key);
+        let end = result?;
+
+        // Output was streamed, so the response only reports how it ended
+        let mut response =
+            CliResponse::from_output(req.id, end.status.code().unwrap_or(-1), b"", b"");
+        response.signal = end.status.signal();
+        response.timed_out = end.timed_out;
+        Ok(InteractiveSession {
+            response,
+            transcript,
+        })
+    }
+
+    /// Pass client input to an interactive session `owner` is running.
+    /// Returns false if there is no such session.
+    pub fn pty_input(&self, owner: &str, id: &str, input: PtyInput) -> bool {
+        match self.sessions.lock().unwrap().get(&run_key(owner, id)) {
+            Some(tx) => tx.send(input).is_ok(),
+            None => false,
+        }
+    }
+
+    /// What the tool's policy does with output past its limits
+    pub fn output_limit_action(&self, tool: &str) -> OutputLimitAction {
+        match self.policy.tools.get(tool) {
+            Some(carapace_policy::ToolPolicy::Cli(policy)) => policy.on_output_limit,
+            _ => OutputLimitAction::default(),
+        }
+    }
+
+    /// Names of the request's environment variables that the tool's policy
+    /// withholds from it
+    pub fn dropped_env(&self, req: &CliRequest) -> Vec<String> {
+        let Some(carapace_policy::ToolPolicy::Cli(policy)) = self.policy.tools.get(&req.tool)
+        else {
+            return Vec::new();
+        };
+        match EnvFilter::new(&policy.env_passthrough) {
+            Ok(filter) => filter.apply(&req.env).1,
+            // Dispatch fails on the invalid pattern anyway
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// The live policy's decision on this request
+    pub fn decide(&self, req: &CliRequest) -> Decision {
+        self.policy.decide_cli(&req.tool, &req.argv)
+    }
+
+    /// Whether the live policy enforces `decision`, or only audits it
+    pub fn enforces(&self, decision: &Decision) -> bool {
+        self.policy.enforces(decision)
+    }
+
+    /// The approval rule holding this request, if the policy allows it but
+    /// requires an operator's approval first
+    pub fn approval_rule(&self, req: &CliRequest) -> Option<&ApprovalRule> {
+        let (cli_policy, _) = self.authorize(req).ok()?;
+        let rule = cli_policy.require_approval.as_ref()?;
+        match rule.matches_argv(&req.argv) {
+            Ok(matches) => matches.then_some(rule),
+            Err(e) => {
+                // Fail closed: hold everything rather than nothing
+                tracing::warn!("Invalid approval pattern for tool '{}': {}", req.tool, e);
+                Some(rule)
             }
         }
+    }
+
+    /// Audit the rule decisions that are not enforced: violations let
+    /// through in audit-only mode, and disagreements with the candidate
+    /// policy. Call before dispatching a request the live policy let through.
+    pub fn audit_unenforced(&self, ctx: &AuditContext, req: &CliRequest) {
+        let live = self.decide(req);
+        if !live.allowed && !self.policy.enforces(&live) {
+            tracing::warn!("Audit-only: would deny request {}: {}", req.id, live);
+            self.audit_logger.log_audit_only_denial(ctx, &live);
+        }
 
-        // Merge policy-injected env vars with request env (policy takes precedence)
-        let mut merged_env = req.env.clone();
+        if let Some(candidate) = &self.candidate {
+            let candidate = candidate.decide_cli(&req.tool, &req.argv);
+            if live.allowed != candidate.allowed {
+                tracing::warn!(
+                    "Candidate policy disagrees on request {} for tool '{}'",
+                    req.id,
+                    req.tool
+                );
+                self.audit_logger
+                    .log_candidate_disagreement(ctx, &live, &candidate);
+            }
+        }
+    }
+
+    /// Check the request against the tool's CLI policy and return the policy
+    /// with the environment to run under. Policy denials fail with the
+    /// `Decision` behind them.
+    fn authorize(&self, req: &CliRequest) -> anyhow::Result<(&CliPolicy, HashMap<String, String>)> {
+        let decision = self.decide(req);
+        // Requests for anything but a CLI tool are always denied
+        let cli_policy = match self.policy.tools.get(&req.tool) {
+            Some(carapace_policy::ToolPolicy::Cli(cli_policy))
+                if !self.policy.enforces(&decision) =>
+            {
+                cli_policy
+            }
+            _ => return Err(decision.into()),
+        };
+
+        // Validate binary path
+        PolicyValidator::validate_binary_path(&cli_policy.binary)?;
+
+        // Only allowlisted client variables reach the tool; policy-injected
+        // ones take precedence
+        let filter = EnvFilter::new(&cli_policy.env_passthrough)?;
+        let (mut merged_env, dropped) = filter.apply(&req.env);
+        for name in dropped.iter().filter(|name| filter.is_denied(name)) {
+            tracing::warn!(
+                "Dropping denied environment variable {} for tool '{}'",
+                name,
+                req.tool
+            );
+        }
         for (key, value) in &cli_policy.env_inject {
             merged_env.insert(key.clone(), value.clone());
         }
 
-        // Execute the command with policy timeout
-        let output = self
-            .execute_command(
-                &cli_policy.binary,
-                &req.argv,
-                &merged_env,
-                cli_policy.timeout_secs,
-            )
-            .await?;
-
-        Ok(CliResponse {
-            id: req.id,
-            exit_code: output.status.code().unwrap_or(-1),
-            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
-            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
-        })
+        Ok((cli_policy, merged_env))
     }
 
-    /// Execute a command with the given argv, environment, and timeout
+    /// Execute the tool's binary with the given argv and environment, under
+    /// its policy's timeout and sandbox
     async fn execute_command(
         &self,
-        binary: &str,
+        key: &RunKey,
+        cli_policy: &CliPolicy,
         argv: &[String],
         env: &HashMap<String, String>,
-        timeout_secs: u64,
-    ) -> anyhow::Result<std::process::Output> {
-        let mut cmd = Command::new(binary);
-
-        // Add arguments
-        for arg in argv {
-            cmd.arg(arg);
-        }
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<CommandOutput> {
+        let sandbox = prepare_sandbox(cli_policy)?;
+        let mut cmd = build_command(&cli_policy.binary, argv, env, sandbox.as_ref());
 
-        // Set environment variables
-        for (key, value) in env {
-            cmd.env(key, value);
+        // Own process group, so timeouts, cancellation and forwarded signals
+        // also reach anything the tool spawned
+        cmd.process_group(0);
+        if let Some(sandbox) = sandbox {
+            sandbox.confine(&mut cmd);
         }
 
         // Capture stdout/stderr
@@ -118,62 +372,196 @@ impl CliDispatcher {
 
         let mut child = cmd.spawn()?;
 
-        // Take stdout/stderr handles BEFORE waiting - we must drain them
-        // concurrently with waiting for exit to prevent pipe buffer deadlock.
-        // If the process fills the OS pipe buffer (~64KB) and nobody is reading,
-        // the process blocks on write and child.wait() hangs forever.
-        let mut stdout_handle = child.stdout.take();
-        let mut stderr_handle = child.stderr.take();
-
-        // Spawn concurrent tasks to drain stdout and stderr
-        let stdout_task = tokio::spawn(async move {
-            let mut buf = Vec::new();
-            if let Some(ref mut out) = stdout_handle {
-                tokio::io::AsyncReadExt::read_to_end(out, &mut buf)
-                    .await
-                    .ok();
-            }
-            buf
-        });
-        let stderr_task = tokio::spawn(async move {
-            let mut buf = Vec::new();
-            if let Some(ref mut err) = stderr_handle {
-                tokio::io::AsyncReadExt::read_to_end(err, &mut buf)
-                    .await
-                    .ok();
-            }
-            buf
-        });
+        if let Some(pid) = child.id() {
+            self.running.lock().unwrap().insert(key.clone(), pid);
+        }
+        let result = wait_for_child(&mut child, cli_policy, cancel).await;
+        self.running.lock().unwrap().remove(key);
+        result
+    }
+}
 
-        // Wait for process exit with timeout (stdout/stderr drain concurrently)
-        match tokio::time::timeout(Duration::from_secs(timeout_secs), child.wait()).await {
-            Ok(Ok(status)) => {
-                // Process exited - collect drained output
-                let stdout = stdout_task.await.unwrap_or_default();
-                let stderr = stderr_task.await.unwrap_or_default();
-                Ok(std::process::Output {
-                    status,
-                    stdout,
-                    stderr,
-                })
-            }
-            Ok(Err(e)) => {
-                stdout_task.abort();
-                stderr_task.abort();
-                Err(anyhow::anyhow!("Command failed: {}", e))
-            }
-            Err(_) => {
-                // Timeout exceeded - kill the process
-                child.kill().await.ok();
-                stdout_task.abort();
-                stderr_task.abort();
-                Err(anyhow::anyhow!(
-                    "Command timed out after {} seconds",
-                    timeout_secs
-                ))
+/// Resolve the tool's sandbox, if its policy has one
+fn prepare_sandbox(cli_policy: &CliPolicy) -> anyhow::Result<Option<Sandbox>> {
+    cli_policy
+        .sandbox
+        .as_ref()
+        .map(Sandbox::prepare)
+        .transpose()
+}
+
+/// Command for `binary` with the given argv and environment. The sandbox's
+/// environment restrictions apply here; its process confinement is
+/// installed separately, after any other `pre_exec` hooks.
+fn build_command(
+    binary: &str,
+    argv: &[String],
+    env: &HashMap<String, String>,
+    sandbox: Option<&Sandbox>,
+) -> Command {
+    let mut cmd = Command::new(binary);
+    if let Some(sandbox) = sandbox {
+        sandbox.restrict_env(&mut cmd);
+    }
+
+    // Add arguments
+    for arg in argv {
+        cmd.arg(arg);
+    }
+
+    // Set environment variables
+    for (key, value) in env {
+        cmd.env(key, value);
+    }
+
+    cmd
+}
+
+/// Wait for a spawned command, draining its output, until it exits, times
+/// out, exceeds an output limit with `kill` policy, or is cancelled
+async fn wait_for_child(
+    child: &mut tokio::process::Child,
+    cli_policy: &CliPolicy,
+    cancel: &CancellationToken,
+) -> anyhow::Result<CommandOutput> {
+    let timeout_secs = cli_policy.timeout_secs;
+
+    // Take stdout/stderr handles BEFORE waiting - we must drain them
+    // concurrently with waiting for exit to prevent pipe buffer deadlock.
+    // If the process fills the OS pipe buffer (~64KB) and nobody is reading,
+    // the process blocks on write and child.wait() hangs forever.
+    let over_limit = CancellationToken::new();
+    let stdout_task = tokio::spawn(capture(
+        child.stdout.take(),
+        cli_policy.max_stdout_bytes,
+        over_limit.clone(),
+    ));
+    let stderr_task = tokio::spawn(capture(
+        child.stderr.take(),
+        cli_policy.max_stderr_bytes,
+        over_limit.clone(),
+    ));
+    let kill_over_limit = cli_policy.on_output_limit == OutputLimitAction::Kill;
+
+    // Wait for process exit with timeout (stdout/stderr drain concurrently)
+    let waited = tokio::select! {
+        waited = tokio::time::timeout(Duration::from_secs(timeout_secs), child.wait()) => Some(waited),
+        _ = over_limit.cancelled(), if kill_over_limit => None,
+        _ = cancel.cancelled() => {
+            kill_process_group(child).await;
+            stdout_task.abort();
+            stderr_task.abort();
+            return Err(anyhow::anyhow!("Command cancelled by client"));
+        }
+    };
+
+    let (status, timed_out, killed) = match waited {
+        Some(Ok(Ok(status))) => (status, false, false),
+        Some(Ok(Err(e))) => {
+            stdout_task.abort();
+            stderr_task.abort();
+            return Err(anyhow::anyhow!("Command failed: {}", e));
+        }
+        Some(Err(_)) => {
+            // Timeout exceeded - kill the process group and report it
+            tracing::warn!("Command timed out after {} seconds", timeout_secs);
+            kill_process_group(child).await;
+            (child.wait().await?, true, true)
+        }
+        None => {
+            tracing::warn!("Command output exceeded its limit, killing it");
+            kill_process_group(child).await;
+            (child.wait().await?, false, true)
+        }
+    };
+
+    // Process exited - collect drained output. After a kill this is
+    // whatever was written before it; a process that escaped the group may
+    // still hold the pipes open, so don't wait for it forever.
+    let stdout = collect_output(stdout_task, killed).await;
+    let stderr = collect_output(stderr_task, killed).await;
+    Ok(CommandOutput {
+        output: std::process::Output {
+            status,
+            stdout: stdout.bytes,
+            stderr: stderr.bytes,
+        },
+        timed_out,
+        stdout_truncated: stdout.truncated,
+        stderr_truncated: stderr.truncated,
+    })
+}
+
+/// Output read from one of a command's streams
+#[derive(Default)]
+struct Captured {
+    bytes: Vec<u8>,
+    /// More than `limit` bytes were written; the rest was discarded
+    truncated: bool,
+}
+
+/// Read a stream to its end, keeping at most `limit` bytes. Past the limit,
+/// `over_limit` is cancelled and the rest is read and discarded, so the
+/// process never blocks on a full pipe.
+async fn capture<R: tokio::io::AsyncRead + Unpin>(
+    stream: Option<R>,
+    limit: Option<u64>,
+    over_limit: CancellationToken,
+) -> Captured {
+    let mut captured = Captured::default();
+    let Some(mut stream) = stream else {
+        return captured;
+    };
+    let limit = limit.map_or(usize::MAX, |limit| {
+        usize::try_from(limit).unwrap_or(usize::MAX)
+    });
+
+    let mut buf = vec![0u8; 8192];
+    loop {
+        let n = match tokio::io::AsyncReadExt::read(&mut stream, &mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        let room = limit - captured.bytes.len();
+        if n > room {
+            captured.bytes.extend_from_slice(&buf[..room]);
+            if !captured.truncated {
+                captured.truncated = true;
+                over_limit.cancel();
             }
+        } else {
+            captured.bytes.extend_from_slice(&buf[..n]);
         }
     }
+    captured
+}
+
+/// Join an output-draining task, giving up after a grace period if the
+/// command was killed
+async fn collect_output(task: tokio::task::JoinHandle<Captured>, killed: bool) -> Captured {
+    if !killed {
+        return task.await.unwrap_or_default();
+    }
+    let abort = task.abort_handle();
+    match tokio::time::timeout(Duration::from_secs(1), task).await {
+        Ok(output) => output.unwrap_or_default(),
+        Err(_) => {
+            abort.abort();
+            Captured::default()
+        }
+    }
+}
+
+/// SIGKILL the child's process group, then reap the child
+pub(crate) async fn kill_process_group(child: &mut tokio::process::Child) {
+    if let Some(pid) = child.id() {
+        // SAFETY: killpg has no memory-safety preconditions; the group id is
+        // the child's pid because it was spawned with process_group(0)
+        unsafe {
+            libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+        }
+    }
+    child.kill().await.ok();
 }
 
 impl Default for CliDispatcher {
@@ -185,7 +573,8 @@ impl Default for CliDispatcher {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use carapace_policy::CliPolicy;
+    use crate::pty::PtyInput;
+    use carapace_policy::PolicyMode;
 
     #[test]
     fn test_cli_dispatcher_creation() {
@@ -202,6 +591,7 @@ mod tests {
             env: HashMap::new(),
             stdin: None,
             cwd: "/tmp".to_string(),
+            tty: None,
         };
 
         let result = dispatcher.dispatch_cli(req).await;
@@ -212,18 +602,29 @@ mod tests {
     async fn test_denied_argv_pattern() {
         let mut policy = PolicyConfig {
             tools: HashMap::new(),
+            mode: carapace_policy::PolicyMode::Enforce,
+            source: None,
         };
 
         policy.tools.insert(
             "test".to_string(),
             carapace_policy::ToolPolicy::Cli(CliPolicy {
                 binary: "/usr/bin/test".to_string(),
+                mode: None,
                 argv_allow_patterns: vec!["list".to_string()],
                 argv_deny_patterns: vec![],
+                env_passthrough: vec![],
                 env_inject: HashMap::new(),
                 cwd_allowed: None,
                 timeout_secs: 30,
+                max_stdout_bytes: None,
+                max_stderr_bytes: None,
+                on_output_limit: carapace_policy::OutputLimitAction::Truncate,
+                max_concurrent: None,
+                require_approval: None,
                 audit: carapace_policy::AuditConfig::default(),
+                interactive: false,
+                sandbox: None,
             }),
         );
 
@@ -235,6 +636,7 @@ mod tests {
             env: HashMap::new(),
             stdin: None,
             cwd: "/tmp".to_string(),
+            tty: None,
         };
 
         let result = dispatcher.dispatch_cli(req).await;
@@ -245,18 +647,29 @@ mod tests {
     async fn test_shell_injection_detection() {
         let mut policy = PolicyConfig {
             tools: HashMap::new(),
+            mode: carapace_policy::PolicyMode::Enforce,
+            source: None,
         };
 
         policy.tools.insert(
             "test".to_string(),
             carapace_policy::ToolPolicy::Cli(CliPolicy {
                 binary: "/usr/bin/test".to_string(),
+                mode: None,
                 argv_allow_patterns: vec!["*".to_string()],
                 argv_deny_patterns: vec![],
+                env_passthrough: vec![],
                 env_inject: HashMap::new(),
                 cwd_allowed: None,
                 timeout_secs: 30,
+                max_stdout_bytes: None,
+                max_stderr_bytes: None,
+                on_output_limit: carapace_policy::OutputLimitAction::Truncate,
+                max_concurrent: None,
+                require_approval: None,
                 audit: carapace_policy::AuditConfig::default(),
+                interactive: false,
+                sandbox: None,
             }),
         );
 
@@ -268,9 +681,863 @@ mod tests {
             env: HashMap::new(),
             stdin: None,
             cwd: "/tmp".to_string(),
+            tty: None,
         };
 
         let result = dispatcher.dispatch_cli(req).await;
         assert!(result.is_err());
     }
+
+    #[tokio::test]
+    async fn test_binary_stdout_preserved() {
+        let mut policy = PolicyConfig {
+            tools: HashMap::new(),
+            mode: carapace_policy::PolicyMode::Enforce,
+            source: None,
+        };
+
+        policy.tools.insert(
+            "printf".to_string(),
+            carapace_policy::ToolPolicy::Cli(CliPolicy {
+                binary: "/usr/bin/printf".to_string(),
+                mode: None,
+                argv_allow_patterns: vec!["*".to_string()],
+                argv_deny_patterns: vec![],
+                env_passthrough: vec![],
+                env_inject: HashMap::new(),
+                cwd_allowed: None,
+                timeout_secs: 30,
+                max_stdout_bytes: None,
+                max_stderr_bytes: None,
+                on_output_limit: carapace_policy::OutputLimitAction::Truncate,
+                max_concurrent: None,
+                require_approval: None,
+                audit: carapace_policy::AuditConfig::default(),
+                interactive: false,
+                sandbox: None,
+            }),
+        );
+
+        let dispatcher = CliDispatcher::with_policy(policy);
+        let req = CliRequest {
+            id: "binary-1".to_string(),
+            tool: "printf".to_string(),
+            argv: vec!["\\377\\376\\000".to_string()],
+            env: HashMap::new(),
+            stdin: None,
+            cwd: "/tmp".to_string(),
+            tty: None,
+        };
+
+        let resp = dispatcher.dispatch_cli(req).await.expect("dispatch failed");
+        assert_eq!(resp.encoding, carapace_protocol::PayloadEncoding::Base64);
+        assert_eq!(resp.stdout_bytes().unwrap(), vec![0xff, 0xfe, 0x00]);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_kills_process() {
+        let mut policy = PolicyConfig {
+            tools: HashMap::new(),
+            mode: carapace_policy::PolicyMode::Enforce,
+            source: None,
+        };
+
+        policy.tools.insert(
+            "sleep".to_string(),
+            carapace_policy::ToolPolicy::Cli(CliPolicy {
+                binary: "/bin/sleep".to_string(),
+                mode: None,
+                argv_allow_patterns: vec!["*".to_string()],
+                argv_deny_patterns: vec![],
+                env_passthrough: vec![],
+                env_inject: HashMap::new(),
+                cwd_allowed: None,
+                timeout_secs: 30,
+                max_stdout_bytes: None,
+                max_stderr_bytes: None,
+                on_output_limit: carapace_policy::OutputLimitAction::Truncate,
+                max_concurrent: None,
+                require_approval: None,
+                audit: carapace_policy::AuditConfig::default(),
+                interactive: false,
+                sandbox: None,
+            }),
+        );
+
+        let dispatcher = CliDispatcher::with_policy(policy);
+        let req = CliRequest {
+            id: "cancel-1".to_string(),
+            tool: "sleep".to_string(),
+            argv: vec!["30".to_string()],
+            env: HashMap::new(),
+            stdin: None,
+            cwd: "/tmp".to_string(),
+            tty: None,
+        };
+
+        let cancel = CancellationToken::new();
+        let trigger = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            trigger.cancel();
+        });
+
+        let start = std::time::Instant::now();
+        let result = dispatcher.dispatch_cli_with_cancel("", req, cancel).await;
+        assert!(result.unwrap_err().to_string().contains("cancelled"));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_env_passthrough() {
+        let mut policy = PolicyConfig {
+            tools: HashMap::new(),
+            mode: carapace_policy::PolicyMode::Enforce,
+            source: None,
+        };
+
+        policy.tools.insert(
+            "env".to_string(),
+            carapace_policy::ToolPolicy::Cli(CliPolicy {
+                binary: "/usr/bin/env".to_string(),
+                mode: None,
+                argv_allow_patterns: vec!["*".to_string()],
+                argv_deny_patterns: vec![],
+                env_passthrough: vec!["GH_*".to_string(), "LD_*".to_string()],
+                env_inject: HashMap::from([("INJECTED".to_string(), "yes".to_string())]),
+                cwd_allowed: None,
+                timeout_secs: 30,
+                max_stdout_bytes: None,
+                max_stderr_bytes: None,
+                on_output_limit: carapace_policy::OutputLimitAction::Truncate,
+                max_concurrent: None,
+                require_approval: None,
+                audit: carapace_policy::AuditConfig::default(),
+                interactive: false,
+                sandbox: None,
+            }),
+        );
+
+        let dispatcher = CliDispatcher::with_policy(policy);
+        let req = CliRequest {
+            id: "env-1".to_string(),
+            tool: "env".to_string(),
+            argv: vec![],
+            env: HashMap::from([
+                ("GH_HOST".to_string(), "github.com".to_string()),
+                ("LD_PRELOAD".to_string(), "/tmp/evil.so".to_string()),
+                ("PATH".to_string(), "/tmp/evil".to_string()),
+                ("AWS_SECRET_ACCESS_KEY".to_string(), "secret".to_string()),
+            ]),
+            stdin: None,
+            cwd: "/tmp".to_string(),
+            tty: None,
+        };
+
+        // Built-in denials win over the policy's patterns
+        assert_eq!(
+            dispatcher.dropped_env(&req),
+            ["AWS_SECRET_ACCESS_KEY", "LD_PRELOAD", "PATH"]
+        );
+
+        let response = dispatcher.dispatch_cli(req).await.unwrap();
+        let stdout = response.stdout;
+        assert!(stdout.contains("GH_HOST=github.com\n"));
+        assert!(stdout.contains("INJECTED=yes\n"));
+        assert!(!stdout.contains("LD_PRELOAD"));
+        assert!(!stdout.contains("AWS_SECRET_ACCESS_KEY"));
+        assert!(!stdout.contains("PATH=/tmp/evil"));
+    }
+
+    fn echo_policy(allow: &str, mode: PolicyMode) -> PolicyConfig {
+        let mut policy = PolicyConfig {
+            tools: HashMap::new(),
+            mode,
+            source: None,
+        };
+        policy.tools.insert(
+            "echo".to_string(),
+            carapace_policy::ToolPolicy::Cli(CliPolicy {
+                binary: "/bin/echo".to_string(),
+                mode: None,
+                argv_allow_patterns: vec![allow.to_string()],
+                argv_deny_patterns: vec![],
+                env_passthrough: vec![],
+                env_inject: HashMap::new(),
+                cwd_allowed: None,
+                timeout_secs: 30,
+                max_stdout_bytes: None,
+                max_stderr_bytes: None,
+                on_output_limit: carapace_policy::OutputLimitAction::Truncate,
+                max_concurrent: None,
+                require_approval: None,
+                audit: carapace_policy::AuditConfig::default(),
+                interactive: false,
+                sandbox: None,
+            }),
+        );
+        policy
+    }
+
+    #[tokio::test]
+    async fn test_audit_only_and_candidate_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_file = dir.path().join("audit.log");
+        let audit_logger = Arc::new(AuditLogger::with_config(
+            true,
+            true,
+            false,
+            Some(log_file.to_string_lossy().to_string()),
+            1024 * 1024,
+            1,
+        ));
+        let request = CliRequest {
+            id: "shadow-1".to_string(),
+            tool: "echo".to_string(),
+            argv: vec!["bye".to_string()],
+            env: HashMap::new(),
+            stdin: None,
+            cwd: "/tmp".to_string(),
+            tty: None,
+        };
+
+        // Enforced, the request is denied
+        let enforcing = CliDispatcher::with_policy(echo_policy("hello*", PolicyMode::Enforce));
+        assert!(enforcing.dispatch_cli(request.clone()).await.is_err());
+
+        // Audit-only lets it through, but logs the denial; the candidate
+        // allows it, which disagrees with the live rules
+        let dispatcher = CliDispatcher::with_policy(echo_policy("hello*", PolicyMode::AuditOnly))
+            .with_candidate_policy(echo_policy("*", PolicyMode::Enforce))
+            .with_audit_logger(audit_logger.clone());
+        dispatcher.audit_unenforced(&AuditContext::new(&request.id, &request.tool), &request);
+        let response = dispatcher.dispatch_cli(request).await.unwrap();
+        assert_eq!(response.stdout, "bye\n");
+
+        audit_logger.flush();
+        let entries: Vec<serde_json::Value> = std::fs::read_to_string(&log_file)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["action_type"], "audit_only");
+        assert_eq!(entries[0]["policy_result"], "deny");
+        assert_eq!(entries[0]["decision"]["rule"], "argv_allow");
+        assert_eq!(entries[0]["decision"]["matched"], "no allow matched");
+        assert_eq!(entries[1]["action_type"], "candidate_policy");
+        assert_eq!(entries[1]["policy_result"], "allow");
+    }
+
+    fn limited_dispatcher(
+        binary: &str,
+        max_stdout_bytes: u64,
+        action: OutputLimitAction,
+    ) -> CliDispatcher {
+        let mut policy = PolicyConfig {
+            tools: HashMap::new(),
+            mode: carapace_policy::PolicyMode::Enforce,
+            source: None,
+        };
+
+        policy.tools.insert(
+            "limited".to_string(),
+            carapace_policy::ToolPolicy::Cli(CliPolicy {
+                binary: binary.to_string(),
+                mode: None,
+                argv_allow_patterns: vec!["*".to_string()],
+                argv_deny_patterns: vec![],
+                env_passthrough: vec![],
+                env_inject: HashMap::new(),
+                cwd_allowed: None,
+                timeout_secs: 30,
+                max_stdout_bytes: Some(max_stdout_bytes),
+                max_stderr_bytes: None,
+                on_output_limit: action,
+                max_concurrent: None,
+                require_approval: None,
+                audit: carapace_policy::AuditConfig::default(),
+                interactive: false,
+                sandbox: None,
+            }),
+        );
+
+        CliDispatcher::with_policy(policy)
+    }
+
+    fn limited_request(id: &str, argv: &[&str]) -> CliRequest {
+        CliRequest {
+            id: id.to_string(),
+            tool: "limited".to_string(),
+            argv: argv.iter().map(|arg| arg.to_string()).collect(),
+            env: HashMap::new(),
+            stdin: None,
+            cwd: "/tmp".to_string(),
+            tty: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_output_truncated_at_limit() {
+        let dispatcher = limited_dispatcher("/usr/bin/seq", 100, OutputLimitAction::Truncate);
+        let response = dispatcher
+            .dispatch_cli(limited_request("limit-1", &["1", "100000"]))
+            .await
+            .unwrap();
+
+        // The command ran to completion; only the output was cut
+        assert_eq!(response.exit_code, 0);
+        assert_eq!(response.signal, None);
+        assert_eq!(response.stdout.len(), 100);
+        assert!(response.stdout.starts_with("1\n2\n3\n"));
+        assert!(response.stdout_truncated);
+        assert!(!response.stderr_truncated);
+
+        // Output within the limit is untouched
+        let response = dispatcher
+            .dispatch_cli(limited_request("limit-2", &["1", "3"]))
+            .await
+            .unwrap();
+        assert_eq!(response.stdout, "1\n2\n3\n");
+        assert!(!response.stdout_truncated);
+    }
+
+    #[tokio::test]
+    async fn test_output_limit_kills_process() {
+        let dispatcher = limited_dispatcher("/usr/bin/yes", 4096, OutputLimitAction::Kill);
+
+        // `yes` never exits on its own; the limit has to stop it
+        let start = std::time::Instant::now();
+        let response = dispatcher
+            .dispatch_cli(limited_request("limit-3", &[]))
+            .await
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(response.signal, Some(libc::SIGKILL));
+        assert!(!response.timed_out);
+        assert!(response.stdout_truncated);
+        assert_eq!(response.stdout.len(), 4096);
+    }
+
+    fn sleep_dispatcher(timeout_secs: u64) -> CliDispatcher {
+        let mut policy = PolicyConfig {
+            tools: HashMap::new(),
+            mode: carapace_policy::PolicyMode::Enforce,
+            source: None,
+        };
+
+        policy.tools.insert(
+            "sleep".to_string(),
+            carapace_policy::ToolPolicy::Cli(CliPolicy {
+                binary: "/bin/sleep".to_string(),
+                mode: None,
+                argv_allow_patterns: vec!["*".to_string()],
+                argv_deny_patterns: vec![],
+                env_passthrough: vec![],
+                env_inject: HashMap::new(),
+                cwd_allowed: None,
+                timeout_secs,
+                max_stdout_bytes: None,
+                max_stderr_bytes: None,
+                on_output_limit: carapace_policy::OutputLimitAction::Truncate,
+                max_concurrent: None,
+                require_approval: None,
+                audit: carapace_policy::AuditConfig::default(),
+                interactive: false,
+                sandbox: None,
+            }),
+        );
+
+        CliDispatcher::with_policy(policy)
+    }
+
+    fn sleep_request(id: &str) -> CliRequest {
+        CliRequest {
+            id: id.to_string(),
+            tool: "sleep".to_string(),
+            argv: vec!["30".to_string()],
+            env: HashMap::new(),
+            stdin: None,
+            cwd: "/tmp".to_string(),
+            tty: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timeout_reported_in_response() {
+        let dispatcher = sleep_dispatcher(1);
+
+        let resp = dispatcher
+            .dispatch_cli(sleep_request("timeout-1"))
+            .await
+            .expect("timeout should produce a response");
+        assert!(resp.timed_out);
+        assert_eq!(resp.signal, Some(libc::SIGKILL));
+        assert_eq!(resp.exit_code, -1);
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_signal_terminates_process() {
+        let dispatcher = std::sync::Arc::new(sleep_dispatcher(30));
+
+        let running = dispatcher.clone();
+        let handle = tokio::spawn(async move {
+            running
+                .dispatch_cli_with_cancel(
+                    "session-a",
+                    sleep_request("signal-1"),
+                    CancellationToken::new(),
+                )
+                .await
+        });
+
+        // Retry until the process has been spawned and registered. Another
+        // connection naming the same id reaches nothing.
+        let mut delivered = false;
+        for _ in 0..50 {
+            assert!(!dispatcher.signal("session-b", "signal-1", libc::SIGTERM));
+            if dispatcher.signal("session-a", "signal-1", libc::SIGTERM) {
+                delivered = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(delivered);
+
+        let resp = handle.await.unwrap().expect("dispatch failed");
+        assert!(!resp.timed_out);
+        assert_eq!(resp.signal, Some(libc::SIGTERM));
+        assert!(!dispatcher.signal("session-a", "signal-1", libc::SIGTERM));
+    }
+
+    #[test]
+    fn test_only_forwardable_signals_accepted() {
+        let dispatcher = CliDispatcher::new();
+        assert!(!dispatcher.signal("", "anything", libc::SIGKILL));
+    }
+
+    fn interactive_dispatcher(
+        tool: &str,
+        binary: &str,
+        transcript_dir: Option<String>,
+    ) -> CliDispatcher {
+        let mut policy = PolicyConfig {
+            tools: HashMap::new(),
+            mode: carapace_policy::PolicyMode::Enforce,
+            source: None,
+        };
+
+        policy.tools.insert(
+            tool.to_string(),
+            carapace_policy::ToolPolicy::Cli(CliPolicy {
+                binary: binary.to_string(),
+                mode: None,
+                argv_allow_patterns: vec!["*".to_string()],
+                argv_deny_patterns: vec![],
+                env_passthrough: vec![],
+                env_inject: HashMap::new(),
+                cwd_allowed: None,
+                timeout_secs: 10,
+                max_stdout_bytes: None,
+                max_stderr_bytes: None,
+                on_output_limit: carapace_policy::OutputLimitAction::Truncate,
+                max_concurrent: None,
+                require_approval: None,
+                interactive: true,
+                sandbox: None,
+                audit: carapace_policy::AuditConfig {
+                    transcript_dir,
+                    ..Default::default()
+                },
+            }),
+        );
+
+        CliDispatcher::with_policy(policy)
+    }
+
+    fn tty_request(id: &str, tool: &str) -> CliRequest {
+        CliRequest {
+            id: id.to_string(),
+            tool: tool.to_string(),
+            argv: vec![],
+            env: HashMap::new(),
+            stdin: None,
+            cwd: "/tmp".to_string(),
+            tty: Some(carapace_protocol::TtySize { rows: 24, cols: 80 }),
+        }
+    }
+
+    /// Concatenate the terminal output of a finished session
+    fn pty_output(rx: &mut tokio::sync::mpsc::UnboundedReceiver<Message>) -> String {
+        let mut output = Vec::new();
+        while let Ok(msg) = rx.try_recv() {
+            if let Message::PtyData(data) = msg {
+                output.extend(data.bytes().unwrap());
+            }
+        }
+        String::from_utf8_lossy(&output).into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_interactive_session_has_terminal() {
+        let dispatcher = interactive_dispatcher("tty", "/usr/bin/tty", None);
+        let req = tty_request("pty-1", "tty");
+        assert!(dispatcher.is_interactive(&req));
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let session = dispatcher
+            .dispatch_interactive("", req, tx, CancellationToken::new())
+            .await
+            .expect("session failed");
+        assert_eq!(session.response.exit_code, 0);
+        assert!(session.transcript.is_none());
+        assert!(pty_output(&mut rx).contains("/dev/pts/"));
+    }
+
+    #[tokio::test]
+    async fn test_interactive_input_and_transcript() {
+        let dir = tempfile::tempdir().unwrap();
+        let dispatcher = std::sync::Arc::new(interactive_dispatcher(
+            "cat",
+            "/bin/cat",
+            Some(dir.path().to_string_lossy().to_string()),
+        ));
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let running = dispatcher.clone();
+        let handle = tokio::spawn(async move {
+            running
+                .dispatch_interactive(
+                    "",
+                    tty_request("pty-2", "cat"),
+                    tx,
+                    CancellationToken::new(),
+                )
+                .await
+        });
+
+        // Retry until the session has started
+        let mut delivered = false;
+        for _ in 0..50 {
+            if dispatcher.pty_input("", "pty-2", PtyInput::Data(b"hello\n".to_vec())) {
+                delivered = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(delivered);
+        // Ctrl-D ends cat's input
+        assert!(dispatcher.pty_input("", "pty-2", PtyInput::Data(vec![0x04])));
+
+        let session = handle.await.unwrap().expect("session failed");
+        assert_eq!(session.response.exit_code, 0);
+        assert!(pty_output(&mut rx).contains("hello"));
+
+        let transcript = session.transcript.expect("transcript path");
+        assert!(transcript.starts_with(dir.path()));
+        let recorded = std::fs::read_to_string(transcript).unwrap();
+        assert!(recorded.contains("hello"));
+    }
+
+    #[test]
+    fn test_not_interactive_without_terminal_or_policy() {
+        let dispatcher = interactive_dispatcher("tty", "/usr/bin/tty", None);
+        let mut req = tty_request("pty-3", "tty");
+        req.tty = None;
+        assert!(!dispatcher.is_interactive(&req));
+
+        let dispatcher = sleep_dispatcher(30);
+        assert!(!dispatcher.is_interactive(&tty_request("pty-4", "sleep")));
+    }
+
+    fn sandboxed_dispatcher(
+        binary: &str,
+        sandbox: carapace_policy::SandboxPolicy,
+    ) -> CliDispatcher {
+        let mut policy = PolicyConfig {
+            tools: HashMap::new(),
+            mode: carapace_policy::PolicyMode::Enforce,
+            source: None,
+        };
+
+        policy.tools.insert(
+            "boxed".to_string(),
+            carapace_policy::ToolPolicy::Cli(CliPolicy {
+                binary: binary.to_string(),
+                mode: None,
+                argv_allow_patterns: vec!["*".to_string()],
+                argv_deny_patterns: vec![],
+                env_passthrough: vec!["FOO".to_string()],
+                env_inject: HashMap::new(),
+                cwd_allowed: None,
+                timeout_secs: 10,
+                max_stdout_bytes: None,
+                max_stderr_bytes: None,
+                on_output_limit: carapace_policy::OutputLimitAction::Truncate,
+                max_concurrent: None,
+                require_approval: None,
+                audit: carapace_policy::AuditConfig::default(),
+                interactive: false,
+                sandbox: Some(sandbox),
+            }),
+        );
+
+        CliDispatcher::with_policy(policy)
+    }
+
+    fn boxed_request(id: &str, argv: &[&str]) -> CliRequest {
+        CliRequest {
+            id: id.to_string(),
+            tool: "boxed".to_string(),
+            argv: argv.iter().map(|arg| arg.to_string()).collect(),
+            env: HashMap::new(),
+            stdin: None,
+            cwd: "/tmp".to_string(),
+            tty: None,
+        }
+    }
+
+    /// User switching and namespaces need root; skip those tests otherwise
+    fn is_root() -> bool {
+        // SAFETY: geteuid has no preconditions
+        unsafe { libc::geteuid() == 0 }
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_clears_environment() {
+        let dispatcher = sandboxed_dispatcher(
+            "/usr/bin/env",
+            carapace_policy::SandboxPolicy {
+                clear_env: true,
+                env_allow: vec!["PATH".to_string()],
+                ..Default::default()
+            },
+        );
+        let mut req = boxed_request("sandbox-env", &[]);
+        req.env.insert("FOO".to_string(), "bar".to_string());
+
+        let response = dispatcher.dispatch_cli(req).await.unwrap();
+        let mut vars: Vec<&str> = response
+            .stdout
+            .lines()
+            .filter_map(|line| line.split('=').next())
+            .collect();
+        vars.sort_unstable();
+        assert_eq!(vars, ["FOO", "PATH"]);
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_rlimits() {
+        let dispatcher = sandboxed_dispatcher(
+            "/bin/cat",
+            carapace_policy::SandboxPolicy {
+                rlimits: carapace_policy::RlimitPolicy {
+                    cpu_secs: Some(5),
+                    file_size_bytes: Some(1 << 20),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        let req = boxed_request("sandbox-rlimits", &["/proc/self/limits"]);
+
+        let response = dispatcher.dispatch_cli(req).await.unwrap();
+        let limit = |name: &str| {
+            let line = response
+                .stdout
+                .lines()
+                .find(|line| line.starts_with(name))
+                .unwrap();
+            line[name.len()..]
+                .split_whitespace()
+                .take(2)
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        assert_eq!(limit("Max cpu time"), "5 5");
+        assert_eq!(limit("Max file size"), "1048576 1048576");
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_seccomp_denies_syscalls() {
+        let seccomp = carapace_policy::SeccompPolicy {
+            deny_dangerous: true,
+            deny_syscalls: vec!["uname".to_string()],
+        };
+        let dispatcher = sandboxed_dispatcher(
+            "/bin/uname",
+            carapace_policy::SandboxPolicy {
+                seccomp: Some(seccomp.clone()),
+                ..Default::default()
+            },
+        );
+        let response = dispatcher
+            .dispatch_cli(boxed_request("sandbox-seccomp-1", &[]))
+            .await
+            .unwrap();
+        assert_ne!(response.exit_code, 0);
+        assert!(response.stdout.is_empty());
+
+        // Filtered processes always have no_new_privs set
+        let dispatcher = sandboxed_dispatcher(
+            "/bin/cat",
+            carapace_policy::SandboxPolicy {
+                seccomp: Some(seccomp),
+                ..Default::default()
+            },
+        );
+        let response = dispatcher
+            .dispatch_cli(boxed_request("sandbox-seccomp-2", &["/proc/self/status"]))
+            .await
+            .unwrap();
+        assert!(response.stdout.contains("NoNewPrivs:\t1"));
+        assert!(response.stdout.contains("Seccomp:\t2"));
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_switches_user() {
+        if !is_root() {
+            return;
+        }
+        let dispatcher = sandboxed_dispatcher(
+            "/usr/bin/id",
+            carapace_policy::SandboxPolicy {
+                uid: Some(65534),
+                gid: Some(65534),
+                ..Default::default()
+            },
+        );
+        let response = dispatcher
+            .dispatch_cli(boxed_request("sandbox-user", &[]))
+            .await
+            .unwrap();
+        assert_eq!(response.exit_code, 0);
+        assert!(response.stdout.starts_with("uid=65534("));
+        assert!(response.stdout.contains(" gid=65534("));
+        // Supplementary groups are dropped
+        let groups = response.stdout.split(" groups=").nth(1).unwrap();
+        assert!(groups.starts_with("65534(") && !groups.contains(','));
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_network_namespace() {
+        if !is_root() {
+            return;
+        }
+        let dispatcher = sandboxed_dispatcher(
+            "/bin/cat",
+            carapace_policy::SandboxPolicy {
+                namespaces: carapace_policy::NamespacePolicy {
+                    network: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        let response = dispatcher
+            .dispatch_cli(boxed_request("sandbox-net", &["/proc/net/dev"]))
+            .await
+            .unwrap();
+        // Two header lines, then only the loopback device
+        let devices: Vec<&str> = response
+            .stdout
+            .lines()
+            .skip(2)
+            .filter_map(|line| line.split(':').next())
+            .map(str::trim)
+            .collect();
+        assert_eq!(devices, ["lo"]);
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_pid_namespace() {
+        if !is_root() {
+            return;
+        }
+        let namespaces = carapace_policy::NamespacePolicy {
+            mount: true,
+            pid: true,
+            ..Default::default()
+        };
+        let dispatcher = sandboxed_dispatcher(
+            "/bin/readlink",
+            carapace_policy::SandboxPolicy {
+                namespaces: namespaces.clone(),
+                ..Default::default()
+            },
+        );
+        let response = dispatcher
+            .dispatch_cli(boxed_request("sandbox-pid-1", &["/proc/self"]))
+            .await
+            .unwrap();
+        assert_eq!(response.exit_code, 0);
+        assert_eq!(response.stdout.trim(), "1");
+
+        // Forwarded signals still end the command, and are reported
+        let dispatcher = std::sync::Arc::new(sandboxed_dispatcher(
+            "/bin/sleep",
+            carapace_policy::SandboxPolicy {
+                namespaces,
+                ..Default::default()
+            },
+        ));
+        let running = dispatcher.clone();
+        let task = tokio::spawn(async move {
+            running
+                .dispatch_cli(boxed_request("sandbox-pid-2", &["30"]))
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(dispatcher.signal("", "sandbox-pid-2", libc::SIGTERM));
+
+        let response = tokio::time::timeout(Duration::from_secs(5), task)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(response.signal, Some(libc::SIGTERM));
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_read_only_bind_mount() {
+        if !is_root() {
+            return;
+        }
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("data"), "mounted\n").unwrap();
+
+        let sandbox = carapace_policy::SandboxPolicy {
+            namespaces: carapace_policy::NamespacePolicy {
+                mount: true,
+                ..Default::default()
+            },
+            bind_mounts: vec![carapace_policy::BindMount {
+                source: source.path().to_str().unwrap().to_string(),
+                target: target.path().to_str().unwrap().to_string(),
+                read_only: true,
+            }],
+            ..Default::default()
+        };
+        let data = target.path().join("data");
+        let data = data.to_str().unwrap();
+
+        let dispatcher = sandboxed_dispatcher("/bin/cat", sandbox.clone());
+        let response = dispatcher
+            .dispatch_cli(boxed_request("sandbox-bind-1", &[data]))
+            .await
+            .unwrap();
+        assert_eq!(response.stdout, "mounted\n");
+
+        let dispatcher = sandboxed_dispatcher("/usr/bin/touch", sandbox);
+        let response = dispatcher
+            .dispatch_cli(boxed_request("sandbox-bind-2", &[data]))
+            .await
+            .unwrap();
+        assert_ne!(response.exit_code, 0);
+        assert!(response.stderr.contains("Read-only file system"));
+
+        // The mount never appeared outside the tool's namespace
+        assert!(!target.path().join("data").exists());
+    }
 }
@@ -0,0 +1,325 @@
+use carapace_policy::{PolicyConfig, ToolPolicy};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::error::{Result, ServerError};
+
+/// Slots held by a running request, released when dropped
+pub struct ConcurrencyPermit {
+    _tool: Option<OwnedSemaphorePermit>,
+    _global: Option<OwnedSemaphorePermit>,
+}
+
+/// A concurrency cap and the requests waiting for it
+struct Slots {
+    semaphore: Arc<Semaphore>,
+    max_concurrent: usize,
+    queued: AtomicUsize,
+}
+
+impl Slots {
+    fn new(max_concurrent: usize) -> Self {
+        Slots {
+            semaphore: Arc::new(Semaphore::new(max_concurrent)),
+            max_concurrent,
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    fn stats(&self) -> SlotStats {
+        SlotStats {
+            max_concurrent: self.max_concurrent,
+            running: self.max_concurrent - self.semaphore.available_permits(),
+            queued: self.queued.load(Ordering::SeqCst),
+        }
+    }
+}
+
+/// Current use of a concurrency cap
+#[derive(Debug, Clone, Serialize)]
+pub struct SlotStats {
+    pub max_concurrent: usize,
+    pub running: usize,
+    pub queued: usize,
+}
+
+/// Snapshot of all caps and queues, for the debug endpoints
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueStats {
+    pub global: Option<SlotStats>,
+    pub tools: HashMap<String, SlotStats>,
+    /// Requests waiting for any slot
+    pub queued: usize,
+    pub max_queued: usize,
+    pub queue_timeout_secs: u64,
+}
+
+/// Limits how many requests run at once, per tool (`max_concurrent` in the
+/// policy) and across all tools. Requests over a limit wait in a bounded
+/// queue for up to the queue timeout.
+pub struct ConcurrencyLimiter {
+    tools: HashMap<String, Slots>,
+    global: Option<Slots>,
+    /// Requests waiting for a slot, across all tools
+    queued: AtomicUsize,
+    max_queued: usize,
+    queue_timeout: Duration,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(
+        policy: &PolicyConfig,
+        global_max_concurrent: Option<usize>,
+        max_queued: usize,
+        queue_timeout: Duration,
+    ) -> Self {
+        let tools = policy
+            .tools
+            .iter()
+            .filter_map(|(name, tool)| {
+                let max_concurrent = match tool {
+                    ToolPolicy::Cli(policy) => policy.max_concurrent,
+                    ToolPolicy::Http(policy) => policy.max_concurrent,
+                }?;
+                Some((name.clone(), Slots::new(max_concurrent.get() as usize)))
+            })
+            .collect();
+
+        ConcurrencyLimiter {
+            tools,
+            global: global_max_concurrent.map(Slots::new),
+            queued: AtomicUsize::new(0),
+            max_queued,
+            queue_timeout,
+        }
+    }
+
+    /// No limits at all
+    pub fn unlimited() -> Self {
+        Self::new(
+            &PolicyConfig {
+                tools: HashMap::new(),
+                mode: carapace_policy::PolicyMode::Enforce,
+                source: None,
+            },
+            None,
+            0,
+            Duration::ZERO,
+        )
+    }
+
+    /// Wait for a slot for `tool`. Fails with `ToolBusy` if the queue is
+    /// full and `QueueTimeout` if no slot frees up in time.
+    pub async fn acquire(&self, tool: &str) -> Result<ConcurrencyPermit> {
+        let slots = self.tools.get(tool);
+        if let Some(permit) = self.try_acquire(slots) {
+            return Ok(permit);
+        }
+
+        if self.queued.fetch_add(1, Ordering::SeqCst) >= self.max_queued {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            return Err(ServerError::ToolBusy {
+                tool: tool.to_string(),
+            });
+        }
+        let _queued = QueuedGuard::new(&self.queued, slots, self.global.as_ref());
+
+        tokio::time::timeout(self.queue_timeout, self.acquire_waiting(slots))
+            .await
+            .map_err(|_| ServerError::QueueTimeout {
+                tool: tool.to_string(),
+            })?
+    }
+
+    fn try_acquire(&self, slots: Option<&Slots>) -> Option<ConcurrencyPermit> {
+        let tool = match slots {
+            Some(slots) => Some(slots.semaphore.clone().try_acquire_owned().ok()?),
+            None => None,
+        };
+        let global = match &self.global {
+            Some(global) => Some(global.semaphore.clone().try_acquire_owned().ok()?),
+            None => None,
+        };
+        Some(ConcurrencyPermit {
+            _tool: tool,
+            _global: global,
+        })
+    }
+
+    async fn acquire_waiting(&self, slots: Option<&Slots>) -> Result<ConcurrencyPermit> {
+        // Tool slot first, so a request never holds a global slot while
+        // waiting for its tool
+        let tool = match slots {
+            Some(slots) => Some(acquire_owned(&slots.semaphore).await?),
+            None => None,
+        };
+        let global = match &self.global {
+            Some(global) => Some(acquire_owned(&global.semaphore).await?),
+            None => None,
+        };
+        Ok(ConcurrencyPermit {
+            _tool: tool,
+            _global: global,
+        })
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        QueueStats {
+            global: self.global.as_ref().map(Slots::stats),
+            tools: self
+                .tools
+                .iter()
+                .map(|(name, slots)| (name.clone(), slots.stats()))
+                .collect(),
+            queued: self.queued.load(Ordering::SeqCst),
+            max_queued: self.max_queued,
+            queue_timeout_secs: self.queue_timeout.as_secs(),
+        }
+    }
+}
+
+async fn acquire_owned(semaphore: &Arc<Semaphore>) -> Result<OwnedSemaphorePermit> {
+    semaphore
+        .clone()
+        .acquire_owned()
+        .await
+        .map_err(|e| ServerError::Other(e.to_string()))
+}
+
+/// Counts a request as queued until it gets its slots or gives up
+struct QueuedGuard<'a> {
+    counters: [Option<&'a AtomicUsize>; 3],
+}
+
+impl<'a> QueuedGuard<'a> {
+    fn new(total: &'a AtomicUsize, tool: Option<&'a Slots>, global: Option<&'a Slots>) -> Self {
+        let counters = [
+            Some(total),
+            tool.map(|slots| &slots.queued),
+            global.map(|slots| &slots.queued),
+        ];
+        // The total was already counted by the caller
+        for counter in counters[1..].iter().flatten() {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }
+        QueuedGuard { counters }
+    }
+}
+
+impl Drop for QueuedGuard<'_> {
+    fn drop(&mut self) {
+        for counter in self.counters.iter().flatten() {
+            counter.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use carapace_policy::CliPolicy;
+
+    fn limiter(
+        max_concurrent: u32,
+        max_queued: usize,
+        queue_timeout: Duration,
+    ) -> ConcurrencyLimiter {
+        let mut policy = PolicyConfig {
+            tools: HashMap::new(),
+            mode: carapace_policy::PolicyMode::Enforce,
+            source: None,
+        };
+        policy.tools.insert(
+            "op".to_string(),
+            ToolPolicy::Cli(CliPolicy {
+                binary: "/usr/bin/op".to_string(),
+                mode: None,
+                argv_allow_patterns: vec![],
+                argv_deny_patterns: vec![],
+                env_passthrough: vec![],
+                env_inject: HashMap::new(),
+                cwd_allowed: None,
+                timeout_secs: 30,
+                max_stdout_bytes: None,
+                max_stderr_bytes: None,
+                on_output_limit: carapace_policy::OutputLimitAction::Truncate,
+                max_concurrent: std::num::NonZeroU32::new(max_concurrent),
+                require_approval: None,
+                interactive: false,
+                sandbox: None,
+                audit: carapace_policy::AuditConfig::default(),
+            }),
+        );
+        ConcurrencyLimiter::new(&policy, None, max_queued, queue_timeout)
+    }
+
+    #[tokio::test]
+    async fn test_waits_for_free_slot() {
+        let limiter = Arc::new(limiter(1, 4, Duration::from_secs(5)));
+        let first = limiter.acquire("op").await.unwrap();
+
+        let waiting = limiter.clone();
+        let second = tokio::spawn(async move { waiting.acquire("op").await.map(|_| ()) });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let stats = limiter.stats();
+        assert_eq!(stats.tools["op"].running, 1);
+        assert_eq!(stats.tools["op"].queued, 1);
+        assert_eq!(stats.queued, 1);
+
+        drop(first);
+        second.await.unwrap().unwrap();
+        assert_eq!(limiter.stats().queued, 0);
+
+        // Tools without a limit never wait
+        let _other = limiter.acquire("gh").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_is_busy() {
+        let limiter = Arc::new(limiter(1, 1, Duration::from_secs(5)));
+        let _running = limiter.acquire("op").await.unwrap();
+
+        let waiting = limiter.clone();
+        let _queued = tokio::spawn(async move { waiting.acquire("op").await.map(|_| ()) });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let err = limiter.acquire("op").await.err().unwrap();
+        assert!(matches!(err, ServerError::ToolBusy { .. }));
+        assert_eq!(limiter.stats().queued, 1);
+    }
+
+    #[tokio::test]
+    async fn test_queue_timeout() {
+        let limiter = limiter(1, 4, Duration::from_millis(100));
+        let _running = limiter.acquire("op").await.unwrap();
+
+        let err = limiter.acquire("op").await.err().unwrap();
+        assert!(matches!(err, ServerError::QueueTimeout { .. }));
+        assert_eq!(limiter.stats().tools["op"].queued, 0);
+    }
+
+    #[tokio::test]
+    async fn test_global_cap() {
+        let limiter = ConcurrencyLimiter::new(
+            &PolicyConfig {
+                tools: HashMap::new(),
+                mode: carapace_policy::PolicyMode::Enforce,
+                source: None,
+            },
+            Some(1),
+            0,
+            Duration::from_secs(5),
+        );
+        let _running = limiter.acquire("gh").await.unwrap();
+        // No queue: the next request is turned away at once
+        assert!(matches!(
+            limiter.acquire("op").await,
+            Err(ServerError::ToolBusy { .. })
+        ));
+        assert_eq!(limiter.stats().global.unwrap().running, 1);
+    }
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::Path;
 
 use crate::error::{Result, ServerError};
@@ -12,6 +13,7 @@ pub struct ServerConfig {
     pub audit: AuditConfig,
 
     /// Rate limiting configuration
+    #[serde(default)]
     pub rate_limiting: RateLimitingConfig,
 }
 
@@ -39,6 +41,76 @@ pub struct AuditConfig {
     /// Sensitive patterns to redact
     #[serde(default = "default_redact_patterns")]
     pub redact_patterns: Vec<String>,
+
+    /// Where entries are exported besides the log file
+    #[serde(default)]
+    pub sinks: Vec<AuditSinkConfig>,
+}
+
+/// An export destination for audit entries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuditSinkConfig {
+    /// RFC 5424 syslog
+    Syslog {
+        #[serde(default)]
+        transport: SyslogTransport,
+
+        /// `host:port` for UDP and TCP, a socket path for unix
+        address: String,
+
+        /// Facility name, e.g. `auth` or `local0`
+        #[serde(default = "default_syslog_facility")]
+        facility: String,
+
+        #[serde(default = "default_app_name")]
+        app_name: String,
+    },
+
+    /// Structured fields in the systemd journal
+    Journald {
+        #[serde(default = "default_journald_socket")]
+        socket: String,
+    },
+
+    /// Batches of entries POSTed as a JSON array
+    Webhook {
+        url: String,
+
+        /// Extra request headers, e.g. `Authorization`
+        #[serde(default)]
+        headers: HashMap<String, String>,
+
+        /// Entries per POST
+        #[serde(default = "default_webhook_batch_size")]
+        batch_size: usize,
+
+        /// Longest an entry waits for its batch to fill
+        #[serde(default = "default_webhook_flush_interval_secs")]
+        flush_interval_secs: u64,
+
+        #[serde(default = "default_webhook_timeout_secs")]
+        timeout_secs: u64,
+
+        /// Batches that could not be delivered wait here for the next retry
+        spool_file: Option<String>,
+
+        /// Spool size past which undeliverable batches are dropped
+        #[serde(default = "default_max_spool_bytes")]
+        max_spool_bytes: u64,
+    },
+}
+
+/// How syslog messages are sent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogTransport {
+    #[default]
+    Udp,
+    /// Octet-counted framing (RFC 6587)
+    Tcp,
+    /// A local datagram socket such as `/dev/log`
+    Unix,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -93,6 +165,7 @@ impl ServerConfig {
                 keep_logs: 10,
                 log_argv: true,
                 redact_patterns: default_redact_patterns(),
+                sinks: vec![],
             },
             rate_limiting: RateLimitingConfig {
                 enabled: std::env::var("CARAPACE_RATE_LIMIT_ENABLED")
@@ -107,6 +180,17 @@ impl ServerConfig {
     }
 }
 
+impl Default for RateLimitingConfig {
+    fn default() -> Self {
+        RateLimitingConfig {
+            enabled: false,
+            default_requests_per_window: default_requests_per_window(),
+            default_window_secs: default_window_secs(),
+            per_tool_limits: vec![],
+        }
+    }
+}
+
 impl Default for ServerConfig {
     fn default() -> Self {
         Self::from_env()
@@ -130,6 +214,34 @@ fn default_window_secs() -> u64 {
     60
 }
 
+fn default_syslog_facility() -> String {
+    "auth".to_string()
+}
+
+fn default_app_name() -> String {
+    "carapace-server".to_string()
+}
+
+fn default_journald_socket() -> String {
+    "/run/systemd/journal/socket".to_string()
+}
+
+fn default_webhook_batch_size() -> usize {
+    100
+}
+
+fn default_webhook_flush_interval_secs() -> u64 {
+    5
+}
+
+fn default_webhook_timeout_secs() -> u64 {
+    10
+}
+
+fn default_max_spool_bytes() -> u64 {
+    100 * 1024 * 1024 // 100MB
+}
+
 fn default_redact_patterns() -> Vec<String> {
     vec![
         "--token".to_string(),
@@ -150,6 +262,46 @@ mod tests {
         assert!(!cfg.policy_path.is_empty());
     }
 
+    #[test]
+    fn test_audit_sinks_from_yaml() {
+        let cfg: ServerConfig = serde_yaml::from_str(
+            r#"
+policy_path: /etc/carapace/policy.yaml
+audit:
+  log_file: /var/log/carapace/audit.log
+  sinks:
+    - type: syslog
+      transport: tcp
+      address: "siem.example.com:6514"
+    - type: journald
+    - type: webhook
+      url: https://siem.example.com/ingest
+      spool_file: /var/spool/carapace/webhook.jsonl
+"#,
+        )
+        .unwrap();
+        assert_eq!(cfg.audit.sinks.len(), 3);
+        match &cfg.audit.sinks[0] {
+            AuditSinkConfig::Syslog {
+                transport,
+                facility,
+                ..
+            } => {
+                assert_eq!(*transport, SyslogTransport::Tcp);
+                assert_eq!(facility, "auth");
+            }
+            other => panic!("Expected syslog, got {:?}", other),
+        }
+        assert!(matches!(
+            &cfg.audit.sinks[2],
+            AuditSinkConfig::Webhook {
+                batch_size: 100,
+                ..
+            }
+        ));
+        assert_eq!(cfg.rate_limiting.default_window_secs, 60);
+    }
+
     #[test]
     fn test_config_defaults() {
         let cfg = ServerConfig::default();
@@ -2,15 +2,20 @@ use anyhow::Result;
 use serde_json::json;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{TcpListener, TcpStream};
 
-use crate::ConnectionTracker;
+use crate::approval::Approvers;
+use crate::{ApprovalQueue, ConcurrencyLimiter, ConnectionTracker};
 
-/// Start HTTP debug server on specified address
+/// Start HTTP debug server on specified address. Approving or denying held
+/// requests takes a token from `approvers`; without it nobody can.
 pub async fn start_debug_server(
     addr: SocketAddr,
     connection_tracker: Arc<ConnectionTracker>,
+    concurrency: Arc<ConcurrencyLimiter>,
+    approvals: Arc<ApprovalQueue>,
+    approvers: Option<Arc<Approvers>>,
 ) -> Result<()> {
     let listener = TcpListener::bind(addr).await?;
 
@@ -19,8 +24,12 @@ pub async fn start_debug_server(
     loop {
         let (socket, _) = listener.accept().await?;
         let tracker = connection_tracker.clone();
+        let concurrency = concurrency.clone();
+        let approvals = approvals.clone();
+        let approvers = approvers.clone();
         tokio::spawn(async move {
-            if let Err(e) = handle_client(socket, tracker).await {
+            if let Err(e) = handle_client(socket, tracker, concurrency, approvals, approvers).await
+            {
                 eprintln!("Error handling debug client: {}", e);
             }
         });
@@ -31,6 +40,9 @@ pub async fn start_debug_server(
 async fn handle_client(
     socket: TcpStream,
     connection_tracker: Arc<ConnectionTracker>,
+    concurrency: Arc<ConcurrencyLimiter>,
+    approvals: Arc<ApprovalQueue>,
+    approvers: Option<Arc<Approvers>>,
 ) -> Result<()> {
     let (reader, mut writer) = socket.into_split();
     let mut bufreader = BufReader::new(reader);
@@ -51,21 +63,45 @@ async fn handle_client(
     // Read headers until blank line
     let mut headers = String::new();
     let mut line = String::new();
+    let mut content_length = 0;
+    let mut token = None;
     loop {
         line.clear();
         bufreader.read_line(&mut line).await?;
         if line == "\n" || line == "\r\n" {
             break;
         }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            } else if name.eq_ignore_ascii_case("authorization") {
+                token = value.trim().strip_prefix("Bearer ").map(str::to_string);
+            }
+        }
         headers.push_str(&line);
     }
 
+    // Request bodies are small JSON documents
+    let mut body = vec![0u8; content_length.min(64 * 1024)];
+    bufreader.read_exact(&mut body).await?;
+
     // Generate response based on path
     let response = match (method, path) {
         ("GET", "/debug/health") => create_json_response(handle_health(&connection_tracker).await),
         ("GET", "/debug/connections") => {
             create_json_response(handle_connections(&connection_tracker).await)
         }
+        ("GET", "/debug/queues") => create_json_response(handle_queues(&concurrency)),
+        ("GET", "/debug/approvals") => create_json_response(json!({
+            "pending": approvals.pending()
+        })),
+        ("POST", _) if path.starts_with("/debug/approvals/") => handle_decision(
+            &approvals,
+            approvers.as_deref(),
+            token.as_deref(),
+            path,
+            &body,
+        ),
         _ => create_not_found_response(path),
     };
 
@@ -117,6 +153,71 @@ async fn handle_connections(connection_tracker: &ConnectionTracker) -> serde_jso
     })
 }
 
+/// Handle GET /debug/queues
+fn handle_queues(concurrency: &ConcurrencyLimiter) -> serde_json::Value {
+    json!(concurrency.stats())
+}
+
+/// Handle POST /debug/approvals/{approval_id}/approve and .../deny. The
+/// operator is whoever holds the bearer token; a JSON body of
+/// `{"approver": "alice"}` must name that same operator.
+fn handle_decision(
+    approvals: &ApprovalQueue,
+    approvers: Option<&Approvers>,
+    token: Option<&str>,
+    path: &str,
+    body: &[u8],
+) -> String {
+    let route = path.trim_start_matches("/debug/approvals/");
+    let (approval_id, approved) = match route.rsplit_once('/') {
+        Some((id, "approve")) => (id, true),
+        Some((id, "deny")) => (id, false),
+        _ => return create_not_found_response(path),
+    };
+
+    let Some(approvers) = approvers else {
+        return create_error_response(
+            "403 Forbidden",
+            "No approvers are configured (CARAPACE_APPROVERS_FILE)",
+        );
+    };
+    let Some(approver) = token.and_then(|token| approvers.identify(token)) else {
+        return create_error_response("401 Unauthorized", "Missing or unknown approver token");
+    };
+    let claimed = serde_json::from_slice::<serde_json::Value>(body)
+        .ok()
+        .and_then(|v| v.get("approver")?.as_str().map(str::to_string));
+    if claimed.is_some_and(|claimed| claimed != approver) {
+        return create_error_response("403 Forbidden", "Token belongs to another approver");
+    }
+
+    if !approvals.decide(approval_id, approved, approver) {
+        return create_error_response("404 Not Found", "No pending request with this approval id");
+    }
+    tracing::info!(
+        "Approval {} {} by {}",
+        approval_id,
+        if approved { "approved" } else { "denied" },
+        approver
+    );
+    create_json_response(json!({
+        "approval_id": approval_id,
+        "approved": approved,
+        "approver": approver
+    }))
+}
+
+/// Create an error response with a JSON body
+fn create_error_response(status: &str, message: &str) -> String {
+    let body = json!({"error": message}).to_string();
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
 /// Create a 404 Not Found response
 fn create_not_found_response(path: &str) -> String {
     let body = json!({"error": "Not found", "path": path}).to_string();
@@ -126,3 +227,80 @@ fn create_not_found_response(path: &str) -> String {
         body
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approval::{ApprovalOutcome, PendingApproval};
+    use crate::audit::AuditLogger;
+    use carapace_protocol::CliRequest;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    const TOKEN: &str = "0123456789abcdef0123";
+
+    fn hold(
+        queue: &Arc<ApprovalQueue>,
+        id: &str,
+    ) -> (String, tokio::task::JoinHandle<ApprovalOutcome>) {
+        let pending = PendingApproval::cli(
+            &CliRequest {
+                id: id.to_string(),
+                tool: "op".to_string(),
+                argv: vec!["item".to_string(), "get".to_string()],
+                env: HashMap::new(),
+                stdin: None,
+                cwd: "/tmp".to_string(),
+                tty: None,
+            },
+            None,
+            25,
+            &AuditLogger::new(),
+        );
+        let approval_id = pending.approval_id.clone();
+        let queue = queue.clone();
+        let held =
+            tokio::spawn(async move { queue.request(pending, Duration::from_secs(5)).await });
+        (approval_id, held)
+    }
+
+    #[tokio::test]
+    async fn test_decisions_need_an_approver_token() {
+        let queue = Arc::new(ApprovalQueue::new());
+        let approvers = Approvers::parse(&format!("alice {}\n", TOKEN)).unwrap();
+        let (approval_id, held) = hold(&queue, "r1");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let path = &format!("/debug/approvals/{}/approve", approval_id);
+
+        // Decisions name the approval, not the client's request id
+        let response = handle_decision(
+            &queue,
+            Some(&approvers),
+            Some(TOKEN),
+            "/debug/approvals/r1/approve",
+            b"",
+        );
+        assert!(response.starts_with("HTTP/1.1 404"));
+
+        let response = handle_decision(&queue, None, Some(TOKEN), path, b"");
+        assert!(response.starts_with("HTTP/1.1 403"));
+        let response = handle_decision(&queue, Some(&approvers), None, path, b"");
+        assert!(response.starts_with("HTTP/1.1 401"));
+        let response = handle_decision(&queue, Some(&approvers), Some("guess"), path, b"");
+        assert!(response.starts_with("HTTP/1.1 401"));
+        // The body cannot name someone else
+        let body = br#"{"approver": "mallory"}"#;
+        let response = handle_decision(&queue, Some(&approvers), Some(TOKEN), path, body);
+        assert!(response.starts_with("HTTP/1.1 403"));
+        assert_eq!(queue.pending().len(), 1);
+
+        let response = handle_decision(&queue, Some(&approvers), Some(TOKEN), path, b"");
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert_eq!(
+            held.await.unwrap(),
+            ApprovalOutcome::Approved {
+                approver: "alice".to_string()
+            }
+        );
+    }
+}
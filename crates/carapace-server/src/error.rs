@@ -20,6 +20,18 @@ pub enum ServerError {
     #[error("Rate limit exceeded for tool {tool}")]
     RateLimitExceeded { tool: String },
 
+    #[error("Too many requests waiting for tool {tool}")]
+    ToolBusy { tool: String },
+
+    #[error("Timed out waiting for a free slot for tool {tool}")]
+    QueueTimeout { tool: String },
+
+    #[error("Request for tool {tool} denied by {approver}")]
+    ApprovalDenied { tool: String, approver: String },
+
+    #[error("Request for tool {tool} was not approved in time")]
+    ApprovalTimeout { tool: String },
+
     #[error("Tool not found in policy: {0}")]
     ToolNotFound(String),
 
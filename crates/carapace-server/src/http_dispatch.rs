@@ -1,31 +1,52 @@
-use carapace_policy::{HttpPolicy, PolicyConfig, PolicyValidator};
-use carapace_protocol::{HttpRequest, HttpResponse, Message, SseEvent};
+use carapace_policy::{ApprovalRule, Decision, HttpPolicy, PolicyConfig};
+use carapace_protocol::{HttpRequest, HttpResponse, Message, PayloadEncoding, SseEvent};
 use reqwest::Client;
 use std::collections::HashMap;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+use crate::audit::{AuditContext, AuditLogger};
 
 /// HTTP request dispatcher with policy enforcement
 pub struct HttpDispatcher {
     policy: PolicyConfig,
+    /// Policy evaluated next to the live one; only disagreements are logged
+    candidate: Option<PolicyConfig>,
+    audit_logger: Arc<AuditLogger>,
     client: Client,
 }
 
 impl HttpDispatcher {
     pub fn new() -> Self {
-        HttpDispatcher {
-            policy: PolicyConfig {
-                tools: HashMap::new(),
-            },
-            client: Client::new(),
-        }
+        Self::with_policy(PolicyConfig {
+            tools: HashMap::new(),
+            mode: carapace_policy::PolicyMode::Enforce,
+            source: None,
+        })
     }
 
     pub fn with_policy(policy: PolicyConfig) -> Self {
         HttpDispatcher {
             policy,
+            candidate: None,
+            audit_logger: Arc::new(AuditLogger::new()),
             client: Client::new(),
         }
     }
 
+    /// Evaluate requests against `candidate` too, and audit where it
+    /// decides differently from the live policy
+    pub fn with_candidate_policy(mut self, candidate: PolicyConfig) -> Self {
+        self.candidate = Some(candidate);
+        self
+    }
+
+    /// Where decisions that are not enforced get audited
+    pub fn with_audit_logger(mut self, audit_logger: Arc<AuditLogger>) -> Self {
+        self.audit_logger = audit_logger;
+        self
+    }
+
     /// Dispatch an HTTP request, validate against policy, and proxy to upstream
     ///
     /// For SSE endpoints, sends SseEvent messages through sse_event_tx and returns None
@@ -35,44 +56,108 @@ impl HttpDispatcher {
         req: HttpRequest,
         sse_event_tx: Option<tokio::sync::mpsc::UnboundedSender<Message>>,
     ) -> anyhow::Result<Option<HttpResponse>> {
-        // Check if tool is allowed in policy
-        let tool_config = self
-            .policy
-            .tools
-            .get(&req.tool)
-            .ok_or_else(|| anyhow::anyhow!("Tool '{}' not in policy", req.tool))?;
-
-        // Get HTTP policy
-        let http_policy = match tool_config {
-            carapace_policy::ToolPolicy::Http(policy) => policy,
-            carapace_policy::ToolPolicy::Cli(_) => {
-                return Err(anyhow::anyhow!(
-                    "Tool '{}' is CLI-only, cannot handle HTTP request",
-                    req.tool
-                ))
-            }
+        self.dispatch_http_with_cancel(req, sse_event_tx, CancellationToken::new())
+            .await
+    }
+
+    /// Like `dispatch_http`, but aborts the upstream request (including an
+    /// SSE stream) when `cancel` fires
+    pub async fn dispatch_http_with_cancel(
+        &self,
+        req: HttpRequest,
+        sse_event_tx: Option<tokio::sync::mpsc::UnboundedSender<Message>>,
+        cancel: CancellationToken,
+    ) -> anyhow::Result<Option<HttpResponse>> {
+        tokio::select! {
+            result = self.dispatch_http_inner(req, sse_event_tx) => result,
+            // Dropping the in-flight future aborts the reqwest request
+            _ = cancel.cancelled() => Err(anyhow::anyhow!("Request cancelled by client")),
+        }
+    }
+
+    async fn dispatch_http_inner(
+        &self,
+        req: HttpRequest,
+        sse_event_tx: Option<tokio::sync::mpsc::UnboundedSender<Message>>,
+    ) -> anyhow::Result<Option<HttpResponse>> {
+        let (http_policy, body) = self.authorize(&req)?;
+
+        // Send request to upstream
+        let response = self
+            .proxy_to_upstream(http_policy, &req, body, sse_event_tx)
+            .await?;
+
+        Ok(response)
+    }
+
+    /// The live policy's decision on this request; none if its body cannot
+    /// be decoded, which fails dispatch whatever the rules say
+    pub fn decide(&self, req: &HttpRequest) -> Option<Decision> {
+        let body = req.body_bytes().ok()?;
+        Some(self.policy.decide_http(&req.tool, body.as_deref()))
+    }
+
+    /// Whether the live policy enforces `decision`, or only audits it
+    pub fn enforces(&self, decision: &Decision) -> bool {
+        self.policy.enforces(decision)
+    }
+
+    /// The approval rule holding this request, if the policy allows it but
+    /// requires an operator's approval first
+    pub fn approval_rule(&self, req: &HttpRequest) -> Option<&ApprovalRule> {
+        let (http_policy, body) = self.authorize(req).ok()?;
+        let rule = http_policy.require_approval.as_ref()?;
+        let body = String::from_utf8(body?).ok()?;
+        let json: serde_json::Value = serde_json::from_str(&body).ok()?;
+        let method = json.get("method")?.as_str()?;
+        rule.matches_jsonrpc(method, &body).then_some(rule)
+    }
+
+    /// Audit the rule decisions that are not enforced: violations let
+    /// through in audit-only mode, and disagreements with the candidate
+    /// policy. Call before dispatching a request the live policy let through.
+    pub fn audit_unenforced(&self, ctx: &AuditContext, req: &HttpRequest) {
+        // Undecodable bodies are rejected whatever the rules say
+        let Ok(body) = req.body_bytes() else {
+            return;
         };
+        let live = self.policy.decide_http(&req.tool, body.as_deref());
+        if !live.allowed && !self.policy.enforces(&live) {
+            tracing::warn!("Audit-only: would deny request {}: {}", req.id, live);
+            self.audit_logger.log_audit_only_denial(ctx, &live);
+        }
 
-        // Validate JSON-RPC method if present
-        if let Some(body) = &req.body {
-            if let Ok(json) = serde_json::from_str::<serde_json::Value>(body) {
-                if let Some(method) = json.get("method").and_then(|v| v.as_str()) {
-                    // Validate method name
-                    PolicyValidator::validate_jsonrpc_method(
-                        method,
-                        &http_policy.jsonrpc_allow_methods,
-                        &http_policy.jsonrpc_deny_methods,
-                    )?;
-
-                    // Validate params (e.g., phone numbers)
-                    PolicyValidator::validate_jsonrpc_params(
-                        method,
-                        body,
-                        &http_policy.jsonrpc_param_filters,
-                    )?;
-                }
+        if let Some(candidate) = &self.candidate {
+            let candidate = candidate.decide_http(&req.tool, body.as_deref());
+            if live.allowed != candidate.allowed {
+                tracing::warn!(
+                    "Candidate policy disagrees on request {} for tool '{}'",
+                    req.id,
+                    req.tool
+                );
+                self.audit_logger
+                    .log_candidate_disagreement(ctx, &live, &candidate);
             }
         }
+    }
+
+    /// Check the request against the tool's HTTP policy and return the policy
+    /// with the decoded request body. Policy denials fail with the
+    /// `Decision` behind them.
+    fn authorize(&self, req: &HttpRequest) -> anyhow::Result<(&HttpPolicy, Option<Vec<u8>>)> {
+        // Bodies may arrive base64-encoded; policy checks run on the raw bytes
+        let body = req.body_bytes()?;
+
+        let decision = self.policy.decide_http(&req.tool, body.as_deref());
+        // Requests for anything but an HTTP tool are always denied
+        let http_policy = match self.policy.tools.get(&req.tool) {
+            Some(carapace_policy::ToolPolicy::Http(http_policy))
+                if !self.policy.enforces(&decision) =>
+            {
+                http_policy
+            }
+            _ => return Err(decision.into()),
+        };
 
         // Validate request path doesn't contain control characters
         if req.path.contains('\n') || req.path.contains('\r') {
@@ -83,7 +168,7 @@ impl HttpDispatcher {
         }
 
         // Check request body size (limit to 100MB)
-        if let Some(body) = &req.body {
+        if let Some(body) = &body {
             if body.len() > 100 * 1024 * 1024 {
                 return Err(anyhow::anyhow!(
                     "Request body too large: {} bytes",
@@ -92,12 +177,7 @@ impl HttpDispatcher {
             }
         }
 
-        // Send request to upstream
-        let response = self
-            .proxy_to_upstream(http_policy, &req, sse_event_tx)
-            .await?;
-
-        Ok(response)
+        Ok((http_policy, body))
     }
 
     /// Proxy request to upstream server
@@ -105,6 +185,7 @@ impl HttpDispatcher {
         &self,
         policy: &HttpPolicy,
         req: &HttpRequest,
+        body: Option<Vec<u8>>,
         sse_event_tx: Option<tokio::sync::mpsc::UnboundedSender<Message>>,
     ) -> anyhow::Result<Option<HttpResponse>> {
         let url = format!("{}{}", policy.upstream, req.path);
@@ -129,13 +210,13 @@ impl HttpDispatcher {
             .headers
             .keys()
             .any(|k| k.to_lowercase() == "content-type");
-        if req.body.is_some() && !has_content_type {
+        if body.is_some() && !has_content_type {
             request_builder = request_builder.header("Content-Type", "application/json");
         }
 
         // Add body
-        if let Some(body) = &req.body {
-            request_builder = request_builder.body(body.clone());
+        if let Some(body) = body {
+            request_builder = request_builder.body(body);
         }
 
         // Determine timeout based on request path
@@ -213,23 +294,28 @@ impl HttpDispatcher {
                 None
             } else {
                 // Fallback: no sender provided
-                tokio::time::timeout(std::time::Duration::from_secs(2), response.text())
+                tokio::time::timeout(std::time::Duration::from_secs(2), response.bytes())
                     .await
                     .ok()
                     .and_then(|r| r.ok())
-                    .or(Some(String::new()))
+                    .map(|b| b.to_vec())
+                    .or(Some(Vec::new()))
             }
         } else {
-            // Regular (non-SSE) endpoints: buffer normally
-            response.text().await.ok()
+            // Regular (non-SSE) endpoints: buffer normally, keeping binary bodies intact
+            response.bytes().await.ok().map(|b| b.to_vec())
         };
 
         // Return HttpResponse wrapper or None for SSE
-        Ok(body.map(|body| HttpResponse {
-            id: req.id.clone(),
-            status,
-            headers,
-            body: Some(body),
+        Ok(body.map(|body| {
+            let encoding = PayloadEncoding::for_bytes(&[&body]);
+            HttpResponse {
+                id: req.id.clone(),
+                status,
+                headers,
+                body: Some(encoding.encode(&body)),
+                encoding,
+            }
         }))
     }
 }
@@ -259,6 +345,7 @@ mod tests {
             path: "/api".to_string(),
             headers: HashMap::new(),
             body: None,
+            encoding: PayloadEncoding::Utf8,
         };
 
         let result = dispatcher.dispatch_http(req, None).await;
@@ -269,18 +356,29 @@ mod tests {
     async fn test_cli_tool_rejects_http() {
         let mut policy = PolicyConfig {
             tools: HashMap::new(),
+            mode: carapace_policy::PolicyMode::Enforce,
+            source: None,
         };
 
         policy.tools.insert(
             "gh".to_string(),
             carapace_policy::ToolPolicy::Cli(carapace_policy::CliPolicy {
                 binary: "/usr/bin/gh".to_string(),
+                mode: None,
                 argv_allow_patterns: vec!["*".to_string()],
                 argv_deny_patterns: vec![],
+                env_passthrough: vec![],
                 env_inject: HashMap::new(),
                 cwd_allowed: None,
                 timeout_secs: 30,
+                max_stdout_bytes: None,
+                max_stderr_bytes: None,
+                on_output_limit: carapace_policy::OutputLimitAction::Truncate,
+                max_concurrent: None,
+                require_approval: None,
                 audit: carapace_policy::AuditConfig::default(),
+                interactive: false,
+                sandbox: None,
             }),
         );
 
@@ -292,6 +390,7 @@ mod tests {
             path: "/api".to_string(),
             headers: HashMap::new(),
             body: None,
+            encoding: PayloadEncoding::Utf8,
         };
 
         let result = dispatcher.dispatch_http(req, None).await;
@@ -307,6 +406,7 @@ mod tests {
             path: "/api\r\nX-Injected: header".to_string(),
             headers: HashMap::new(),
             body: None,
+            encoding: PayloadEncoding::Utf8,
         };
 
         // Path contains \r\n, should be detected
@@ -324,6 +424,7 @@ mod tests {
             path: "/api".to_string(),
             headers: HashMap::new(),
             body: Some(huge_body),
+            encoding: PayloadEncoding::Utf8,
         };
 
         assert!(req.body.as_ref().unwrap().len() > 100 * 1024 * 1024);
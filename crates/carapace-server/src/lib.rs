@@ -1,17 +1,26 @@
+pub mod approval;
 pub mod audit;
+pub mod audit_sink;
+pub mod capture;
 pub mod cli_dispatch;
+pub mod concurrency;
 pub mod config;
 pub mod connection_tracker;
 pub mod debug_server;
 pub mod error;
 pub mod http_dispatch;
 pub mod listener;
+pub mod pty;
 pub mod rate_limiter;
+pub mod sandbox;
 
-pub use audit::AuditLogger;
+pub use approval::ApprovalQueue;
+pub use audit::{AuditBackpressure, AuditContext, AuditFsync, AuditLogger, AuditSession};
+pub use audit_sink::AuditSink;
 pub use cli_dispatch::CliDispatcher;
+pub use concurrency::ConcurrencyLimiter;
 pub use connection_tracker::ConnectionTracker;
 pub use error::{Result, ServerError};
 pub use http_dispatch::HttpDispatcher;
-pub use listener::Listener;
+pub use listener::{DenialDetail, Listener};
 pub use rate_limiter::RateLimiter;
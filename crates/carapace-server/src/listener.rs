@@ -1,13 +1,22 @@
-use carapace_protocol::{Message, MessageCodec};
+use carapace_policy::Decision;
+use carapace_protocol::{
+    chunking, Capabilities, Message, MessageCodec, PayloadEncoding, PolicyDenial, SseEvent,
+};
 use futures::{SinkExt, StreamExt};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::sync::Mutex;
 use tokio_util::codec::{FramedRead, FramedWrite};
+use tokio_util::sync::CancellationToken;
 
-use crate::audit::AuditLogger;
+use crate::approval::{ApprovalOutcome, ApprovalQueue, PendingApproval};
+use crate::audit::{AuditContext, AuditLogger, AuditSession};
 use crate::cli_dispatch::CliDispatcher;
+use crate::concurrency::{ConcurrencyLimiter, ConcurrencyPermit};
 use crate::http_dispatch::HttpDispatcher;
+use crate::pty::PtyInput;
 use crate::rate_limiter::RateLimiter;
 use crate::Result;
 
@@ -17,6 +26,95 @@ pub struct Listener {
     http_dispatcher: Arc<HttpDispatcher>,
     audit_logger: Arc<AuditLogger>,
     rate_limiter: Arc<RateLimiter>,
+    concurrency: Arc<ConcurrencyLimiter>,
+    approvals: Arc<ApprovalQueue>,
+    denial_detail: DenialDetail,
+    peer: Option<String>,
+}
+
+/// How much of the policy rule behind a denial is disclosed to the client
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DenialDetail {
+    /// Only that the policy denied the request
+    None,
+    /// The kind of rule and the pattern that matched
+    #[default]
+    Rule,
+    /// The rule's position in the policy file as well
+    Full,
+}
+
+impl std::str::FromStr for DenialDetail {
+    type Err = crate::ServerError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(DenialDetail::None),
+            "rule" => Ok(DenialDetail::Rule),
+            "full" => Ok(DenialDetail::Full),
+            _ => Err(crate::ServerError::ConfigError(format!(
+                "Invalid denial detail '{}': expected none, rule or full",
+                s
+            ))),
+        }
+    }
+}
+
+impl DenialDetail {
+    /// Strip what should not be disclosed from a `policy_denied` reply
+    fn disclose(self, response: Message) -> Message {
+        let Message::Error(mut err) = response else {
+            return response;
+        };
+        match self {
+            DenialDetail::Full => {}
+            DenialDetail::Rule => {
+                if let Some(denial) = &mut err.denial {
+                    denial.location = None;
+                }
+            }
+            DenialDetail::None => {
+                if err.denial.take().is_some() {
+                    err.message = "Request denied by policy".to_string();
+                }
+            }
+        }
+        Message::Error(err)
+    }
+}
+
+/// The connection a request came on, which it is tracked under while it
+/// runs
+fn owner(ctx: &AuditContext) -> &str {
+    ctx.session.as_ref().map_or("", |s| s.session_id.as_str())
+}
+
+/// Byte length of a CLI output payload, as the tool wrote it
+fn output_len(encoding: PayloadEncoding, payload: &str) -> usize {
+    match encoding {
+        PayloadEncoding::Utf8 => payload.len(),
+        PayloadEncoding::Base64 => encoding.decode(payload).map_or(payload.len(), |b| b.len()),
+    }
+}
+
+/// Reply to a request the policy denied
+fn policy_denied(id: String, decision: &Decision) -> Message {
+    // The position goes only into `denial`, which is disclosed separately
+    let message = Decision {
+        location: None,
+        ..decision.clone()
+    }
+    .to_string();
+    Message::Error(carapace_protocol::ErrorMessage {
+        id: Some(id),
+        code: "policy_denied".to_string(),
+        message,
+        denial: Some(PolicyDenial {
+            rule: decision.rule.to_string(),
+            matched: decision.matched.clone(),
+            location: decision.location.as_ref().map(|l| l.to_string()),
+        }),
+    })
 }
 
 impl Listener {
@@ -26,6 +124,10 @@ impl Listener {
             http_dispatcher,
             audit_logger: Arc::new(AuditLogger::new()),
             rate_limiter: Arc::new(RateLimiter::new(1000, 60)),
+            concurrency: Arc::new(ConcurrencyLimiter::unlimited()),
+            approvals: Arc::new(ApprovalQueue::new()),
+            denial_detail: DenialDetail::default(),
+            peer: None,
         }
     }
 
@@ -40,17 +142,56 @@ impl Listener {
             http_dispatcher,
             audit_logger,
             rate_limiter,
+            concurrency: Arc::new(ConcurrencyLimiter::unlimited()),
+            approvals: Arc::new(ApprovalQueue::new()),
+            denial_detail: DenialDetail::default(),
+            peer: None,
         }
     }
 
+    /// Limit concurrent requests; shared by all connections of a server
+    pub fn with_concurrency_limiter(mut self, concurrency: Arc<ConcurrencyLimiter>) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    /// Queue for requests held for approval; shared by all connections of
+    /// a server and the debug server
+    pub fn with_approval_queue(mut self, approvals: Arc<ApprovalQueue>) -> Self {
+        self.approvals = approvals;
+        self
+    }
+
+    /// How much of the rule behind a policy denial clients are told
+    pub fn with_denial_detail(mut self, denial_detail: DenialDetail) -> Self {
+        self.denial_detail = denial_detail;
+        self
+    }
+
+    /// Address of the agent on the other end, recorded in audit entries
+    pub fn with_peer(mut self, peer: String) -> Self {
+        self.peer = Some(peer);
+        self
+    }
+
     /// Start listening for messages (typically on stdin/stdout)
     pub async fn listen<R, W>(&self, stdin: R, stdout: W) -> Result<()>
     where
         R: AsyncRead + Unpin + Send + 'static,
         W: AsyncWrite + Unpin + Send + 'static,
     {
-        let mut frame_read = FramedRead::new(stdin, MessageCodec);
-        let frame_write = Arc::new(Mutex::new(FramedWrite::new(stdout, MessageCodec)));
+        let mut frame_read = FramedRead::new(stdin, MessageCodec::new());
+        let frame_write = Arc::new(Mutex::new(FramedWrite::new(stdout, MessageCodec::new())));
+
+        // Every request audited on this connection carries its session
+        let mut session = AuditSession::new(self.peer.clone());
+
+        // Set once the client agrees to receive large responses as chunks
+        let chunking = Arc::new(AtomicBool::new(false));
+
+        // Cancellation tokens of requests still being dispatched, by request id
+        let in_flight: Arc<std::sync::Mutex<HashMap<String, CancellationToken>>> =
+            Arc::new(std::sync::Mutex::new(HashMap::new()));
 
         // Create unbounded channel for SSE events
         // Events sent through this channel are forwarded to client by background task
@@ -85,19 +226,111 @@ impl Listener {
                 Ok(msg) => {
                     // Handle Ping immediately (don't dispatch)
                     if let Message::Ping(ping) = &msg {
+                        if let Some(agent) = &ping.agent {
+                            tracing::info!("Agent '{}' connected", agent);
+                            session.agent = Some(agent.clone());
+                        }
+                        // A Ping carrying capabilities is the connection handshake
+                        let accepted = ping
+                            .capabilities
+                            .as_ref()
+                            .map(|offer| Capabilities::supported().accept(offer));
                         let pong = Message::Pong(carapace_protocol::PingPong {
                             id: ping.id.clone(),
                             timestamp: ping.timestamp,
+                            capabilities: accepted.clone(),
+                            agent: None,
                         });
                         let mut writer = frame_write.lock().await;
                         if let Err(e) = writer.send(pong).await {
                             tracing::error!("Failed to send Pong: {}", e);
                         } else if let Err(e) = writer.flush().await {
                             tracing::error!("Failed to flush Pong: {}", e);
+                        } else if let Some(accepted) = accepted {
+                            // Switch only after the Pong went out in the old encoding
+                            tracing::info!(
+                                "Negotiated wire encoding: {}, compression: {}, chunking: {}",
+                                accepted.encoding(),
+                                accepted
+                                    .compression()
+                                    .map_or("none".to_string(), |c| c.to_string()),
+                                accepted.chunking
+                            );
+                            let codec = writer.encoder_mut();
+                            codec.set_encoding(accepted.encoding());
+                            codec.set_compression(accepted.compression());
+                            chunking.store(accepted.chunking, Ordering::SeqCst);
                         }
                         continue;
                     }
 
+                    // Handle Cancel immediately: the request it targets is still
+                    // running in its own task
+                    if let Message::Cancel(cancel) = &msg {
+                        let token = in_flight.lock().unwrap().remove(&cancel.id);
+                        match token {
+                            Some(token) => {
+                                tracing::info!("Cancelling request {}", cancel.id);
+                                token.cancel();
+                            }
+                            None => tracing::debug!(
+                                "Cancel for unknown or finished request {}",
+                                cancel.id
+                            ),
+                        }
+                        continue;
+                    }
+
+                    // Forward signals to the process group of a request this
+                    // connection is running
+                    if let Message::Signal(signal) = &msg {
+                        if self.cli_dispatcher.signal(
+                            &session.session_id,
+                            &signal.id,
+                            signal.signal,
+                        ) {
+                            tracing::info!(
+                                "Forwarded signal {} to request {}",
+                                signal.signal,
+                                signal.id
+                            );
+                        } else {
+                            tracing::debug!(
+                                "Signal {} not delivered to request {}",
+                                signal.signal,
+                                signal.id
+                            );
+                        }
+                        continue;
+                    }
+
+                    // Route terminal input to an interactive session this
+                    // connection is running
+                    if let Message::PtyData(data) = &msg {
+                        match data.bytes() {
+                            Ok(bytes) => {
+                                self.cli_dispatcher.pty_input(
+                                    &session.session_id,
+                                    &data.id,
+                                    PtyInput::Data(bytes),
+                                );
+                            }
+                            Err(e) => tracing::warn!("Invalid PtyData for {}: {}", data.id, e),
+                        }
+                        continue;
+                    }
+                    if let Message::PtyResize(resize) = &msg {
+                        self.cli_dispatcher.pty_input(
+                            &session.session_id,
+                            &resize.id,
+                            PtyInput::Resize(carapace_protocol::TtySize {
+                                rows: resize.rows,
+                                cols: resize.cols,
+                            }),
+                        );
+                        continue;
+                    }
+
                     // Log what type of message we received
                     match &msg {
                         Message::CliRequest(_) => tracing::debug!("Received CliRequest message"),
@@ -111,6 +344,12 @@ impl Listener {
                         Message::Ping(_) | Message::Pong(_) => {
                             tracing::debug!("Received Ping/Pong message")
                         }
+                        Message::Chunk(_) => tracing::debug!("Received Chunk message"),
+                        Message::Cancel(_) => tracing::debug!("Received Cancel message"),
+                        Message::Signal(_) => tracing::debug!("Received Signal message"),
+                        Message::PtyData(_) | Message::PtyResize(_) => {
+                            tracing::debug!("Received terminal message")
+                        }
                     }
 
                     // Spawn dispatch as a separate task so the message loop isn't blocked.
@@ -120,26 +359,76 @@ impl Listener {
                     let http_dispatcher = self.http_dispatcher.clone();
                     let audit_logger = self.audit_logger.clone();
                     let rate_limiter = self.rate_limiter.clone();
+                    let concurrency = self.concurrency.clone();
+                    let approvals = self.approvals.clone();
+                    let denial_detail = self.denial_detail;
                     let fw = frame_write.clone();
                     let sse_tx = sse_event_tx.clone();
+                    let chunking = chunking.load(Ordering::SeqCst);
+                    let cancel = CancellationToken::new();
+                    let request_id = msg.id().map(|id| id.to_string());
+                    if let Some(id) = &request_id {
+                        // A second request with the id of one still running
+                        // would take over its cancellation and signals
+                        let duplicate = {
+                            let mut in_flight = in_flight.lock().unwrap();
+                            if in_flight.contains_key(id) {
+                                true
+                            } else {
+                                in_flight.insert(id.clone(), cancel.clone());
+                                false
+                            }
+                        };
+                        if duplicate {
+                            tracing::warn!("Rejecting request {}: already in flight", id);
+                            let response = Message::Error(carapace_protocol::ErrorMessage {
+                                id: Some(id.clone()),
+                                code: "duplicate_request".to_string(),
+                                message: format!("Request {} is already in flight", id),
+                                denial: None,
+                            });
+                            Self::send_response(&frame_write, response, false).await;
+                            continue;
+                        }
+                    }
+                    let in_flight = in_flight.clone();
+                    let mut ctx = AuditContext::for_message(&session, &msg);
                     tokio::spawn(async move {
-                        let response = Self::dispatch_message_static(
+                        let admitted = Self::admit(
                             &cli_dispatcher,
                             &http_dispatcher,
                             &audit_logger,
-                            &rate_limiter,
-                            msg,
-                            Some(sse_tx),
+                            &concurrency,
+                            &approvals,
+                            &mut ctx,
+                            &msg,
+                            &cancel,
                         )
                         .await;
+                        let response = match admitted {
+                            Ok(_permit) => {
+                                Self::dispatch_message_static(
+                                    &cli_dispatcher,
+                                    &http_dispatcher,
+                                    &audit_logger,
+                                    &rate_limiter,
+                                    &ctx,
+                                    msg,
+                                    Some(sse_tx),
+                                    cancel,
+                                )
+                                .await
+                            }
+                            Err(response) => response,
+                        };
+
+                        if let Some(id) = &request_id {
+                            in_flight.lock().unwrap().remove(id);
+                        }
 
                         if let Some(response) = response {
-                            let mut writer = fw.lock().await;
-                            if let Err(e) = writer.send(response).await {
-                                tracing::error!("Failed to send response: {}", e);
-                            } else if let Err(e) = writer.flush().await {
-                                tracing::error!("Failed to flush response: {}", e);
-                            }
+                            let response = denial_detail.disclose(response);
+                            Self::send_response(&fw, response, chunking).await;
                         }
                     });
                 }
@@ -153,60 +442,398 @@ impl Listener {
         Ok(())
     }
 
+    /// Decide whether a request may be dispatched: it must pass the policy,
+    /// then wait for any approval it needs without taking a slot, then for
+    /// a concurrency slot. The slot is held until the request, including
+    /// any SSE stream, has finished. On failure, returns the reply to send
+    /// instead of dispatching, if any.
+    #[allow(clippy::too_many_arguments)]
+    async fn admit(
+        cli_dispatcher: &CliDispatcher,
+        http_dispatcher: &HttpDispatcher,
+        audit_logger: &AuditLogger,
+        concurrency: &ConcurrencyLimiter,
+        approvals: &ApprovalQueue,
+        ctx: &mut AuditContext,
+        msg: &Message,
+        cancel: &CancellationToken,
+    ) -> std::result::Result<Option<ConcurrencyPermit>, Option<Message>> {
+        if let Some(denial) =
+            Self::check_policy(cli_dispatcher, http_dispatcher, audit_logger, ctx, msg)
+        {
+            return Err(Some(denial));
+        }
+        Self::await_approval(
+            approvals,
+            cli_dispatcher,
+            http_dispatcher,
+            audit_logger,
+            ctx,
+            msg,
+            cancel,
+        )
+        .await?;
+        Self::acquire_slot(concurrency, audit_logger, ctx, msg, cancel).await
+    }
+
+    /// Deny a CLI or HTTP request its tool's policy does not allow, and
+    /// audit it with the decision and rule behind it. The decision is kept
+    /// in `ctx` either way. Returns the reply to a denied request.
+    fn check_policy(
+        cli_dispatcher: &CliDispatcher,
+        http_dispatcher: &HttpDispatcher,
+        audit_logger: &AuditLogger,
+        ctx: &mut AuditContext,
+        msg: &Message,
+    ) -> Option<Message> {
+        let (id, decision) = match msg {
+            Message::CliRequest(req) => {
+                let decision = cli_dispatcher.decide(req);
+                ctx.decision = Some(decision.clone());
+                if !cli_dispatcher.enforces(&decision) {
+                    return None;
+                }
+                audit_logger.log_cli_request(ctx, false, Some(decision.rule.as_str()));
+                (&req.id, decision)
+            }
+            Message::HttpRequest(req) => {
+                // An undecodable body fails dispatch instead
+                let decision = http_dispatcher.decide(req)?;
+                ctx.decision = Some(decision.clone());
+                if !http_dispatcher.enforces(&decision) {
+                    return None;
+                }
+                audit_logger.log_http_request(ctx, req, false, Some(decision.rule.as_str()));
+                (&req.id, decision)
+            }
+            _ => return None,
+        };
+        tracing::warn!("Request {} denied: {}", id, decision);
+        Some(policy_denied(id.clone(), &decision))
+    }
+
+    /// Hold a request its tool's policy requires approval for until an
+    /// operator decides. On denial, returns the reply to send instead of
+    /// dispatching, if any.
+    async fn await_approval(
+        approvals: &ApprovalQueue,
+        cli_dispatcher: &CliDispatcher,
+        http_dispatcher: &HttpDispatcher,
+        audit_logger: &AuditLogger,
+        ctx: &AuditContext,
+        msg: &Message,
+        cancel: &CancellationToken,
+    ) -> std::result::Result<(), Option<Message>> {
+        let session_id = ctx.session.as_ref().map(|s| s.session_id.clone());
+        let pending = match msg {
+            Message::CliRequest(req) => cli_dispatcher
+                .approval_rule(req)
+                .map(|rule| PendingApproval::cli(req, session_id, rule.timeout_secs, audit_logger)),
+            Message::HttpRequest(req) => http_dispatcher.approval_rule(req).map(|rule| {
+                PendingApproval::http(req, session_id, rule.timeout_secs, audit_logger)
+            }),
+            _ => None,
+        };
+        let Some(pending) = pending else {
+            return Ok(());
+        };
+
+        let (id, tool) = (pending.request_id.clone(), pending.tool.clone());
+        let timeout = std::time::Duration::from_secs(pending.timeout_secs);
+        tracing::info!("Request {} for tool '{}' awaits approval", id, tool);
+        let start = std::time::Instant::now();
+        let outcome = tokio::select! {
+            outcome = approvals.request(pending, timeout) => outcome,
+            _ = cancel.cancelled() => {
+                let latency_ms = start.elapsed().as_millis() as u64;
+                audit_logger.log_cancellation(ctx, latency_ms);
+                return Err(None);
+            }
+        };
+        let latency_ms = start.elapsed().as_millis() as u64;
+
+        let (code, err) = match outcome {
+            ApprovalOutcome::Approved { approver } => {
+                tracing::info!("Request {} approved by {}", id, approver);
+                audit_logger.log_approval(ctx, Some(&approver), None, latency_ms);
+                return Ok(());
+            }
+            ApprovalOutcome::Denied { approver } => {
+                audit_logger.log_approval(
+                    ctx,
+                    Some(&approver),
+                    Some("approval_denied"),
+                    latency_ms,
+                );
+                let err = crate::ServerError::ApprovalDenied {
+                    tool: tool.clone(),
+                    approver,
+                };
+                ("approval_denied", err)
+            }
+            ApprovalOutcome::TimedOut => {
+                audit_logger.log_approval(ctx, None, Some("approval_timeout"), latency_ms);
+                let err = crate::ServerError::ApprovalTimeout { tool: tool.clone() };
+                ("approval_timeout", err)
+            }
+        };
+        tracing::warn!("Request {} for tool '{}' not run: {}", id, tool, err);
+        Err(Some(Message::Error(carapace_protocol::ErrorMessage {
+            id: Some(id),
+            code: code.to_string(),
+            message: err.to_string(),
+            denial: None,
+        })))
+    }
+
+    /// Wait for a concurrency slot for a CLI or HTTP request. On failure,
+    /// returns the reply to send instead of dispatching, if any.
+    async fn acquire_slot(
+        concurrency: &ConcurrencyLimiter,
+        audit_logger: &AuditLogger,
+        ctx: &AuditContext,
+        msg: &Message,
+        cancel: &CancellationToken,
+    ) -> std::result::Result<Option<ConcurrencyPermit>, Option<Message>> {
+        let (id, tool) = match msg {
+            Message::CliRequest(req) => (&req.id, &req.tool),
+            Message::HttpRequest(req) => (&req.id, &req.tool),
+            _ => return Ok(None),
+        };
+
+        let start = std::time::Instant::now();
+        let err = tokio::select! {
+            acquired = concurrency.acquire(tool) => match acquired {
+                Ok(permit) => return Ok(Some(permit)),
+                Err(e) => e,
+            },
+            _ = cancel.cancelled() => {
+                // Gave up while queued
+                let latency_ms = start.elapsed().as_millis() as u64;
+                audit_logger.log_cancellation(ctx, latency_ms);
+                return Err(None);
+            }
+        };
+
+        let code = match err {
+            crate::ServerError::QueueTimeout { .. } => "queue_timeout",
+            _ => "busy",
+        };
+        tracing::warn!("Request {} for tool '{}' not run: {}", id, tool, err);
+        match msg {
+            Message::CliRequest(_) => audit_logger.log_cli_request(ctx, false, Some(code)),
+            Message::HttpRequest(req) => audit_logger.log_http_request(ctx, req, false, Some(code)),
+            _ => {}
+        }
+        Err(Some(Message::Error(carapace_protocol::ErrorMessage {
+            id: Some(id.clone()),
+            code: code.to_string(),
+            message: err.to_string(),
+            denial: None,
+        })))
+    }
+
+    /// Run an interactive CLI session. Terminal output streams through
+    /// `output`; the final response goes the same way so that it cannot
+    /// overtake the last of the output.
+    async fn dispatch_interactive(
+        cli_dispatcher: &CliDispatcher,
+        audit_logger: &AuditLogger,
+        ctx: &AuditContext,
+        req: carapace_protocol::CliRequest,
+        output: tokio::sync::mpsc::UnboundedSender<Message>,
+        cancel: CancellationToken,
+    ) -> Option<Message> {
+        let start = std::time::Instant::now();
+
+        match cli_dispatcher
+            .dispatch_interactive(owner(ctx), req.clone(), output.clone(), cancel.clone())
+            .await
+        {
+            Ok(session) => {
+                let duration_ms = start.elapsed().as_millis() as u64;
+                audit_logger.log_pty_session(
+                    ctx,
+                    session.response.exit_code,
+                    duration_ms,
+                    session.transcript.as_deref(),
+                );
+                let _ = output.send(Message::CliResponse(session.response));
+                None
+            }
+            Err(_) if cancel.is_cancelled() => {
+                let latency_ms = start.elapsed().as_millis() as u64;
+                audit_logger.log_cancellation(ctx, latency_ms);
+                None
+            }
+            Err(e) => {
+                let latency_ms = start.elapsed().as_millis() as u64;
+                audit_logger.log_pty_session(ctx, -1, latency_ms, None);
+                tracing::error!("Interactive session error: {}", e);
+                Some(Message::Error(carapace_protocol::ErrorMessage {
+                    id: Some(req.id),
+                    code: "cli_error".to_string(),
+                    message: e.to_string(),
+                    denial: None,
+                }))
+            }
+        }
+    }
+
+    /// Write a response, splitting it into chunks if the client supports them.
+    /// The writer lock is released between chunks so Pongs and other
+    /// responses are not stuck behind one large download.
+    async fn send_response<W>(
+        frame_write: &Mutex<FramedWrite<W, MessageCodec>>,
+        response: Message,
+        chunking: bool,
+    ) where
+        W: AsyncWrite + Unpin,
+    {
+        let frames = if chunking {
+            let encoding = frame_write.lock().await.encoder().encoding();
+            match chunking::split(response, chunking::CHUNK_SIZE, encoding) {
+                Ok(frames) => frames,
+                Err(e) => {
+                    tracing::error!("Failed to chunk response: {}", e);
+                    return;
+                }
+            }
+        } else {
+            vec![response]
+        };
+
+        for frame in frames {
+            let mut writer = frame_write.lock().await;
+            if let Err(e) = writer.send(frame).await {
+                tracing::error!("Failed to send response: {}", e);
+                return;
+            } else if let Err(e) = writer.flush().await {
+                tracing::error!("Failed to flush response: {}", e);
+                return;
+            }
+        }
+    }
+
+    /// Pass SSE events on to `output` while keeping a copy of each for the
+    /// capture store. The copies are returned once the returned sender and
+    /// its clones are dropped.
+    fn tap_sse_events(
+        output: tokio::sync::mpsc::UnboundedSender<Message>,
+    ) -> (
+        tokio::sync::mpsc::UnboundedSender<Message>,
+        tokio::task::JoinHandle<Vec<SseEvent>>,
+    ) {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let capture = tokio::spawn(async move {
+            let mut events = Vec::new();
+            while let Some(msg) = rx.recv().await {
+                if let Message::SseEvent(event) = &msg {
+                    events.push(event.clone());
+                }
+                if output.send(msg).is_err() {
+                    break;
+                }
+            }
+            events
+        });
+        (tx, capture)
+    }
+
     /// Dispatch incoming message to appropriate handler.
     /// Static method so it can be called from spawned tasks without borrowing self.
+    #[allow(clippy::too_many_arguments)]
     async fn dispatch_message_static(
         cli_dispatcher: &CliDispatcher,
         http_dispatcher: &HttpDispatcher,
         audit_logger: &AuditLogger,
         rate_limiter: &RateLimiter,
+        ctx: &AuditContext,
         msg: Message,
         sse_event_tx: Option<tokio::sync::mpsc::UnboundedSender<Message>>,
+        cancel: CancellationToken,
     ) -> Option<Message> {
         match msg {
             Message::CliRequest(req) => {
                 // Rate limit check
                 if let Err(e) = rate_limiter.check_request(&req.tool).await {
                     tracing::warn!("Rate limit exceeded for CLI tool '{}': {}", req.tool, e);
-                    audit_logger.log_cli_request(
-                        &req.id,
-                        &req.tool,
-                        &req.argv,
-                        false,
-                        Some("rate_limit_exceeded"),
-                    );
+                    audit_logger.log_cli_request(ctx, false, Some("rate_limit_exceeded"));
                     return Some(Message::Error(carapace_protocol::ErrorMessage {
                         id: Some(req.id),
                         code: "rate_limited".to_string(),
                         message: e.to_string(),
+                        denial: None,
                     }));
                 }
 
-                // Audit log the request
-                audit_logger.log_cli_request(&req.id, &req.tool, &req.argv, true, None);
+                // Audit log the request with the rule that let it through
+                audit_logger.log_cli_request(ctx, true, None);
+                cli_dispatcher.audit_unenforced(ctx, &req);
+                let dropped = cli_dispatcher.dropped_env(&req);
+                if !dropped.is_empty() {
+                    audit_logger.log_env_dropped(ctx, &dropped);
+                }
+
+                if let (true, Some(output)) = (cli_dispatcher.is_interactive(&req), &sse_event_tx) {
+                    return Self::dispatch_interactive(
+                        cli_dispatcher,
+                        audit_logger,
+                        ctx,
+                        req,
+                        output.clone(),
+                        cancel,
+                    )
+                    .await;
+                }
 
                 let start = std::time::Instant::now();
 
-                match cli_dispatcher.dispatch_cli(req.clone()).await {
+                match cli_dispatcher
+                    .dispatch_cli_with_cancel(owner(ctx), req.clone(), cancel.clone())
+                    .await
+                {
                     Ok(resp) => {
                         let latency_ms = start.elapsed().as_millis() as u64;
                         audit_logger.log_cli_response(
-                            &req.id,
+                            ctx,
                             resp.exit_code,
-                            resp.stdout.len(),
-                            resp.stderr.len(),
+                            output_len(resp.encoding, &resp.stdout),
+                            output_len(resp.encoding, &resp.stderr),
                             latency_ms,
                         );
+                        audit_logger.capture_cli(ctx, &req, &resp);
+                        let truncated: Vec<&str> = [
+                            ("stdout", resp.stdout_truncated),
+                            ("stderr", resp.stderr_truncated),
+                        ]
+                        .into_iter()
+                        .filter_map(|(stream, truncated)| truncated.then_some(stream))
+                        .collect();
+                        if !truncated.is_empty() {
+                            audit_logger.log_output_truncated(
+                                ctx,
+                                &truncated,
+                                cli_dispatcher.output_limit_action(&req.tool).as_str(),
+                            );
+                        }
                         Some(Message::CliResponse(resp))
                     }
+                    Err(_) if cancel.is_cancelled() => {
+                        // The client has gone away; there is nobody to answer
+                        let latency_ms = start.elapsed().as_millis() as u64;
+                        audit_logger.log_cancellation(ctx, latency_ms);
+                        None
+                    }
                     Err(e) => {
                         let latency_ms = start.elapsed().as_millis() as u64;
-                        audit_logger.log_cli_response(&req.id, -1, 0, 0, latency_ms);
+                        audit_logger.log_cli_response(ctx, -1, 0, 0, latency_ms);
                         tracing::error!("CLI dispatch error: {}", e);
                         Some(Message::Error(carapace_protocol::ErrorMessage {
                             id: Some(req.id),
                             code: "cli_error".to_string(),
                             message: e.to_string(),
+                            denial: None,
                         }))
                     }
                 }
@@ -222,40 +849,41 @@ impl Listener {
                 // Rate limit check
                 if let Err(e) = rate_limiter.check_request(&req.tool).await {
                     tracing::warn!("Rate limit exceeded for HTTP tool '{}': {}", req.tool, e);
-                    audit_logger.log_http_request(
-                        &req.id,
-                        &req.tool,
-                        &req.method,
-                        &req.path,
-                        false,
-                        Some("rate_limit_exceeded"),
-                    );
+                    audit_logger.log_http_request(ctx, &req, false, Some("rate_limit_exceeded"));
                     return Some(Message::Error(carapace_protocol::ErrorMessage {
                         id: Some(req.id),
                         code: "rate_limited".to_string(),
                         message: e.to_string(),
+                        denial: None,
                     }));
                 }
 
-                // Audit log the request
-                audit_logger.log_http_request(
-                    &req.id,
-                    &req.tool,
-                    &req.method,
-                    &req.path,
-                    true,
-                    None,
-                );
+                // Audit log the request with the rule that let it through
+                audit_logger.log_http_request(ctx, &req, true, None);
+                http_dispatcher.audit_unenforced(ctx, &req);
+
+                let (sse_event_tx, sse_capture) = match sse_event_tx {
+                    Some(tx) if audit_logger.captures(&req.tool) => {
+                        let (tx, capture) = Self::tap_sse_events(tx);
+                        (Some(tx), Some(capture))
+                    }
+                    tx => (tx, None),
+                };
 
                 let start = std::time::Instant::now();
 
-                match http_dispatcher
-                    .dispatch_http(req.clone(), sse_event_tx)
-                    .await
-                {
+                let result = http_dispatcher
+                    .dispatch_http_with_cancel(req.clone(), sse_event_tx, cancel.clone())
+                    .await;
+                let events = match sse_capture {
+                    Some(capture) => capture.await.unwrap_or_default(),
+                    None => Vec::new(),
+                };
+                match result {
                     Ok(Some(response)) => {
                         let latency_ms = start.elapsed().as_millis() as u64;
-                        audit_logger.log_http_response(&req.id, response.status, latency_ms);
+                        audit_logger.log_http_response(ctx, response.status, latency_ms);
+                        audit_logger.capture_http(ctx, &req, Some(&response), &events);
                         tracing::info!(
                             "HTTP request {} succeeded with status {}",
                             response.id,
@@ -265,18 +893,25 @@ impl Listener {
                     }
                     Ok(None) => {
                         let latency_ms = start.elapsed().as_millis() as u64;
-                        audit_logger.log_http_response(&req.id, 200, latency_ms);
+                        audit_logger.log_http_response(ctx, 200, latency_ms);
+                        audit_logger.capture_http(ctx, &req, None, &events);
                         tracing::info!("SSE streaming completed for request {}", req.id);
                         None
                     }
+                    Err(_) if cancel.is_cancelled() => {
+                        let latency_ms = start.elapsed().as_millis() as u64;
+                        audit_logger.log_cancellation(ctx, latency_ms);
+                        None
+                    }
                     Err(e) => {
                         let latency_ms = start.elapsed().as_millis() as u64;
-                        audit_logger.log_http_response(&req.id, 500, latency_ms);
+                        audit_logger.log_http_response(ctx, 500, latency_ms);
                         tracing::error!("HTTP dispatch failed for {}: {}", req.id, e);
                         Some(Message::Error(carapace_protocol::ErrorMessage {
                             id: Some(req.id),
                             code: "http_error".to_string(),
                             message: format!("HTTP dispatch error: {}", e),
+                            denial: None,
                         }))
                     }
                 }
@@ -286,7 +921,12 @@ impl Listener {
             | Message::HttpResponse(_)
             | Message::SseEvent(_)
             | Message::Ping(_)
-            | Message::Pong(_) => {
+            | Message::Pong(_)
+            | Message::Chunk(_)
+            | Message::Cancel(_)
+            | Message::Signal(_)
+            | Message::PtyData(_)
+            | Message::PtyResize(_) => {
                 // Server should not receive these from client (Ping handled in listen loop)
                 tracing::warn!("Unexpected message type from client");
                 None
@@ -305,4 +945,950 @@ mod tests {
         let http_dispatcher = Arc::new(HttpDispatcher::new());
         let _listener = Listener::new(cli_dispatcher, http_dispatcher);
     }
+
+    #[test]
+    fn test_output_len_counts_raw_bytes() {
+        let resp =
+            carapace_protocol::CliResponse::from_output("1".to_string(), 0, &[0xff; 10], b"");
+        assert_eq!(resp.encoding, PayloadEncoding::Base64);
+        assert_eq!(output_len(resp.encoding, &resp.stdout), 10);
+        assert_eq!(output_len(PayloadEncoding::Utf8, "h\u{e9}llo"), 6);
+    }
+
+    #[tokio::test]
+    async fn test_ping_negotiates_wire_encoding() {
+        use carapace_protocol::{PingPong, WireEncoding};
+
+        let listener = Listener::new(
+            Arc::new(CliDispatcher::new()),
+            Arc::new(HttpDispatcher::new()),
+        );
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        tokio::spawn(async move { listener.listen(server_read, server_write).await });
+
+        let (client_read, client_write) = tokio::io::split(client);
+        let mut frame_read = FramedRead::new(client_read, MessageCodec::new());
+        let mut frame_write = FramedWrite::new(client_write, MessageCodec::new());
+
+        let ping = |id: &str, capabilities| {
+            Message::Ping(PingPong {
+                id: id.to_string(),
+                timestamp: 0,
+                capabilities,
+                agent: None,
+            })
+        };
+        frame_write
+            .send(ping(
+                "handshake",
+                Some(Capabilities::with_encoding(WireEncoding::MessagePack)),
+            ))
+            .await
+            .unwrap();
+
+        match frame_read.next().await.unwrap().unwrap() {
+            Message::Pong(pong) => {
+                assert_eq!(
+                    pong.capabilities.map(|c| c.encoding()),
+                    Some(WireEncoding::MessagePack)
+                );
+            }
+            other => panic!("Expected Pong, got {:?}", other),
+        }
+
+        // Later frames from the server use the negotiated encoding
+        frame_write.send(ping("after", None)).await.unwrap();
+        let mut raw = frame_read.into_inner();
+        let mut prefix = [0u8; 4];
+        tokio::io::AsyncReadExt::read_exact(&mut raw, &mut prefix)
+            .await
+            .unwrap();
+        assert_eq!(u32::from_be_bytes(prefix) >> 28, 2);
+    }
+
+    #[tokio::test]
+    async fn test_large_response_sent_as_chunks() {
+        use carapace_policy::{CliPolicy, PolicyConfig, ToolPolicy};
+        use carapace_protocol::chunking::Reassembler;
+        use carapace_protocol::{CliRequest, PingPong};
+        use std::collections::HashMap;
+
+        let mut policy = PolicyConfig {
+            tools: HashMap::new(),
+            mode: carapace_policy::PolicyMode::Enforce,
+            source: None,
+        };
+        policy.tools.insert(
+            "seq".to_string(),
+            ToolPolicy::Cli(CliPolicy {
+                binary: "/usr/bin/seq".to_string(),
+                mode: None,
+                argv_allow_patterns: vec!["*".to_string()],
+                argv_deny_patterns: vec![],
+                env_passthrough: vec![],
+                env_inject: HashMap::new(),
+                cwd_allowed: None,
+                timeout_secs: 30,
+                max_stdout_bytes: None,
+                max_stderr_bytes: None,
+                on_output_limit: carapace_policy::OutputLimitAction::Truncate,
+                max_concurrent: None,
+                require_approval: None,
+                audit: carapace_policy::AuditConfig::default(),
+                interactive: false,
+                sandbox: None,
+            }),
+        );
+        let listener = Listener::new(
+            Arc::new(CliDispatcher::with_policy(policy)),
+            Arc::new(HttpDispatcher::new()),
+        );
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        tokio::spawn(async move { listener.listen(server_read, server_write).await });
+
+        let (client_read, client_write) = tokio::io::split(client);
+        let mut frame_read = FramedRead::new(client_read, MessageCodec::new());
+        let mut frame_write = FramedWrite::new(client_write, MessageCodec::new());
+
+        frame_write
+            .send(Message::Ping(PingPong {
+                id: "handshake".to_string(),
+                timestamp: 0,
+                capabilities: Some(Capabilities {
+                    chunking: true,
+                    ..Default::default()
+                }),
+                agent: None,
+            }))
+            .await
+            .unwrap();
+        match frame_read.next().await.unwrap().unwrap() {
+            Message::Pong(pong) => assert!(pong.capabilities.unwrap().chunking),
+            other => panic!("Expected Pong, got {:?}", other),
+        }
+
+        frame_write
+            .send(Message::CliRequest(CliRequest {
+                id: "big".to_string(),
+                tool: "seq".to_string(),
+                argv: vec!["1".to_string(), "200000".to_string()],
+                env: HashMap::new(),
+                stdin: None,
+                cwd: "/tmp".to_string(),
+                tty: None,
+            }))
+            .await
+            .unwrap();
+
+        let mut reassembler = Reassembler::new();
+        let mut chunks = 0;
+        let response = loop {
+            match frame_read.next().await.unwrap().unwrap() {
+                Message::Chunk(chunk) => {
+                    chunks += 1;
+                    if let Some(msg) = reassembler.push(chunk).unwrap() {
+                        break msg;
+                    }
+                }
+                other => panic!("Expected Chunk, got {:?}", other),
+            }
+        };
+
+        assert!(chunks > 1);
+        match response {
+            Message::CliResponse(resp) => {
+                assert_eq!(resp.exit_code, 0);
+                assert_eq!(resp.stdout.lines().count(), 200000);
+            }
+            other => panic!("Expected CliResponse, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stops_request_and_is_audited() {
+        use carapace_policy::{CliPolicy, PolicyConfig, ToolPolicy};
+        use carapace_protocol::{Cancel, CliRequest, PingPong};
+
+        let mut policy = PolicyConfig {
+            tools: HashMap::new(),
+            mode: carapace_policy::PolicyMode::Enforce,
+            source: None,
+        };
+        policy.tools.insert(
+            "sleep".to_string(),
+            ToolPolicy::Cli(CliPolicy {
+                binary: "/bin/sleep".to_string(),
+                mode: None,
+                argv_allow_patterns: vec!["*".to_string()],
+                argv_deny_patterns: vec![],
+                env_passthrough: vec![],
+                env_inject: HashMap::new(),
+                cwd_allowed: None,
+                timeout_secs: 30,
+                max_stdout_bytes: None,
+                max_stderr_bytes: None,
+                on_output_limit: carapace_policy::OutputLimitAction::Truncate,
+                max_concurrent: None,
+                require_approval: None,
+                audit: carapace_policy::AuditConfig::default(),
+                interactive: false,
+                sandbox: None,
+            }),
+        );
+        let audit_dir = tempfile::tempdir().unwrap();
+        let audit_file = audit_dir.path().join("audit.log");
+        let audit_logger = Arc::new(AuditLogger::with_config(
+            true,
+            true,
+            false,
+            Some(audit_file.to_string_lossy().to_string()),
+            1024 * 1024,
+            1,
+        ));
+        let listener = Listener::with_audit_and_rate_limit(
+            Arc::new(CliDispatcher::with_policy(policy)),
+            Arc::new(HttpDispatcher::new()),
+            audit_logger.clone(),
+            Arc::new(RateLimiter::new(1000, 60)),
+        );
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        tokio::spawn(async move { listener.listen(server_read, server_write).await });
+
+        let (client_read, client_write) = tokio::io::split(client);
+        let mut frame_read = FramedRead::new(client_read, MessageCodec::new());
+        let mut frame_write = FramedWrite::new(client_write, MessageCodec::new());
+
+        frame_write
+            .send(Message::CliRequest(CliRequest {
+                id: "long".to_string(),
+                tool: "sleep".to_string(),
+                argv: vec!["30".to_string()],
+                env: HashMap::new(),
+                stdin: None,
+                cwd: "/tmp".to_string(),
+                tty: None,
+            }))
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        frame_write
+            .send(Message::Cancel(Cancel {
+                id: "long".to_string(),
+            }))
+            .await
+            .unwrap();
+
+        // Nothing is sent back for the cancelled request; the next frame is the Pong
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        frame_write
+            .send(Message::Ping(PingPong {
+                id: "after-cancel".to_string(),
+                timestamp: 0,
+                capabilities: None,
+                agent: None,
+            }))
+            .await
+            .unwrap();
+        match frame_read.next().await.unwrap().unwrap() {
+            Message::Pong(pong) => assert_eq!(pong.id, "after-cancel"),
+            other => panic!("Expected Pong, got {:?}", other),
+        }
+
+        audit_logger.flush();
+        let audit = std::fs::read_to_string(&audit_file).unwrap();
+        assert!(audit
+            .lines()
+            .any(|l| l.contains("\"cancelled\"") && l.contains("\"long\"")));
+    }
+
+    #[tokio::test]
+    async fn test_busy_when_queue_full() {
+        use carapace_policy::{CliPolicy, PolicyConfig, ToolPolicy};
+        use carapace_protocol::CliRequest;
+
+        let mut policy = PolicyConfig {
+            tools: HashMap::new(),
+            mode: carapace_policy::PolicyMode::Enforce,
+            source: None,
+        };
+        policy.tools.insert(
+            "sleep".to_string(),
+            ToolPolicy::Cli(CliPolicy {
+                binary: "/bin/sleep".to_string(),
+                mode: None,
+                argv_allow_patterns: vec!["*".to_string()],
+                argv_deny_patterns: vec![],
+                env_passthrough: vec![],
+                env_inject: HashMap::new(),
+                cwd_allowed: None,
+                timeout_secs: 30,
+                max_stdout_bytes: None,
+                max_stderr_bytes: None,
+                on_output_limit: carapace_policy::OutputLimitAction::Truncate,
+                max_concurrent: std::num::NonZeroU32::new(1),
+                require_approval: None,
+                audit: carapace_policy::AuditConfig::default(),
+                interactive: false,
+                sandbox: None,
+            }),
+        );
+        // One running, one queued, the third is turned away
+        let concurrency = Arc::new(ConcurrencyLimiter::new(
+            &policy,
+            None,
+            1,
+            std::time::Duration::from_secs(30),
+        ));
+        let listener = Listener::new(
+            Arc::new(CliDispatcher::with_policy(policy)),
+            Arc::new(HttpDispatcher::new()),
+        )
+        .with_concurrency_limiter(concurrency.clone());
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        tokio::spawn(async move { listener.listen(server_read, server_write).await });
+
+        let (client_read, client_write) = tokio::io::split(client);
+        let mut frame_read = FramedRead::new(client_read, MessageCodec::new());
+        let mut frame_write = FramedWrite::new(client_write, MessageCodec::new());
+
+        for id in ["first", "second", "third"] {
+            frame_write
+                .send(Message::CliRequest(CliRequest {
+                    id: id.to_string(),
+                    tool: "sleep".to_string(),
+                    argv: vec!["30".to_string()],
+                    env: HashMap::new(),
+                    stdin: None,
+                    cwd: "/tmp".to_string(),
+                    tty: None,
+                }))
+                .await
+                .unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+
+        match frame_read.next().await.unwrap().unwrap() {
+            Message::Error(err) => {
+                assert_eq!(err.id.as_deref(), Some("third"));
+                assert_eq!(err.code, "busy");
+            }
+            other => panic!("Expected busy error, got {:?}", other),
+        }
+        let stats = concurrency.stats();
+        assert_eq!(stats.tools["sleep"].running, 1);
+        assert_eq!(stats.tools["sleep"].queued, 1);
+    }
+
+    #[tokio::test]
+    async fn test_request_held_for_approval() {
+        use carapace_policy::{ApprovalRule, CliPolicy, PolicyConfig, ToolPolicy};
+        use carapace_protocol::CliRequest;
+
+        let mut policy = PolicyConfig {
+            tools: HashMap::new(),
+            mode: carapace_policy::PolicyMode::Enforce,
+            source: None,
+        };
+        policy.tools.insert(
+            "echo".to_string(),
+            ToolPolicy::Cli(CliPolicy {
+                binary: "/bin/echo".to_string(),
+                mode: None,
+                argv_allow_patterns: vec!["*".to_string()],
+                argv_deny_patterns: vec![],
+                env_passthrough: vec![],
+                env_inject: HashMap::new(),
+                cwd_allowed: None,
+                timeout_secs: 30,
+                max_stdout_bytes: None,
+                max_stderr_bytes: None,
+                on_output_limit: carapace_policy::OutputLimitAction::Truncate,
+                max_concurrent: None,
+                require_approval: Some(ApprovalRule {
+                    argv_patterns: vec!["prod*".to_string()],
+                    jsonrpc_methods: vec![],
+                    jsonrpc_param_filters: HashMap::new(),
+                    timeout_secs: 5,
+                }),
+                audit: carapace_policy::AuditConfig::default(),
+                interactive: false,
+                sandbox: None,
+            }),
+        );
+        let approvals = Arc::new(ApprovalQueue::new());
+        let listener = Listener::new(
+            Arc::new(CliDispatcher::with_policy(policy)),
+            Arc::new(HttpDispatcher::new()),
+        )
+        .with_approval_queue(approvals.clone());
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        tokio::spawn(async move { listener.listen(server_read, server_write).await });
+
+        let (client_read, client_write) = tokio::io::split(client);
+        let mut frame_read = FramedRead::new(client_read, MessageCodec::new());
+        let mut frame_write = FramedWrite::new(client_write, MessageCodec::new());
+        let request = |id: &str, arg: &str| {
+            Message::CliRequest(CliRequest {
+                id: id.to_string(),
+                tool: "echo".to_string(),
+                argv: vec![arg.to_string()],
+                env: HashMap::new(),
+                stdin: None,
+                cwd: "/tmp".to_string(),
+                tty: None,
+            })
+        };
+
+        // Not matched by the rule: runs at once
+        frame_write.send(request("dev", "dev")).await.unwrap();
+        match frame_read.next().await.unwrap().unwrap() {
+            Message::CliResponse(resp) => assert_eq!(resp.stdout, "dev\n"),
+            other => panic!("Expected CliResponse, got {:?}", other),
+        }
+
+        for (id, approved) in [("approved", true), ("denied", false)] {
+            frame_write.send(request(id, "prod")).await.unwrap();
+            while approvals.pending().is_empty() {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+            let pending = approvals.pending().remove(0);
+            assert_eq!(pending.request_id, id);
+            assert!(approvals.decide(&pending.approval_id, approved, "alice"));
+
+            match frame_read.next().await.unwrap().unwrap() {
+                Message::CliResponse(resp) if approved => assert_eq!(resp.stdout, "prod\n"),
+                Message::Error(err) if !approved => {
+                    assert_eq!(err.id.as_deref(), Some("denied"));
+                    assert_eq!(err.code, "approval_denied");
+                    assert!(err.message.contains("alice"));
+                }
+                other => panic!("Unexpected reply {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_policy_denial_detail() {
+        use carapace_policy::{PolicyConfig, PolicySource};
+        use carapace_protocol::CliRequest;
+
+        let yaml = "tools:\n  echo:\n    type: cli\n    binary: /bin/echo\n    argv_allow_patterns:\n      - \"hello*\"\n";
+        let mut policy: PolicyConfig = serde_yaml::from_str(yaml).unwrap();
+        policy.source = Some(PolicySource::new("policy.yaml", yaml));
+
+        for detail in [DenialDetail::Full, DenialDetail::Rule, DenialDetail::None] {
+            let listener = Listener::new(
+                Arc::new(CliDispatcher::with_policy(policy.clone())),
+                Arc::new(HttpDispatcher::new()),
+            )
+            .with_denial_detail(detail);
+            let (client, server) = tokio::io::duplex(64 * 1024);
+            let (server_read, server_write) = tokio::io::split(server);
+            tokio::spawn(async move { listener.listen(server_read, server_write).await });
+
+            let (client_read, client_write) = tokio::io::split(client);
+            let mut frame_read = FramedRead::new(client_read, MessageCodec::new());
+            let mut frame_write = FramedWrite::new(client_write, MessageCodec::new());
+            frame_write
+                .send(Message::CliRequest(CliRequest {
+                    id: "denied".to_string(),
+                    tool: "echo".to_string(),
+                    argv: vec!["bye".to_string()],
+                    env: HashMap::new(),
+                    stdin: None,
+                    cwd: "/tmp".to_string(),
+                    tty: None,
+                }))
+                .await
+                .unwrap();
+
+            let Message::Error(err) = frame_read.next().await.unwrap().unwrap() else {
+                panic!("Expected policy_denied error");
+            };
+            assert_eq!(err.code, "policy_denied");
+            match detail {
+                DenialDetail::Full => {
+                    let denial = err.denial.unwrap();
+                    assert_eq!(denial.rule, "argv_allow");
+                    assert_eq!(denial.matched.as_deref(), Some("no allow matched"));
+                    assert_eq!(denial.location.as_deref(), Some("policy.yaml:5"));
+                }
+                DenialDetail::Rule => {
+                    assert_eq!(
+                        err.message,
+                        "Tool 'echo' denied by argv_allow: no allow matched"
+                    );
+                    assert_eq!(err.denial.unwrap().location, None);
+                }
+                DenialDetail::None => {
+                    assert_eq!(err.message, "Request denied by policy");
+                    assert!(err.denial.is_none());
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_policy_denials_audited() {
+        use carapace_policy::PolicyConfig;
+        use carapace_protocol::{CliRequest, HttpRequest};
+
+        let policy: PolicyConfig = serde_yaml::from_str(
+            r#"
+tools:
+  echo:
+    type: cli
+    binary: /bin/echo
+    argv_allow_patterns: ["hello*"]
+  signal-cli:
+    type: http
+    upstream: "http://127.0.0.1:1"
+    jsonrpc_deny_methods: [deleteEverything]
+    jsonrpc_param_filters:
+      send:
+        field: recipient
+        allow_patterns: ["+1*"]
+"#,
+        )
+        .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let log_file = dir.path().join("audit.log");
+        let audit_logger = Arc::new(AuditLogger::with_config(
+            true,
+            true,
+            false,
+            Some(log_file.to_string_lossy().to_string()),
+            1024 * 1024,
+            1,
+        ));
+        let listener = Listener::with_audit_and_rate_limit(
+            Arc::new(CliDispatcher::with_policy(policy.clone())),
+            Arc::new(HttpDispatcher::with_policy(policy)),
+            audit_logger.clone(),
+            Arc::new(RateLimiter::new(1000, 60)),
+        );
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        tokio::spawn(async move { listener.listen(server_read, server_write).await });
+
+        let (client_read, client_write) = tokio::io::split(client);
+        let mut frame_read = FramedRead::new(client_read, MessageCodec::new());
+        let mut frame_write = FramedWrite::new(client_write, MessageCodec::new());
+
+        let cli = |id: &str, tool: &str, arg: &str| {
+            Message::CliRequest(CliRequest {
+                id: id.to_string(),
+                tool: tool.to_string(),
+                argv: vec![arg.to_string()],
+                env: HashMap::new(),
+                stdin: None,
+                cwd: "/tmp".to_string(),
+                tty: None,
+            })
+        };
+        let rpc = |id: &str, method: &str| {
+            Message::HttpRequest(HttpRequest {
+                id: id.to_string(),
+                tool: "signal-cli".to_string(),
+                method: "POST".to_string(),
+                path: "/api/v1/rpc".to_string(),
+                headers: HashMap::new(),
+                body: Some(format!(
+                    r#"{{"jsonrpc":"2.0","method":"{}","params":{{"recipient":["+44123"]}}}}"#,
+                    method
+                )),
+                encoding: PayloadEncoding::Utf8,
+            })
+        };
+        let requests = [
+            cli("unknown", "curl", "example.com"),
+            cli("wrong-type", "signal-cli", "send"),
+            cli("argv", "echo", "bye"),
+            rpc("method", "deleteEverything"),
+            rpc("param", "send"),
+        ];
+        for request in requests {
+            frame_write.send(request).await.unwrap();
+            match frame_read.next().await.unwrap().unwrap() {
+                Message::Error(err) => assert_eq!(err.code, "policy_denied"),
+                other => panic!("Expected policy_denied, got {:?}", other),
+            }
+        }
+
+        audit_logger.flush();
+        let entries: Vec<serde_json::Value> = std::fs::read_to_string(&log_file)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let reasons: Vec<(&str, &str)> = entries
+            .iter()
+            .map(|e| {
+                assert_eq!(e["policy_result"], "deny");
+                assert_eq!(e["decision"]["allowed"], false);
+                (
+                    e["request_id"].as_str().unwrap(),
+                    e["reason"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            reasons,
+            [
+                ("unknown", "unknown_tool"),
+                ("wrong-type", "wrong_tool_type"),
+                ("argv", "argv_allow"),
+                ("method", "jsonrpc_deny_method"),
+                ("param", "param_allow"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_request_and_response_share_context() {
+        use carapace_policy::PolicyConfig;
+        use carapace_protocol::{CliRequest, PingPong};
+
+        let policy: PolicyConfig = serde_yaml::from_str(
+            r#"
+tools:
+  echo:
+    type: cli
+    binary: /bin/echo
+    argv_allow_patterns: ["hello*"]
+"#,
+        )
+        .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let log_file = dir.path().join("audit.log");
+        let audit_logger = Arc::new(AuditLogger::with_config(
+            true,
+            true,
+            false,
+            Some(log_file.to_string_lossy().to_string()),
+            1024 * 1024,
+            1,
+        ));
+        let listener = Listener::with_audit_and_rate_limit(
+            Arc::new(CliDispatcher::with_policy(policy.clone())),
+            Arc::new(HttpDispatcher::with_policy(policy)),
+            audit_logger.clone(),
+            Arc::new(RateLimiter::new(1000, 60)),
+        )
+        .with_peer("192.0.2.7:40000".to_string());
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        tokio::spawn(async move { listener.listen(server_read, server_write).await });
+
+        let (client_read, client_write) = tokio::io::split(client);
+        let mut frame_read = FramedRead::new(client_read, MessageCodec::new());
+        let mut frame_write = FramedWrite::new(client_write, MessageCodec::new());
+
+        frame_write
+            .send(Message::Ping(PingPong {
+                id: "handshake".to_string(),
+                timestamp: 0,
+                capabilities: None,
+                agent: Some("laptop".to_string()),
+            }))
+            .await
+            .unwrap();
+        assert!(matches!(
+            frame_read.next().await.unwrap().unwrap(),
+            Message::Pong(_)
+        ));
+
+        for (id, arg) in [("one", "hello"), ("two", "hello again")] {
+            frame_write
+                .send(Message::CliRequest(CliRequest {
+                    id: id.to_string(),
+                    tool: "echo".to_string(),
+                    argv: vec![arg.to_string()],
+                    env: HashMap::new(),
+                    stdin: None,
+                    cwd: "/tmp".to_string(),
+                    tty: None,
+                }))
+                .await
+                .unwrap();
+            assert!(matches!(
+                frame_read.next().await.unwrap().unwrap(),
+                Message::CliResponse(_)
+            ));
+        }
+
+        audit_logger.flush();
+        let entries: Vec<serde_json::Value> = std::fs::read_to_string(&log_file)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let actions: Vec<&str> = entries
+            .iter()
+            .map(|e| e["action_type"].as_str().unwrap())
+            .collect();
+        assert_eq!(actions, ["cli", "cli_response", "cli", "cli_response"]);
+
+        // Both records of a request stand on their own, and one connection
+        // is one session
+        for entry in &entries {
+            assert_eq!(entry["tool"], "echo");
+            assert_eq!(entry["policy_result"], "allow");
+            assert_eq!(entry["decision"]["rule"], "argv_allow");
+            assert_eq!(entry["session_id"], entries[0]["session_id"]);
+            assert_eq!(entry["peer"], "192.0.2.7:40000");
+            assert_eq!(entry["agent"], "laptop");
+        }
+        assert!(entries[0]["session_id"].is_string());
+        assert_eq!(entries[1]["argv"], serde_json::json!(["hello"]));
+        assert_eq!(entries[1]["exit_code"], 0);
+    }
+
+    /// Open a connection to `listener`, as an agent would
+    fn connect(
+        listener: &Arc<Listener>,
+    ) -> (
+        FramedRead<tokio::io::ReadHalf<tokio::io::DuplexStream>, MessageCodec>,
+        FramedWrite<tokio::io::WriteHalf<tokio::io::DuplexStream>, MessageCodec>,
+    ) {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        let listener = listener.clone();
+        tokio::spawn(async move { listener.listen(server_read, server_write).await });
+        let (client_read, client_write) = tokio::io::split(client);
+        (
+            FramedRead::new(client_read, MessageCodec::new()),
+            FramedWrite::new(client_write, MessageCodec::new()),
+        )
+    }
+
+    fn sleep_request(id: &str, secs: &str) -> Message {
+        Message::CliRequest(carapace_protocol::CliRequest {
+            id: id.to_string(),
+            tool: "sleep".to_string(),
+            argv: vec![secs.to_string()],
+            env: HashMap::new(),
+            stdin: None,
+            cwd: "/tmp".to_string(),
+            tty: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_approvals_of_connections_reusing_an_id() {
+        let policy: carapace_policy::PolicyConfig = serde_yaml::from_str(
+            r#"
+tools:
+  echo:
+    type: cli
+    binary: /bin/echo
+    argv_allow_patterns: ["*"]
+    require_approval:
+      argv_patterns: ["prod*"]
+      timeout_secs: 5
+"#,
+        )
+        .unwrap();
+        let approvals = Arc::new(ApprovalQueue::new());
+        let listener = Arc::new(
+            Listener::new(
+                Arc::new(CliDispatcher::with_policy(policy)),
+                Arc::new(HttpDispatcher::new()),
+            )
+            .with_approval_queue(approvals.clone()),
+        );
+        let (mut read_a, mut write_a) = connect(&listener);
+        let (mut read_b, mut write_b) = connect(&listener);
+        let echo = |arg: &str| {
+            Message::CliRequest(carapace_protocol::CliRequest {
+                id: "1".to_string(),
+                tool: "echo".to_string(),
+                argv: vec![arg.to_string()],
+                env: HashMap::new(),
+                stdin: None,
+                cwd: "/tmp".to_string(),
+                tty: None,
+            })
+        };
+
+        write_a.send(echo("prod-a")).await.unwrap();
+        write_b.send(echo("prod-b")).await.unwrap();
+        while approvals.pending().len() < 2 {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let pending = approvals.pending();
+        let held = |arg: &str| {
+            pending
+                .iter()
+                .find(|p| p.argv.as_deref() == Some(&[arg.to_string()][..]))
+                .unwrap()
+        };
+        let (a, b) = (held("prod-a"), held("prod-b"));
+        assert_eq!((a.request_id.as_str(), b.request_id.as_str()), ("1", "1"));
+        assert_ne!(a.session_id, b.session_id);
+
+        // Approving B's request runs exactly that one; A's stays held
+        assert!(approvals.decide(&b.approval_id, true, "alice"));
+        match read_b.next().await.unwrap().unwrap() {
+            Message::CliResponse(resp) => assert_eq!(resp.stdout, "prod-b\n"),
+            other => panic!("Expected CliResponse, got {:?}", other),
+        }
+        let still_held = approvals.pending();
+        assert_eq!(still_held.len(), 1);
+        assert_eq!(still_held[0].approval_id, a.approval_id);
+
+        assert!(approvals.decide(&a.approval_id, false, "alice"));
+        match read_a.next().await.unwrap().unwrap() {
+            Message::Error(err) => assert_eq!(err.code, "approval_denied"),
+            other => panic!("Expected approval_denied, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_signals_scoped_to_their_connection() {
+        use carapace_protocol::Signal;
+
+        let policy: carapace_policy::PolicyConfig = serde_yaml::from_str(
+            r#"
+tools:
+  sleep:
+    type: cli
+    binary: /bin/sleep
+    argv_allow_patterns: ["*"]
+"#,
+        )
+        .unwrap();
+        let listener = Arc::new(Listener::new(
+            Arc::new(CliDispatcher::with_policy(policy)),
+            Arc::new(HttpDispatcher::new()),
+        ));
+        let (mut read_a, mut write_a) = connect(&listener);
+        let (mut read_b, mut write_b) = connect(&listener);
+
+        write_a.send(sleep_request("shared", "30")).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        // The same id again on the same connection is refused
+        write_a.send(sleep_request("shared", "30")).await.unwrap();
+        match read_a.next().await.unwrap().unwrap() {
+            Message::Error(err) => {
+                assert_eq!(err.id.as_deref(), Some("shared"));
+                assert_eq!(err.code, "duplicate_request");
+            }
+            other => panic!("Expected Error, got {:?}", other),
+        }
+
+        // Another connection naming the id reaches nothing, and may use it
+        write_b
+            .send(Message::Signal(Signal {
+                id: "shared".to_string(),
+                signal: libc::SIGTERM,
+            }))
+            .await
+            .unwrap();
+        write_b.send(sleep_request("shared", "0")).await.unwrap();
+        match read_b.next().await.unwrap().unwrap() {
+            Message::CliResponse(resp) => {
+                assert_eq!(resp.exit_code, 0);
+                assert_eq!(resp.signal, None);
+            }
+            other => panic!("Expected CliResponse, got {:?}", other),
+        }
+
+        // The first request was still running: only its own signal ends it
+        write_a
+            .send(Message::Signal(Signal {
+                id: "shared".to_string(),
+                signal: libc::SIGINT,
+            }))
+            .await
+            .unwrap();
+        let response = tokio::time::timeout(std::time::Duration::from_secs(5), read_a.next())
+            .await
+            .expect("signal did not end the request");
+        match response.unwrap().unwrap() {
+            Message::CliResponse(resp) => assert_eq!(resp.signal, Some(libc::SIGINT)),
+            other => panic!("Expected CliResponse, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_terminal_input_scoped_to_its_connection() {
+        use carapace_protocol::{CliRequest, PtyData, TtySize};
+
+        let policy: carapace_policy::PolicyConfig = serde_yaml::from_str(
+            r#"
+tools:
+  cat:
+    type: cli
+    binary: /bin/cat
+    argv_allow_patterns: ["*"]
+    interactive: true
+    timeout_secs: 10
+"#,
+        )
+        .unwrap();
+        let listener = Arc::new(Listener::new(
+            Arc::new(CliDispatcher::with_policy(policy)),
+            Arc::new(HttpDispatcher::new()),
+        ));
+        let (mut read_a, mut write_a) = connect(&listener);
+        let (_read_b, mut write_b) = connect(&listener);
+
+        write_a
+            .send(Message::CliRequest(CliRequest {
+                id: "session".to_string(),
+                tool: "cat".to_string(),
+                argv: vec![],
+                env: HashMap::new(),
+                stdin: None,
+                cwd: "/tmp".to_string(),
+                tty: Some(TtySize { rows: 24, cols: 80 }),
+            }))
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+
+        // Another connection types into the session's id
+        write_b
+            .send(Message::PtyData(PtyData::new(
+                "session".to_string(),
+                b"intruder\n",
+            )))
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        for input in [&b"mine\n"[..], &[0x04]] {
+            write_a
+                .send(Message::PtyData(PtyData::new("session".to_string(), input)))
+                .await
+                .unwrap();
+        }
+
+        let mut output = Vec::new();
+        loop {
+            let msg = tokio::time::timeout(std::time::Duration::from_secs(5), read_a.next())
+                .await
+                .expect("session did not end")
+                .unwrap()
+                .unwrap();
+            match msg {
+                Message::PtyData(data) => output.extend(data.bytes().unwrap()),
+                Message::CliResponse(resp) => {
+                    assert_eq!(resp.exit_code, 0);
+                    break;
+                }
+                other => panic!("Expected PtyData or CliResponse, got {:?}", other),
+            }
+        }
+        let output = String::from_utf8_lossy(&output);
+        assert!(output.contains("mine"), "{}", output);
+        assert!(!output.contains("intruder"), "{}", output);
+    }
 }
@@ -1,6 +1,9 @@
-use carapace_policy::PolicyConfig;
+use carapace_policy::{PolicyConfig, ToolPolicy};
+use carapace_server::audit_sink;
+use carapace_server::config::ServerConfig;
 use carapace_server::{
-    AuditLogger, CliDispatcher, ConnectionTracker, HttpDispatcher, Listener, RateLimiter, Result,
+    ApprovalQueue, AuditBackpressure, AuditFsync, AuditLogger, CliDispatcher, ConcurrencyLimiter,
+    ConnectionTracker, DenialDetail, HttpDispatcher, Listener, RateLimiter, Result,
 };
 use clap::Parser;
 use std::sync::Arc;
@@ -34,6 +37,15 @@ fn env_u64(name: &str, default: u64) -> u64 {
         .unwrap_or(default)
 }
 
+/// Write out queued audit entries before exiting
+fn flush_audit(audit_logger: &AuditLogger) {
+    audit_logger.flush();
+    let dropped = audit_logger.dropped();
+    if dropped > 0 {
+        tracing::warn!("{} audit entries were dropped with a queue full", dropped);
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
@@ -49,9 +61,20 @@ async fn main() -> Result<()> {
 
     tracing::info!("carapace-server starting");
 
+    // Optional server config file; environment variables take precedence
+    let config = match std::env::var("CARAPACE_SERVER_CONFIG") {
+        Ok(config_file) if !config_file.is_empty() => {
+            tracing::info!("Loading server config from: {}", config_file);
+            Some(ServerConfig::from_file(&config_file)?)
+        }
+        _ => None,
+    };
+
     // Load policy from YAML file
     let policy_file = std::env::var("CARAPACE_POLICY_FILE")
-        .unwrap_or_else(|_| "/etc/carapace/policy.yaml".to_string());
+        .ok()
+        .or_else(|| config.as_ref().map(|c| c.policy_path.clone()))
+        .unwrap_or_else(|| "/etc/carapace/policy.yaml".to_string());
 
     tracing::info!("Loading policy from: {}", policy_file);
     let policy = PolicyConfig::from_file(&policy_file)
@@ -61,27 +84,121 @@ async fn main() -> Result<()> {
         policy.tools.len()
     );
 
-    // Create CLI dispatcher with policy
-    let cli_dispatcher = Arc::new(CliDispatcher::with_policy(policy.clone()));
-
-    // Create HTTP dispatcher with policy
-    let http_dispatcher = Arc::new(HttpDispatcher::with_policy(policy));
-
-    // Create audit logger (configurable via env)
-    let audit_log_file = std::env::var("CARAPACE_AUDIT_LOG").unwrap_or_else(|_| String::new());
-    let audit_logger = Arc::new(if audit_log_file.is_empty() {
+    // Create audit logger (configurable via env); what gets logged for
+    // each tool comes from its `audit` settings in the policy
+    let audit_log_file = std::env::var("CARAPACE_AUDIT_LOG")
+        .ok()
+        .or_else(|| config.as_ref().and_then(|c| c.audit.log_file.clone()))
+        .unwrap_or_default();
+    let (max_size_bytes, keep_logs) = config.as_ref().map_or((100 * 1024 * 1024, 10), |c| {
+        (c.audit.max_size_bytes, c.audit.keep_logs)
+    });
+    let audit_logger = if audit_log_file.is_empty() {
         AuditLogger::new()
     } else {
         tracing::info!("Audit logging to file: {}", audit_log_file);
-        AuditLogger::with_config(
+        let logger = AuditLogger::with_config(
+            true,
             true,
             true,
-            false,
             Some(audit_log_file),
-            100 * 1024 * 1024,
-            10,
-        )
-    });
+            max_size_bytes,
+            keep_logs,
+        );
+
+        // Entries are written by a dedicated thread behind a bounded queue
+        let queue_size = env_u32("CARAPACE_AUDIT_QUEUE_SIZE", 4096) as usize;
+        let backpressure: AuditBackpressure = match std::env::var("CARAPACE_AUDIT_BACKPRESSURE") {
+            Ok(value) if !value.is_empty() => value.parse()?,
+            _ => AuditBackpressure::default(),
+        };
+        let fsync: AuditFsync = match std::env::var("CARAPACE_AUDIT_FSYNC") {
+            Ok(value) if !value.is_empty() => value.parse()?,
+            _ => AuditFsync::default(),
+        };
+        tracing::info!(
+            "Audit writer: queue of {}, {:?} when full, fsync {:?}",
+            queue_size,
+            backpressure,
+            fsync
+        );
+        let logger = logger
+            .with_queue(queue_size, backpressure)
+            .with_fsync(fsync);
+
+        // Optional signed checkpoints over the audit hash chain
+        match std::env::var("CARAPACE_AUDIT_SIGNING_KEY") {
+            Ok(key_file) if !key_file.is_empty() => {
+                let key = carapace_server::audit::load_signing_key(&key_file)?;
+                let interval = env_u64("CARAPACE_AUDIT_CHECKPOINT_INTERVAL", 1000);
+                tracing::info!(
+                    "Audit checkpoints every {} entries, public key {}",
+                    interval,
+                    carapace_server::audit::public_key_base64(&key)
+                );
+                logger.with_signing_key(key, interval)
+            }
+            _ => logger,
+        }
+    };
+
+    // Export destinations from the server config
+    let mut audit_logger = audit_logger.with_policy(&policy);
+    for sink_config in config.iter().flat_map(|c| &c.audit.sinks) {
+        let sink = audit_sink::build_sink(sink_config)?;
+        tracing::info!("Exporting audit entries to {}", sink.name());
+        audit_logger = audit_logger.with_sink(sink);
+    }
+
+    // Optional forensic capture of full payloads, for tools that opt in
+    if let Ok(capture_dir) = std::env::var("CARAPACE_CAPTURE_DIR") {
+        if !capture_dir.is_empty() {
+            let key_file = std::env::var("CARAPACE_CAPTURE_KEY").map_err(|_| {
+                carapace_server::error::ServerError::ConfigError(
+                    "CARAPACE_CAPTURE_DIR is set but CARAPACE_CAPTURE_KEY is not".to_string(),
+                )
+            })?;
+            let key = carapace_server::capture::load_capture_key(&key_file)?;
+            let max_bytes = env_u64("CARAPACE_CAPTURE_MAX_BYTES", 1024 * 1024 * 1024);
+            tracing::info!(
+                "Capturing payloads to {} (at most {} bytes)",
+                capture_dir,
+                max_bytes
+            );
+            let store = carapace_server::capture::CaptureStore::open(&capture_dir, key, max_bytes)?;
+            audit_logger = audit_logger.with_capture(store);
+        }
+    }
+    let audit_logger = Arc::new(audit_logger);
+
+    // Optional candidate policy, evaluated next to the live one so that its
+    // disagreements show up in the audit log before it goes live
+    let candidate = match std::env::var("CARAPACE_CANDIDATE_POLICY_FILE") {
+        Ok(candidate_file) if !candidate_file.is_empty() => {
+            tracing::info!("Evaluating candidate policy from: {}", candidate_file);
+            Some(
+                PolicyConfig::from_file(&candidate_file)
+                    .map_err(|e| carapace_server::error::ServerError::ConfigError(e.to_string()))?,
+            )
+        }
+        _ => None,
+    };
+
+    // Create CLI dispatcher with policy
+    let mut cli_dispatcher =
+        CliDispatcher::with_policy(policy.clone()).with_audit_logger(audit_logger.clone());
+    if let Some(candidate) = &candidate {
+        cli_dispatcher = cli_dispatcher.with_candidate_policy(candidate.clone());
+    }
+    let cli_dispatcher = Arc::new(cli_dispatcher);
+
+    // Create HTTP dispatcher with policy
+    let mut http_dispatcher =
+        HttpDispatcher::with_policy(policy.clone()).with_audit_logger(audit_logger.clone());
+    if let Some(candidate) = candidate {
+        http_dispatcher = http_dispatcher.with_candidate_policy(candidate);
+    }
+    let http_dispatcher = Arc::new(http_dispatcher);
 
     // Create rate limiter (configurable via env)
     let rate_max = env_u32("CARAPACE_RATE_LIMIT_MAX", 1000);
@@ -93,6 +210,52 @@ async fn main() -> Result<()> {
         rate_window
     );
 
+    // Concurrency limits: per tool from the policy, plus an optional
+    // global cap (configurable via env)
+    let global_max_concurrent = match env_u32("CARAPACE_MAX_CONCURRENT", 0) {
+        0 => None,
+        max => Some(max as usize),
+    };
+    let max_queued = env_u32("CARAPACE_MAX_QUEUED", 64) as usize;
+    let queue_timeout = env_u64("CARAPACE_QUEUE_TIMEOUT_SECS", 30);
+    let concurrency = Arc::new(ConcurrencyLimiter::new(
+        &policy,
+        global_max_concurrent,
+        max_queued,
+        std::time::Duration::from_secs(queue_timeout),
+    ));
+    tracing::info!(
+        "Concurrency: global cap {:?}, queue of {} with {} second timeout",
+        global_max_concurrent,
+        max_queued,
+        queue_timeout
+    );
+
+    // Requests held for approval; operators decide through the debug server
+    let approvals = Arc::new(ApprovalQueue::new());
+    let needs_approval = policy.tools.values().any(|tool| match tool {
+        ToolPolicy::Cli(policy) => policy.require_approval.is_some(),
+        ToolPolicy::Http(policy) => policy.require_approval.is_some(),
+    });
+    let approvers = match std::env::var("CARAPACE_APPROVERS_FILE") {
+        Ok(approvers_file) if !approvers_file.is_empty() => Some(Arc::new(
+            carapace_server::approval::Approvers::load(&approvers_file)?,
+        )),
+        _ => None,
+    };
+    if needs_approval && (args.debug_listen.is_none() || approvers.is_none()) {
+        tracing::warn!(
+            "Policy requires approvals but --debug-listen or CARAPACE_APPROVERS_FILE is not set: held requests will time out"
+        );
+    }
+
+    // What clients learn about the rule behind a policy denial
+    let denial_detail: DenialDetail = match std::env::var("CARAPACE_DENIAL_DETAIL") {
+        Ok(detail) if !detail.is_empty() => detail.parse()?,
+        _ => DenialDetail::default(),
+    };
+    tracing::info!("Policy denial detail for clients: {:?}", denial_detail);
+
     // Connection limit (configurable via env)
     let max_connections = env_u32("CARAPACE_MAX_CONNECTIONS", 100) as usize;
     tracing::info!("Max concurrent connections: {}", max_connections);
@@ -105,11 +268,25 @@ async fn main() -> Result<()> {
         let debug_addr_parsed: std::net::SocketAddr = debug_addr.parse().map_err(|e| {
             carapace_server::ServerError::ConfigError(format!("Invalid debug address: {}", e))
         })?;
+        // Approval tokens travel in plain HTTP, so they stay on this host
+        if needs_approval && !debug_addr_parsed.ip().is_loopback() {
+            return Err(carapace_server::ServerError::ConfigError(format!(
+                "Policy requires approvals, so --debug-listen must be a loopback address, not {}",
+                debug_addr_parsed
+            )));
+        }
         let tracker_clone = connection_tracker.clone();
+        let concurrency = concurrency.clone();
+        let approvals = approvals.clone();
         tokio::spawn(async move {
-            if let Err(e) =
-                carapace_server::debug_server::start_debug_server(debug_addr_parsed, tracker_clone)
-                    .await
+            if let Err(e) = carapace_server::debug_server::start_debug_server(
+                debug_addr_parsed,
+                tracker_clone,
+                concurrency,
+                approvals,
+                approvers,
+            )
+            .await
             {
                 tracing::error!("Debug server error: {}", e);
             }
@@ -152,6 +329,8 @@ async fn main() -> Result<()> {
                             let tracker_clone = connection_tracker.clone();
                             let audit_logger = audit_logger.clone();
                             let rate_limiter = rate_limiter.clone();
+                            let concurrency = concurrency.clone();
+                            let approvals = approvals.clone();
                             let mut shutdown_rx = shutdown_tx.subscribe();
 
                             tokio::spawn(async move {
@@ -163,7 +342,11 @@ async fn main() -> Result<()> {
                                     http_dispatcher,
                                     audit_logger,
                                     rate_limiter,
-                                );
+                                )
+                                .with_concurrency_limiter(concurrency)
+                                .with_approval_queue(approvals)
+                                .with_denial_detail(denial_detail)
+                                .with_peer(addr.to_string());
 
                                 // Run connection until it closes or shutdown signal received
                                 tokio::select! {
@@ -238,6 +421,7 @@ async fn main() -> Result<()> {
             }
         }
 
+        flush_audit(&audit_logger);
         tracing::info!("Server shutdown complete");
     } else {
         // SSH mode: single connection on stdin/stdout
@@ -252,14 +436,26 @@ async fn main() -> Result<()> {
         let conn_listener = Listener::with_audit_and_rate_limit(
             cli_dispatcher,
             http_dispatcher,
-            audit_logger,
+            audit_logger.clone(),
             rate_limiter,
-        );
+        )
+        .with_concurrency_limiter(concurrency)
+        .with_approval_queue(approvals)
+        .with_denial_detail(denial_detail);
+        // sshd puts the client's address first in SSH_CLIENT
+        let conn_listener = match std::env::var("SSH_CLIENT") {
+            Ok(client) => match client.split_whitespace().next() {
+                Some(peer) => conn_listener.with_peer(peer.to_string()),
+                None => conn_listener,
+            },
+            Err(_) => conn_listener,
+        };
         let result = conn_listener
             .listen(tokio::io::stdin(), tokio::io::stdout())
             .await;
 
         connection_tracker.unregister(stdin_addr).await;
+        flush_audit(&audit_logger);
         result?;
     }
 
@@ -0,0 +1,218 @@
+use carapace_protocol::{Message, PtyData, TtySize};
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::{Child, Command};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::sandbox::Sandbox;
+
+/// Client input for a running interactive session
+#[derive(Debug)]
+pub enum PtyInput {
+    Data(Vec<u8>),
+    Resize(TtySize),
+}
+
+/// A pseudo-terminal pair. The child gets the slave side as its controlling
+/// terminal; the server reads and writes the master side.
+pub struct Pty {
+    master: OwnedFd,
+    slave: OwnedFd,
+}
+
+impl Pty {
+    pub fn open(size: TtySize) -> io::Result<Self> {
+        let mut master = -1;
+        let mut slave = -1;
+        let winsize = winsize(size);
+        // SAFETY: the out-pointers are valid for the call; name and termios
+        // may be null
+        let rc = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                &winsize,
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: openpty succeeded, so both descriptors are open and ours
+        unsafe {
+            Ok(Pty {
+                master: OwnedFd::from_raw_fd(master),
+                slave: OwnedFd::from_raw_fd(slave),
+            })
+        }
+    }
+
+    /// Spawn `cmd` as a session leader with the slave side as its controlling
+    /// terminal and stdio, confined to `sandbox` if given. Its process group
+    /// id equals its pid, like a command spawned with `process_group(0)`.
+    pub fn spawn(self, mut cmd: Command, sandbox: Option<Sandbox>) -> io::Result<(Child, OwnedFd)> {
+        cmd.stdin(self.slave.try_clone()?);
+        cmd.stdout(self.slave.try_clone()?);
+        cmd.stderr(self.slave.try_clone()?);
+        // SAFETY: setsid and ioctl are async-signal-safe
+        unsafe {
+            cmd.pre_exec(|| {
+                if libc::setsid() == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                if libc::ioctl(0, libc::TIOCSCTTY, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        if let Some(sandbox) = sandbox {
+            sandbox.confine(&mut cmd);
+        }
+        let child = cmd.spawn()?;
+        // Drop our copies of the slave side (including the ones held by
+        // `cmd`), so reading the master fails once the child's side closes
+        drop(cmd);
+        drop(self.slave);
+        Ok((child, self.master))
+    }
+}
+
+/// Apply a new window size to the terminal
+fn resize(master: &OwnedFd, size: TtySize) {
+    let winsize = winsize(size);
+    // SAFETY: TIOCSWINSZ reads a winsize from a valid pointer
+    if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &winsize) } == -1 {
+        tracing::warn!("Failed to resize terminal: {}", io::Error::last_os_error());
+    }
+}
+
+fn winsize(size: TtySize) -> libc::winsize {
+    libc::winsize {
+        ws_row: size.rows,
+        ws_col: size.cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+/// How an interactive session ended
+pub struct SessionEnd {
+    pub status: ExitStatus,
+    /// The session was killed for exceeding its timeout
+    pub timed_out: bool,
+}
+
+/// Everything a running session needs besides the child itself
+pub struct Session {
+    pub id: String,
+    pub master: OwnedFd,
+    pub input: mpsc::UnboundedReceiver<PtyInput>,
+    pub output: mpsc::UnboundedSender<Message>,
+    pub transcript: Option<PathBuf>,
+    pub timeout: std::time::Duration,
+}
+
+impl Session {
+    /// Relay terminal data until the child exits, times out or is cancelled.
+    /// Output is sent as `PtyData` and appended to the transcript, if any.
+    pub async fn run(
+        mut self,
+        child: &mut Child,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<SessionEnd> {
+        let mut reader = tokio::fs::File::from_std(File::from(self.master.try_clone()?));
+        let mut writer = tokio::fs::File::from_std(File::from(self.master.try_clone()?));
+        let mut transcript = match &self.transcript {
+            Some(path) => Some(open_transcript(path).await?),
+            None => None,
+        };
+
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        let mut buf = vec![0u8; 8192];
+        loop {
+            tokio::select! {
+                read = reader.read(&mut buf) => match read {
+                    // EIO once every process holding the terminal has exited
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if let Some(file) = transcript.as_mut() {
+                            if let Err(e) = file.write_all(&buf[..n]).await {
+                                tracing::warn!("Failed to write transcript: {}", e);
+                                transcript = None;
+                            }
+                        }
+                        let _ = self
+                            .output
+                            .send(Message::PtyData(PtyData::new(self.id.clone(), &buf[..n])));
+                    }
+                },
+                Some(input) = self.input.recv() => match input {
+                    PtyInput::Data(bytes) => {
+                        writer.write_all(&bytes).await?;
+                        writer.flush().await?;
+                    }
+                    PtyInput::Resize(size) => resize(&self.master, size),
+                },
+                _ = tokio::time::sleep_until(deadline) => {
+                    return Self::timed_out(child, &mut transcript).await;
+                }
+                _ = cancel.cancelled() => {
+                    crate::cli_dispatch::kill_process_group(child).await;
+                    return Err(anyhow::anyhow!("Session cancelled by client"));
+                }
+            }
+        }
+
+        // The terminal closed; the child may still be exiting
+        let status = tokio::select! {
+            status = child.wait() => status?,
+            _ = tokio::time::sleep_until(deadline) => {
+                return Self::timed_out(child, &mut transcript).await;
+            }
+            _ = cancel.cancelled() => {
+                crate::cli_dispatch::kill_process_group(child).await;
+                return Err(anyhow::anyhow!("Session cancelled by client"));
+            }
+        };
+        if let Some(file) = transcript.as_mut() {
+            file.flush().await.ok();
+        }
+        Ok(SessionEnd {
+            status,
+            timed_out: false,
+        })
+    }
+
+    async fn timed_out(
+        child: &mut Child,
+        transcript: &mut Option<tokio::fs::File>,
+    ) -> anyhow::Result<SessionEnd> {
+        tracing::warn!("Interactive session timed out");
+        crate::cli_dispatch::kill_process_group(child).await;
+        if let Some(file) = transcript.as_mut() {
+            file.flush().await.ok();
+        }
+        Ok(SessionEnd {
+            status: child.wait().await?,
+            timed_out: true,
+        })
+    }
+}
+
+async fn open_transcript(path: &Path) -> io::Result<tokio::fs::File> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+}
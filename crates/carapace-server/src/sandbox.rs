@@ -0,0 +1,551 @@
+use carapace_policy::SandboxPolicy;
+use std::ffi::CString;
+use std::io;
+use tokio::process::Command;
+
+/// Syscalls denied by `deny_dangerous`: tracing other processes, changing
+/// mounts, namespaces or kernel state, and loading code into the kernel
+const DANGEROUS_SYSCALLS: &[&str] = &[
+    "ptrace",
+    "process_vm_readv",
+    "process_vm_writev",
+    "mount",
+    "umount2",
+    "pivot_root",
+    "chroot",
+    "setns",
+    "unshare",
+    "open_by_handle_at",
+    "name_to_handle_at",
+    "swapon",
+    "swapoff",
+    "reboot",
+    "kexec_load",
+    "kexec_file_load",
+    "init_module",
+    "finit_module",
+    "delete_module",
+    "bpf",
+    "perf_event_open",
+    "userfaultfd",
+    "io_uring_setup",
+    "keyctl",
+    "add_key",
+    "request_key",
+    "acct",
+    "syslog",
+    "settimeofday",
+    "clock_settime",
+    "adjtimex",
+    "sethostname",
+    "setdomainname",
+];
+
+/// Syscalls a policy may deny by name
+const SYSCALLS: &[(&str, libc::c_long)] = &[
+    ("ptrace", libc::SYS_ptrace),
+    ("process_vm_readv", libc::SYS_process_vm_readv),
+    ("process_vm_writev", libc::SYS_process_vm_writev),
+    ("mount", libc::SYS_mount),
+    ("umount2", libc::SYS_umount2),
+    ("pivot_root", libc::SYS_pivot_root),
+    ("chroot", libc::SYS_chroot),
+    ("setns", libc::SYS_setns),
+    ("unshare", libc::SYS_unshare),
+    ("open_by_handle_at", libc::SYS_open_by_handle_at),
+    ("name_to_handle_at", libc::SYS_name_to_handle_at),
+    ("swapon", libc::SYS_swapon),
+    ("swapoff", libc::SYS_swapoff),
+    ("reboot", libc::SYS_reboot),
+    ("kexec_load", libc::SYS_kexec_load),
+    ("kexec_file_load", libc::SYS_kexec_file_load),
+    ("init_module", libc::SYS_init_module),
+    ("finit_module", libc::SYS_finit_module),
+    ("delete_module", libc::SYS_delete_module),
+    ("bpf", libc::SYS_bpf),
+    ("perf_event_open", libc::SYS_perf_event_open),
+    ("userfaultfd", libc::SYS_userfaultfd),
+    ("io_uring_setup", libc::SYS_io_uring_setup),
+    ("keyctl", libc::SYS_keyctl),
+    ("add_key", libc::SYS_add_key),
+    ("request_key", libc::SYS_request_key),
+    ("acct", libc::SYS_acct),
+    ("syslog", libc::SYS_syslog),
+    ("settimeofday", libc::SYS_settimeofday),
+    ("clock_settime", libc::SYS_clock_settime),
+    ("adjtimex", libc::SYS_adjtimex),
+    ("sethostname", libc::SYS_sethostname),
+    ("setdomainname", libc::SYS_setdomainname),
+    ("socket", libc::SYS_socket),
+    ("socketpair", libc::SYS_socketpair),
+    ("connect", libc::SYS_connect),
+    ("bind", libc::SYS_bind),
+    ("listen", libc::SYS_listen),
+    ("accept", libc::SYS_accept),
+    ("accept4", libc::SYS_accept4),
+    ("sendto", libc::SYS_sendto),
+    ("sendmsg", libc::SYS_sendmsg),
+    ("recvfrom", libc::SYS_recvfrom),
+    ("recvmsg", libc::SYS_recvmsg),
+    ("kill", libc::SYS_kill),
+    ("tkill", libc::SYS_tkill),
+    ("tgkill", libc::SYS_tgkill),
+    ("setuid", libc::SYS_setuid),
+    ("setgid", libc::SYS_setgid),
+    ("setreuid", libc::SYS_setreuid),
+    ("setregid", libc::SYS_setregid),
+    ("setresuid", libc::SYS_setresuid),
+    ("setresgid", libc::SYS_setresgid),
+    ("setgroups", libc::SYS_setgroups),
+    ("fchown", libc::SYS_fchown),
+    ("fchownat", libc::SYS_fchownat),
+    ("fchmod", libc::SYS_fchmod),
+    ("fchmodat", libc::SYS_fchmodat),
+    ("mkdirat", libc::SYS_mkdirat),
+    ("mknodat", libc::SYS_mknodat),
+    ("unlinkat", libc::SYS_unlinkat),
+    ("renameat2", libc::SYS_renameat2),
+    ("linkat", libc::SYS_linkat),
+    ("symlinkat", libc::SYS_symlinkat),
+    ("truncate", libc::SYS_truncate),
+    ("ftruncate", libc::SYS_ftruncate),
+    ("uname", libc::SYS_uname),
+];
+
+/// `seccomp_data.arch` of the architecture we were built for
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_003e); // AUDIT_ARCH_X86_64
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7); // AUDIT_ARCH_AARCH64
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const AUDIT_ARCH: Option<u32> = None;
+
+/// x32 syscalls share the x86_64 audit arch but set this bit in the number
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: Option<u32> = Some(0x4000_0000);
+#[cfg(not(target_arch = "x86_64"))]
+const X32_SYSCALL_BIT: Option<u32> = None;
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+type Resource = libc::c_int;
+
+struct Mount {
+    source: CString,
+    target: CString,
+    read_only: bool,
+}
+
+/// A tool's sandbox, resolved from its policy ahead of time: the code that
+/// runs between fork and exec must not allocate, so every path, limit and
+/// filter is prepared here.
+pub struct Sandbox {
+    clear_env: bool,
+    env_allow: Vec<String>,
+    uid: Option<libc::uid_t>,
+    gid: Option<libc::gid_t>,
+    rlimits: Vec<(Resource, u64)>,
+    no_new_privs: bool,
+    seccomp: Option<Vec<libc::sock_filter>>,
+    unshare_flags: libc::c_int,
+    pid_namespace: bool,
+    mount_proc: bool,
+    bind_mounts: Vec<Mount>,
+}
+
+impl Sandbox {
+    pub fn prepare(policy: &SandboxPolicy) -> anyhow::Result<Self> {
+        let namespaces = &policy.namespaces;
+        if !policy.bind_mounts.is_empty() && !namespaces.mount {
+            // Without a private mount namespace they would land on the host
+            return Err(anyhow::anyhow!(
+                "Sandbox bind mounts require the mount namespace"
+            ));
+        }
+
+        let mut unshare_flags = 0;
+        if namespaces.mount {
+            unshare_flags |= libc::CLONE_NEWNS;
+        }
+        if namespaces.network {
+            unshare_flags |= libc::CLONE_NEWNET;
+        }
+        if namespaces.pid {
+            unshare_flags |= libc::CLONE_NEWPID;
+        }
+
+        let limits = &policy.rlimits;
+        let rlimits = [
+            (libc::RLIMIT_CPU, limits.cpu_secs),
+            (libc::RLIMIT_AS, limits.memory_bytes),
+            (libc::RLIMIT_FSIZE, limits.file_size_bytes),
+            (libc::RLIMIT_NPROC, limits.max_processes),
+        ]
+        .into_iter()
+        .filter_map(|(resource, limit)| limit.map(|limit| (resource, limit)))
+        .collect();
+
+        let seccomp = match &policy.seccomp {
+            Some(seccomp) => {
+                let mut names: Vec<&str> = Vec::new();
+                if seccomp.deny_dangerous {
+                    names.extend(DANGEROUS_SYSCALLS);
+                }
+                names.extend(seccomp.deny_syscalls.iter().map(String::as_str));
+                Some(seccomp_filter(&names)?)
+            }
+            None => None,
+        };
+
+        let bind_mounts = policy
+            .bind_mounts
+            .iter()
+            .map(|mount| {
+                Ok(Mount {
+                    source: CString::new(mount.source.as_str())?,
+                    target: CString::new(mount.target.as_str())?,
+                    read_only: mount.read_only,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Sandbox {
+            clear_env: policy.clear_env,
+            env_allow: policy.env_allow.clone(),
+            uid: policy.uid,
+            gid: policy.gid,
+            rlimits,
+            no_new_privs: policy.no_new_privs || seccomp.is_some(),
+            seccomp,
+            unshare_flags,
+            pid_namespace: namespaces.pid,
+            mount_proc: namespaces.pid && namespaces.mount,
+            bind_mounts,
+        })
+    }
+
+    /// Start from an empty environment, keeping only the allowed server
+    /// variables. Must run before the request's variables are added.
+    pub fn restrict_env(&self, cmd: &mut Command) {
+        if !self.clear_env {
+            return;
+        }
+        cmd.env_clear();
+        for key in &self.env_allow {
+            if let Some(value) = std::env::var_os(key) {
+                cmd.env(key, value);
+            }
+        }
+    }
+
+    /// Enter the sandbox in the child before exec. Registered after any
+    /// other `pre_exec` hooks, since the seccomp filter and dropped
+    /// privileges may forbid what they do.
+    pub fn confine(self, cmd: &mut Command) {
+        // SAFETY: `enter` only makes async-signal-safe calls and does not
+        // allocate
+        unsafe {
+            cmd.pre_exec(move || self.enter());
+        }
+    }
+
+    fn enter(&self) -> io::Result<()> {
+        // SAFETY: plain syscalls on valid, NUL-terminated strings and
+        // structs prepared by `prepare`
+        unsafe {
+            if self.unshare_flags != 0 {
+                check(libc::unshare(self.unshare_flags))?;
+            }
+            if self.unshare_flags & libc::CLONE_NEWNS != 0 {
+                // Keep our mounts from propagating back to the host
+                check(libc::mount(
+                    std::ptr::null(),
+                    c"/".as_ptr(),
+                    std::ptr::null(),
+                    libc::MS_REC | libc::MS_PRIVATE,
+                    std::ptr::null(),
+                ))?;
+                for mount in &self.bind_mounts {
+                    check(libc::mount(
+                        mount.source.as_ptr(),
+                        mount.target.as_ptr(),
+                        std::ptr::null(),
+                        libc::MS_BIND | libc::MS_REC,
+                        std::ptr::null(),
+                    ))?;
+                    if mount.read_only {
+                        check(libc::mount(
+                            std::ptr::null(),
+                            mount.target.as_ptr(),
+                            std::ptr::null(),
+                            libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY,
+                            std::ptr::null(),
+                        ))?;
+                    }
+                }
+            }
+            if self.pid_namespace {
+                fork_into_pid_namespace()?;
+                if self.mount_proc {
+                    check(libc::mount(
+                        c"proc".as_ptr(),
+                        c"/proc".as_ptr(),
+                        c"proc".as_ptr(),
+                        libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                        std::ptr::null(),
+                    ))?;
+                }
+            }
+
+            for &(resource, limit) in &self.rlimits {
+                let rlimit = libc::rlimit {
+                    rlim_cur: limit as libc::rlim_t,
+                    rlim_max: limit as libc::rlim_t,
+                };
+                check(libc::setrlimit(resource, &rlimit))?;
+            }
+
+            if self.uid.is_some() || self.gid.is_some() {
+                check(libc::setgroups(0, std::ptr::null()))?;
+            }
+            if let Some(gid) = self.gid {
+                check(libc::setgid(gid))?;
+            }
+            if let Some(uid) = self.uid {
+                check(libc::setuid(uid))?;
+            }
+
+            if self.no_new_privs {
+                check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+            }
+            // Last: the filter may deny syscalls used above
+            if let Some(filter) = &self.seccomp {
+                let program = libc::sock_fprog {
+                    len: filter.len() as libc::c_ushort,
+                    filter: filter.as_ptr() as *mut libc::sock_filter,
+                };
+                check(libc::prctl(
+                    libc::PR_SET_SECCOMP,
+                    libc::SECCOMP_MODE_FILTER,
+                    &program as *const libc::sock_fprog,
+                ))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check(rc: libc::c_int) -> io::Result<()> {
+    if rc == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Only children of the caller join a new PID namespace, so fork: the child
+/// becomes pid 1 inside and goes on to exec the tool, while this process
+/// stays outside, waits and exits with the child's status. Timeouts and
+/// forwarded signals reach this outer process through the process group;
+/// the child is killed when it dies.
+///
+/// # Safety
+///
+/// Must only be called between fork and exec of a spawned command.
+unsafe fn fork_into_pid_namespace() -> io::Result<()> {
+    // Closed by the outer process when it exits, so the child can tell
+    // whether it outlived it before PR_SET_PDEATHSIG was in place
+    let mut alive = [0; 2];
+    check(libc::pipe2(alive.as_mut_ptr(), libc::O_CLOEXEC))?;
+    let [read_end, write_end] = alive;
+
+    match libc::fork() {
+        -1 => Err(io::Error::last_os_error()),
+        0 => {
+            check(libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL))?;
+            libc::close(write_end);
+            let mut poll = libc::pollfd {
+                fd: read_end,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            if libc::poll(&mut poll, 1, 0) != 0 {
+                libc::_exit(1);
+            }
+            libc::close(read_end);
+            Ok(())
+        }
+        child => {
+            // Close everything but stdio and the pipe, including the pipe
+            // the parent uses to learn about exec failures; the child still
+            // holds it and reports or closes it on exec
+            close_fds_from(3, write_end);
+            libc::_exit(wait_exit_code(child))
+        }
+    }
+}
+
+/// Close every descriptor from `first` up, except `keep`
+unsafe fn close_fds_from(first: libc::c_int, keep: libc::c_int) {
+    let ranges = [(first, keep - 1), (keep + 1, libc::c_int::MAX)];
+    for (low, high) in ranges {
+        if low > high {
+            continue;
+        }
+        let closed = libc::syscall(libc::SYS_close_range, low as u32, high as u32, 0) == 0;
+        if !closed {
+            // Kernels before 5.9
+            for fd in low..high.min(4096) {
+                libc::close(fd);
+            }
+        }
+    }
+}
+
+/// Wait for `child` and turn its status into ours; a child killed by a
+/// signal is reported by dying of the same signal
+unsafe fn wait_exit_code(child: libc::pid_t) -> libc::c_int {
+    let mut status = 0;
+    while libc::waitpid(child, &mut status, 0) == -1 {
+        if io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+            return 127;
+        }
+    }
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::kill(libc::getpid(), signal);
+        return 128 + signal;
+    }
+    libc::WEXITSTATUS(status)
+}
+
+/// BPF program that fails the named syscalls with EPERM, kills the process
+/// on a foreign architecture and allows everything else
+fn seccomp_filter(names: &[&str]) -> anyhow::Result<Vec<libc::sock_filter>> {
+    let arch = AUDIT_ARCH.ok_or_else(|| {
+        anyhow::anyhow!("Seccomp profiles are not supported on this architecture")
+    })?;
+
+    let mut numbers = names
+        .iter()
+        .map(|name| {
+            SYSCALLS
+                .iter()
+                .find(|(known, _)| known == name)
+                .map(|&(_, number)| number as u32)
+                .ok_or_else(|| anyhow::anyhow!("Unknown syscall in seccomp profile: {}", name))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    numbers.sort_unstable();
+    numbers.dedup();
+
+    let checks = numbers.len() + usize::from(X32_SYSCALL_BIT.is_some());
+    if checks > u8::MAX as usize {
+        return Err(anyhow::anyhow!("Too many syscalls in seccomp profile"));
+    }
+
+    // Offsets into struct seccomp_data
+    const NR: u32 = 0;
+    const ARCH: u32 = 4;
+
+    let mut filter = vec![
+        bpf_stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, ARCH),
+        bpf_jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, arch, 1, 0),
+        bpf_stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        bpf_stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, NR),
+    ];
+    // Each check jumps to the deny return, which follows the allow return
+    // placed after the last check
+    let mut remaining = checks;
+    if let Some(bit) = X32_SYSCALL_BIT {
+        filter.push(bpf_jump(
+            libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K,
+            bit,
+            remaining as u8,
+            0,
+        ));
+        remaining -= 1;
+    }
+    for number in numbers {
+        filter.push(bpf_jump(
+            libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+            number,
+            remaining as u8,
+            0,
+        ));
+        remaining -= 1;
+    }
+    filter.push(bpf_stmt(
+        libc::BPF_RET | libc::BPF_K,
+        libc::SECCOMP_RET_ALLOW,
+    ));
+    filter.push(bpf_stmt(
+        libc::BPF_RET | libc::BPF_K,
+        libc::SECCOMP_RET_ERRNO | libc::EPERM as u32,
+    ));
+    Ok(filter)
+}
+
+fn bpf_stmt(code: u32, k: u32) -> libc::sock_filter {
+    bpf_jump(code, k, 0, 0)
+}
+
+fn bpf_jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use carapace_policy::{BindMount, SeccompPolicy};
+
+    #[test]
+    fn test_unknown_syscall_rejected() {
+        let policy = SandboxPolicy {
+            seccomp: Some(SeccompPolicy {
+                deny_dangerous: true,
+                deny_syscalls: vec!["no_such_syscall".to_string()],
+            }),
+            ..Default::default()
+        };
+        let err = Sandbox::prepare(&policy).err().unwrap();
+        assert!(err.to_string().contains("no_such_syscall"));
+    }
+
+    #[test]
+    fn test_bind_mounts_require_mount_namespace() {
+        let policy = SandboxPolicy {
+            bind_mounts: vec![BindMount {
+                source: "/etc".to_string(),
+                target: "/mnt".to_string(),
+                read_only: true,
+            }],
+            ..Default::default()
+        };
+        assert!(Sandbox::prepare(&policy).is_err());
+    }
+
+    #[test]
+    fn test_seccomp_implies_no_new_privs() {
+        let policy = SandboxPolicy {
+            seccomp: Some(SeccompPolicy {
+                deny_dangerous: true,
+                deny_syscalls: vec!["ptrace".to_string()],
+            }),
+            ..Default::default()
+        };
+        let sandbox = Sandbox::prepare(&policy).unwrap();
+        assert!(sandbox.no_new_privs);
+        // arch check (3) + load + x32 check + one check per distinct syscall
+        // + allow + deny
+        let expected = 4 + usize::from(X32_SYSCALL_BIT.is_some()) + DANGEROUS_SYSCALLS.len() + 2;
+        assert_eq!(sandbox.seccomp.unwrap().len(), expected);
+    }
+}
@@ -74,6 +74,8 @@ tools:
 fn test_cli_dispatcher_creation_with_empty_policy() {
     let policy = PolicyConfig {
         tools: HashMap::new(),
+        mode: carapace_policy::PolicyMode::Enforce,
+        source: None,
     };
 
     let _dispatcher = CliDispatcher::with_policy(policy);
@@ -85,6 +87,8 @@ fn test_cli_dispatcher_creation_with_empty_policy() {
 fn test_http_dispatcher_creation_with_empty_policy() {
     let policy = PolicyConfig {
         tools: HashMap::new(),
+        mode: carapace_policy::PolicyMode::Enforce,
+        source: None,
     };
 
     let _dispatcher = HttpDispatcher::with_policy(policy);
@@ -96,6 +100,8 @@ fn test_http_dispatcher_creation_with_empty_policy() {
 fn test_listener_creation() {
     let policy = PolicyConfig {
         tools: HashMap::new(),
+        mode: carapace_policy::PolicyMode::Enforce,
+        source: None,
     };
 
     let cli_dispatcher = Arc::new(CliDispatcher::with_policy(policy.clone()));
@@ -283,6 +289,8 @@ fn test_dispatcher_creation_with_empty_policy() {
     // Policy with no tools
     let policy = PolicyConfig {
         tools: HashMap::new(),
+        mode: carapace_policy::PolicyMode::Enforce,
+        source: None,
     };
 
     // Creating dispatchers should work
@@ -5,7 +5,7 @@
 ///
 /// We use a mock HTTP server to simulate signal-cli or other HTTP upstreams.
 use carapace_policy::{HttpPolicy, PolicyConfig, ToolPolicy};
-use carapace_protocol::HttpRequest;
+use carapace_protocol::{HttpRequest, PayloadEncoding};
 use carapace_server::http_dispatch::HttpDispatcher;
 use std::collections::HashMap;
 use std::net::SocketAddr;
@@ -71,10 +71,13 @@ async fn test_http_dispatch_allowed_request() {
     // Create policy
     let http_policy = HttpPolicy {
         upstream: format!("http://{}", mock_addr),
+        mode: None,
         jsonrpc_allow_methods: vec!["version".to_string(), "send".to_string()],
         jsonrpc_deny_methods: vec![],
         jsonrpc_param_filters: HashMap::new(),
         rate_limit: None,
+        max_concurrent: None,
+        require_approval: None,
         timeout_secs: None,
         audit: Default::default(),
     };
@@ -82,7 +85,11 @@ async fn test_http_dispatch_allowed_request() {
     let mut tools = HashMap::new();
     tools.insert("signal-cli".to_string(), ToolPolicy::Http(http_policy));
 
-    let policy = PolicyConfig { tools };
+    let policy = PolicyConfig {
+        tools,
+        mode: carapace_policy::PolicyMode::Enforce,
+        source: None,
+    };
 
     // Create dispatcher with policy
     let dispatcher = HttpDispatcher::with_policy(policy);
@@ -99,6 +106,7 @@ async fn test_http_dispatch_allowed_request() {
             h
         },
         body: Some(r#"{"jsonrpc":"2.0","id":"1","method":"version","params":{}}"#.to_string()),
+        encoding: PayloadEncoding::Utf8,
     };
 
     // Dispatch request
@@ -123,10 +131,13 @@ async fn test_http_dispatch_denied_method() {
     // Create policy that denies 'deleteEverything'
     let http_policy = HttpPolicy {
         upstream: format!("http://{}", mock_addr),
+        mode: None,
         jsonrpc_allow_methods: vec!["version".to_string(), "send".to_string()],
         jsonrpc_deny_methods: vec!["deleteEverything".to_string()],
         jsonrpc_param_filters: HashMap::new(),
         rate_limit: None,
+        max_concurrent: None,
+        require_approval: None,
         timeout_secs: None,
         audit: Default::default(),
     };
@@ -134,7 +145,11 @@ async fn test_http_dispatch_denied_method() {
     let mut tools = HashMap::new();
     tools.insert("signal-cli".to_string(), ToolPolicy::Http(http_policy));
 
-    let policy = PolicyConfig { tools };
+    let policy = PolicyConfig {
+        tools,
+        mode: carapace_policy::PolicyMode::Enforce,
+        source: None,
+    };
     let dispatcher = HttpDispatcher::with_policy(policy);
 
     // Create HTTP request for denied method
@@ -147,6 +162,7 @@ async fn test_http_dispatch_denied_method() {
         body: Some(
             r#"{"jsonrpc":"2.0","id":"1","method":"deleteEverything","params":{}}"#.to_string(),
         ),
+        encoding: PayloadEncoding::Utf8,
     };
 
     // Dispatch should fail policy validation
@@ -169,10 +185,13 @@ async fn test_http_dispatch_tool_not_in_policy() {
     // Create policy with only 'signal-cli' tool
     let http_policy = HttpPolicy {
         upstream: format!("http://{}", mock_addr),
+        mode: None,
         jsonrpc_allow_methods: vec!["version".to_string()],
         jsonrpc_deny_methods: vec![],
         jsonrpc_param_filters: HashMap::new(),
         rate_limit: None,
+        max_concurrent: None,
+        require_approval: None,
         timeout_secs: None,
         audit: Default::default(),
     };
@@ -180,7 +199,11 @@ async fn test_http_dispatch_tool_not_in_policy() {
     let mut tools = HashMap::new();
     tools.insert("signal-cli".to_string(), ToolPolicy::Http(http_policy));
 
-    let policy = PolicyConfig { tools };
+    let policy = PolicyConfig {
+        tools,
+        mode: carapace_policy::PolicyMode::Enforce,
+        source: None,
+    };
     let dispatcher = HttpDispatcher::with_policy(policy);
 
     // Try to use 'unknown-tool' which is not in policy
@@ -191,6 +214,7 @@ async fn test_http_dispatch_tool_not_in_policy() {
         path: "/api/v1/rpc".to_string(),
         headers: HashMap::new(),
         body: Some(r#"{"jsonrpc":"2.0","id":"1","method":"version","params":{}}"#.to_string()),
+        encoding: PayloadEncoding::Utf8,
     };
 
     // Dispatch should fail because tool is not in policy
@@ -212,10 +236,13 @@ async fn test_http_dispatch_preserves_request_path() {
     // Create policy
     let http_policy = HttpPolicy {
         upstream: format!("http://{}", mock_addr),
+        mode: None,
         jsonrpc_allow_methods: vec!["version".to_string()],
         jsonrpc_deny_methods: vec![],
         jsonrpc_param_filters: HashMap::new(),
         rate_limit: None,
+        max_concurrent: None,
+        require_approval: None,
         timeout_secs: None,
         audit: Default::default(),
     };
@@ -223,7 +250,11 @@ async fn test_http_dispatch_preserves_request_path() {
     let mut tools = HashMap::new();
     tools.insert("signal-cli".to_string(), ToolPolicy::Http(http_policy));
 
-    let policy = PolicyConfig { tools };
+    let policy = PolicyConfig {
+        tools,
+        mode: carapace_policy::PolicyMode::Enforce,
+        source: None,
+    };
     let dispatcher = HttpDispatcher::with_policy(policy);
 
     // Create request with specific path
@@ -234,6 +265,7 @@ async fn test_http_dispatch_preserves_request_path() {
         path: "/api/v1/rpc".to_string(), // Specific path that should be preserved
         headers: HashMap::new(),
         body: Some(r#"{"jsonrpc":"2.0","id":"1","method":"version","params":{}}"#.to_string()),
+        encoding: PayloadEncoding::Utf8,
     };
 
     // Dispatch request
@@ -266,10 +298,13 @@ async fn test_http_dispatch_with_json_rpc_param_filtering() {
 
     let http_policy = HttpPolicy {
         upstream: format!("http://{}", mock_addr),
+        mode: None,
         jsonrpc_allow_methods: vec!["send".to_string()],
         jsonrpc_deny_methods: vec![],
         jsonrpc_param_filters: param_filters,
         rate_limit: None,
+        max_concurrent: None,
+        require_approval: None,
         timeout_secs: None,
         audit: Default::default(),
     };
@@ -277,7 +312,11 @@ async fn test_http_dispatch_with_json_rpc_param_filtering() {
     let mut tools = HashMap::new();
     tools.insert("signal-cli".to_string(), ToolPolicy::Http(http_policy));
 
-    let policy = PolicyConfig { tools };
+    let policy = PolicyConfig {
+        tools,
+        mode: carapace_policy::PolicyMode::Enforce,
+        source: None,
+    };
     let dispatcher = HttpDispatcher::with_policy(policy);
 
     // Request with allowed phone number
@@ -290,6 +329,7 @@ async fn test_http_dispatch_with_json_rpc_param_filtering() {
         body: Some(
             r#"{"jsonrpc":"2.0","id":"1","method":"send","params":{"recipientNumber":"+15551234567","message":"test"}}"#.to_string(),
         ),
+        encoding: PayloadEncoding::Utf8,
     };
 
     // Should succeed
@@ -305,9 +345,103 @@ async fn test_http_dispatch_with_json_rpc_param_filtering() {
         body: Some(
             r#"{"jsonrpc":"2.0","id":"1","method":"send","params":{"recipientNumber":"+18009999999","message":"test"}}"#.to_string(),
         ),
+        encoding: PayloadEncoding::Utf8,
     };
 
     // Should fail policy validation (number not in allow list)
     let response = dispatcher.dispatch_http(blocked_req, None).await;
     assert!(response.is_err());
 }
+
+#[tokio::test]
+async fn test_http_dispatch_binary_body_roundtrip() {
+    // Mock upstream that echoes the raw request body back as application/octet-stream
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Failed to bind mock server");
+    let mock_addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        if let Ok((mut socket, _)) = listener.accept().await {
+            let mut buf = Vec::new();
+            let mut tmp = [0u8; 4096];
+            // Read headers plus the 4-byte body
+            while !buf.windows(4).any(|w| w == b"\r\n\r\n") || buf.len() < 4 {
+                let n = socket.read(&mut tmp).await.unwrap_or(0);
+                if n == 0 {
+                    break;
+                }
+                buf.extend_from_slice(&tmp[..n]);
+            }
+            let header_end = buf.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+            while buf.len() < header_end + 4 {
+                let n = socket.read(&mut tmp).await.unwrap_or(0);
+                if n == 0 {
+                    break;
+                }
+                buf.extend_from_slice(&tmp[..n]);
+            }
+            let body = buf[header_end..].to_vec();
+
+            let mut response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\n\r\n",
+                body.len()
+            )
+            .into_bytes();
+            response.extend_from_slice(&body);
+            let _ = socket.write_all(&response).await;
+        }
+    });
+
+    let http_policy = HttpPolicy {
+        upstream: format!("http://{}", mock_addr),
+        mode: None,
+        jsonrpc_allow_methods: vec![],
+        jsonrpc_deny_methods: vec![],
+        jsonrpc_param_filters: HashMap::new(),
+        rate_limit: None,
+        max_concurrent: None,
+        require_approval: None,
+        timeout_secs: None,
+        audit: Default::default(),
+    };
+
+    let mut tools = HashMap::new();
+    tools.insert("files".to_string(), ToolPolicy::Http(http_policy));
+    let dispatcher = HttpDispatcher::with_policy(PolicyConfig {
+        tools,
+        mode: carapace_policy::PolicyMode::Enforce,
+        source: None,
+    });
+
+    // gzip magic followed by bytes that are not valid UTF-8
+    let raw = vec![0x1f, 0x8b, 0xff, 0x00];
+    let encoding = PayloadEncoding::for_bytes(&[&raw]);
+    assert_eq!(encoding, PayloadEncoding::Base64);
+
+    let http_req = HttpRequest {
+        id: "binary-1".to_string(),
+        tool: "files".to_string(),
+        method: "POST".to_string(),
+        path: "/upload".to_string(),
+        headers: {
+            let mut h = HashMap::new();
+            h.insert(
+                "Content-Type".to_string(),
+                "application/octet-stream".to_string(),
+            );
+            h
+        },
+        body: Some(encoding.encode(&raw)),
+        encoding,
+    };
+
+    let response = dispatcher
+        .dispatch_http(http_req, None)
+        .await
+        .expect("dispatch failed")
+        .expect("expected buffered response");
+
+    assert_eq!(response.encoding, PayloadEncoding::Base64);
+    assert_eq!(response.body_bytes().unwrap(), Some(raw));
+}
@@ -1,5 +1,5 @@
 use carapace_policy::PolicyValidator;
-use carapace_protocol::{HttpRequest, HttpResponse};
+use carapace_protocol::{HttpRequest, HttpResponse, PayloadEncoding};
 use std::collections::HashMap;
 
 #[test]
@@ -48,6 +48,7 @@ fn test_http_response_status_codes() {
             status: status as u16,
             headers: HashMap::new(),
             body: None,
+            encoding: PayloadEncoding::Utf8,
         };
 
         assert_eq!(resp.status, status as u16);
@@ -65,6 +66,7 @@ fn test_http_response_headers_preservation() {
         status: 200,
         headers,
         body: None,
+        encoding: PayloadEncoding::Utf8,
     };
 
     assert_eq!(resp.headers.len(), 2);
@@ -81,6 +83,7 @@ fn test_http_response_smuggling_attempt() {
         status: 200,
         headers: HashMap::new(),
         body: Some(malicious_body.to_string()),
+        encoding: PayloadEncoding::Utf8,
     };
 
     // Body should be treated as opaque data
@@ -221,6 +224,7 @@ fn test_response_body_encoding() {
             h
         },
         body: Some(r#"{"status":"ok","message":"你好"}"#.to_string()),
+        encoding: PayloadEncoding::Utf8,
     };
 
     assert!(resp.body.is_some());
@@ -262,6 +266,7 @@ fn test_concurrent_http_requests_isolation() {
             path: "/api".to_string(),
             headers: HashMap::new(),
             body: Some(r#"{"method":"send","to":"+15551234567"}"#.to_string()),
+            encoding: PayloadEncoding::Utf8,
         },
         HttpRequest {
             id: "req-2".to_string(),
@@ -270,6 +275,7 @@ fn test_concurrent_http_requests_isolation() {
             path: "/api".to_string(),
             headers: HashMap::new(),
             body: Some(r#"{"method":"receive"}"#.to_string()),
+            encoding: PayloadEncoding::Utf8,
         },
     ];
 
@@ -57,6 +57,8 @@ fn test_missing_policy_file() {
     // If no policy file exists, all tools should be denied
     let config = PolicyConfig {
         tools: HashMap::new(),
+        mode: carapace_policy::PolicyMode::Enforce,
+        source: None,
     };
 
     assert!(config.tools.is_empty(), "Empty config means deny all");
@@ -3,7 +3,7 @@
 /// Verifies that SSE events are delivered in real-time without buffering,
 /// addressing the issue where events were previously delayed by 2 seconds.
 use carapace_policy::{HttpPolicy, PolicyConfig, ToolPolicy};
-use carapace_protocol::{HttpRequest, Message};
+use carapace_protocol::{HttpRequest, Message, PayloadEncoding};
 use carapace_server::HttpDispatcher;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -14,16 +14,21 @@ use tokio::sync::mpsc;
 fn create_test_dispatcher() -> Arc<HttpDispatcher> {
     let mut policy = PolicyConfig {
         tools: HashMap::new(),
+        mode: carapace_policy::PolicyMode::Enforce,
+        source: None,
     };
 
     policy.tools.insert(
         "signal-cli".to_string(),
         ToolPolicy::Http(HttpPolicy {
             upstream: "http://127.0.0.1:18080".to_string(),
+            mode: None,
             jsonrpc_allow_methods: vec!["send".to_string(), "receive".to_string()],
             jsonrpc_deny_methods: vec![],
             jsonrpc_param_filters: HashMap::new(),
             rate_limit: None,
+            max_concurrent: None,
+            require_approval: None,
             timeout_secs: Some(30),
             audit: Default::default(),
         }),
@@ -46,6 +51,7 @@ async fn test_sse_events_sent_immediately_not_buffered() {
         path: "/api/v1/events".to_string(),
         headers: HashMap::new(),
         body: None,
+        encoding: PayloadEncoding::Utf8,
     };
 
     // Create mpsc channel for events
@@ -101,6 +107,7 @@ async fn test_non_sse_endpoints_still_get_single_response() {
             h
         },
         body: Some(r#"{"jsonrpc":"2.0","id":"1","method":"version"}"#.to_string()),
+        encoding: PayloadEncoding::Utf8,
     };
 
     let (tx, _rx) = mpsc::unbounded_channel::<Message>();
@@ -133,6 +140,7 @@ async fn test_dispatcher_handles_both_sse_and_regular() {
         path: "/api/v1/events".to_string(),
         headers: HashMap::new(),
         body: None,
+        encoding: PayloadEncoding::Utf8,
     };
 
     let (tx1, _rx1) = mpsc::unbounded_channel();
@@ -154,6 +162,7 @@ async fn test_dispatcher_handles_both_sse_and_regular() {
             h
         },
         body: Some(r#"{"jsonrpc":"2.0"}"#.to_string()),
+        encoding: PayloadEncoding::Utf8,
     };
 
     let (tx2, _rx2) = mpsc::unbounded_channel();
@@ -181,6 +190,7 @@ async fn test_dispatcher_error_handling() {
         path: "/api/v1/events".to_string(),
         headers: HashMap::new(),
         body: None,
+        encoding: PayloadEncoding::Utf8,
     };
 
     let (tx, _rx) = mpsc::unbounded_channel();
@@ -217,6 +227,7 @@ async fn test_sse_streaming_is_real_time_not_buffered() {
         path: "/api/v1/events".to_string(),
         headers: HashMap::new(),
         body: None,
+        encoding: PayloadEncoding::Utf8,
     };
 
     let (tx, _rx) = mpsc::unbounded_channel();
@@ -1,10 +1,16 @@
-use carapace_protocol::CliRequest;
+use carapace_policy::EnvFilter;
+use carapace_protocol::{CliRequest, PayloadEncoding};
 use std::collections::HashMap;
+use std::io::Write;
 use std::path::PathBuf;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::OwnedWriteHalf;
 use tokio::net::UnixStream;
+use tokio::signal::unix::{signal, SignalKind};
 use uuid::Uuid;
 
+mod tty;
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // Get argv[0] to extract tool name
@@ -17,8 +23,10 @@ async fn main() -> anyhow::Result<()> {
     // Get current working directory
     let cwd = std::env::current_dir()?.to_str().unwrap_or("/").to_string();
 
-    // Collect environment variables
-    let env: HashMap<String, String> = std::env::vars().collect();
+    // Collect only the environment variables tools may need; the server
+    // filters again against the tool's policy
+    let patterns = env_passthrough(std::env::var("CARAPACE_ENV_PASSTHROUGH").ok().as_deref());
+    let env = collect_env(&EnvFilter::new(&patterns)?, std::env::vars());
 
     // Create CLI request
     let request_id = Uuid::new_v4().to_string();
@@ -29,6 +37,8 @@ async fn main() -> anyhow::Result<()> {
         env,
         stdin: None,
         cwd,
+        // Interactive tools run in a pseudo-terminal if we are attached to one
+        tty: tty::window_size(),
     };
 
     // Connect to agent socket (with timeout)
@@ -56,71 +66,221 @@ async fn main() -> anyhow::Result<()> {
         }
     };
 
-    // Serialize request to JSON
-    let request_json = serde_json::to_vec(&cli_req)?;
+    // Serialize request to JSON, newline-terminated. Agents that accept
+    // control lines (forwarded signals, terminal input, cancel) say so, and
+    // the write half stays open for them; closing it cancels the request.
+    let mut request_json = serde_json::to_vec(&cli_req)?;
+    request_json.push(b'\n');
 
     // Send request
     stream.write_all(&request_json).await?;
-    stream.shutdown().await?;
-
-    // Read response using dynamic buffer (with timeout)
-    let mut response_buf = Vec::with_capacity(65536);
-    let mut tmp = [0u8; 8192];
-    let read_result = tokio::time::timeout(std::time::Duration::from_secs(60), async {
-        loop {
-            let n = stream.read(&mut tmp).await?;
-            if n == 0 {
-                break;
+    let (read_half, mut write_half) = stream.into_split();
+
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sighup = signal(SignalKind::hangup())?;
+    let mut sigwinch = signal(SignalKind::window_change())?;
+
+    // The agent answers with JSON lines: whether it accepts control lines,
+    // terminal output of an interactive session (`pty_data`), then the
+    // response itself. Older agents read until EOF and send just the
+    // response, without a newline.
+    let mut reader = BufReader::new(read_half);
+    let mut line = Vec::with_capacity(65536);
+    let control =
+        match tokio::time::timeout(ACCEPT_TIMEOUT, reader.read_until(b'\n', &mut line)).await {
+            Ok(Ok(n)) if n > 0 => match accepted(&line) {
+                Some(control) => {
+                    line.clear();
+                    Some(control)
+                }
+                None => {
+                    eprintln!("Error: Unexpected reply from agent");
+                    std::process::exit(1);
+                }
+            },
+            // EOF or a read error: the loop below reports it
+            Ok(_) => Some(Control { signals: false }),
+            Err(_) => {
+                // Partly read bytes stay in `line` for the loop below
+                let _ = write_half.shutdown().await;
+                None
             }
-            response_buf.extend_from_slice(&tmp[..n]);
-        }
-        Ok::<(), std::io::Error>(())
-    })
-    .await;
+        };
+    let forward_signals = control.is_some_and(|c| c.signals);
 
-    match read_result {
-        Ok(Ok(())) => {}
-        Ok(Err(e)) => {
-            eprintln!("Error: Failed to read response from agent: {}", e);
-            std::process::exit(1);
-        }
-        Err(_) => {
-            eprintln!("Error: Timed out waiting for response from agent");
-            std::process::exit(1);
-        }
-    }
+    let mut stdin = tokio::io::stdin();
+    let mut stdin_buf = [0u8; 4096];
+    let mut stdin_open = control.is_some();
+    // Set once the session turns out to be interactive
+    let mut raw_mode: Option<tty::RawMode> = None;
+    // Interactive sessions are bounded by the server's policy timeout instead
+    let timeout = tokio::time::sleep(std::time::Duration::from_secs(60));
+    tokio::pin!(timeout);
 
-    if response_buf.is_empty() {
-        eprintln!("Error: No response from agent");
-        std::process::exit(1);
-    }
+    // SIGTERM cancels the request. SIGINT and SIGHUP are forwarded to the
+    // remote process where the agent can deliver them; a second SIGINT
+    // cancels the request outright, in case the tool ignores the first.
+    // Without control lines the shim can only stop waiting.
+    let mut last_signal = None;
+    let response_json: serde_json::Value = loop {
+        let received = tokio::select! {
+            read = reader.read_until(b'\n', &mut line) => {
+                match read {
+                    Ok(0) if line.is_empty() => {
+                        drop(raw_mode.take());
+                        // An agent that cannot forward signals cancels the
+                        // request instead
+                        if let Some(signo) = last_signal {
+                            std::process::exit(128 + signo);
+                        }
+                        eprintln!("Error: No response from agent");
+                        std::process::exit(1);
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        drop(raw_mode.take());
+                        eprintln!("Error: Failed to read response from agent: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+                let value: serde_json::Value = serde_json::from_slice(&line)?;
+                line.clear();
+                if value["type"] != "pty_data" {
+                    break value;
+                }
+                if raw_mode.is_none() {
+                    raw_mode = Some(tty::RawMode::enable()?);
+                }
+                let data = PayloadEncoding::Base64.decode(value["data"].as_str().unwrap_or(""))?;
+                let mut out = std::io::stdout().lock();
+                out.write_all(&data)?;
+                out.flush()?;
+                continue;
+            }
+            read = stdin.read(&mut stdin_buf), if raw_mode.is_some() && stdin_open => {
+                match read {
+                    Ok(n) if n > 0 => {
+                        let data = PayloadEncoding::Base64.encode(&stdin_buf[..n]);
+                        let line = format!("{{\"type\":\"pty_data\",\"data\":\"{}\"}}\n", data);
+                        let _ = write_half.write_all(line.as_bytes()).await;
+                    }
+                    _ => stdin_open = false,
+                }
+                continue;
+            }
+            _ = sigwinch.recv() => {
+                if let (Some(_), Some(size)) = (&raw_mode, tty::window_size()) {
+                    let line = format!(
+                        "{{\"type\":\"pty_resize\",\"rows\":{},\"cols\":{}}}\n",
+                        size.rows, size.cols
+                    );
+                    let _ = write_half.write_all(line.as_bytes()).await;
+                }
+                continue;
+            }
+            _ = &mut timeout, if raw_mode.is_none() => {
+                eprintln!("Error: Timed out waiting for response from agent");
+                std::process::exit(1);
+            }
+            _ = sigint.recv() => libc::SIGINT,
+            _ = sigterm.recv() => libc::SIGTERM,
+            _ = sighup.recv() => libc::SIGHUP,
+        };
 
-    let n = response_buf.len();
+        let repeated = last_signal == Some(received);
+        last_signal = Some(received);
+        if control.is_none() {
+            drop(raw_mode.take());
+            std::process::exit(128 + received);
+        }
+        if !forward_signals || received == libc::SIGTERM || (repeated && received == libc::SIGINT) {
+            drop(raw_mode.take());
+            cancel(&mut write_half, received).await;
+        }
+        forward_signal(&mut write_half, received).await;
+    };
+    // Back to the normal terminal before printing anything else
+    drop(raw_mode);
 
-    // Parse response
-    let _ = n; // response_buf already sized correctly
-    let response_json: serde_json::Value = serde_json::from_slice(&response_buf)?;
+    // Extract fields. Like a shell, report a timeout as 124 and death by
+    // signal N as 128+N.
+    let exit_code = if response_json["timed_out"].as_bool() == Some(true) {
+        124
+    } else if let Some(signo) = response_json["signal"].as_i64() {
+        128 + signo as i32
+    } else {
+        response_json["exit_code"].as_i64().unwrap_or(-1) as i32
+    };
 
-    // Extract fields
-    let exit_code = response_json["exit_code"].as_i64().unwrap_or(-1) as i32;
+    // Output may be base64-encoded when the tool produced non-UTF-8 bytes
+    let encoding: PayloadEncoding =
+        serde_json::from_value(response_json["encoding"].clone()).unwrap_or_default();
 
-    let stdout = response_json["stdout"].as_str().unwrap_or("");
+    let stdout = encoding.decode(response_json["stdout"].as_str().unwrap_or(""))?;
 
-    let stderr = response_json["stderr"].as_str().unwrap_or("");
+    let stderr = encoding.decode(response_json["stderr"].as_str().unwrap_or(""))?;
 
-    // Print output
+    // Write output as raw bytes so binary output survives
     if !stdout.is_empty() {
-        print!("{}", stdout);
+        let mut out = std::io::stdout().lock();
+        out.write_all(&stdout)?;
+        out.flush()?;
     }
 
     if !stderr.is_empty() {
-        eprint!("{}", stderr);
+        let mut err = std::io::stderr().lock();
+        err.write_all(&stderr)?;
+        err.flush()?;
+    }
+
+    // Output cut off by the server's limits is marked after the fact, on
+    // stderr so the marker never mixes with (possibly binary) stdout
+    for stream in ["stdout", "stderr"] {
+        if response_json[format!("{}_truncated", stream)].as_bool() == Some(true) {
+            eprintln!("carapace: {} truncated by server policy", stream);
+        }
     }
 
     // Exit with response code
     std::process::exit(exit_code);
 }
 
+/// How long to wait for the agent to accept control lines before treating
+/// it as an agent that reads the request until EOF
+const ACCEPT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// What the agent accepts while the request runs
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Control {
+    /// Signals reach the remote process; otherwise they can only cancel it
+    signals: bool,
+}
+
+/// The agent's `{"type":"accepted","signals":B}` line, if `line` is one
+fn accepted(line: &[u8]) -> Option<Control> {
+    let value: serde_json::Value = serde_json::from_slice(line).ok()?;
+    (value["type"] == "accepted").then(|| Control {
+        signals: value["signals"].as_bool().unwrap_or(false),
+    })
+}
+
+/// Ask the agent to deliver `signo` to the remote process
+async fn forward_signal(write_half: &mut OwnedWriteHalf, signo: i32) {
+    let line = format!("{{\"type\":\"signal\",\"signal\":{}}}\n", signo);
+    // Best effort: if the agent is gone the read side sees EOF
+    let _ = write_half.write_all(line.as_bytes()).await;
+}
+
+/// Ask the agent to cancel the running command, then exit the way a process
+/// killed by `signo` would
+async fn cancel(write_half: &mut OwnedWriteHalf, signo: i32) -> ! {
+    // Best effort: if the agent is gone there is nothing left to cancel
+    let _ = write_half.write_all(b"{\"type\":\"cancel\"}\n").await;
+    let _ = write_half.shutdown().await;
+    std::process::exit(128 + signo);
+}
+
 /// Extract tool name from argv[0]
 fn extract_tool_name(argv0: &str) -> String {
     PathBuf::from(argv0)
@@ -130,6 +290,35 @@ fn extract_tool_name(argv0: &str) -> String {
         .to_string()
 }
 
+/// Client environment variables sent with every request
+const DEFAULT_ENV_PASSTHROUGH: &[&str] = &["LANG", "LC_*", "TERM", "COLORTERM", "NO_COLOR", "TZ"];
+
+/// Passthrough patterns: the defaults plus the comma-separated glob
+/// patterns of `CARAPACE_ENV_PASSTHROUGH`
+fn env_passthrough(extra: Option<&str>) -> Vec<String> {
+    let mut patterns: Vec<String> = DEFAULT_ENV_PASSTHROUGH
+        .iter()
+        .map(|p| p.to_string())
+        .collect();
+    if let Some(extra) = extra {
+        patterns.extend(
+            extra
+                .split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(String::from),
+        );
+    }
+    patterns
+}
+
+fn collect_env(
+    filter: &EnvFilter,
+    vars: impl Iterator<Item = (String, String)>,
+) -> HashMap<String, String> {
+    vars.filter(|(name, _)| filter.allows(name)).collect()
+}
+
 /// Get the path to the agent socket
 fn get_agent_socket_path() -> String {
     // Try to get from environment variable first
@@ -152,6 +341,44 @@ mod tests {
         assert_eq!(extract_tool_name("/"), "unknown");
     }
 
+    #[test]
+    fn test_accepted_line() {
+        assert_eq!(
+            accepted(b"{\"type\":\"accepted\",\"signals\":true}\n"),
+            Some(Control { signals: true })
+        );
+        assert_eq!(
+            accepted(b"{\"type\":\"accepted\",\"signals\":false}\n"),
+            Some(Control { signals: false })
+        );
+        assert_eq!(
+            accepted(b"{\"type\":\"cli_response\",\"exit_code\":0}\n"),
+            None
+        );
+        assert_eq!(accepted(b"{\"exit_code\":0"), None);
+    }
+
+    #[test]
+    fn test_env_passthrough() {
+        let patterns = env_passthrough(Some("GH_*, OP_ACCOUNT,,LD_PRELOAD"));
+        let filter = EnvFilter::new(&patterns).unwrap();
+        let vars = [
+            ("LANG", "C.UTF-8"),
+            ("GH_HOST", "github.com"),
+            ("OP_ACCOUNT", "me"),
+            ("HOME", "/home/user"),
+            ("AWS_SECRET_ACCESS_KEY", "secret"),
+            ("LD_PRELOAD", "/tmp/evil.so"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+
+        let env = collect_env(&filter, vars);
+        let mut names: Vec<&str> = env.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        assert_eq!(names, ["GH_HOST", "LANG", "OP_ACCOUNT"]);
+    }
+
     #[test]
     fn test_agent_socket_path_default() {
         // Clear the env var if it exists, then get path
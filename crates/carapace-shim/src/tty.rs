@@ -0,0 +1,52 @@
+use carapace_protocol::TtySize;
+use std::io::{self, IsTerminal};
+
+/// Size of the terminal the shim runs in, if stdin and stdout both are one
+pub fn window_size() -> Option<TtySize> {
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        return None;
+    }
+    // SAFETY: winsize is plain data, and TIOCGWINSZ writes one to a valid pointer
+    let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut ws) } == -1 {
+        return None;
+    }
+    Some(TtySize {
+        rows: ws.ws_row,
+        cols: ws.ws_col,
+    })
+}
+
+/// Puts the local terminal into raw mode, so keystrokes (Ctrl-C included)
+/// reach the remote tool unprocessed. The original settings come back on drop.
+pub struct RawMode {
+    original: libc::termios,
+}
+
+impl RawMode {
+    pub fn enable() -> io::Result<Self> {
+        // SAFETY: termios is plain data, filled in by tcgetattr before use
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let original = termios;
+        // SAFETY: cfmakeraw and tcsetattr only touch the struct we pass
+        unsafe {
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(RawMode { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: restores settings previously returned by tcgetattr
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
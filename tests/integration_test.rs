@@ -265,6 +265,8 @@ fn test_large_payload_handling() {
         exit_code: 0,
         stdout: large_output,
         stderr: "".to_string(),
+        stdout_truncated: false,
+        stderr_truncated: false,
     };
 
     let msg = Message::CliResponse(resp);
@@ -306,6 +306,8 @@ fn test_request_response_correlation() {
         exit_code: 0,
         stdout: "".to_string(),
         stderr: "".to_string(),
+        stdout_truncated: false,
+        stderr_truncated: false,
     });
 
     assert_eq!(req.id(), Some(req_id));